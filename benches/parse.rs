@@ -0,0 +1,64 @@
+//! Measures the front end on a large generated source.
+//! Run with `cargo bench --bench parse`.
+//!
+//! Before the AST was stored in an arena, each node was a 272-byte `Node` with boxed children.
+//! The same measurement on that tree (034a2d6), without the node count, against the arena
+//! which replaced it (aa9ccc1) gave, as the best of five runs on an x86_64 Linux host:
+//!
+//! ```text
+//! boxed tree: 644709 bytes, 2000 functions: 139.84ms
+//! arena: 644709 bytes, 2000 functions, 234006 nodes (88 bytes each): 91.30ms
+//! ```
+//!
+//! The arena parses and analyzes the source about a third faster, with nodes a third of the size.
+use dynamite_compiler::{CompileOptions, Compiler};
+use std::time::{Duration, Instant};
+
+const FUNCTIONS: usize = 2000;
+const ITERATIONS: usize = 5;
+
+fn generate_source(functions: usize) -> String {
+    let mut code = String::from("int g;\n");
+    for i in 0..functions {
+        code += &format!(
+            "int f{i}(int a, int b) {{
+    int x = a * {i} + b;
+    int arr[4] = {{1, 2, 3, 4}};
+    int *p = &x;
+    for (int j = 0; j < 10; j++) {{
+        x += (a << 2) ^ (b >> 1) | j % 3;
+        if (x > 100 && b != 0) x = x / b - arr[j % 4]; else *p -= 1;
+    }}
+    while (x < 0) x = x + {i};
+    g = x ? x : -x;
+    return x + g;
+}}
+"
+        );
+    }
+    code += "int main() { return f0(1, 2); }\n";
+    code
+}
+
+fn main() {
+    let code = generate_source(FUNCTIONS);
+    let mut best = Duration::MAX;
+    let mut node_count = 0;
+    for _ in 0..ITERATIONS {
+        let compiler = Compiler::new(&code, CompileOptions::default());
+        let start = Instant::now();
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        best = best.min(start.elapsed());
+        node_count = ast.nodes.len();
+    }
+    println!(
+        "parse+analyze: {} bytes, {} functions, {} nodes ({} bytes each): {:.2?}",
+        code.len(),
+        FUNCTIONS,
+        node_count,
+        std::mem::size_of::<dynamite_compiler::node::Node>(),
+        best
+    );
+}
@@ -0,0 +1,111 @@
+//! Compares the x86_64 code generated for tests/c/many_functions.c with and without
+//! register allocation, and the time to run it when `cc` can assemble it.
+//! Run with `cargo bench --bench regalloc`.
+//!
+//! The assembly of the stack machine which generated code before the mid-level IR, pushing
+//! and popping every operand, is compared as well when its path is given in `BASELINE_ASM`:
+//!
+//! ```sh
+//! git worktree add /tmp/baseline 1982846
+//! (cd /tmp/baseline && cargo run -q -- -o asm tests/c/many_functions.c) > /tmp/baseline.s
+//! BASELINE_ASM=/tmp/baseline.s cargo bench --bench regalloc
+//! ```
+//!
+//! On an x86_64 Linux host, this gave:
+//!
+//! ```text
+//! regalloc: 1642 instructions, 338 memory operands, run: 538.52µs
+//! no-regalloc: 2265 instructions, 1290 memory operands, run: 822.15µs
+//! baseline: 5293 instructions, 2836 memory operands, run: 878.58µs
+//! ```
+//!
+//! Register allocation leaves less than a third of the instructions of the stack machine,
+//! and an eighth of its memory accesses. The program runs for less than a millisecond,
+//! mostly starting the process, so its run times vary by more than their differences.
+use dynamite_compiler::generator::Target;
+use dynamite_compiler::ir::opt::OptOptions;
+use dynamite_compiler::{CompileOptions, Compiler};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const SOURCE: &str = "tests/c/many_functions.c";
+const ITERATIONS: usize = 20;
+
+/// Returns the number of instructions and of those reading or writing memory,
+/// counting `push` and `pop` as the stack machine used them for every operand.
+fn count_instructions(asm: &str) -> (usize, usize) {
+    let instructions = asm
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('.') && !line.ends_with(':'))
+        .collect::<Vec<_>>();
+    let memory = instructions
+        .iter()
+        .filter(|line| line.contains('[') || line.starts_with("push") || line.starts_with("pop"))
+        .count();
+    (instructions.len(), memory)
+}
+
+/// Assembles the program and returns the best time of its runs, if `cc` is available.
+fn run_time(asm: &str, name: &str) -> Option<Duration> {
+    let dir = std::env::temp_dir();
+    let asm_path = dir.join(format!("dynamite_bench_{name}.s"));
+    let exe_path = dir.join(format!("dynamite_bench_{name}"));
+    std::fs::write(&asm_path, format!("{asm}\n")).ok()?;
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&exe_path)
+        .arg(&asm_path)
+        .status()
+        .ok()?;
+    if !status.success() {
+        return None;
+    }
+    let mut best = Duration::MAX;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        let output = Command::new(&exe_path).output().ok()?;
+        best = best.min(start.elapsed());
+        assert!(output.status.success(), "{} failed", exe_path.display());
+    }
+    Some(best)
+}
+
+/// Prints the counts of the assembly, and its run time on an x86_64 Linux host.
+fn report(name: &str, asm: &str, target: Target) {
+    let (instructions, memory) = count_instructions(asm);
+    // the program only runs on an x86_64 Linux host
+    let time = if Target::host() == target {
+        run_time(asm, name)
+    } else {
+        None
+    };
+    println!(
+        "{name}: {instructions} instructions, {memory} memory operands, run: {}",
+        time.map_or(String::from("skipped"), |time| format!("{:.2?}", time))
+    );
+}
+
+fn main() {
+    let code = std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(SOURCE))
+        .expect("failed to read the source");
+    let target = Target::from_triple("x86_64-linux-gnu").unwrap();
+    let mut no_regalloc = OptOptions::default();
+    no_regalloc.apply_flag("no-regalloc").unwrap();
+    for (name, opt) in [
+        ("regalloc", OptOptions::default()),
+        ("no-regalloc", no_regalloc),
+    ] {
+        let options = CompileOptions::default()
+            .with_file_name(SOURCE)
+            .with_target(target)
+            .with_opt(opt);
+        let asm = Compiler::new(&code, options).compile().unwrap();
+        report(name, &asm, target);
+    }
+    if let Ok(path) = std::env::var("BASELINE_ASM") {
+        let asm = std::fs::read_to_string(&path).expect("failed to read the baseline");
+        report("baseline", &asm, target);
+    }
+}
@@ -1,28 +1,40 @@
 use crate::ctype::Type;
-use crate::error;
-use crate::func::Func;
+use crate::error::{self, ErrorCode, SyntaxError};
+use crate::fold;
+use crate::func::{Func, InlineHint};
 use crate::global::{GlobalVariable, GlobalVariableData};
-use crate::node::{Node, NodeType};
+use crate::node::{BinaryOp, EvalError, Node, NodeArena, NodeId, NodeKind, UnaryOp};
 use crate::token::{Token, TokenType};
-use crate::tokenizer::TYPES;
+use crate::tokenizer::{FUNCTION_SPECIFIERS, TYPES};
+use crate::warning::Warning;
+use indexmap::IndexMap;
 use std::collections::HashMap;
 use std::iter::repeat;
 
 pub struct AstBuilderImpl<'a> {
     error_logger: &'a dyn error::ErrorLogger,
     tokens: &'a Vec<Token>,
+    eof: Token,
     cur: usize,
     offset_size: usize,
+    loop_depth: usize,
     scope_stack: Vec<HashMap<String, Identifier>>,
-    pub functions: HashMap<String, Func>,
-    pub global_variables: HashMap<String, GlobalVariable>,
+    // declaration and whether it is used, of each local variable in the current function
+    local_usages: HashMap<usize, (Token, bool)>,
+    pub functions: IndexMap<String, Func>,
+    pub global_variables: IndexMap<String, GlobalVariable>,
     pub string_literals: Vec<String>,
+    pub nodes: NodeArena,
 }
 
-pub struct ProgramAst<'a> {
-    pub functions: &'a HashMap<String, Func>,
-    pub global_variables: &'a HashMap<String, GlobalVariable>,
-    pub string_literals: &'a Vec<String>,
+/// The functions, global variables and string literals of a program, owned apart from the builder.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramAst {
+    pub functions: IndexMap<String, Func>,
+    pub global_variables: IndexMap<String, GlobalVariable>,
+    pub string_literals: Vec<String>,
+    /// The nodes of all the functions, referred to by their bodies and arguments.
+    pub nodes: NodeArena,
 }
 
 pub enum Identifier {
@@ -31,6 +43,21 @@ pub enum Identifier {
     Static(Type),
 }
 
+type ParseResult<T> = Result<T, SyntaxError>;
+
+const COMPOUND_ASSIGNMENTS: [(&str, BinaryOp); 10] = [
+    ("+=", BinaryOp::Add),
+    ("-=", BinaryOp::Sub),
+    ("*=", BinaryOp::Mul),
+    ("/=", BinaryOp::Div),
+    ("%=", BinaryOp::Mod),
+    ("<<=", BinaryOp::BitLeft),
+    (">>=", BinaryOp::BitRight),
+    ("&=", BinaryOp::BitAnd),
+    ("^=", BinaryOp::BitXor),
+    ("|=", BinaryOp::BitOr),
+];
+
 pub trait AstBuilder {
     fn build(&mut self, is_debug: bool) -> ProgramAst;
 }
@@ -38,15 +65,21 @@ pub trait AstBuilder {
 impl AstBuilder for AstBuilderImpl<'_> {
     fn build(&mut self, is_debug: bool) -> ProgramAst {
         while !self.at_eof() {
-            self.global_definition()
+            if let Err(err) = self.global_definition() {
+                self.scope_stack.truncate(1);
+                self.loop_depth = 0;
+                self.recover(err);
+                self.synchronize_global();
+            }
         }
         if is_debug {
             self.print_functions();
         }
         ProgramAst {
-            functions: &self.functions,
-            global_variables: &self.global_variables,
-            string_literals: &self.string_literals,
+            functions: std::mem::take(&mut self.functions),
+            global_variables: std::mem::take(&mut self.global_variables),
+            string_literals: std::mem::take(&mut self.string_literals),
+            nodes: std::mem::take(&mut self.nodes),
         }
     }
 }
@@ -82,63 +115,70 @@ pub fn reserved_functions() -> HashMap<String, Identifier> {
 
 impl<'a> AstBuilderImpl<'a> {
     pub fn new(error_logger: &'a dyn error::ErrorLogger, tokens: &'a Vec<Token>) -> Self {
+        let eof = Token {
+            tt: TokenType::EOF,
+            pos: tokens
+                .last()
+                .map(|t| t.pos + t.s_value.len().max(1))
+                .unwrap_or(0),
+            ..Token::default()
+        };
         Self {
             error_logger,
             tokens,
+            eof,
             cur: 0,
             offset_size: 0,
+            loop_depth: 0,
             scope_stack: vec![reserved_functions()],
-            functions: HashMap::new(),
-            global_variables: HashMap::new(),
+            local_usages: HashMap::new(),
+            functions: IndexMap::new(),
+            global_variables: IndexMap::new(),
             string_literals: Vec::new(),
+            nodes: NodeArena::default(),
         }
     }
+    fn current(&self) -> &Token {
+        self.tokens.get(self.cur).unwrap_or(&self.eof)
+    }
     fn attempt_reserved(&mut self, s_value: &str) -> Option<Token> {
-        if let TokenType::Reserved = self.tokens[self.cur].tt {
-            if self.tokens[self.cur].s_value == s_value {
-                self.cur += 1;
-                return Some(self.tokens[self.cur - 1].clone());
-            }
+        let token = self.current();
+        if let TokenType::Reserved = token.tt
+            && token.s_value == s_value
+        {
+            self.cur += 1;
+            return Some(self.tokens[self.cur - 1].clone());
         }
         None
     }
     fn attempt_ident(&mut self) -> Option<Token> {
-        if let TokenType::Ident = self.tokens[self.cur].tt {
+        if let TokenType::Ident = self.current().tt {
             self.cur += 1;
             Some(self.tokens[self.cur - 1].clone())
         } else {
             None
         }
     }
-    fn expect_reserved(&mut self, s_value: &str) -> Token {
-        if let TokenType::Reserved = self.tokens[self.cur].tt {
-            if self.tokens[self.cur].s_value == s_value {
-                self.cur += 1;
-                return self.tokens[self.cur - 1].clone();
-            }
-        }
-        self.error_logger.print_error_position(
-            self.tokens[self.cur].pos,
-            &format!("`{}` expected", s_value),
-        );
-        unreachable!()
+    fn expect_reserved(&mut self, s_value: &str) -> ParseResult<Token> {
+        self.attempt_reserved(s_value).ok_or_else(|| {
+            SyntaxError::new(
+                ErrorCode::ExpectedToken,
+                self.current().pos,
+                &format!("`{}` expected", s_value),
+            )
+        })
     }
-    fn expect_number(&mut self) -> Token {
-        if let TokenType::Num = self.tokens[self.cur].tt {
-        } else {
-            self.error_logger.print_error_position(
-                self.tokens[self.cur].pos,
-                &format!(
-                    "number expected, but got {}",
-                    &self.tokens[self.cur].s_value
-                ),
+    fn expect_number(&mut self) -> ParseResult<Token> {
+        self.attempt(TokenType::Num).ok_or_else(|| {
+            SyntaxError::new(
+                ErrorCode::ExpectedNumber,
+                self.current().pos,
+                &format!("number expected, but got {}", &self.current().s_value),
             )
-        }
-        self.cur += 1;
-        self.tokens[self.cur - 1].clone()
+        })
     }
     fn attempt(&mut self, tt: TokenType) -> Option<Token> {
-        if tt == self.tokens[self.cur].tt {
+        if tt == self.current().tt {
             self.cur += 1;
             Some(self.tokens[self.cur - 1].clone())
         } else {
@@ -148,26 +188,98 @@ impl<'a> AstBuilderImpl<'a> {
     fn at_eof(&self) -> bool {
         self.cur >= self.tokens.len()
     }
-    fn expect_ident_with_type(&mut self, ty: Type) -> (Token, Type) {
+    fn report(&self, code: ErrorCode, pos: usize, msg: &str) {
+        self.error_logger.report(SyntaxError::new(code, pos, msg));
+    }
+    /// Reports an error that aborted a statement or a declaration.
+    /// Once the error limit is reached, the rest of the input is skipped.
+    fn recover(&mut self, err: SyntaxError) {
+        self.error_logger.report(err);
+        if self.error_logger.error_limit_reached() {
+            self.cur = self.tokens.len();
+        }
+    }
+    /// Skips tokens until the end of the current statement, so that parsing can resume
+    /// at the next `;`, the `}` closing the current block, or the next declaration.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while !self.at_eof() {
+            let token = &self.tokens[self.cur];
+            if let TokenType::Reserved = token.tt {
+                match token.s_value.as_str() {
+                    ";" if depth == 0 => {
+                        self.cur += 1;
+                        return;
+                    }
+                    "}" if depth == 0 => return,
+                    "}" if depth == 1 => {
+                        self.cur += 1;
+                        return;
+                    }
+                    "}" => depth -= 1,
+                    "{" => depth += 1,
+                    s if depth == 0 && TYPES.contains(&s) => return,
+                    _ => {}
+                }
+            }
+            self.cur += 1;
+        }
+    }
+    /// Skips tokens until the end of the current top-level declaration,
+    /// or until a type which seems to begin the next one.
+    fn synchronize_global(&mut self) {
+        let mut depth = 0;
+        while !self.at_eof() {
+            let token = &self.tokens[self.cur];
+            if let TokenType::Reserved = token.tt {
+                match token.s_value.as_str() {
+                    // a type never follows `,` or `(` at the beginning of a declaration
+                    s if depth == 0
+                        && (TYPES.contains(&s) || FUNCTION_SPECIFIERS.contains(&s))
+                        && (self.cur == 0
+                            || ![",", "("]
+                                .contains(&self.tokens[self.cur - 1].s_value.as_str())) =>
+                    {
+                        return;
+                    }
+                    ";" if depth == 0 => {
+                        self.cur += 1;
+                        return;
+                    }
+                    "}" if depth <= 1 => {
+                        self.cur += 1;
+                        return;
+                    }
+                    "}" => depth -= 1,
+                    "{" => depth += 1,
+                    _ => {}
+                }
+            }
+            self.cur += 1;
+        }
+    }
+    fn expect_ident_with_type(&mut self, ty: Type) -> ParseResult<(Token, Type)> {
         let mut ty = ty.clone();
         while self.attempt_reserved("*").is_some() {
             ty = Type::Ptr(Box::new(ty));
         }
         if let Some(t) = self.attempt_ident() {
             while self.attempt_reserved("[").is_some() {
-                let n = self.expect_number();
+                let n = self.expect_number()?;
                 ty = Type::Arr(Box::new(ty), n.i_value);
-                self.expect_reserved("]");
+                self.expect_reserved("]")?;
             }
-            (t, ty)
+            Ok((t, ty))
         } else {
-            self.error_logger
-                .print_error_position(self.tokens[self.cur].pos, "ident expected");
-            unreachable!();
+            Err(SyntaxError::new(
+                ErrorCode::ExpectedIdentifier,
+                self.current().pos,
+                "ident expected",
+            ))
         }
     }
-    fn new_local_variable(&mut self, ty: Type) -> Node {
-        let (t, ty) = self.expect_ident_with_type(ty);
+    fn new_local_variable(&mut self, ty: Type) -> ParseResult<NodeId> {
+        let (t, ty) = self.expect_ident_with_type(ty)?;
         self.offset_size += ty.size_of();
         let segment_size = if let Some(dest) = ty.dest_type() {
             dest.size_of()
@@ -176,23 +288,37 @@ impl<'a> AstBuilderImpl<'a> {
         };
         self.offset_size += segment_size - self.offset_size % segment_size;
         if self.scope_stack.last().unwrap().contains_key(&t.s_value) {
-            self.error_logger
-                .print_error_position(t.pos, "invalid redeclaration");
+            self.report(ErrorCode::Redeclaration, t.pos, "invalid redeclaration");
         }
         self.scope_stack.last_mut().unwrap().insert(
             t.s_value.clone(),
             Identifier::Local(ty.clone(), self.offset_size),
         );
-        Node {
-            token: Some(t),
-            nt: NodeType::LocalVar,
-            cty: Some(ty),
-            offset: Some(self.offset_size),
-            ..Node::default()
+        self.local_usages
+            .insert(self.offset_size, (t.clone(), false));
+        Ok(self
+            .nodes
+            .alloc(Node::new(NodeKind::LocalVar(self.offset_size), Some(t.pos)).with_type(ty)))
+    }
+    /// Parses a parameter, whose name may be omitted in a function declaration,
+    /// and returns its type and its variable if it is named.
+    fn parameter(&mut self, ty: Type) -> ParseResult<(Type, Option<NodeId>)> {
+        let start = self.cur;
+        let mut unnamed = ty.clone();
+        while self.attempt_reserved("*").is_some() {
+            unnamed = Type::Ptr(Box::new(unnamed));
+        }
+        if self.current().tt == TokenType::Reserved
+            && matches!(self.current().s_value.as_str(), "," | ")")
+        {
+            return Ok((unnamed, None));
         }
+        self.cur = start;
+        let arg = self.new_local_variable(ty)?;
+        Ok((self.nodes.resolve_type(arg).unwrap(), Some(arg)))
     }
     fn attempt_type(&mut self) -> Option<Type> {
-        let pos = self.tokens[self.cur].pos;
+        let pos = self.current().pos;
         let mut map: HashMap<&str, usize> = HashMap::new();
         repeat(())
             .find(|_| {
@@ -214,49 +340,148 @@ impl<'a> AstBuilderImpl<'a> {
         if *map.entry("char").or_default() == 1 {
             return Some(Type::I8);
         }
-        self.error_logger.print_error_position(pos, "invalid type");
-        unreachable!();
+        self.report(ErrorCode::InvalidType, pos, "invalid type");
+        Some(Type::I32)
     }
-    fn expect_type(&mut self) -> Type {
-        if let Some(ty) = self.attempt_type() {
-            ty
-        } else {
-            self.error_logger
-                .print_error_position(self.tokens[self.cur].pos, "type expected");
-            unreachable!()
+    fn expect_type(&mut self) -> ParseResult<Type> {
+        self.attempt_type().ok_or_else(|| {
+            SyntaxError::new(ErrorCode::ExpectedType, self.current().pos, "type expected")
+        })
+    }
+    /// Parses the `inline` specifiers and the `__attribute__((...))` lists before a declarator,
+    /// updating the inlining hint and returning the first token of them, if any.
+    /// The inlining attributes take precedence over `inline`.
+    fn function_specifiers(&mut self, hint: &mut InlineHint) -> ParseResult<Option<Token>> {
+        let mut first = None;
+        loop {
+            if let Some(t) = self.attempt_reserved("inline") {
+                if *hint == InlineHint::None {
+                    *hint = InlineHint::Inline;
+                }
+                first.get_or_insert(t);
+            } else if let Some(t) = self.attempt_reserved("__attribute__") {
+                first.get_or_insert(t);
+                self.expect_reserved("(")?;
+                self.expect_reserved("(")?;
+                while self.attempt_reserved(")").is_none() {
+                    let name = self.attempt_ident().ok_or_else(|| {
+                        SyntaxError::new(
+                            ErrorCode::ExpectedIdentifier,
+                            self.current().pos,
+                            "attribute name expected",
+                        )
+                    })?;
+                    // `__noinline__` is the same attribute as `noinline`
+                    let trimmed = name
+                        .s_value
+                        .strip_prefix("__")
+                        .and_then(|s| s.strip_suffix("__"))
+                        .unwrap_or(&name.s_value);
+                    match trimmed {
+                        "always_inline" => *hint = InlineHint::Always,
+                        "noinline" => *hint = InlineHint::Never,
+                        _ => self.error_logger.warn(
+                            Warning::UnknownAttributes,
+                            name.pos,
+                            &format!("unknown attribute '{}' ignored", name.s_value),
+                        ),
+                    }
+                    // skips the arguments of the attribute
+                    if self.attempt_reserved("(").is_some() {
+                        let mut depth = 1;
+                        while depth > 0 {
+                            if self.at_eof() {
+                                return Err(SyntaxError::new(
+                                    ErrorCode::ExpectedToken,
+                                    self.current().pos,
+                                    "`)` expected",
+                                ));
+                            }
+                            match self.current().s_value.as_str() {
+                                "(" if self.current().tt == TokenType::Reserved => depth += 1,
+                                ")" if self.current().tt == TokenType::Reserved => depth -= 1,
+                                _ => {}
+                            }
+                            self.cur += 1;
+                        }
+                    }
+                    if self.attempt_reserved(",").is_none() {
+                        self.expect_reserved(")")?;
+                        break;
+                    }
+                }
+                self.expect_reserved(")")?;
+            } else {
+                return Ok(first);
+            }
         }
     }
-    fn global_definition(&mut self) {
-        let ty = self.expect_type();
+    fn global_definition(&mut self) -> ParseResult<()> {
+        let mut inline = InlineHint::None;
+        let specifier = self.function_specifiers(&mut inline)?;
+        let ty = self.expect_type()?;
+        let specifier = specifier.or(self.function_specifiers(&mut inline)?);
         let cur_to_back = self.cur;
-        let (t, return_type) = self.expect_ident_with_type(ty.clone());
+        let (t, return_type) = self.expect_ident_with_type(ty.clone())?;
         if self.attempt_reserved("(").is_some() {
             // function
             self.offset_size = 0;
+            self.local_usages.clear();
             self.scope_stack.push(HashMap::new());
-            let mut args: Vec<Node> = Vec::new();
+            let mut params = Vec::new();
             if self.attempt_reserved(")").is_none() {
                 loop {
-                    let ty = self.expect_type();
-                    args.push(self.new_local_variable(ty));
+                    let ty = self.expect_type()?;
+                    params.push(self.parameter(ty)?);
                     if self.attempt_reserved(",").is_none() {
                         break;
                     }
                 }
-                self.expect_reserved(")");
+                self.expect_reserved(")")?;
             }
-            if args.len() >= 7 {
-                self.error_logger
-                    .print_error_position(t.pos, "count of args must be less than 7")
+            if params.len() >= 7 {
+                self.report(
+                    ErrorCode::TooManyArguments,
+                    t.pos,
+                    "count of args must be less than 7",
+                );
             }
-            let arg_types: Vec<Type> = args
-                .iter()
-                .map(|arg| arg.resolve_type().clone().unwrap())
-                .collect();
+            let arg_types: Vec<Type> = params.iter().map(|(ty, _)| ty.clone()).collect();
             self.scope_stack.first_mut().unwrap().insert(
                 t.s_value.clone(),
                 Identifier::Static(Type::Func(arg_types.clone(), Box::new(return_type.clone()))),
             );
+            if self.attempt_reserved(";").is_some() {
+                // a declaration, which does not replace the definition
+                self.scope_stack.pop();
+                if self
+                    .functions
+                    .get(&t.s_value)
+                    .is_none_or(|f| f.body.is_none())
+                {
+                    self.functions.insert(
+                        t.s_value.clone(),
+                        Func {
+                            cty: Type::Func(arg_types, Box::new(return_type)),
+                            token: Some(t.clone()),
+                            inline,
+                            ..Func::default()
+                        },
+                    );
+                }
+                return Ok(());
+            }
+            let mut args = Vec::new();
+            for (_, arg) in &params {
+                match arg {
+                    Some(arg) => args.push(*arg),
+                    None => self.report(
+                        ErrorCode::ExpectedIdentifier,
+                        t.pos,
+                        "parameter name omitted",
+                    ),
+                }
+            }
             self.functions.insert(
                 t.s_value.clone(),
                 Func {
@@ -265,8 +490,8 @@ impl<'a> AstBuilderImpl<'a> {
                     ..Func::default()
                 },
             );
-            let body = self.consume_block();
-            self.scope_stack.pop();
+            let body = self.consume_block()?;
+            self.pop_scope(Warning::UnusedParameter);
             self.functions.insert(
                 t.s_value.clone(),
                 Func {
@@ -275,14 +500,28 @@ impl<'a> AstBuilderImpl<'a> {
                     offset_size: self.offset_size,
                     token: Some(t.clone()),
                     args,
+                    inline,
                 },
             );
         } else {
             // global variable
+            if let Some(specifier) = specifier
+                && inline != InlineHint::None
+            {
+                self.report(
+                    ErrorCode::InvalidType,
+                    specifier.pos,
+                    "'inline' and inlining attributes can only appear on functions",
+                );
+            }
             self.cur = cur_to_back; // back the cursor
             loop {
-                let (t, ty) = self.expect_ident_with_type(ty.clone());
-                let data = self.attempt_reserved("=").map(|_| self.global_data());
+                let (t, ty) = self.expect_ident_with_type(ty.clone())?;
+                let data = if self.attempt_reserved("=").is_some() {
+                    Some(self.global_data()?)
+                } else {
+                    None
+                };
                 self.scope_stack
                     .last_mut()
                     .unwrap()
@@ -298,616 +537,657 @@ impl<'a> AstBuilderImpl<'a> {
                     break;
                 }
             }
-            self.expect_reserved(";");
+            self.expect_reserved(";")?;
         }
+        Ok(())
+    }
+    fn pop_scope(&mut self, warning: Warning) {
+        let scope = self.scope_stack.pop().unwrap();
+        scope
+            .values()
+            .filter_map(|ident| match ident {
+                Identifier::Local(_, offset) => self.local_usages.get(offset),
+                _ => None,
+            })
+            .filter(|(_, is_used)| !is_used)
+            .for_each(|(t, _)| {
+                self.error_logger.warn(
+                    warning,
+                    t.pos,
+                    &format!(
+                        "unused {} '{}'",
+                        if let Warning::UnusedParameter = warning {
+                            "parameter"
+                        } else {
+                            "variable"
+                        },
+                        t.s_value
+                    ),
+                )
+            });
     }
     fn resolve_name(&mut self, s: &str) -> Option<&Identifier> {
         self.scope_stack.iter().rev().find_map(|map| map.get(s))
     }
-    fn global_data(&mut self) -> GlobalVariableData {
+    fn global_data(&mut self) -> ParseResult<GlobalVariableData> {
         if self.attempt_reserved("{").is_some() {
             let mut vec = Vec::new();
             if self.attempt_reserved("}").is_none() {
                 loop {
-                    vec.push(self.global_data());
+                    vec.push(self.global_data()?);
                     if self.attempt_reserved(",").is_none() {
                         break;
                     }
                 }
-                self.expect_reserved("}");
+                self.expect_reserved("}")?;
             }
-            GlobalVariableData::Arr(vec)
+            Ok(GlobalVariableData::Arr(vec))
         } else if let Some(t) = self.attempt(TokenType::Str) {
-            GlobalVariableData::Elem(self.new_string_literal(&t.s_value))
+            Ok(GlobalVariableData::Elem(format!(
+                "@.str.{}",
+                self.new_string_literal(&t.s_value)
+            )))
         } else {
-            let equality = self.equality();
-            GlobalVariableData::Elem(format!("{}", self.eval(&equality)))
+            let equality = self.equality()?;
+            Ok(GlobalVariableData::Elem(format!("{}", self.eval(equality))))
         }
     }
-    fn eval(&mut self, node: &Node) -> i64 {
-        match node.nt {
-            NodeType::Num => node.value.unwrap() as i64,
-            NodeType::Eq => {
-                (self.eval(node.lhs.as_ref().unwrap()) == self.eval(node.rhs.as_ref().unwrap()))
-                    as i64
-            }
-            NodeType::Ne => {
-                (self.eval(node.lhs.as_ref().unwrap()) != self.eval(node.rhs.as_ref().unwrap()))
-                    as i64
-            }
-            NodeType::Le => {
-                (self.eval(node.lhs.as_ref().unwrap()) <= self.eval(node.rhs.as_ref().unwrap()))
-                    as i64
-            }
-            NodeType::Lt => {
-                (self.eval(node.lhs.as_ref().unwrap()) < self.eval(node.rhs.as_ref().unwrap()))
-                    as i64
-            }
-            NodeType::Add => {
-                self.eval(node.lhs.as_ref().unwrap()) + self.eval(node.rhs.as_ref().unwrap())
-            }
-            NodeType::Sub => {
-                self.eval(node.lhs.as_ref().unwrap()) - self.eval(node.rhs.as_ref().unwrap())
-            }
-            NodeType::Mul => {
-                self.eval(node.lhs.as_ref().unwrap()) * self.eval(node.rhs.as_ref().unwrap())
-            }
-            NodeType::Div => {
-                self.eval(node.lhs.as_ref().unwrap()) / self.eval(node.rhs.as_ref().unwrap())
-            }
-            NodeType::Mod => {
-                self.eval(node.lhs.as_ref().unwrap()) % self.eval(node.rhs.as_ref().unwrap())
-            }
-            _ => {
-                self.error_logger.print_error_position(
-                    node.token.as_ref().unwrap().pos,
-                    "initializer element is not a compile-time constant",
-                );
-                unreachable!()
+    fn eval(&mut self, node: NodeId) -> i64 {
+        let culprit = match self.nodes.eval(node) {
+            Ok(value) => return value,
+            Err(EvalError::NotConstant(culprit)) => culprit,
+            Err(
+                error @ (EvalError::DivisionByZero(culprit)
+                | EvalError::Overflow(culprit)
+                | EvalError::InvalidShift(culprit)),
+            ) => {
+                let (warning, msg) = fold::undefined_behavior(&self.nodes, error).unwrap();
+                self.error_logger
+                    .warn(warning, self.nodes[culprit].pos.unwrap_or(0), msg);
+                culprit
             }
-        }
+        };
+        self.report(
+            ErrorCode::NonConstantExpression,
+            self.nodes[culprit].pos.unwrap_or(0),
+            "initializer element is not a compile-time constant",
+        );
+        0
     }
-    fn stmt(&mut self) -> Node {
+    fn new_node(&mut self, kind: NodeKind, token: &Token) -> NodeId {
+        self.nodes.alloc(Node::new(kind, Some(token.pos)))
+    }
+    fn new_num(&mut self, token: Option<&Token>, value: usize) -> NodeId {
+        self.nodes
+            .alloc(Node::new(NodeKind::Num(value), token.map(|t| t.pos)))
+    }
+    /// Makes a binary operation, with the pointer on the left of an addition.
+    fn new_binary(&mut self, token: &Token, op: BinaryOp, lhs: NodeId, rhs: NodeId) -> NodeId {
+        let (lhs, rhs) = if op == BinaryOp::Add
+            && self.nodes.dest_type(lhs).is_none()
+            && self.nodes.dest_type(rhs).is_some()
+        {
+            (rhs, lhs)
+        } else {
+            (lhs, rhs)
+        };
+        self.new_node(NodeKind::Binary(op, lhs, rhs), token)
+    }
+    fn stmt(&mut self) -> ParseResult<NodeId> {
         if let Some(t) = self.attempt_reserved("if") {
-            self.expect_reserved("(");
-            let cond = self.expr();
-            self.expect_reserved(")");
-            let then = self.stmt();
-            let mut els: Option<Node> = None;
+            self.expect_reserved("(")?;
+            let cond = self.condition()?;
+            self.expect_reserved(")")?;
+            let then = self.stmt()?;
+            let mut els = None;
             if self.attempt_reserved("else").is_some() {
-                els = Some(self.stmt());
+                els = Some(self.stmt()?);
             }
-            return Node::new_if_node(Some(t), cond, then, els);
+            return Ok(self.new_node(NodeKind::If { cond, then, els }, &t));
         }
         if let Some(t) = self.attempt_reserved("while") {
-            self.expect_reserved("(");
-            let cond = self.expr();
-            self.expect_reserved(")");
-            return Node::new_while_node(Some(t), cond, self.stmt());
+            self.expect_reserved("(")?;
+            let cond = self.condition()?;
+            self.expect_reserved(")")?;
+            let body = self.loop_body()?;
+            return Ok(self.new_node(NodeKind::While { cond, body }, &t));
         }
         if let Some(t) = self.attempt_reserved("for") {
             self.scope_stack.push(HashMap::new());
-            self.expect_reserved("(");
-            let mut ini: Option<Node> = None;
-            let mut cond: Option<Node> = None;
-            let mut upd: Option<Node> = None;
+            self.expect_reserved("(")?;
+            let mut ini = None;
+            let mut cond = None;
+            let mut upd = None;
             if self.attempt_reserved(";").is_none() {
                 ini = Some(if let Some(ty) = self.attempt_type() {
-                    self.local_variable_definition(ty)
+                    self.local_variable_definition(ty)?
                 } else {
-                    self.expr()
+                    self.expr()?
                 });
-                self.expect_reserved(";");
+                self.expect_reserved(";")?;
             }
             if self.attempt_reserved(";").is_none() {
-                cond = Some(self.expr());
-                self.expect_reserved(";");
+                cond = Some(self.condition()?);
+                self.expect_reserved(";")?;
             }
             if self.attempt_reserved(")").is_none() {
-                upd = Some(self.expr());
-                self.expect_reserved(")");
+                upd = Some(self.expr()?);
+                self.expect_reserved(")")?;
             }
-            self.scope_stack.pop();
-            return Node::new_for_node(Some(t), ini, cond, upd, self.stmt());
+            let body = self.loop_body()?;
+            self.pop_scope(Warning::UnusedVariable);
+            return Ok(self.new_node(
+                NodeKind::For {
+                    ini,
+                    cond,
+                    upd,
+                    body,
+                },
+                &t,
+            ));
         }
-        if let Some(node) = self.consume_block() {
-            return node;
+        if let Some(node) = self.consume_block()? {
+            return Ok(node);
         }
         let node = if let Some(ty) = self.attempt_type() {
-            self.local_variable_definition(ty)
+            self.local_variable_definition(ty)?
         } else if let Some(t) = self.attempt_reserved("break") {
-            Node {
-                token: Some(t),
-                nt: NodeType::Break,
-                ..Node::default()
+            if self.loop_depth == 0 {
+                self.report(ErrorCode::UnexpectedBreak, t.pos, "unexpected break found");
             }
+            self.new_node(NodeKind::Break, &t)
         } else if let Some(t) = self.attempt_reserved("return") {
-            Node::new_with_op_and_lhs(Some(t), NodeType::Return, self.expr())
+            let value = self.expr()?;
+            self.new_node(NodeKind::Return(value), &t)
         } else {
-            self.expr()
+            self.expr()?
         };
-        self.expect_reserved(";");
-        node
+        self.expect_reserved(";")?;
+        Ok(node)
+    }
+
+    /// Parses the condition of `if`, `while` and `for` statements.
+    fn condition(&mut self) -> ParseResult<NodeId> {
+        let is_parenthesized =
+            self.current().tt == TokenType::Reserved && self.current().s_value == "(";
+        let cond = self.expr()?;
+        if let NodeKind::Assign(..) = self.nodes[cond].kind
+            && !is_parenthesized
+        {
+            self.error_logger.warn(
+                Warning::Parentheses,
+                self.nodes[cond].pos.unwrap(),
+                "using the result of an assignment as a condition without parentheses",
+            );
+        }
+        Ok(cond)
     }
 
-    pub fn local_variable_definition(&mut self, ty: Type) -> Node {
+    fn loop_body(&mut self) -> ParseResult<NodeId> {
+        self.loop_depth += 1;
+        let body = self.stmt();
+        self.loop_depth -= 1;
+        body
+    }
+
+    pub fn local_variable_definition(&mut self, ty: Type) -> ParseResult<NodeId> {
         let mut vec = Vec::new();
+        let mut pos = None;
         loop {
-            let node = self.new_local_variable(ty.clone());
+            let node = self.new_local_variable(ty.clone())?;
+            pos = pos.or(self.nodes[node].pos);
             if let Some(token) = self.attempt_reserved("=") {
                 // if initializer element exists, push into AST
-                vec.push(self.local_variable_initialization(&node, &token));
+                vec.push(self.local_variable_initialization(node, &token)?);
             }
             if self.attempt_reserved(",").is_none() {
                 break;
             }
         }
-        Node {
-            nt: NodeType::DefVar,
-            children: vec,
-            ..Node::default()
-        }
+        Ok(self.nodes.alloc(Node::new(NodeKind::DefVar(vec), pos)))
     }
 
-    fn local_variable_initialization(&mut self, node: &Node, assign_token: &Token) -> Node {
+    fn local_variable_initialization(
+        &mut self,
+        node: NodeId,
+        assign_token: &Token,
+    ) -> ParseResult<NodeId> {
         if let Some(b_token) = self.attempt_reserved("{") {
             let mut vec = Vec::new();
             if self.attempt_reserved("}").is_none() {
                 let mut index = 0;
                 loop {
-                    let rhs = Node::new_with_num(None, index);
-                    let mut node = Node {
-                        token: Some(b_token.clone()),
-                        nt: NodeType::Add,
-                        lhs: Some(Box::new(node.clone())),
-                        rhs: Some(Box::new(rhs)),
-                        ..Node::default()
-                    };
-                    node = Node {
-                        token: Some(b_token.clone()),
-                        nt: NodeType::Deref,
-                        lhs: Some(Box::new(node)),
-                        ..Node::default()
-                    };
-                    vec.push(self.local_variable_initialization(&node, assign_token));
+                    let lhs = self.nodes.duplicate(node);
+                    let rhs = self.new_num(None, index);
+                    let elem = self.new_node(NodeKind::Binary(BinaryOp::Add, lhs, rhs), &b_token);
+                    let elem = self.new_node(NodeKind::Unary(UnaryOp::Deref, elem), &b_token);
+                    vec.push(self.local_variable_initialization(elem, assign_token)?);
                     if self.attempt_reserved(",").is_none() {
                         break;
                     }
                     index += 1;
                 }
-                self.expect_reserved("}");
-            }
-            Node {
-                token: Some(assign_token.clone()),
-                nt: NodeType::Block,
-                children: vec,
-                ..Node::default()
+                self.expect_reserved("}")?;
             }
+            Ok(self.new_node(NodeKind::Block(vec), assign_token))
         } else {
-            Node {
-                token: Some(assign_token.clone()),
-                nt: NodeType::Assign,
-                lhs: Some(Box::new(node.clone())),
-                rhs: Some(Box::new(self.expr())),
-                ..Node::default()
-            }
+            let rhs = self.expr()?;
+            Ok(self.new_node(NodeKind::Assign(node, rhs), assign_token))
         }
     }
 
-    pub fn consume_block(&mut self) -> Option<Node> {
+    pub fn consume_block(&mut self) -> ParseResult<Option<NodeId>> {
         if let Some(t) = self.attempt_reserved("{") {
             self.scope_stack.push(HashMap::new());
-            let mut children: Vec<Node> = Vec::new();
+            let (scope_depth, loop_depth) = (self.scope_stack.len(), self.loop_depth);
+            let mut children = Vec::new();
             while self.attempt_reserved("}").is_none() {
-                children.push(self.stmt());
-            }
-            self.scope_stack.pop();
-            Some(Node {
-                token: Some(t),
-                nt: NodeType::Block,
-                children,
-                ..Node::default()
-            })
+                if self.at_eof() {
+                    return Err(SyntaxError::new(
+                        ErrorCode::ExpectedToken,
+                        self.current().pos,
+                        "`}` expected",
+                    ));
+                }
+                match self.stmt() {
+                    Ok(node) => children.push(node),
+                    Err(err) => {
+                        self.scope_stack.truncate(scope_depth);
+                        self.loop_depth = loop_depth;
+                        self.recover(err);
+                        self.synchronize();
+                    }
+                }
+            }
+            self.pop_scope(Warning::UnusedVariable);
+            Ok(Some(self.new_node(NodeKind::Block(children), &t)))
         } else {
-            None
+            Ok(None)
         }
     }
-    pub fn expr(&mut self) -> Node {
+    pub fn expr(&mut self) -> ParseResult<NodeId> {
         self.assign()
     }
-    fn assign(&mut self) -> Node {
-        let mut node = self.ternary();
+    fn assign(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.ternary()?;
         if let Some(t) = self.attempt_reserved("=") {
+            self.check_assignable(node);
             // left-associative => while, right-associative => recursive function
-            node = Node::new_with_op(Some(t), NodeType::Assign, node, self.assign())
-        } else if let Some(t) = self.attempt_reserved("+=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::Add, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved("-=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::Sub, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved("*=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::Mul, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved("/=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::Div, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved("%=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::Mod, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved("<<=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::BitLeft, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved(">>=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::BitRight, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved("&=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::BitAnd, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved("^=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::BitXor, node, self.assign()),
-            )
-        } else if let Some(t) = self.attempt_reserved("|=") {
-            node = Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::BitOr, node, self.assign()),
-            )
+            let rhs = self.assign()?;
+            node = self.new_node(NodeKind::Assign(node, rhs), &t);
+        } else if let Some((t, op)) = COMPOUND_ASSIGNMENTS
+            .iter()
+            .find_map(|(s, op)| self.attempt_reserved(s).map(|t| (t, *op)))
+        {
+            self.check_assignable(node);
+            let lhs = self.nodes.duplicate(node);
+            let rhs = self.assign()?;
+            let value = self.new_binary(&t, op, node, rhs);
+            node = self.new_node(NodeKind::Assign(lhs, value), &t);
         }
-        node
+        Ok(node)
     }
-    fn ternary(&mut self) -> Node {
-        let node = self.logical_or();
+    /// Reports the operand of an assignment, `++` or `--` unless it is a modifiable object.
+    fn check_assignable(&self, node: NodeId) {
+        let msg = match self.nodes.resolve_type(node) {
+            _ if !self.nodes.is_lvalue(node) => "expression is not assignable".to_string(),
+            Some(ty @ Type::Arr(..)) => format!("array type '{}' is not assignable", ty),
+            _ => return,
+        };
+        self.report(
+            ErrorCode::NotAssignable,
+            self.nodes.first_pos(node).unwrap_or(0),
+            &msg,
+        );
+    }
+    /// Reports the operand of `&` unless it is an object or a function.
+    fn check_addressable(&self, node: NodeId) {
+        match self.nodes.resolve_type(node) {
+            _ if self.nodes.is_lvalue(node) => {}
+            Some(Type::Func(..)) => {}
+            ty => self.report(
+                ErrorCode::NotAddressable,
+                self.nodes.first_pos(node).unwrap_or(0),
+                &format!(
+                    "cannot take the address of an rvalue of type '{}'",
+                    ty.unwrap_or_default()
+                ),
+            ),
+        }
+    }
+    fn ternary(&mut self) -> ParseResult<NodeId> {
+        let node = self.logical_or()?;
         if let Some(t) = self.attempt_reserved("?") {
-            let then = self.logical_or();
-            self.expect_reserved(":");
-            let els = self.logical_or();
-            return Node::new_if_node(Some(t), node, then, Some(els));
+            let then = self.logical_or()?;
+            self.expect_reserved(":")?;
+            let els = self.logical_or()?;
+            return Ok(self.new_node(
+                NodeKind::Conditional {
+                    cond: node,
+                    then,
+                    els,
+                },
+                &t,
+            ));
         }
-        node
+        Ok(node)
     }
-    fn logical_or(&mut self) -> Node {
-        let mut node = self.logical_and();
+    fn logical_or(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.logical_and()?;
         loop {
             if let Some(t) = self.attempt_reserved("||") {
-                node = Node::new_with_op(Some(t), NodeType::LogicalOr, node, self.logical_and());
+                let rhs = self.logical_and()?;
+                node = self.new_binary(&t, BinaryOp::LogicalOr, node, rhs);
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn logical_and(&mut self) -> Node {
-        let mut node = self.bitwise_or();
+    fn logical_and(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.bitwise_or()?;
         loop {
             if let Some(t) = self.attempt_reserved("&&") {
-                node = Node::new_with_op(Some(t), NodeType::LogicalAnd, node, self.bitwise_or());
+                let rhs = self.bitwise_or()?;
+                node = self.new_binary(&t, BinaryOp::LogicalAnd, node, rhs);
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn bitwise_or(&mut self) -> Node {
-        let mut node = self.bitwise_xor();
+    fn bitwise_or(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.bitwise_xor()?;
         loop {
             if let Some(t) = self.attempt_reserved("|") {
-                node = Node::new_with_op(Some(t), NodeType::BitOr, node, self.bitwise_xor())
+                let rhs = self.bitwise_xor()?;
+                node = self.new_binary(&t, BinaryOp::BitOr, node, rhs)
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn bitwise_xor(&mut self) -> Node {
-        let mut node = self.bitwise_and();
+    fn bitwise_xor(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.bitwise_and()?;
         loop {
             if let Some(t) = self.attempt_reserved("^") {
-                node = Node::new_with_op(Some(t), NodeType::BitXor, node, self.bitwise_and())
+                let rhs = self.bitwise_and()?;
+                node = self.new_binary(&t, BinaryOp::BitXor, node, rhs)
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn bitwise_and(&mut self) -> Node {
-        let mut node = self.equality();
+    fn bitwise_and(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.equality()?;
         loop {
             if let Some(t) = self.attempt_reserved("&") {
-                node = Node::new_with_op(Some(t), NodeType::BitAnd, node, self.equality())
+                let rhs = self.equality()?;
+                node = self.new_binary(&t, BinaryOp::BitAnd, node, rhs)
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn equality(&mut self) -> Node {
-        let mut node = self.relational();
+    fn equality(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.relational()?;
         loop {
             if let Some(t) = self.attempt_reserved("==") {
-                node = Node::new_with_op(Some(t), NodeType::Eq, node, self.relational())
+                let rhs = self.relational()?;
+                node = self.new_binary(&t, BinaryOp::Eq, node, rhs)
             } else if let Some(t) = self.attempt_reserved("!=") {
-                node = Node::new_with_op(Some(t), NodeType::Ne, node, self.relational())
+                let rhs = self.relational()?;
+                node = self.new_binary(&t, BinaryOp::Ne, node, rhs)
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn relational(&mut self) -> Node {
-        let mut node = self.bit_shift();
+    fn relational(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.bit_shift()?;
         loop {
             if let Some(t) = self.attempt_reserved("<") {
-                node = Node::new_with_op(Some(t), NodeType::Lt, node, self.bit_shift())
+                let rhs = self.bit_shift()?;
+                node = self.new_binary(&t, BinaryOp::Lt, node, rhs)
             } else if let Some(t) = self.attempt_reserved("<=") {
-                node = Node::new_with_op(Some(t), NodeType::Le, node, self.bit_shift())
+                let rhs = self.bit_shift()?;
+                node = self.new_binary(&t, BinaryOp::Le, node, rhs)
             } else if let Some(t) = self.attempt_reserved(">") {
-                node = Node::new_with_op(Some(t), NodeType::Lt, self.bit_shift(), node)
+                let lhs = self.bit_shift()?;
+                node = self.new_binary(&t, BinaryOp::Lt, lhs, node)
             } else if let Some(t) = self.attempt_reserved(">=") {
-                node = Node::new_with_op(Some(t), NodeType::Le, self.bit_shift(), node)
+                let lhs = self.bit_shift()?;
+                node = self.new_binary(&t, BinaryOp::Le, lhs, node)
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn bit_shift(&mut self) -> Node {
-        let mut node = self.add();
+    fn bit_shift(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.add()?;
         loop {
             if let Some(t) = self.attempt_reserved("<<") {
-                node = Node::new_with_op(Some(t), NodeType::BitLeft, node, self.add())
+                let rhs = self.add()?;
+                node = self.new_binary(&t, BinaryOp::BitLeft, node, rhs)
             } else if let Some(t) = self.attempt_reserved(">>") {
-                node = Node::new_with_op(Some(t), NodeType::BitRight, node, self.add())
+                let rhs = self.add()?;
+                node = self.new_binary(&t, BinaryOp::BitRight, node, rhs)
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn add(&mut self) -> Node {
-        let mut node = self.mul();
+    fn add(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.mul()?;
         loop {
             if let Some(t) = self.attempt_reserved("+") {
-                node = Node::new_with_op(Some(t), NodeType::Add, node, self.mul())
+                let rhs = self.mul()?;
+                node = self.new_binary(&t, BinaryOp::Add, node, rhs)
             } else if let Some(t) = self.attempt_reserved("-") {
-                node = Node::new_with_op(Some(t), NodeType::Sub, node, self.mul())
+                let rhs = self.mul()?;
+                node = self.new_binary(&t, BinaryOp::Sub, node, rhs)
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn mul(&mut self) -> Node {
-        let mut node = self.unary();
+    fn mul(&mut self) -> ParseResult<NodeId> {
+        let mut node = self.unary()?;
         loop {
             if let Some(t) = self.attempt_reserved("*") {
-                node = Node::new_with_op(Some(t), NodeType::Mul, node, self.unary())
+                let rhs = self.unary()?;
+                node = self.new_binary(&t, BinaryOp::Mul, node, rhs)
             } else if let Some(t) = self.attempt_reserved("/") {
-                node = Node::new_with_op(Some(t), NodeType::Div, node, self.unary())
+                let rhs = self.unary()?;
+                node = self.new_binary(&t, BinaryOp::Div, node, rhs)
             } else if let Some(t) = self.attempt_reserved("%") {
-                node = Node::new_with_op(Some(t), NodeType::Mod, node, self.unary())
+                let rhs = self.unary()?;
+                node = self.new_binary(&t, BinaryOp::Mod, node, rhs)
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
-    fn unary(&mut self) -> Node {
+    fn unary(&mut self) -> ParseResult<NodeId> {
         if let Some(t) = self.attempt_reserved("sizeof") {
-            return Node {
-                token: Some(t),
-                value: Some(self.unary().resolve_type().unwrap().size_of()),
-                cty: Some(Type::I32),
-                ..Node::default()
+            let operand = self.unary()?;
+            let value = if let Some(ty) = self.nodes.resolve_type(operand) {
+                ty.size_of()
+            } else {
+                self.report(
+                    ErrorCode::UnresolvedType,
+                    t.pos,
+                    "cannot resolve the type of the operand",
+                );
+                0
             };
+            return Ok(self
+                .nodes
+                .alloc(Node::new(NodeKind::Num(value), Some(t.pos)).with_type(Type::I32)));
         }
         if self.attempt_reserved("+").is_some() {
         } else if let Some(t) = self.attempt_reserved("-") {
-            return Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Sub,
-                Node::new_with_num(Some(t.clone()), 0),
-                self.prim(),
-            );
+            let zero = self.new_num(Some(&t), 0);
+            let operand = self.prim()?;
+            return Ok(self.new_binary(&t, BinaryOp::Sub, zero, operand));
         }
         if let Some(t) = self.attempt_reserved("&") {
-            return Node {
-                token: Some(t),
-                nt: NodeType::Addr,
-                lhs: Some(Box::new(self.unary())),
-                ..Node::default()
-            };
+            let operand = self.unary()?;
+            self.check_addressable(operand);
+            return Ok(self.new_node(NodeKind::Unary(UnaryOp::Addr, operand), &t));
         }
-        if let Some(t) = self.attempt_reserved("*") {
-            return Node {
-                token: Some(t),
-                nt: NodeType::Deref,
-                lhs: Some(Box::new(self.unary())),
-                ..Node::default()
-            };
-        }
-        if let Some(t) = self.attempt_reserved("~") {
-            return Node {
-                token: Some(t),
-                nt: NodeType::BitNot,
-                lhs: Some(Box::new(self.unary())),
-                ..Node::default()
-            };
+        for (s, op) in [("*", UnaryOp::Deref), ("~", UnaryOp::BitNot)] {
+            if let Some(t) = self.attempt_reserved(s) {
+                let operand = self.unary()?;
+                return Ok(self.new_node(NodeKind::Unary(op, operand), &t));
+            }
         }
         if let Some(t) = self.attempt_reserved("!") {
-            return Node {
-                token: Some(t),
-                nt: NodeType::Eq,
-                lhs: Some(Box::new(self.unary())),
-                rhs: Some(Box::new(Node::new_with_num(None, 0))),
-                ..Node::default()
-            };
+            let operand = self.unary()?;
+            let zero = self.new_num(None, 0);
+            return Ok(self.new_node(NodeKind::Binary(BinaryOp::Eq, operand, zero), &t));
         }
-        if let Some(t) = self.attempt_reserved("++") {
-            let node = self.unary();
-            return Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::Add, node, Node::new_with_num(None, 1)),
-            );
-        }
-        if let Some(t) = self.attempt_reserved("--") {
-            let node = self.unary();
-            return Node::new_with_op(
-                Some(t.clone()),
-                NodeType::Assign,
-                node.clone(),
-                Node::new_with_op(Some(t), NodeType::Sub, node, Node::new_with_num(None, 1)),
-            );
+        for (s, op) in [("++", BinaryOp::Add), ("--", BinaryOp::Sub)] {
+            if let Some(t) = self.attempt_reserved(s) {
+                let node = self.unary()?;
+                self.check_assignable(node);
+                let lhs = self.nodes.duplicate(node);
+                let one = self.new_num(None, 1);
+                let value = self.new_binary(&t, op, node, one);
+                return Ok(self.new_node(NodeKind::Assign(lhs, value), &t));
+            }
         }
         self.prim()
     }
-    fn prim(&mut self) -> Node {
+    fn prim(&mut self) -> ParseResult<NodeId> {
         let mut node = if self.attempt_reserved("(").is_some() {
-            let node = self.expr();
-            self.expect_reserved(")");
+            let node = self.expr()?;
+            self.expect_reserved(")")?;
             node
         } else if let Some(t) = self.attempt(TokenType::Str) {
             // String literal
-            let node = Node {
-                token: Some(t.clone()),
-                nt: NodeType::GlobalVar,
-                dest: self.new_string_literal(&t.s_value),
-                ..Node::default()
-            };
-            Node {
-                token: Some(t.clone()),
-                nt: NodeType::Addr,
-                cty: Some(Type::Ptr(Box::new(Type::I8))),
-                lhs: Some(Box::new(node)),
-                ..Node::default()
-            }
+            let ty = Type::Arr(
+                Box::new(Type::I8),
+                t.s_value.replace("\\\\", "*").replace('\\', "").len() + 1,
+            );
+            let index = self.new_string_literal(&t.s_value);
+            let node = self
+                .nodes
+                .alloc(Node::new(NodeKind::StringLiteral(index), Some(t.pos)).with_type(ty));
+            self.nodes.alloc(
+                Node::new(NodeKind::Unary(UnaryOp::Addr, node), Some(t.pos))
+                    .with_type(Type::Ptr(Box::new(Type::I8))),
+            )
         } else if let Some(t) = self.attempt(TokenType::Num) {
             // Number literal
-            Node {
-                token: Some(t.clone()),
-                nt: NodeType::Num,
-                value: Some(t.i_value),
-                cty: Some(Type::I32),
-                ..Node::default()
-            }
+            self.nodes
+                .alloc(Node::new(NodeKind::Num(t.i_value), Some(t.pos)).with_type(Type::I32))
         } else if let Some(t) = self.attempt_ident() {
-            if let Some(ident) = self.resolve_name(&t.s_value) {
-                match ident {
-                    Identifier::Local(ty, offset) => Node {
-                        token: Some(t.clone()),
-                        nt: NodeType::LocalVar,
-                        cty: Some(ty.clone()),
-                        offset: Some(*offset),
-                        ..Node::default()
-                    },
-                    Identifier::Static(ty) => Node {
-                        token: Some(t.clone()),
-                        nt: NodeType::GlobalVar,
-                        cty: Some(ty.clone()),
-                        global_name: t.s_value.clone(),
-                        ..Node::default()
-                    },
-                    Identifier::TypeDef(..) => {
-                        unimplemented!()
+            let resolved = match self.resolve_name(&t.s_value) {
+                Some(Identifier::Local(ty, offset)) => Some((ty.clone(), Some(*offset))),
+                Some(Identifier::Static(ty)) => Some((ty.clone(), None)),
+                Some(Identifier::TypeDef(..)) => unimplemented!(),
+                None => None,
+            };
+            let (kind, ty) = match resolved {
+                Some((ty, Some(offset))) => {
+                    if let Some(usage) = self.local_usages.get_mut(&offset) {
+                        usage.1 = true;
                     }
+                    (NodeKind::LocalVar(offset), ty)
                 }
-            } else {
-                self.error_logger
-                    .print_error_position(t.pos, "undefined variable");
-                unreachable!();
-            }
+                Some((ty, None)) => (NodeKind::GlobalVar(t.s_value.clone()), ty),
+                None if self.current().tt == TokenType::Reserved
+                    && self.current().s_value == "(" =>
+                {
+                    // implicitly declare `int name()` as C89 does
+                    self.error_logger.warn(
+                        Warning::ImplicitFunctionDeclaration,
+                        t.pos,
+                        &format!("implicit declaration of function '{}'", t.s_value),
+                    );
+                    let ty = Type::Func(vec![], Box::new(Type::I32));
+                    self.scope_stack
+                        .first_mut()
+                        .unwrap()
+                        .insert(t.s_value.clone(), Identifier::Static(ty.clone()));
+                    self.functions.insert(
+                        t.s_value.clone(),
+                        Func {
+                            cty: ty.clone(),
+                            token: Some(t.clone()),
+                            ..Func::default()
+                        },
+                    );
+                    (NodeKind::GlobalVar(t.s_value.clone()), ty)
+                }
+                None => {
+                    // keep parsing with a placeholder so that following errors are also reported
+                    self.report(ErrorCode::UndefinedVariable, t.pos, "undefined variable");
+                    (NodeKind::GlobalVar(t.s_value.clone()), Type::I32)
+                }
+            };
+            self.nodes.alloc(Node::new(kind, Some(t.pos)).with_type(ty))
         } else {
-            self.error_logger
-                .print_error_position(self.tokens[self.cur].pos, "unexpected token");
-            unreachable!();
+            return Err(SyntaxError::new(
+                ErrorCode::UnexpectedToken,
+                self.current().pos,
+                "unexpected token",
+            ));
         };
         loop {
             if self.attempt_reserved("(").is_some() {
                 // Call function
-                let t = node.token.clone().unwrap();
-                let return_type = if let Some(Type::Func(_, return_type)) = node.resolve_type() {
-                    *return_type.clone()
-                } else {
-                    unreachable!()
+                let pos = self.nodes[node].pos;
+                // the callee is checked by the semantic analysis
+                let return_type = match self.nodes.resolve_type(node) {
+                    Some(Type::Func(_, return_type)) => *return_type,
+                    _ => Type::I32,
                 };
-                let mut args: Vec<Node> = Vec::new();
+                let mut args = Vec::new();
                 if self.attempt_reserved(")").is_none() {
-                    args.push(self.expr());
+                    args.push(self.expr()?);
                     while self.attempt_reserved(")").is_none() {
-                        self.expect_reserved(",");
-                        args.push(self.expr());
+                        self.expect_reserved(",")?;
+                        args.push(self.expr()?);
                     }
                     if args.len() >= 7 {
-                        self.error_logger
-                            .print_error_position(t.pos, "count of args must be less than 7")
+                        self.report(
+                            ErrorCode::TooManyArguments,
+                            pos.unwrap_or(0),
+                            "count of args must be less than 7",
+                        );
                     }
                     // conforming to cdecl
                     // arguments are pushed onto the stack, from right to left
                     args.reverse();
                 }
-                let s_value = t.s_value.clone();
-                node = Node {
-                    token: Some(t),
-                    nt: NodeType::CallFunc,
-                    global_name: s_value,
-                    cty: Some(return_type),
-                    args,
-                    ..Node::default()
-                }
+                node = self.nodes.alloc(
+                    Node::new(NodeKind::CallFunc { callee: node, args }, pos)
+                        .with_type(return_type),
+                );
             } else if let Some(b_token) = self.attempt_reserved("[") {
                 // Subscript array
-                node = Node::new_with_op(Some(b_token.clone()), NodeType::Add, node, self.expr());
-                self.expect_reserved("]");
-                node = Node {
-                    token: Some(b_token.clone()),
-                    nt: NodeType::Deref,
-                    lhs: Some(Box::new(node)),
-                    ..Node::default()
-                }
+                let index = self.expr()?;
+                node = self.new_binary(&b_token, BinaryOp::Add, node, index);
+                self.expect_reserved("]")?;
+                node = self.new_node(NodeKind::Unary(UnaryOp::Deref, node), &b_token);
             } else if let Some(token) = self.attempt_reserved("++") {
                 // Suffix increment
-                node = Node::new_with_op_and_lhs(Some(token), NodeType::SuffixIncr, node);
+                self.check_assignable(node);
+                node = self.new_node(NodeKind::Unary(UnaryOp::SuffixIncr, node), &token);
             } else if let Some(token) = self.attempt_reserved("--") {
                 // Suffix decrement
-                node = Node::new_with_op_and_lhs(Some(token), NodeType::SuffixDecr, node);
+                self.check_assignable(node);
+                node = self.new_node(NodeKind::Unary(UnaryOp::SuffixDecr, node), &token);
             } else {
-                return node;
+                return Ok(node);
             }
         }
     }
 
-    fn new_string_literal(&mut self, s: &str) -> String {
+    /// Adds a string literal to the program, and returns its number.
+    fn new_string_literal(&mut self, s: &str) -> usize {
         self.string_literals.push(s.to_string());
-        format!("@.str.{}", self.string_literals.len() - 1)
+        self.string_literals.len() - 1
     }
 
     pub fn print_functions(&self) {
@@ -915,14 +1195,15 @@ impl<'a> AstBuilderImpl<'a> {
             .iter()
             .filter(|(_, f)| f.body.is_some())
             .for_each(|(s, f)| {
-                eprintln!("[FUNC: {}]\n{}", s, f.to_debug_string());
+                eprintln!("[FUNC: {}]\n{}", s, f.to_debug_string(&self.nodes));
             })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::error::NopLogger;
+    use crate::error::{ErrorLogger, ErrorPrinter, NopLogger};
+    use crate::tokenizer::Tokenizer;
 
     use super::*;
 
@@ -964,8 +1245,8 @@ mod tests {
         let mut ast_builder = AstBuilderImpl::new(&error_logger, tokens);
         let program_ast = ast_builder.build(false);
         assert_eq!(program_ast.functions.len(), 0);
-        assert_eq!(*program_ast.global_variables, {
-            let mut map = HashMap::new();
+        assert_eq!(program_ast.global_variables, {
+            let mut map = IndexMap::new();
             map.insert(
                 "x".to_string(),
                 GlobalVariable {
@@ -977,4 +1258,134 @@ mod tests {
         });
         assert_eq!(program_ast.string_literals.len(), 0);
     }
+
+    #[test]
+    fn test_ast_builder_reports_all_errors() {
+        let code = "int a = 1\nint main() { int b = 2 int c; c = d; if (b { b = 1; } return b +; }\nint f(int x y) { return x; }";
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        AstBuilderImpl::new(&error_printer, &tokens).build(false);
+        assert_eq!(
+            error_printer
+                .errors()
+                .iter()
+                .map(|e| (&code[e.pos..e.pos + 1], e.msg.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("i", "`;` expected"),
+                ("i", "`;` expected"),
+                ("d", "undefined variable"),
+                ("{", "`)` expected"),
+                (";", "unexpected token"),
+                ("y", "`)` expected"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ast_builder_reports_invalid_assignment_targets() {
+        let code = "int f() { return 0; }\nint main() { int x; int a[2]; 3 = x; f() = 1; x + x += 2; a = 0; x++; --f; &3; &f; return 0; }";
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        AstBuilderImpl::new(&error_printer, &tokens).build(false);
+        assert_eq!(
+            error_printer
+                .errors()
+                .iter()
+                .map(|e| (&code[e.pos..e.pos + 1], e.msg.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("3", "expression is not assignable"),
+                ("f", "expression is not assignable"),
+                ("x", "expression is not assignable"),
+                ("a", "array type 'int [2]' is not assignable"),
+                ("f", "expression is not assignable"),
+                ("3", "cannot take the address of an rvalue of type 'int'"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ast_builder_parses_function_specifiers() {
+        let code = "inline int a() { return 0; }
+__attribute__((noinline, unused)) int b() { return 0; }
+inline int __attribute__((__always_inline__, aligned(8))) c() { return 0; }
+int d() { return 0; }
+inline int x;";
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        let program_ast = AstBuilderImpl::new(&error_printer, &tokens).build(false);
+        assert_eq!(
+            program_ast
+                .functions
+                .values()
+                .map(|f| f.inline)
+                .collect::<Vec<_>>(),
+            vec![
+                InlineHint::Inline,
+                InlineHint::Never,
+                InlineHint::Always,
+                InlineHint::None,
+            ]
+        );
+        assert_eq!(
+            error_printer
+                .diagnostics()
+                .iter()
+                .map(|d| (&code[d.pos..d.pos + 1], d.msg.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("u", "unknown attribute 'unused' ignored"),
+                ("a", "unknown attribute 'aligned' ignored"),
+                (
+                    "i",
+                    "'inline' and inlining attributes can only appear on functions"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ast_builder_parses_function_declarations() {
+        let code = "int sum(int, char *);
+int twice(int n);
+int twice(int n) { return sum(n, 0) * 2; }
+int twice(int);
+int main() { return twice(1); }
+int f(int) { return 0; }";
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        let program_ast = AstBuilderImpl::new(&error_printer, &tokens).build(false);
+        let sum = &program_ast.functions["sum"];
+        assert!(sum.body.is_none());
+        assert_eq!(
+            sum.cty,
+            Type::Func(
+                vec![Type::I32, Type::Ptr(Box::new(Type::I8))],
+                Box::new(Type::I32)
+            )
+        );
+        // the declaration after the definition keeps it
+        let twice = &program_ast.functions["twice"];
+        assert!(twice.body.is_some());
+        assert_eq!(twice.args.len(), 1);
+        assert_eq!(
+            error_printer
+                .diagnostics()
+                .iter()
+                .map(|d| (&code[d.pos..d.pos + 1], d.msg.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("f", "parameter name omitted")]
+        );
+    }
+
+    #[test]
+    fn test_ast_builder_stops_at_error_limit() {
+        let code = "int main() { a; b; c; d; }";
+        let error_printer = ErrorPrinter::new(code).with_error_limit(2);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        AstBuilderImpl::new(&error_printer, &tokens).build(false);
+        assert_eq!(error_printer.error_count(), 2);
+        assert!(error_printer.error_limit_reached());
+    }
 }
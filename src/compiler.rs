@@ -0,0 +1,308 @@
+use crate::ast::{AstBuilder, AstBuilderImpl, ProgramAst};
+use crate::error::{self, Diagnostic, DiagnosticsFormat, ErrorLogger, ErrorPrinter};
+use crate::fold::ConstantFolder;
+use crate::generator::{self, Target};
+use crate::ir::opt::{OptOptions, Pass};
+use crate::ir::{self, Function, Module};
+use crate::sema;
+use crate::token::Token;
+use crate::tokenizer::Tokenizer;
+use crate::warning::{WarningChecker, WarningOptions};
+use std::fmt;
+
+/// What `Compiler::codegen` generates.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputKind {
+    #[default]
+    Assembly,
+    LlvmIr,
+}
+
+impl OutputKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "asm" => Some(OutputKind::Assembly),
+            "llvm" => Some(OutputKind::LlvmIr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    is_debug: bool,
+    error_limit: usize,
+    warnings: WarningOptions,
+    diagnostics_format: DiagnosticsFormat,
+    file_name: String,
+    target: Target,
+    output: OutputKind,
+    opt: OptOptions,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            is_debug: false,
+            error_limit: error::DEFAULT_ERROR_LIMIT,
+            warnings: WarningOptions::default(),
+            diagnostics_format: DiagnosticsFormat::default(),
+            file_name: String::from("<input>"),
+            target: Target::host(),
+            output: OutputKind::default(),
+            opt: OptOptions::default(),
+        }
+    }
+}
+
+impl CompileOptions {
+    /// Prints the tokens and the functions to stderr while compiling.
+    pub fn with_debug(self, is_debug: bool) -> Self {
+        Self { is_debug, ..self }
+    }
+    /// Maximum number of errors to report before stopping, 0 for no limit.
+    pub fn with_error_limit(self, error_limit: usize) -> Self {
+        Self {
+            error_limit,
+            ..self
+        }
+    }
+    pub fn with_warnings(self, warnings: WarningOptions) -> Self {
+        Self { warnings, ..self }
+    }
+    pub fn with_diagnostics_format(self, diagnostics_format: DiagnosticsFormat) -> Self {
+        Self {
+            diagnostics_format,
+            ..self
+        }
+    }
+    /// The name of the source file shown in diagnostics.
+    pub fn with_file_name(self, file_name: &str) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            ..self
+        }
+    }
+    pub fn with_target(self, target: Target) -> Self {
+        Self { target, ..self }
+    }
+    pub fn with_output(self, output: OutputKind) -> Self {
+        Self { output, ..self }
+    }
+    /// The optimization passes run on the mid-level IR.
+    pub fn with_opt(self, opt: OptOptions) -> Self {
+        Self { opt, ..self }
+    }
+    pub fn target(&self) -> Target {
+        self.target
+    }
+    pub fn output(&self) -> OutputKind {
+        self.output
+    }
+    pub fn opt(&self) -> &OptOptions {
+        &self.opt
+    }
+}
+
+/// The diagnostics of a compilation which failed.
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub diagnostics: Vec<Diagnostic>,
+    /// The diagnostics written in the format of the options.
+    pub rendered: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.rendered)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Compiles a source code, stage by stage or at once with `compile`.
+/// The diagnostics reported by the stages are kept until the compiler is dropped.
+pub struct Compiler<'a> {
+    code: &'a str,
+    options: CompileOptions,
+    error_printer: ErrorPrinter<'a>,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(code: &'a str, options: CompileOptions) -> Self {
+        let error_printer = ErrorPrinter::new(code)
+            .with_file_name(&options.file_name)
+            .with_format(options.diagnostics_format)
+            .with_error_limit(options.error_limit)
+            .with_warning_options(options.warnings.clone());
+        Self {
+            code,
+            options,
+            error_printer,
+        }
+    }
+
+    pub fn code(&self) -> &'a str {
+        self.code
+    }
+
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    pub fn tokenize(&self) -> Result<Vec<Token>, CompileError> {
+        Tokenizer::tokenize(self.code, self.options.is_debug).map_err(|e| {
+            self.error_printer.report(e);
+            self.error()
+        })
+    }
+
+    /// Builds the program, recovering from syntax errors so that `analyze` reports all of them.
+    pub fn parse(&self, tokens: &[Token]) -> ProgramAst {
+        let tokens = tokens.to_vec();
+        AstBuilderImpl::new(&self.error_printer, &tokens).build(self.options.is_debug)
+    }
+
+    /// Annotates the types, checks the warnings and folds the constant expressions,
+    /// and fails if any error has been reported. The warnings of whole function bodies
+    /// are not checked after syntax errors, whose recovered statements would give false ones.
+    pub fn analyze(&self, ast: &mut ProgramAst) -> Result<(), CompileError> {
+        let has_syntax_errors = self.error_printer.error_count() > 0;
+        sema::SemanticAnalyzer::new(&self.error_printer).analyze(ast);
+        if !has_syntax_errors {
+            WarningChecker::new(&self.error_printer).check(ast);
+        }
+        if self.error_printer.error_count() > 0 {
+            return Err(self.error());
+        }
+        ConstantFolder::new(&self.error_printer).fold(ast);
+        // the division by zero warnings may be errors with `-Werror`
+        if self.error_printer.error_count() > 0 {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    /// Lowers an analyzed program to the mid-level IR shared by the backends,
+    /// and runs the optimization passes of the options on it.
+    /// The functions are optimized after the functions they call, which are inlined into them
+    /// before they are optimized again. The IR is verified after each pass.
+    pub fn lower(&self, ast: &ProgramAst) -> Module {
+        let mut module = ir::lower::lower(ast);
+        let check = |module: &Module, func: &Function, stage: &str| {
+            if let Err(e) = ir::verify::verify_function(module, func) {
+                panic!("{} after {}\n{}", e, stage, func);
+            }
+        };
+        let after_pass = |module: &Module, pass: Pass, func: &Function| {
+            if self.options.is_debug {
+                eprintln!("; after {}\n{}", pass.name(), func);
+            }
+            check(module, func, pass.name());
+        };
+        for i in ir::inline::bottom_up_order(&module) {
+            // keeps the signature in the module while the function is optimized
+            let declaration = Function {
+                name: module.functions[i].name.clone(),
+                signature: module.functions[i].signature.clone(),
+                params: vec![],
+                slots: vec![],
+                blocks: vec![],
+                value_count: 0,
+                inline: module.functions[i].inline,
+            };
+            let mut func = std::mem::replace(&mut module.functions[i], declaration);
+            check(&module, &func, "lowering");
+            ir::opt::optimize(&mut func, &self.options.opt, |pass, func| {
+                after_pass(&module, pass, func)
+            });
+            let by_size = self.options.opt.is_enabled(Pass::Inline);
+            if ir::inline::inline(&mut func, &module, by_size) {
+                after_pass(&module, Pass::Inline, &func);
+                ir::opt::optimize(&mut func, &self.options.opt, |pass, func| {
+                    after_pass(&module, pass, func)
+                });
+            }
+            module.functions[i] = func;
+        }
+        module
+    }
+
+    /// Generates the output of the options from a lowered program.
+    pub fn codegen(&self, module: &Module) -> String {
+        self.codegen_as(module, self.options.output)
+    }
+
+    /// Generates the output of the kind, so that one program can be given to several backends.
+    pub fn codegen_as(&self, module: &Module, output: OutputKind) -> String {
+        let target = self.options.target;
+        match output {
+            OutputKind::Assembly => generator::new(target, &self.options.opt)
+                .generate(module)
+                .to_string(target.os),
+            OutputKind::LlvmIr => {
+                generator::llvm::generator::IrGenerator::new(target, &self.options.opt)
+                    .generate(module)
+            }
+        }
+    }
+
+    /// Runs all the stages.
+    pub fn compile(&self) -> Result<String, CompileError> {
+        let tokens = self.tokenize()?;
+        let mut ast = self.parse(&tokens);
+        self.analyze(&mut ast)?;
+        let module = self.lower(&ast);
+        Ok(self.codegen(&module))
+    }
+
+    /// Returns the errors and warnings reported so far, in order of the position.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.error_printer.diagnostics()
+    }
+
+    pub fn print_diagnostics(&self) {
+        self.error_printer.print_diagnostics();
+    }
+
+    fn error(&self) -> CompileError {
+        CompileError {
+            diagnostics: self.diagnostics(),
+            rendered: self.error_printer.render_diagnostics(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    #[test]
+    fn test_compiler_stages() {
+        let options = CompileOptions::default().with_output(OutputKind::LlvmIr);
+        let compiler = Compiler::new("int main() { return 1 + 2; }", options);
+        let tokens = compiler.tokenize().unwrap();
+        assert_eq!(tokens.len(), 11);
+        let mut ast = compiler.parse(&tokens);
+        assert!(ast.functions.contains_key("main"));
+        compiler.analyze(&mut ast).unwrap();
+        let module = compiler.lower(&ast);
+        assert!(compiler.codegen(&module).contains("define i32 @main()"));
+        assert!(compiler.diagnostics().is_empty());
+        // the program outlives the compiler, and can be moved to another thread
+        let cloned = ast.clone();
+        let handle = std::thread::spawn(move || cloned.functions.len());
+        assert_eq!(handle.join().unwrap(), 1);
+        assert!(
+            compiler
+                .codegen_as(&module, OutputKind::Assembly)
+                .contains("main:")
+        );
+
+        let compiler = Compiler::new("int main() { return x; }", CompileOptions::default());
+        let err = compiler.compile().unwrap_err();
+        assert_eq!(err.diagnostics[0].code, ErrorCode::UndefinedVariable);
+        assert!(err.to_string().ends_with("1 error generated.\n"));
+    }
+}
@@ -1,6 +1,7 @@
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Default)]
 pub enum Type {
     I8,
+    #[default]
     I32,
     Ptr(Box<Type>),
     Arr(Box<Type>, usize),
@@ -17,6 +18,9 @@ impl Type {
             Type::Func(..) => 1,
         }
     }
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::I8 | Type::I32)
+    }
     pub fn dest_type(&self) -> Option<Type> {
         match self {
             Type::Ptr(c) => Some(*c.clone()),
@@ -26,8 +30,25 @@ impl Type {
     }
 }
 
-impl Default for Type {
-    fn default() -> Self {
-        Self::I32
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::I8 => write!(f, "char"),
+            Type::I32 => write!(f, "int"),
+            Type::Ptr(c) => match c.as_ref() {
+                Type::Ptr(_) => write!(f, "{}*", c),
+                _ => write!(f, "{} *", c),
+            },
+            Type::Arr(c, s) => write!(f, "{} [{}]", c, s),
+            Type::Func(args, r) => write!(
+                f,
+                "{} ({})",
+                r,
+                args.iter()
+                    .map(|a| a.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
     }
 }
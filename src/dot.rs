@@ -0,0 +1,159 @@
+use crate::ast::ProgramAst;
+use crate::emit::node_detail;
+use crate::ir::{Module, Terminator};
+use crate::node::{NodeArena, NodeId};
+
+/// Renders the AST of each function as a graph for Graphviz.
+pub fn ast_to_dot(code: &str, ast: &ProgramAst) -> String {
+    let mut lines = vec![
+        String::from("digraph ast {"),
+        String::from("  node [shape=box];"),
+    ];
+    let mut count = 0;
+    for (name, f) in ast.functions.iter() {
+        let id = format!("n{}", count);
+        count += 1;
+        lines.push(format!(
+            "  {} [label=\"{}\" shape=ellipse];",
+            id,
+            escape(&format!("{}: {}", name, f.cty))
+        ));
+        for (edge, node) in f
+            .args
+            .iter()
+            .map(|arg| ("param", *arg))
+            .chain(f.body.map(|body| ("body", body)))
+        {
+            let child = node_to_dot(code, &ast.nodes, node, &mut count, &mut lines);
+            lines.push(format!("  {} -> {} [label=\"{}\"];", id, child, edge));
+        }
+    }
+    lines.push(String::from("}"));
+    lines.join("\n")
+}
+
+/// Writes the node and its descendants, and returns the identifier of the node.
+fn node_to_dot(
+    code: &str,
+    nodes: &NodeArena,
+    node: NodeId,
+    count: &mut usize,
+    lines: &mut Vec<String>,
+) -> String {
+    let id = format!("n{}", count);
+    *count += 1;
+    let mut label = nodes[node].kind.name();
+    if let Some(detail) = node_detail(code, &nodes[node]) {
+        label += &format!(" {}", detail);
+    }
+    if !nodes[node].kind.is_statement()
+        && let Some(ty) = nodes.resolve_type(node)
+    {
+        label += &format!("\n{}", ty);
+    }
+    lines.push(format!("  {} [label=\"{}\"];", id, escape(&label)));
+    for (edge, child) in nodes.edges(node) {
+        let child = node_to_dot(code, nodes, child, count, lines);
+        lines.push(format!("  {} -> {} [label=\"{}\"];", id, child, edge));
+    }
+    id
+}
+
+/// Renders the basic blocks of each function of the mid-level IR as a cluster for Graphviz.
+pub fn cfg_to_dot(module: &Module) -> String {
+    let mut lines = vec![
+        String::from("digraph cfg {"),
+        String::from("  node [shape=box];"),
+    ];
+    for func in module.functions.iter().filter(|f| !f.blocks.is_empty()) {
+        let name = escape(&func.name);
+        lines.push(format!("  subgraph \"cluster_{}\" {{", name));
+        lines.push(format!("    label=\"{}\";", name));
+        for id in func.block_ids() {
+            let block = func.block(id);
+            // `\l` ends a left-justified line
+            let label = [id.to_string()]
+                .into_iter()
+                .chain(block.insts.iter().map(|inst| inst.to_string()))
+                .chain([block.term.to_string()])
+                .map(|line| escape(&line) + "\\l")
+                .collect::<String>();
+            lines.push(format!("    \"{}.{}\" [label=\"{}\"];", name, id, label));
+            let successors = match block.term {
+                Terminator::Jump(target) => vec![(target, None)],
+                Terminator::Branch { then, els, .. } => {
+                    vec![(then, Some("true")), (els, Some("false"))]
+                }
+                Terminator::Return(_) => vec![],
+            };
+            for (to, edge) in successors {
+                lines.push(format!(
+                    "    \"{}.{}\" -> \"{}.{}\"{};",
+                    name,
+                    id,
+                    name,
+                    to,
+                    edge.map_or(String::new(), |edge| format!(" [label=\"{}\"]", edge))
+                ));
+            }
+        }
+        lines.push(String::from("  }"));
+    }
+    lines.push(String::from("}"));
+    lines.join("\n")
+}
+
+/// Escapes a string to be quoted in the DOT language.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{AstBuilder, AstBuilderImpl};
+    use crate::error::ErrorPrinter;
+    use crate::tokenizer::Tokenizer;
+    use crate::{CompileOptions, Compiler};
+
+    #[test]
+    fn test_dot() {
+        let code = "int main() { if (1) printf(\"a\\n\"); return 0; }";
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        let mut ast_builder = AstBuilderImpl::new(&error_printer, &tokens);
+        let ast = ast_builder.build(false);
+        let ast_dot = ast_to_dot(code, &ast);
+        assert!(ast_dot.starts_with(
+            "digraph ast {\n  node [shape=box];\n  n0 [label=\"main: int ()\" shape=ellipse];\n"
+        ));
+        assert!(ast_dot.contains(
+            "  n2 [label=\"If\"];\n  n3 [label=\"Num 1\\nint\"];\n  n2 -> n3 [label=\"cond\"];\n"
+        ));
+        let code = "int f(int a) { if (a) g(); return 0; }";
+        let compiler = Compiler::new(code, CompileOptions::default());
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        assert_eq!(
+            cfg_to_dot(&compiler.lower(&ast)),
+            [
+                "digraph cfg {",
+                "  node [shape=box];",
+                "  subgraph \"cluster_f\" {",
+                "    label=\"f\";",
+                "    \"f.bb0\" [label=\"bb0\\l%3 = extend i32 %0\\lbr %3, bb1, bb2\\l\"];",
+                "    \"f.bb0\" -> \"f.bb1\" [label=\"true\"];",
+                "    \"f.bb0\" -> \"f.bb2\" [label=\"false\"];",
+                "    \"f.bb1\" [label=\"bb1\\l%2 = call @g()\\ljmp bb2\\l\"];",
+                "    \"f.bb1\" -> \"f.bb2\";",
+                "    \"f.bb2\" [label=\"bb2\\lret 0\\l\"];",
+                "  }",
+                "}",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use crate::emit::{Emit, EmitFormat};
+use crate::generator::Target;
+use crate::{CompileError, CompileOptions, OutputKind, emit_with_options, generate_with_options};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Where the driver stops, chosen by `-S` and `-c` as gcc does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// `-S`: write assembly, or LLVM IR with `-emit-llvm`.
+    Assembly,
+    /// `-c`: write an object file.
+    Object,
+    /// Link an executable.
+    Executable,
+}
+
+/// The language of the inputs, chosen by `-x` or by the file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    C,
+    Assembler,
+}
+
+impl Language {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "c" => Some(Language::C),
+            "assembler" => Some(Language::Assembler),
+            _ => None,
+        }
+    }
+}
+
+/// Why the driver stopped: the diagnostics of a source which does not compile,
+/// or a message about the inputs or the tools it runs.
+#[derive(Debug)]
+pub enum DriverError {
+    Compile(CompileError),
+    Message(String),
+}
+
+impl From<CompileError> for DriverError {
+    fn from(e: CompileError) -> Self {
+        DriverError::Compile(e)
+    }
+}
+
+impl From<String> for DriverError {
+    fn from(msg: String) -> Self {
+        DriverError::Message(msg)
+    }
+}
+
+pub struct Driver {
+    pub stage: Stage,
+    /// Output path given by `-o`.
+    pub output: Option<String>,
+    /// Language given by `-x`, which is otherwise decided by the file extension.
+    pub language: Option<Language>,
+    /// Intermediate representations given by `--emit`, written instead of running the stage.
+    pub emits: Vec<Emit>,
+    pub emit_format: EmitFormat,
+    /// Arguments passed through to the linker, such as `-lm`.
+    pub linker_args: Vec<String>,
+    pub options: CompileOptions,
+}
+
+impl Driver {
+    pub fn new(options: CompileOptions) -> Self {
+        Self {
+            stage: Stage::Executable,
+            output: None,
+            language: None,
+            emits: Vec::new(),
+            emit_format: EmitFormat::default(),
+            linker_args: Vec::new(),
+            options,
+        }
+    }
+
+    /// Compiles the C sources among the inputs, and assembles and links them with the rest.
+    pub fn run(&self, inputs: &[String]) -> Result<(), DriverError> {
+        if inputs.is_empty() {
+            return Err("no input files".to_string().into());
+        }
+        if self.output.is_some()
+            && (self.stage != Stage::Executable || !self.emits.is_empty())
+            && inputs.len() * self.emits.len().max(1) > 1
+        {
+            return Err("cannot specify -o when generating multiple output files"
+                .to_string()
+                .into());
+        }
+        if !self.emits.is_empty() {
+            return inputs.iter().try_for_each(|input| self.emit(input));
+        }
+        match self.stage {
+            Stage::Assembly => inputs.iter().try_for_each(|input| {
+                Ok(write_output(
+                    &self.output_path(input),
+                    &self.compile(input)?,
+                )?)
+            }),
+            Stage::Object => inputs
+                .iter()
+                .try_for_each(|input| self.assemble(input, &self.output_path(input))),
+            Stage::Executable => {
+                // the objects of the units compiled so far are deleted when one fails
+                let mut temp_objects = Vec::new();
+                let result = self.build(inputs, &mut temp_objects);
+                temp_objects.iter().for_each(|(_, object)| {
+                    let _ = fs::remove_file(object);
+                });
+                result
+            }
+        }
+    }
+
+    /// Assembles the inputs which are not objects to temporary objects, and links all of them.
+    fn build<'a>(
+        &self,
+        inputs: &'a [String],
+        temp_objects: &mut Vec<(&'a String, String)>,
+    ) -> Result<(), DriverError> {
+        for (i, input) in inputs.iter().enumerate() {
+            if self.language_of(input).is_none() {
+                continue;
+            }
+            let object = std::env::temp_dir()
+                .join(format!("dynamite-{}-{}.o", std::process::id(), i))
+                .to_string_lossy()
+                .to_string();
+            temp_objects.push((input, object.clone()));
+            self.assemble(input, &object)?;
+        }
+        let objects = inputs
+            .iter()
+            .map(|input| {
+                temp_objects
+                    .iter()
+                    .find(|(source, _)| *source == input)
+                    .map_or(input.clone(), |(_, object)| object.clone())
+            })
+            .collect::<Vec<String>>();
+        Ok(self.link(&objects)?)
+    }
+
+    /// Returns the path given by `-o`, or the input file name with the extension of the stage.
+    fn output_path(&self, input: &str) -> String {
+        if let Some(output) = &self.output {
+            return output.clone();
+        }
+        let extension = match self.stage {
+            Stage::Assembly if self.options.output() == OutputKind::LlvmIr => "ll",
+            Stage::Assembly => "s",
+            Stage::Object => "o",
+            Stage::Executable => return "a.out".to_string(),
+        };
+        Self::with_extension(input, extension)
+    }
+
+    /// Returns the file name of the input in the current directory, with the extension replaced.
+    fn with_extension(input: &str, extension: &str) -> String {
+        Path::new(input)
+            .with_extension(extension)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Returns the name of the input shown in diagnostics.
+    fn file_name(input: &str) -> &str {
+        if input == "-" { "<stdin>" } else { input }
+    }
+
+    /// Writes the representations given by `--emit` of a C source, each to its own file.
+    fn emit(&self, input: &str) -> Result<(), DriverError> {
+        let code = self.read(input)?;
+        let outputs = emit_with_options(
+            &code,
+            &self.emits,
+            self.emit_format,
+            &self.options.clone().with_file_name(Self::file_name(input)),
+        )?;
+        self.emits
+            .iter()
+            .zip(outputs)
+            .try_for_each(|(emit, output)| {
+                let path = self.output.clone().unwrap_or_else(|| {
+                    Self::with_extension(input, emit.extension(self.emit_format))
+                });
+                Ok(write_output(&path, &(output + "\n"))?)
+            })
+    }
+
+    /// Returns the language of the input, or `None` for one passed through to the linker.
+    /// The standard input `-` is read as C unless `-x` says otherwise.
+    fn language_of(&self, input: &str) -> Option<Language> {
+        if self.language.is_some() {
+            return self.language;
+        }
+        match Path::new(input).extension().and_then(|e| e.to_str()) {
+            _ if input == "-" => Some(Language::C),
+            Some("c") => Some(Language::C),
+            Some("s") => Some(Language::Assembler),
+            _ => None,
+        }
+    }
+
+    /// Reads the input file, or the standard input for `-`.
+    fn read(&self, input: &str) -> Result<String, String> {
+        if input == "-" {
+            let mut code = String::new();
+            return std::io::stdin()
+                .read_to_string(&mut code)
+                .map(|_| code)
+                .map_err(|e| format!("cannot read the standard input: {}", e));
+        }
+        fs::read_to_string(input).map_err(|e| format!("cannot read \"{}\": {}", input, e))
+    }
+
+    fn compile(&self, input: &str) -> Result<String, DriverError> {
+        let code = self.read(input)?;
+        let assembly = generate_with_options(
+            &code,
+            &self.options.clone().with_file_name(Self::file_name(input)),
+        )?;
+        Ok(assembly + "\n")
+    }
+
+    /// Writes an object file from a C source or an assembly file.
+    fn assemble(&self, input: &str, output: &str) -> Result<(), DriverError> {
+        match self.language_of(input) {
+            Some(Language::C) => {}
+            Some(Language::Assembler) => {
+                return Ok(run(
+                    Command::new(self.cc()).args(["-x", "assembler", "-c", "-o", output, "-"]),
+                    Some(&self.read(input)?),
+                )?);
+            }
+            None => {
+                return Ok(run(
+                    Command::new(self.cc()).args(["-c", "-o", output, input]),
+                    None,
+                )?);
+            }
+        }
+        let code = self.compile(input)?;
+        let mut command = if self.options.output() == OutputKind::LlvmIr {
+            let mut clang = Command::new("clang");
+            clang.arg(format!("--target={}", self.options.target().llvm_triple()));
+            clang.args(["-x", "ir"]);
+            clang
+        } else {
+            let mut cc = Command::new(self.cc());
+            cc.args(["-x", "assembler"]);
+            cc
+        };
+        Ok(run(command.args(["-c", "-o", output, "-"]), Some(&code))?)
+    }
+
+    /// Returns the compiler used to assemble and link, which is the gcc cross toolchain
+    /// named after the triple, such as `aarch64-linux-gnu-gcc`, for a target other than the host.
+    fn cc(&self) -> String {
+        if self.options.target() == Target::host() {
+            "cc".to_string()
+        } else {
+            format!("{}-gcc", self.options.target().triple())
+        }
+    }
+
+    fn link(&self, objects: &[String]) -> Result<(), String> {
+        run(
+            Command::new(self.cc())
+                .args(["-o", &self.output_path("")])
+                .args(objects)
+                .args(&self.linker_args),
+            None,
+        )
+    }
+}
+
+/// Writes to the file, or to the standard output for `-`.
+fn write_output(path: &str, contents: &str) -> Result<(), String> {
+    if path == "-" {
+        print!("{}", contents);
+        return Ok(());
+    }
+    fs::write(path, contents).map_err(|e| format!("cannot write \"{}\": {}", path, e))
+}
+
+fn run(command: &mut Command, stdin: Option<&str>) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot execute '{}': {}", program, e))?;
+    if let Some(input) = stdin {
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .map_err(|e| format!("cannot write to '{}': {}", program, e))?;
+    }
+    let status = child
+        .wait()
+        .map_err(|e| format!("cannot execute '{}': {}", program, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} command failed with {}", program, status))
+    }
+}
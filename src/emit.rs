@@ -0,0 +1,380 @@
+use crate::ast::ProgramAst;
+use crate::func::Func;
+use crate::json::Json;
+use crate::node::{Node, NodeArena, NodeId, NodeKind, ValueCategory};
+use crate::token::{Token, TokenType};
+use crate::tokenizer::Tokenizer;
+
+/// An intermediate representation which can be written by `--emit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    /// The AST as parsed.
+    Ast,
+    /// The AST after the semantic analysis, with the types and implicit conversions.
+    TypedAst,
+    /// The mid-level IR lowered from the AST, which the backends generate code from.
+    Ir,
+    Asm,
+    Llvm,
+    /// The AST after the semantic analysis in the DOT language of Graphviz.
+    AstDot,
+    /// The basic blocks of the mid-level IR of each function in the DOT language of Graphviz.
+    CfgDot,
+}
+
+impl Emit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "typed-ast" => Some(Emit::TypedAst),
+            "ir" => Some(Emit::Ir),
+            "asm" => Some(Emit::Asm),
+            "llvm" => Some(Emit::Llvm),
+            "ast-dot" => Some(Emit::AstDot),
+            "cfg-dot" => Some(Emit::CfgDot),
+            _ => None,
+        }
+    }
+    /// Returns the extension of the file written for this representation.
+    pub fn extension(&self, format: EmitFormat) -> &'static str {
+        match (self, format) {
+            (Emit::Tokens, EmitFormat::Text) => "tokens",
+            (Emit::Tokens, EmitFormat::Json) => "tokens.json",
+            (Emit::Ast, EmitFormat::Text) => "ast",
+            (Emit::Ast, EmitFormat::Json) => "ast.json",
+            (Emit::TypedAst, EmitFormat::Text) => "typed.ast",
+            (Emit::TypedAst, EmitFormat::Json) => "typed.ast.json",
+            (Emit::Ir, _) => "ir",
+            (Emit::Asm, _) => "s",
+            (Emit::Llvm, _) => "ll",
+            (Emit::AstDot, _) => "ast.dot",
+            (Emit::CfgDot, _) => "cfg.dot",
+        }
+    }
+}
+
+/// How tokens and ASTs are written, set by `--emit-format`. The mid-level IR, assembly and LLVM IR are always text.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EmitFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl EmitFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(EmitFormat::Text),
+            "json" => Some(EmitFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the tokens and the ASTs of a source code.
+pub struct Emitter<'a> {
+    code: &'a str,
+    format: EmitFormat,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn new(code: &'a str, format: EmitFormat) -> Self {
+        Self { code, format }
+    }
+
+    pub fn tokens(&self, tokens: &[Token]) -> String {
+        match self.format {
+            EmitFormat::Text => tokens
+                .iter()
+                .map(|token| {
+                    let (line, column) = self.line_and_column(token.pos);
+                    format!(
+                        "{}:{} {} {}",
+                        line,
+                        column,
+                        token_kind(token),
+                        &self.code[token.pos..self.token_end(token.pos)]
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            EmitFormat::Json => Json::Array(
+                tokens
+                    .iter()
+                    .map(|token| self.token_to_json(token))
+                    .collect(),
+            )
+            .to_string(),
+        }
+    }
+
+    pub fn program(&self, ast: &ProgramAst) -> String {
+        let functions = ast.functions.iter().collect::<Vec<_>>();
+        let global_variables = ast.global_variables.iter().collect::<Vec<_>>();
+        match self.format {
+            EmitFormat::Text => global_variables
+                .iter()
+                .map(|(name, gv)| format!("global {}: {}", name, gv.ty))
+                .chain(
+                    ast.string_literals
+                        .iter()
+                        .enumerate()
+                        .map(|(i, s)| format!("string .str.{}: \"{}\"", i, s)),
+                )
+                .chain(
+                    functions
+                        .iter()
+                        .map(|(name, f)| self.func_to_text(&ast.nodes, name, f)),
+                )
+                .collect::<Vec<String>>()
+                .join("\n"),
+            EmitFormat::Json => Json::object([
+                (
+                    "globals",
+                    Json::Array(
+                        global_variables
+                            .iter()
+                            .map(|(name, gv)| {
+                                Json::object([
+                                    ("name", name.as_str().into()),
+                                    ("type", gv.ty.to_string().into()),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+                (
+                    "string_literals",
+                    Json::Array(
+                        ast.string_literals
+                            .iter()
+                            .map(|s| s.as_str().into())
+                            .collect(),
+                    ),
+                ),
+                (
+                    "functions",
+                    Json::Array(
+                        functions
+                            .iter()
+                            .map(|(name, f)| self.func_to_json(&ast.nodes, name, f))
+                            .collect(),
+                    ),
+                ),
+            ])
+            .to_string(),
+        }
+    }
+
+    fn token_to_json(&self, token: &Token) -> Json {
+        let (line, column) = self.line_and_column(token.pos);
+        let end = self.token_end(token.pos);
+        Json::object([
+            ("kind", token_kind(token).into()),
+            ("text", self.code[token.pos..end].into()),
+            ("line", line.into()),
+            ("column", column.into()),
+            ("span", span_to_json(token.pos, end)),
+        ])
+    }
+
+    fn func_to_text(&self, nodes: &NodeArena, name: &str, f: &Func) -> String {
+        [format!(
+            "function {}: {}, stack size {}",
+            name, f.cty, f.offset_size
+        )]
+        .into_iter()
+        .chain(
+            f.args
+                .iter()
+                .map(|arg| self.node_to_text(nodes, *arg, "param ", 1)),
+        )
+        .chain(f.body.map(|body| self.node_to_text(nodes, body, "", 1)))
+        .collect::<Vec<String>>()
+        .join("\n")
+    }
+
+    fn func_to_json(&self, nodes: &NodeArena, name: &str, f: &Func) -> Json {
+        Json::object([
+            ("name", name.into()),
+            ("type", f.cty.to_string().into()),
+            ("stack_size", f.offset_size.into()),
+            (
+                "params",
+                Json::Array(
+                    f.args
+                        .iter()
+                        .map(|arg| self.node_to_json(nodes, *arg))
+                        .collect(),
+                ),
+            ),
+            (
+                "body",
+                f.body
+                    .map_or(Json::Null, |body| self.node_to_json(nodes, body)),
+            ),
+        ])
+    }
+
+    /// Writes the node in a line, and its children indented in the following lines.
+    fn node_to_text(&self, nodes: &NodeArena, id: NodeId, edge: &str, depth: usize) -> String {
+        let node = &nodes[id];
+        let mut line = format!("{}{}{}", "  ".repeat(depth), edge, node.kind.name());
+        if let Some(detail) = node_detail(self.code, node) {
+            line += &format!(" {}", detail);
+        }
+        if !node.kind.is_statement()
+            && let Some(ty) = nodes.resolve_type(id)
+        {
+            line += &format!(" : {}", ty);
+        }
+        if let Some(ValueCategory::LValue) = node.category {
+            line += " lvalue";
+        }
+        [line]
+            .into_iter()
+            .chain(nodes.edges(id).into_iter().map(|(edge, child)| {
+                self.node_to_text(nodes, child, &format!("{}: ", edge), depth + 1)
+            }))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn node_to_json(&self, nodes: &NodeArena, id: NodeId) -> Json {
+        let node = &nodes[id];
+        let mut members = vec![(String::from("kind"), node.kind.name().into())];
+        if let Some(detail) = node_detail(self.code, node) {
+            members.push((String::from("detail"), detail.into()));
+        }
+        members.push((
+            String::from("type"),
+            nodes
+                .resolve_type(id)
+                .filter(|_| !node.kind.is_statement())
+                .map_or(Json::Null, |ty| ty.to_string().into()),
+        ));
+        members.push((
+            String::from("category"),
+            match node.category {
+                Some(ValueCategory::LValue) => "lvalue".into(),
+                Some(ValueCategory::RValue) => "rvalue".into(),
+                None => Json::Null,
+            },
+        ));
+        members.push((
+            String::from("span"),
+            match (nodes.first_pos(id), nodes.last_pos(id)) {
+                (Some(start), Some(last)) => span_to_json(start, self.token_end(last)),
+                _ => Json::Null,
+            },
+        ));
+        let mut lists = Vec::<(String, Vec<Json>)>::new();
+        for (edge, child) in nodes.edges(id) {
+            let child = self.node_to_json(nodes, child);
+            match edge {
+                "children" | "args" => match lists.iter_mut().find(|(name, _)| name == edge) {
+                    Some((_, list)) => list.push(child),
+                    None => lists.push((edge.to_string(), vec![child])),
+                },
+                _ => members.push((edge.to_string(), child)),
+            }
+        }
+        members.extend(
+            lists
+                .into_iter()
+                .map(|(name, list)| (name, Json::Array(list))),
+        );
+        Json::Object(members)
+    }
+
+    /// Returns the 1-based line and column of the position, counting the column in characters.
+    fn line_and_column(&self, pos: usize) -> (usize, usize) {
+        let before = &self.code[..pos.min(self.code.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+
+    fn token_end(&self, pos: usize) -> usize {
+        pos + Tokenizer::token_length(&self.code[pos.min(self.code.len())..])
+    }
+}
+
+fn token_kind(token: &Token) -> &'static str {
+    match token.tt {
+        TokenType::Reserved => "reserved",
+        TokenType::Ident => "identifier",
+        TokenType::Num => "number",
+        TokenType::Str => "string",
+        TokenType::EOF => "eof",
+    }
+}
+
+fn span_to_json(start: usize, end: usize) -> Json {
+    Json::object([("start", start.into()), ("end", end.into())])
+}
+
+/// Returns what distinguishes a leaf node, such as the value of a number or the name of a variable.
+pub(crate) fn node_detail(code: &str, node: &Node) -> Option<String> {
+    match &node.kind {
+        NodeKind::Num(value) => Some((*value as i64).to_string()),
+        NodeKind::LocalVar(offset) => Some(format!(
+            "{} @{}",
+            node.pos
+                .and_then(|pos| code.get(pos..))
+                .map_or("", |s| &s[..Tokenizer::token_length(s)]),
+            offset
+        )),
+        NodeKind::GlobalVar(name) => Some(name.clone()),
+        NodeKind::StringLiteral(index) => Some(format!("@.str.{}", index)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{AstBuilder, AstBuilderImpl};
+    use crate::error::ErrorPrinter;
+
+    #[test]
+    fn test_emit_tokens_and_ast() {
+        let code = "int main() {\n  return 'a' + 1;\n}";
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        let emitter = Emitter::new(code, EmitFormat::Text);
+        assert_eq!(
+            emitter.tokens(&tokens[5..8]),
+            "2:3 reserved return\n2:10 number 'a'\n2:14 reserved +"
+        );
+        let mut ast_builder = AstBuilderImpl::new(&error_printer, &tokens);
+        let ast = ast_builder.build(false);
+        assert_eq!(
+            emitter.program(&ast),
+            [
+                "function main: int (), stack size 0",
+                "  Block",
+                "    children: Return",
+                "      lhs: Add : int",
+                "        lhs: Num 97 : int",
+                "        rhs: Num 1 : int",
+            ]
+            .join("\n")
+        );
+        assert!(
+            Emitter::new(code, EmitFormat::Json)
+                .program(&ast)
+                .contains(r#"{"kind":"Add","type":"int","category":null,"span":{"start":22,"end":29},"lhs":{"kind":"Num""#)
+        );
+        // statements have no type
+        assert!(
+            Emitter::new(code, EmitFormat::Json)
+                .program(&ast)
+                .contains(r#"{"kind":"Return","type":null,"#)
+        );
+    }
+}
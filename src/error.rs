@@ -1,21 +1,174 @@
+use crate::json::Json;
+use crate::tokenizer::Tokenizer;
+use crate::warning::{Warning, WarningOptions};
+use std::cell::RefCell;
+
 const COLOR_RESET: &str = "\x1b[0m";
 const COLOR_RED: &str = "\x1b[31m";
 const COLOR_CYAN: &str = "\x1b[36m";
+const COLOR_MAGENTA: &str = "\x1b[35m";
+
+pub const DEFAULT_ERROR_LIMIT: usize = 20;
 
 pub fn error(err: &str) {
     eprintln!("{}{}{}", COLOR_RED, err, COLOR_RESET);
     std::process::exit(1);
 }
 
-#[derive(Debug)]
+/// Prints a warning about the command line, which does not stop the compilation.
+pub fn warning(msg: &str) {
+    eprintln!("{}warning: {}{}", COLOR_MAGENTA, msg, COLOR_RESET);
+}
+
+/// Identifies the kind of a diagnostic with a code which does not change between releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    UnexpectedCharacter,
+    UnterminatedLiteral,
+    MultiCharacterConstant,
+    ExpectedToken,
+    ExpectedNumber,
+    ExpectedIdentifier,
+    ExpectedType,
+    UnexpectedToken,
+    UndefinedVariable,
+    Redeclaration,
+    InvalidType,
+    TooManyArguments,
+    NonConstantExpression,
+    UnexpectedBreak,
+    UnresolvedType,
+    NotAFunction,
+    IncompatibleTypes,
+    InvalidOperands,
+    InvalidIndirection,
+    NotAssignable,
+    NotAddressable,
+    Warning(Warning),
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedCharacter => "E0001",
+            ErrorCode::UnterminatedLiteral => "E0002",
+            ErrorCode::MultiCharacterConstant => "E0003",
+            ErrorCode::ExpectedToken => "E0100",
+            ErrorCode::ExpectedNumber => "E0101",
+            ErrorCode::ExpectedIdentifier => "E0102",
+            ErrorCode::ExpectedType => "E0103",
+            ErrorCode::UnexpectedToken => "E0104",
+            ErrorCode::UndefinedVariable => "E0200",
+            ErrorCode::Redeclaration => "E0201",
+            ErrorCode::InvalidType => "E0202",
+            ErrorCode::TooManyArguments => "E0203",
+            ErrorCode::NonConstantExpression => "E0204",
+            ErrorCode::UnexpectedBreak => "E0205",
+            ErrorCode::UnresolvedType => "E0206",
+            ErrorCode::NotAFunction => "E0207",
+            ErrorCode::IncompatibleTypes => "E0208",
+            ErrorCode::InvalidOperands => "E0209",
+            ErrorCode::InvalidIndirection => "E0210",
+            ErrorCode::NotAssignable => "E0211",
+            ErrorCode::NotAddressable => "E0212",
+            ErrorCode::Warning(warning) => warning.code(),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedCharacter => "unexpected-character",
+            ErrorCode::UnterminatedLiteral => "unterminated-literal",
+            ErrorCode::MultiCharacterConstant => "multi-character-constant",
+            ErrorCode::ExpectedToken => "expected-token",
+            ErrorCode::ExpectedNumber => "expected-number",
+            ErrorCode::ExpectedIdentifier => "expected-identifier",
+            ErrorCode::ExpectedType => "expected-type",
+            ErrorCode::UnexpectedToken => "unexpected-token",
+            ErrorCode::UndefinedVariable => "undefined-variable",
+            ErrorCode::Redeclaration => "redeclaration",
+            ErrorCode::InvalidType => "invalid-type",
+            ErrorCode::TooManyArguments => "too-many-arguments",
+            ErrorCode::NonConstantExpression => "non-constant-expression",
+            ErrorCode::UnexpectedBreak => "unexpected-break",
+            ErrorCode::UnresolvedType => "unresolved-type",
+            ErrorCode::NotAFunction => "not-a-function",
+            ErrorCode::IncompatibleTypes => "incompatible-types",
+            ErrorCode::InvalidOperands => "invalid-operands",
+            ErrorCode::InvalidIndirection => "invalid-indirection",
+            ErrorCode::NotAssignable => "not-assignable",
+            ErrorCode::NotAddressable => "not-addressable",
+            ErrorCode::Warning(warning) => warning.name(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct SyntaxError {
-    pos: usize,
-    msg: &'static str,
+    pub code: ErrorCode,
+    pub pos: usize,
+    pub msg: String,
 }
 
 impl SyntaxError {
-    pub fn new(pos: usize, msg: &'static str) -> Self {
-        Self { pos, msg }
+    pub fn new(code: ErrorCode, pos: usize, msg: &str) -> Self {
+        Self {
+            code,
+            pos,
+            msg: msg.to_string(),
+        }
+    }
+}
+
+/// How diagnostics are printed, set by `--diagnostics-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DiagnosticsFormat {
+    #[default]
+    Text,
+    Json,
+    Sarif,
+}
+
+impl DiagnosticsFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(DiagnosticsFormat::Text),
+            "json" => Some(DiagnosticsFormat::Json),
+            "sarif" => Some(DiagnosticsFormat::Sarif),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub pos: usize,
+    pub msg: String,
+    pub code: ErrorCode,
+}
+
+impl Diagnostic {
+    /// Returns the warning which produced this diagnostic, even if promoted by `-Werror`.
+    pub fn warning(&self) -> Option<Warning> {
+        match self.code {
+            ErrorCode::Warning(warning) => Some(warning),
+            _ => None,
+        }
     }
 }
 
@@ -23,22 +176,307 @@ pub trait ErrorLogger {
     fn print_error_position(&self, pos: usize, msg: &str);
     fn line_from_position(&self, pos: usize) -> (usize, usize, &str);
     fn print_syntax_error_position(&self, err: SyntaxError) {
-        self.print_error_position(err.pos, err.msg);
+        self.print_error_position(err.pos, &err.msg);
+    }
+    /// Records a non-fatal error so that compilation can go on and report the rest.
+    fn report(&self, _err: SyntaxError) {}
+    /// Records a warning if it is enabled.
+    fn warn(&self, _warning: Warning, _pos: usize, _msg: &str) {}
+    fn error_count(&self) -> usize {
+        0
+    }
+    fn error_limit_reached(&self) -> bool {
+        false
     }
 }
 
 pub struct ErrorPrinter<'a> {
     code: &'a str,
+    file_name: String,
+    format: DiagnosticsFormat,
+    error_limit: usize,
+    warning_options: WarningOptions,
+    diagnostics: RefCell<Vec<Diagnostic>>,
 }
 
 impl<'a> ErrorPrinter<'a> {
     pub fn new(code: &'a str) -> Self {
-        Self { code }
+        Self {
+            code,
+            file_name: String::from("<input>"),
+            format: DiagnosticsFormat::default(),
+            error_limit: DEFAULT_ERROR_LIMIT,
+            warning_options: WarningOptions::default(),
+            diagnostics: RefCell::new(Vec::new()),
+        }
+    }
+    pub fn with_file_name(self, file_name: &str) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            ..self
+        }
+    }
+    pub fn with_format(self, format: DiagnosticsFormat) -> Self {
+        Self { format, ..self }
+    }
+    /// `error_limit` of 0 means unlimited, like `-ferror-limit=0` of clang.
+    pub fn with_error_limit(self, error_limit: usize) -> Self {
+        Self {
+            error_limit,
+            ..self
+        }
+    }
+    pub fn with_warning_options(self, warning_options: WarningOptions) -> Self {
+        Self {
+            warning_options,
+            ..self
+        }
+    }
+    /// Returns the reported diagnostics up to the error limit, in order of the position.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut error_count = 0;
+        let mut diagnostics = self
+            .diagnostics
+            .borrow()
+            .iter()
+            .filter(|d| {
+                if d.severity == Severity::Error {
+                    error_count += 1;
+                }
+                self.error_limit == 0 || error_count <= self.error_limit
+            })
+            .cloned()
+            .collect::<Vec<Diagnostic>>();
+        diagnostics.sort_by_key(|d| d.pos);
+        diagnostics
+    }
+    pub fn errors(&self) -> Vec<Diagnostic> {
+        self.diagnostics()
+            .into_iter()
+            .filter(|d| d.severity == Severity::Error)
+            .collect()
+    }
+    pub fn warning_count(&self) -> usize {
+        self.diagnostics()
+            .iter()
+            .filter(|d| d.severity == Severity::Warning)
+            .count()
+    }
+    fn format_diagnostic(&self, diagnostic: &Diagnostic) -> String {
+        let msg = match (diagnostic.severity, diagnostic.warning()) {
+            (Severity::Error, Some(warning)) => {
+                format!("{} [-Werror,-W{}]", diagnostic.msg, warning.name())
+            }
+            (Severity::Warning, Some(warning)) => {
+                format!("warning: {} [-W{}]", diagnostic.msg, warning.name())
+            }
+            (_, None) => diagnostic.msg.clone(),
+        };
+        let color = match diagnostic.severity {
+            Severity::Error => COLOR_RED,
+            Severity::Warning => COLOR_MAGENTA,
+        };
+        self.format_position(diagnostic.pos, &msg, color)
+    }
+    fn reported_error_count(&self) -> usize {
+        self.diagnostics
+            .borrow()
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count()
+    }
+    fn format_error_position(&self, pos: usize, msg: &str) -> String {
+        self.format_position(pos, msg, COLOR_RED)
+    }
+    fn format_position(&self, pos: usize, msg: &str, color: &str) -> String {
+        let (row, col, line) = self.line_from_position(pos);
+        let (_, column) = self.line_and_column(pos);
+        let row_number = format!("{} | ", row);
+        format!(
+            "\n{}:{}:{}\n{}{}{}{}\n{}{}^ {}{}",
+            self.file_name,
+            row,
+            column,
+            row_number,
+            COLOR_CYAN,
+            line,
+            COLOR_RESET,
+            " ".repeat(col + row_number.len()),
+            color,
+            if pos >= self.code.len() {
+                "unexpected eof while parsing"
+            } else {
+                msg
+            },
+            COLOR_RESET
+        )
+    }
+    /// Returns the 1-based line and column of the position, counting the column in characters.
+    fn line_and_column(&self, pos: usize) -> (usize, usize) {
+        let (row, col, line) = self.line_from_position(pos);
+        (row, line[..col].chars().count() + 1)
+    }
+    /// Returns the byte range of the token at the position.
+    fn range(&self, pos: usize) -> (usize, usize) {
+        let pos = pos.min(self.code.len());
+        (pos, pos + Tokenizer::token_length(&self.code[pos..]))
+    }
+    fn diagnostic_to_json(&self, diagnostic: &Diagnostic) -> Json {
+        let (line, column) = self.line_and_column(diagnostic.pos);
+        let (start, end) = self.range(diagnostic.pos);
+        Json::object([
+            ("file", self.file_name.as_str().into()),
+            ("line", line.into()),
+            ("column", column.into()),
+            (
+                "range",
+                Json::object([("start", start.into()), ("end", end.into())]),
+            ),
+            ("severity", diagnostic.severity.name().into()),
+            ("code", diagnostic.code.code().into()),
+            ("name", diagnostic.code.name().into()),
+            ("message", diagnostic.msg.as_str().into()),
+        ])
+    }
+    fn diagnostic_to_sarif(&self, diagnostic: &Diagnostic) -> Json {
+        let (start_line, start_column) = self.line_and_column(diagnostic.pos);
+        let (start, end) = self.range(diagnostic.pos);
+        let (end_line, end_column) = self.line_and_column(end);
+        Json::object([
+            ("ruleId", diagnostic.code.code().into()),
+            ("level", diagnostic.severity.name().into()),
+            (
+                "message",
+                Json::object([("text", diagnostic.msg.as_str().into())]),
+            ),
+            (
+                "locations",
+                Json::Array(vec![Json::object([(
+                    "physicalLocation",
+                    Json::object([
+                        (
+                            "artifactLocation",
+                            Json::object([("uri", self.file_name.as_str().into())]),
+                        ),
+                        (
+                            "region",
+                            Json::object([
+                                ("startLine", start_line.into()),
+                                ("startColumn", start_column.into()),
+                                ("endLine", end_line.into()),
+                                ("endColumn", end_column.into()),
+                                ("byteOffset", start.into()),
+                                ("byteLength", (end - start).into()),
+                            ]),
+                        ),
+                    ]),
+                )])]),
+            ),
+        ])
+    }
+    /// Returns the diagnostics as a SARIF 2.1.0 log.
+    pub fn to_sarif(&self) -> Json {
+        let diagnostics = self.diagnostics();
+        let mut codes = diagnostics.iter().map(|d| d.code).collect::<Vec<_>>();
+        codes.sort_by_key(|code| code.code());
+        codes.dedup();
+        let rules = codes
+            .iter()
+            .map(|code| Json::object([("id", code.code().into()), ("name", code.name().into())]))
+            .collect();
+        Json::object([
+            (
+                "$schema",
+                "https://json.schemastore.org/sarif-2.1.0.json".into(),
+            ),
+            ("version", "2.1.0".into()),
+            (
+                "runs",
+                Json::Array(vec![Json::object([
+                    (
+                        "tool",
+                        Json::object([(
+                            "driver",
+                            Json::object([
+                                ("name", env!("CARGO_PKG_NAME").into()),
+                                ("version", env!("CARGO_PKG_VERSION").into()),
+                                ("rules", Json::Array(rules)),
+                            ]),
+                        )]),
+                    ),
+                    ("columnKind", "unicodeCodePoints".into()),
+                    (
+                        "results",
+                        Json::Array(
+                            diagnostics
+                                .iter()
+                                .map(|d| self.diagnostic_to_sarif(d))
+                                .collect(),
+                        ),
+                    ),
+                ])]),
+            ),
+        ])
+    }
+    /// Returns the diagnostics as a JSON object with a `diagnostics` array.
+    pub fn to_json(&self) -> Json {
+        Json::object([(
+            "diagnostics",
+            Json::Array(
+                self.diagnostics()
+                    .iter()
+                    .map(|d| self.diagnostic_to_json(d))
+                    .collect(),
+            ),
+        )])
+    }
+    pub fn print_diagnostics(&self) {
+        eprint!("{}", self.render_diagnostics());
+    }
+    /// Returns the diagnostics written in the format, as printed by `print_diagnostics`.
+    pub fn render_diagnostics(&self) -> String {
+        match self.format {
+            DiagnosticsFormat::Text => self.render_text_diagnostics(),
+            DiagnosticsFormat::Json => format!("{}\n", self.to_json()),
+            DiagnosticsFormat::Sarif => format!("{}\n", self.to_sarif()),
+        }
+    }
+    fn render_text_diagnostics(&self) -> String {
+        let mut lines = self
+            .diagnostics()
+            .iter()
+            .map(|d| self.format_diagnostic(d))
+            .collect::<Vec<String>>();
+        if self.error_limit_reached() {
+            lines.push(format!(
+                "{}too many errors emitted, stopping now [-ferror-limit=]{}",
+                COLOR_RED, COLOR_RESET
+            ));
+        }
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        match (self.warning_count(), self.error_count()) {
+            (0, 0) => {}
+            (warnings, 0) => lines.push(format!(
+                "{} warning{} generated.",
+                warnings,
+                plural(warnings)
+            )),
+            (0, errors) => lines.push(format!("{} error{} generated.", errors, plural(errors))),
+            (warnings, errors) => lines.push(format!(
+                "{} warning{} and {} error{} generated.",
+                warnings,
+                plural(warnings),
+                errors,
+                plural(errors)
+            )),
+        }
+        lines.iter().map(|line| line.clone() + "\n").collect()
     }
 }
 
 impl ErrorLogger for ErrorPrinter<'_> {
     fn line_from_position(&self, pos: usize) -> (usize, usize, &str) {
+        let pos = pos.min(self.code.len());
         let (line_count, start_index) = self.code[..pos]
             .char_indices()
             .filter(|(_, c)| *c == '\n')
@@ -57,23 +495,43 @@ impl ErrorLogger for ErrorPrinter<'_> {
         )
     }
     fn print_error_position(&self, pos: usize, msg: &str) {
-        let (row, col, line) = self.line_from_position(pos);
-        let row_number = format!("{} | ", row);
-        panic!(
-            "\n{}{}{}{}\n{}{}^ {}{}",
-            row_number,
-            COLOR_CYAN,
-            line,
-            COLOR_RESET,
-            " ".repeat(col + row_number.len()),
-            COLOR_RED,
-            if pos >= self.code.len() {
-                "unexpected eof while parsing"
-            } else {
-                msg
-            },
-            COLOR_RESET
-        )
+        panic!("{}", self.format_error_position(pos, msg))
+    }
+    fn report(&self, err: SyntaxError) {
+        if !self.error_limit_reached() {
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                severity: Severity::Error,
+                pos: err.pos,
+                msg: err.msg,
+                code: err.code,
+            });
+        }
+    }
+    fn warn(&self, warning: Warning, pos: usize, msg: &str) {
+        if self.warning_options.is_enabled(warning) && !self.error_limit_reached() {
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                severity: if self.warning_options.werror {
+                    Severity::Error
+                } else {
+                    Severity::Warning
+                },
+                pos,
+                msg: msg.to_string(),
+                code: ErrorCode::Warning(warning),
+            });
+        }
+    }
+    fn error_count(&self) -> usize {
+        let count = self.reported_error_count();
+        if self.error_limit == 0 {
+            count
+        } else {
+            count.min(self.error_limit)
+        }
+    }
+    /// True once one more error than the limit has been reported.
+    fn error_limit_reached(&self) -> bool {
+        self.error_limit != 0 && self.reported_error_count() > self.error_limit
     }
 }
 #[derive(Default)]
@@ -85,3 +543,23 @@ impl ErrorLogger for NopLogger {
         (0, 0, "")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics_to_json() {
+        let code = "int main() {\n  return xyz;\n}";
+        let error_printer = ErrorPrinter::new(code).with_file_name("main.c");
+        error_printer.report(SyntaxError::new(
+            ErrorCode::UndefinedVariable,
+            22,
+            "undefined variable",
+        ));
+        assert_eq!(
+            error_printer.to_json().to_string(),
+            r#"{"diagnostics":[{"file":"main.c","line":2,"column":10,"range":{"start":22,"end":25},"severity":"error","code":"E0200","name":"undefined-variable","message":"undefined variable"}]}"#
+        );
+    }
+}
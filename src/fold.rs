@@ -0,0 +1,343 @@
+use crate::ast::ProgramAst;
+use crate::error::ErrorLogger;
+use crate::node::{BinaryOp, EvalError, Node, NodeArena, NodeId, NodeKind, UnaryOp};
+use crate::warning::Warning;
+
+/// Replaces the constant expressions of an analyzed program with their values,
+/// and the statements branching on constant conditions with the branches taken.
+/// The divisions by a constant 0 are reported and left to the program.
+pub struct ConstantFolder<'a> {
+    error_logger: &'a dyn ErrorLogger,
+}
+
+impl<'a> ConstantFolder<'a> {
+    pub fn new(error_logger: &'a dyn ErrorLogger) -> Self {
+        Self { error_logger }
+    }
+
+    pub fn fold(&self, ast: &mut ProgramAst) {
+        let nodes = &mut ast.nodes;
+        ast.functions
+            .values()
+            .filter_map(|f| f.body)
+            .for_each(|body| self.stmt(nodes, body));
+    }
+
+    fn stmt(&self, nodes: &mut NodeArena, node: NodeId) {
+        match nodes[node].kind {
+            NodeKind::Block(ref children) | NodeKind::DefVar(ref children) => children
+                .clone()
+                .into_iter()
+                .for_each(|child| self.stmt(nodes, child)),
+            NodeKind::If { cond, then, els } => {
+                self.expr(nodes, cond);
+                let NodeKind::Num(value) = nodes[cond].kind else {
+                    self.stmt(nodes, then);
+                    els.into_iter().for_each(|els| self.stmt(nodes, els));
+                    return;
+                };
+                match if value != 0 { Some(then) } else { els } {
+                    Some(taken) => {
+                        self.stmt(nodes, taken);
+                        nodes[node] = nodes[taken].clone();
+                    }
+                    None => Self::replace_with_block(nodes, node, vec![]),
+                }
+            }
+            NodeKind::While { cond, body } => {
+                self.expr(nodes, cond);
+                if nodes[cond].kind == NodeKind::Num(0) {
+                    return Self::replace_with_block(nodes, node, vec![]);
+                }
+                self.stmt(nodes, body);
+            }
+            NodeKind::For {
+                ini,
+                cond,
+                upd,
+                body,
+            } => {
+                if let Some(ini) = ini {
+                    self.stmt(nodes, ini);
+                }
+                if let Some(cond) = cond {
+                    self.expr(nodes, cond);
+                    if nodes[cond].kind == NodeKind::Num(0) {
+                        return Self::replace_with_block(nodes, node, ini.into_iter().collect());
+                    }
+                }
+                if let Some(upd) = upd {
+                    self.expr(nodes, upd);
+                }
+                self.stmt(nodes, body);
+            }
+            NodeKind::Return(value) => self.expr(nodes, value),
+            NodeKind::Break => {}
+            _ => self.expr(nodes, node),
+        }
+    }
+
+    /// Replaces the statement with a block of the children, such as the statements never run.
+    fn replace_with_block(nodes: &mut NodeArena, node: NodeId, children: Vec<NodeId>) {
+        nodes[node] = Node::new(NodeKind::Block(children), nodes[node].pos);
+    }
+
+    fn expr(&self, nodes: &mut NodeArena, node: NodeId) {
+        // the operand which is not evaluated is left as written
+        let children = match nodes[node].kind {
+            NodeKind::Binary(op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), lhs, rhs) => {
+                self.expr(nodes, lhs);
+                match nodes[lhs].kind {
+                    NodeKind::Num(value) if (value != 0) == (op == BinaryOp::LogicalOr) => vec![],
+                    _ => vec![rhs],
+                }
+            }
+            NodeKind::Conditional { cond, then, els } => {
+                self.expr(nodes, cond);
+                match nodes[cond].kind {
+                    NodeKind::Num(value) => vec![if value != 0 { then } else { els }],
+                    _ => vec![then, els],
+                }
+            }
+            _ => nodes.edges(node).into_iter().map(|(_, n)| n).collect(),
+        };
+        children
+            .into_iter()
+            .for_each(|child| self.expr(nodes, child));
+        if !Self::has_constant_operands(nodes, node) {
+            return;
+        }
+        match nodes.eval(node) {
+            Ok(value) => nodes[node].kind = NodeKind::Num(value as usize),
+            Err(
+                error @ (EvalError::DivisionByZero(culprit)
+                | EvalError::Overflow(culprit)
+                | EvalError::InvalidShift(culprit)),
+            ) if culprit == node => {
+                let (warning, msg) = undefined_behavior(nodes, error).unwrap();
+                self.error_logger
+                    .warn(warning, nodes[node].pos.unwrap_or(0), msg)
+            }
+            Err(_) => {
+                // the conditional operator whose branch taken is not constant
+                if let NodeKind::Conditional { cond, then, els } = nodes[node].kind
+                    && let NodeKind::Num(value) = nodes[cond].kind
+                {
+                    let taken = if value != 0 { then } else { els };
+                    if nodes[taken].cty == nodes[node].cty {
+                        nodes[node] = nodes[taken].clone();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns whether the operands evaluated by the operator are all folded,
+    /// so that evaluating it does not go down the whole expression again.
+    fn has_constant_operands(nodes: &NodeArena, node: NodeId) -> bool {
+        let is_num = |n: NodeId| matches!(nodes[n].kind, NodeKind::Num(_));
+        match nodes[node].kind {
+            NodeKind::Binary(BinaryOp::LogicalAnd | BinaryOp::LogicalOr, lhs, _)
+            | NodeKind::Conditional { cond: lhs, .. } => is_num(lhs),
+            NodeKind::Binary(_, lhs, rhs) => is_num(lhs) && is_num(rhs),
+            NodeKind::Unary(UnaryOp::Cast | UnaryOp::BitNot, operand) => is_num(operand),
+            _ => false,
+        }
+    }
+}
+
+/// Returns the warning for the undefined behavior which stops the evaluation of a constant
+/// expression, with its message.
+pub fn undefined_behavior(nodes: &NodeArena, error: EvalError) -> Option<(Warning, &'static str)> {
+    match error {
+        EvalError::NotConstant(_) => None,
+        EvalError::DivisionByZero(_) => {
+            Some((Warning::DivisionByZero, "division by zero is undefined"))
+        }
+        EvalError::Overflow(_) => Some((
+            Warning::IntegerOverflow,
+            "overflow in expression of type 'int' is undefined",
+        )),
+        EvalError::InvalidShift(node) => {
+            let NodeKind::Binary(_, _, rhs) = nodes[node].kind else {
+                unreachable!("shift expected")
+            };
+            Some((
+                Warning::ShiftCountOverflow,
+                if nodes.eval(rhs).is_ok_and(|count| count < 0) {
+                    "shift count is negative"
+                } else {
+                    "shift count is not less than the width of type 'int'"
+                },
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{AstBuilder, AstBuilderImpl};
+    use crate::error::{Diagnostic, ErrorCode, ErrorPrinter};
+    use crate::sema::SemanticAnalyzer;
+    use crate::tokenizer::Tokenizer;
+
+    fn fold(code: &str) -> (String, Vec<Diagnostic>) {
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        let mut ast = AstBuilderImpl::new(&error_printer, &tokens).build(false);
+        SemanticAnalyzer::new(&error_printer).analyze(&mut ast);
+        ConstantFolder::new(&error_printer).fold(&mut ast);
+        let body = ast.functions["main"].body.unwrap();
+        (
+            ast.nodes.to_debug_string(body, 2),
+            error_printer.diagnostics(),
+        )
+    }
+
+    #[test]
+    fn test_fold_expressions() {
+        let (ast, warnings) = fold(
+            "int main() { int a; char c; c = 200 + 100; a = sizeof(a) * 2 - 10 << 1; return a && 0 || (1 ? -1 : a); }",
+        );
+        assert_eq!(
+            ast,
+            [
+                "Block:",
+                "  children->DefVar:",
+                "  children->DefVar:",
+                "  children->Assign:",
+                "    lhs->LocalVar: { type: I8, offset: 10 }",
+                "    rhs->Num: 44",
+                "  children->Assign:",
+                "    lhs->LocalVar: { type: I32, offset: 8 }",
+                "    rhs->Num: -4",
+                "  children->Return:",
+                "    lhs->LogicalOr:",
+                "      lhs->LogicalAnd:",
+                "        lhs->LocalVar: { type: I32, offset: 8 }",
+                "        rhs->Num: 0",
+                "      rhs->Num: -1",
+            ]
+            .join("\n")
+        );
+        assert!(warnings.iter().all(|w| w.warning().is_none()));
+    }
+
+    #[test]
+    fn test_fold_branches_on_constant_conditions() {
+        let (ast, _) = fold(
+            "int main() { int a; if (2 > 1) a = 1; else a = 2; while (1 - 1) a = 3; for (a = 4; 0;) a = 5; if (0) return 6; return a; }",
+        );
+        assert_eq!(
+            ast,
+            [
+                "Block:",
+                "  children->DefVar:",
+                "  children->Assign:",
+                "    lhs->LocalVar: { type: I32, offset: 8 }",
+                "    rhs->Num: 1",
+                "  children->Block:",
+                "  children->Block:",
+                "    children->Assign:",
+                "      lhs->LocalVar: { type: I32, offset: 8 }",
+                "      rhs->Num: 4",
+                "  children->Block:",
+                "  children->Return:",
+                "    lhs->LocalVar: { type: I32, offset: 8 }",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_fold_reports_division_by_zero() {
+        let (ast, warnings) =
+            fold("int main() { int a; a = 1 / (2 - 2) + 1; if (0) a = a % 0; return 0 && 1 / 0; }");
+        assert!(ast.contains("  children->Assign:\n    lhs->LocalVar: { type: I32, offset: 8 }\n    rhs->Add:\n      lhs->Div:\n        lhs->Num: 1\n        rhs->Num: 0\n      rhs->Num: 1\n"), "{}", ast);
+        assert!(
+            ast.ends_with("  children->Return:\n    lhs->Num: 0"),
+            "{}",
+            ast
+        );
+        // the division never run is not reported
+        assert_eq!(
+            warnings
+                .iter()
+                .filter(|w| w.warning().is_some())
+                .map(|w| (w.code, w.pos, w.msg.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(
+                ErrorCode::Warning(Warning::DivisionByZero),
+                26,
+                "division by zero is undefined"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_fold_reports_integer_overflow() {
+        let (ast, warnings) = fold(
+            "int main() { int a; a = 2147483647 + 1; a = -2147483647 - 1; a = (-2147483647 - 1) / -1; a = (-2147483647 - 1) % -1; a = 65536 * 65536; a = 1 << 31; return 2147483647 + 0; }",
+        );
+        // the operations overflowing are left to the program
+        assert_eq!(ast.matches("rhs->Add:").count(), 1, "{}", ast);
+        assert!(ast.contains("rhs->Num: -2147483648"), "{}", ast);
+        assert!(
+            ast.contains("rhs->Div:") && ast.contains("rhs->Mod:") && ast.contains("rhs->BitLeft:"),
+            "{}",
+            ast
+        );
+        assert!(
+            ast.ends_with("  children->Return:\n    lhs->Num: 2147483647"),
+            "{}",
+            ast
+        );
+        assert_eq!(
+            warnings
+                .iter()
+                .filter(|w| w.warning().is_some())
+                .map(|w| (w.code, w.msg.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    ErrorCode::Warning(Warning::IntegerOverflow),
+                    "overflow in expression of type 'int' is undefined"
+                );
+                5
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fold_reports_invalid_shift_counts() {
+        let (ast, warnings) =
+            fold("int main() { int a; a = 1 << 40; a = 8 >> -1; a = -1 << 31; return 1 >> 32; }");
+        assert!(ast.contains("rhs->Num: -2147483648"), "{}", ast);
+        assert_eq!(
+            ast.matches("BitLeft:").count() + ast.matches("BitRight:").count(),
+            3
+        );
+        assert_eq!(
+            warnings
+                .iter()
+                .filter(|w| w.warning().is_some())
+                .map(|w| (w.code, w.msg.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    ErrorCode::Warning(Warning::ShiftCountOverflow),
+                    "shift count is not less than the width of type 'int'"
+                ),
+                (
+                    ErrorCode::Warning(Warning::ShiftCountOverflow),
+                    "shift count is negative"
+                ),
+                (
+                    ErrorCode::Warning(Warning::ShiftCountOverflow),
+                    "shift count is not less than the width of type 'int'"
+                ),
+            ]
+        );
+    }
+}
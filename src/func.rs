@@ -1,26 +1,54 @@
 use crate::ctype::Type;
-use crate::node::Node;
+use crate::node::{NodeArena, NodeId};
 use crate::token::Token;
 
-#[derive(Default)]
+/// Whether a function should be inlined into its callers,
+/// given by `inline` or `__attribute__((always_inline))` and `__attribute__((noinline))`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InlineHint {
+    /// Left to the size of the function.
+    #[default]
+    None,
+    /// Declared `inline`, so inlined up to a larger size.
+    Inline,
+    Always,
+    Never,
+}
+
+impl InlineHint {
+    /// Returns the function attribute giving the hint in the IR and in LLVM.
+    pub fn attribute(&self) -> Option<&'static str> {
+        match self {
+            InlineHint::None => None,
+            InlineHint::Inline => Some("inlinehint"),
+            InlineHint::Always => Some("alwaysinline"),
+            InlineHint::Never => Some("noinline"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Func {
-    pub body: Option<Node>,
+    pub body: Option<NodeId>,
     pub cty: Type,
     pub offset_size: usize,
     pub token: Option<Token>,
-    pub args: Vec<Node>,
+    pub args: Vec<NodeId>,
+    pub inline: InlineHint,
 }
 
 impl Func {
-    pub fn to_debug_string(&self) -> String {
+    pub fn to_debug_string(&self, nodes: &NodeArena) -> String {
         format!(
             "args:\n{:?}\nbody:\n{:?}",
             self.args
                 .iter()
-                .map(|arg| format!("  {}", arg.to_debug_string(4))),
+                .map(|arg| format!("  {}", nodes.to_debug_string(*arg, 4)))
+                .collect::<Vec<String>>(),
             self.body
                 .iter()
-                .map(|n| format!("  {}", n.to_debug_string(4))),
+                .map(|n| format!("  {}", nodes.to_debug_string(*n, 4)))
+                .collect::<Vec<String>>(),
         )
     }
 }
@@ -1,46 +1,260 @@
 use super::assembly::Assembly;
 use super::instruction::{
     InstOperand::{self, *},
-    InstOperator::{self, *},
+    InstOperator::*,
     Register::{self, *},
 };
-use crate::ast::{Identifier, ProgramAst, reserved_functions};
+use super::peephole;
 use crate::ctype::Type;
-use crate::error;
-use crate::func::Func;
 use crate::generator::Os;
+use crate::generator::regalloc::{self, Allocation, Location, Registers};
+use crate::generator::tail_call;
 use crate::global::{GlobalVariable, GlobalVariableData};
-use crate::node::{Node, NodeType};
+use crate::ir::opt::{OptOptions, Pass};
+use crate::ir::{
+    Address, BinOp, BlockId, Function, Inst, Module, Operand, SlotId, Symbol, Terminator, Ty, Value,
+};
+use std::collections::HashMap;
 use std::fmt::Display;
 
-pub struct AsmGenerator<'a> {
-    error_logger: &'a dyn error::ErrorLogger,
+pub struct AsmGenerator {
     target_os: Os,
+    allocates_registers: bool,
+    makes_tail_calls: bool,
+    optimizes_peepholes: bool,
 }
 
 const ARGS_REG: [Register; 8] = [X0, X1, X2, X3, X4, X5, X6, X7];
 
-impl crate::generator::Generator for AsmGenerator<'_> {
-    fn generate(&self, ast: ProgramAst) -> Box<dyn crate::generator::Assembly> {
-        self.generate(ast)
+/// The registers given to the values. `x0` to `x8` are left for computing and passing
+/// the arguments, `x16` for addresses, and `x18` to the platform.
+const REGISTERS: Registers<Register> = Registers {
+    caller_saved: &[X9, X10, X11, X12, X13, X14, X15],
+    callee_saved: &[X19, X20, X21, X22, X23, X24, X25, X26, X27, X28],
+};
+
+impl crate::generator::Generator for AsmGenerator {
+    fn generate(&self, module: &Module) -> Box<dyn crate::generator::Assembly> {
+        self.generate(module)
+    }
+}
+
+/// Where the slots and the values of a function are kept, above the stack pointer.
+struct Frame {
+    /// The offset of each slot from the stack pointer.
+    slots: Vec<usize>,
+    allocation: Allocation<Register>,
+    /// The callee-saved registers used, with the offset each is saved at.
+    saved_registers: Vec<(Register, usize)>,
+    /// The offset of the home of the first spilled value from the stack pointer.
+    spills_offset: usize,
+    /// The offset of the place each phi takes its operand from,
+    /// written at the end of the predecessors so that the phis are evaluated at once.
+    phi_operands: HashMap<Value, usize>,
+    /// The size of the frame, aligned to 16 bytes.
+    size: usize,
+}
+
+impl Frame {
+    /// Lays out the frame, keeping the bottom for the arguments passed on the stack.
+    fn new(func: &Function, outgoing_size: usize, registers: &Registers<Register>) -> Self {
+        let allocation = regalloc::allocate(func, registers);
+        let mut size = outgoing_size;
+        let slots = func
+            .slots
+            .iter()
+            .map(|slot| {
+                let offset = size.next_multiple_of(slot.align.max(1));
+                size = offset + slot.size;
+                offset
+            })
+            .collect();
+        size = size.next_multiple_of(8);
+        let saved_registers = allocation
+            .callee_saved
+            .iter()
+            .map(|reg| {
+                size += 8;
+                (*reg, size - 8)
+            })
+            .collect();
+        let spills_offset = size;
+        size += allocation.spill_count * 8;
+        let phi_operands = func
+            .blocks
+            .iter()
+            .flat_map(|block| block.phis())
+            .map(|phi| {
+                size += 8;
+                (phi.dst().unwrap(), size - 8)
+            })
+            .collect();
+        Self {
+            slots,
+            allocation,
+            saved_registers,
+            spills_offset,
+            phi_operands,
+            size: size.next_multiple_of(16),
+        }
+    }
+
+    fn phi_operand(&self, phi: Value) -> (Assembly, InstOperand) {
+        Self::memory(self.phi_operands[&phi], 8)
+    }
+
+    fn spilled(&self, index: usize) -> (Assembly, InstOperand) {
+        Self::memory(self.spills_offset + 8 * index, 8)
+    }
+
+    fn slot(&self, slot: SlotId, size: usize) -> (Assembly, InstOperand) {
+        Self::memory(self.slots[slot.0 as usize], size)
+    }
+
+    /// Returns the memory at the offset from the stack pointer, with the instructions
+    /// computing its address in `x16` if the offset does not fit the load and store instructions.
+    fn memory(offset: usize, size: usize) -> (Assembly, InstOperand) {
+        if offset.is_multiple_of(size) && offset / size < 4096 {
+            (vec![].into(), Offset(SP, offset))
+        } else {
+            (
+                vec![
+                    load_imm(X16, offset as i64),
+                    Assembly::inst3(ADD, X16, SP, X16),
+                ]
+                .into(),
+                Ptr(X16),
+            )
+        }
+    }
+
+    fn load(&self, reg: Register, operand: &Operand) -> Assembly {
+        match operand {
+            Operand::Const(value) => load_imm(reg, *value),
+            Operand::Value(value) => match self.allocation.location(*value) {
+                Location::Reg(from) if from == reg => vec![].into(),
+                Location::Reg(from) => Assembly::inst2(MOV, reg, from),
+                Location::Stack(index) => {
+                    let (setup, memory) = self.spilled(index);
+                    vec![setup, Assembly::inst2(LDR, reg, memory)].into()
+                }
+            },
+        }
+    }
+
+    /// Returns the register holding the operand, with the instructions loading it
+    /// to the scratch register if it is not kept in one.
+    fn register(&self, scratch: Register, operand: &Operand) -> (Assembly, Register) {
+        match operand {
+            Operand::Value(value) if let Location::Reg(reg) = self.allocation.location(*value) => {
+                (vec![].into(), reg)
+            }
+            _ => (self.load(scratch, operand), scratch),
+        }
+    }
+
+    /// Returns the register to compute the value in, which is its own register or `x0` for a spilled value.
+    fn result_register(&self, value: Value) -> Register {
+        match self.allocation.location(value) {
+            Location::Reg(reg) => reg,
+            Location::Stack(_) => X0,
+        }
+    }
+
+    fn store(&self, value: Value, reg: Register) -> Assembly {
+        match self.allocation.location(value) {
+            Location::Reg(to) if to == reg => vec![].into(),
+            Location::Reg(to) => Assembly::inst2(MOV, to, reg),
+            Location::Stack(index) => {
+                let (setup, memory) = self.spilled(index);
+                vec![setup, Assembly::inst2(STR, reg, memory)].into()
+            }
+        }
+    }
+
+    /// Returns the memory accessed with the size, and the instructions computing its address in `x1`.
+    fn address(&self, addr: &Address, size: usize) -> (Assembly, InstOperand) {
+        match addr {
+            Address::Slot(slot) => self.slot(*slot, size),
+            Address::Ptr(operand) => {
+                let (setup, reg) = self.register(X1, operand);
+                (setup, Ptr(reg))
+            }
+        }
+    }
+
+    fn save_registers(&self) -> Assembly {
+        self.saved_registers
+            .iter()
+            .map(|(reg, offset)| {
+                let (setup, memory) = Self::memory(*offset, 8);
+                vec![setup, Assembly::inst2(STR, *reg, memory)].into()
+            })
+            .collect::<Vec<Assembly>>()
+            .into()
+    }
+
+    /// Restores the callee-saved registers and returns to the caller.
+    fn epilogue(&self) -> Assembly {
+        vec![self.leave(), Assembly::inst0(RET)].into()
+    }
+
+    /// Restores the callee-saved registers, and frees the frame restoring the frame pointer
+    /// and the return address of the caller.
+    fn leave(&self) -> Assembly {
+        vec![
+            self.saved_registers
+                .iter()
+                .map(|(reg, offset)| {
+                    let (setup, memory) = Self::memory(*offset, 8);
+                    vec![setup, Assembly::inst2(LDR, *reg, memory)].into()
+                })
+                .collect::<Vec<Assembly>>()
+                .into(),
+            Assembly::inst2(MOV, SP, X29),
+            Assembly::inst3(LDP, X29, X30, PostIndex(SP, 16)),
+        ]
+        .into()
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct Options {
-    offset: usize,
-    breakable_branch_num: usize,
-    args_offset: usize,
+/// Moves the constant to the register, 16 bits at a time if it does not fit a single `mov`.
+fn load_imm(reg: Register, value: i64) -> Assembly {
+    if (-65535..=65535).contains(&value) {
+        return Assembly::inst2(MOV, reg, value);
+    }
+    let bits = value as u64;
+    vec![
+        Assembly::inst2(MOVZ, reg, (bits & 0xffff) as usize),
+        [16, 32, 48]
+            .into_iter()
+            .filter(|shift| (bits >> shift) & 0xffff != 0)
+            .map(|shift| {
+                Assembly::inst3(
+                    MOVK,
+                    reg,
+                    ((bits >> shift) & 0xffff) as usize,
+                    format!("lsl #{}", shift),
+                )
+            })
+            .collect::<Vec<Assembly>>()
+            .into(),
+    ]
+    .into()
 }
 
-impl<'a> AsmGenerator<'a> {
-    pub fn new(error_logger: &'a dyn error::ErrorLogger, target_os: Os) -> Self {
+impl AsmGenerator {
+    /// Without allocating registers, every value is kept on the stack.
+    pub fn new(target_os: Os, options: &OptOptions) -> Self {
         Self {
-            error_logger,
             target_os,
+            allocates_registers: options.is_enabled(Pass::RegAlloc),
+            makes_tail_calls: options.is_enabled(Pass::TailCall),
+            optimizes_peepholes: options.is_enabled(Pass::Peephole),
         }
     }
-    fn generate(&self, ast: ProgramAst) -> Box<dyn crate::generator::Assembly> {
+
+    fn generate(&self, module: &Module) -> Box<dyn crate::generator::Assembly> {
         Box::<Assembly>::new(
             vec![
                 if let Os::MacOS = self.target_os {
@@ -49,20 +263,32 @@ impl<'a> AsmGenerator<'a> {
                     ".section .text"
                 }
                 .into(),
-                ast.functions
+                module
+                    .functions
                     .iter()
-                    .map(|(name, f)| {
-                        let func_offset_with_alignment = f.offset_size.div_ceil(16) * 16;
-                        self.gen_func(name, f, func_offset_with_alignment)
-                    })
+                    .enumerate()
+                    .map(|(i, f)| self.gen_func(module, i, f))
                     .collect::<Vec<Assembly>>()
                     .into(),
-                ast.global_variables
+                if let Os::MacOS = self.target_os {
+                    ".section __DATA,__data"
+                } else {
+                    ".section .data"
+                }
+                .into(),
+                module
+                    .global_variables
                     .iter()
                     .map(|(s, gv)| self.gen_global_variable(s, gv))
                     .collect::<Vec<Assembly>>()
                     .into(),
-                self.gen_string_literals(ast.string_literals),
+                self.gen_string_literals(&module.string_literals),
+                if let Os::Linux = self.target_os {
+                    // marks the stack as non-executable for the linker
+                    ".section .note.GNU-stack,\"\",@progbits".into()
+                } else {
+                    vec![].into()
+                },
             ]
             .into(),
         )
@@ -83,7 +309,7 @@ impl<'a> AsmGenerator<'a> {
                     .enumerate()
                     .map(|(i, str)| {
                         vec![
-                            format!("L_.str.{}:", i).into(),
+                            format!("{}:", self.local_label(&format!("@.str.{}", i))).into(),
                             format!("  .asciz \"{}\"", str).into(),
                         ]
                         .into()
@@ -97,13 +323,14 @@ impl<'a> AsmGenerator<'a> {
 
     fn gen_global_variable(&self, name: &str, gv: &GlobalVariable) -> Assembly {
         vec![
+            "  .p2align 3".into(),
             format!("{}:", self.with_prefix(name)).into(),
-            Self::gen_initializer_element(&gv.ty, gv.data.as_ref()),
+            self.gen_initializer_element(&gv.ty, gv.data.as_ref()),
         ]
         .into()
     }
 
-    fn gen_initializer_element(ty: &Type, data: Option<&GlobalVariableData>) -> Assembly {
+    fn gen_initializer_element(&self, ty: &Type, data: Option<&GlobalVariableData>) -> Assembly {
         match ty {
             Type::Arr(children_ty, size) => {
                 if let Some(GlobalVariableData::Arr(v)) = data {
@@ -112,7 +339,7 @@ impl<'a> AsmGenerator<'a> {
                             .enumerate()
                             .filter(|(i, _)| i < size)
                             .map(|(_, d)| {
-                                Self::gen_initializer_element(children_ty.as_ref(), Some(d))
+                                self.gen_initializer_element(children_ty.as_ref(), Some(d))
                             })
                             .collect::<Vec<Assembly>>()
                             .into(),
@@ -152,502 +379,353 @@ impl<'a> AsmGenerator<'a> {
             _ => format!(
                 "  .8byte {}",
                 if let Some(GlobalVariableData::Elem(s)) = data {
-                    s
+                    self.local_label(s)
                 } else {
-                    "0"
+                    "0".to_string()
                 }
             )
             .into(),
         }
     }
 
-    fn gen_func(&self, name: &str, func: &Func, offset: usize) -> Assembly {
-        if func.body.is_none() {
-            return vec![].into();
-        }
-        vec![
-            format!("	.globl	{}", self.with_prefix(name)).into(),
-            "	.p2align	2".into(),
-            format!("{}:", self.with_prefix(name)).into(),
+    /// Returns the arguments of the call which are passed on the stack.
+    /// Apple passes the variadic arguments on the stack, and the others pass the first 8 in registers.
+    fn stack_args<'b>(
+        &self,
+        module: &Module,
+        callee: &str,
+        args: &'b [(Ty, Operand)],
+    ) -> &'b [(Ty, Operand)] {
+        let in_registers = match (self.target_os, module.signature(callee)) {
+            (Os::MacOS, Some(signature)) if signature.is_variadic => signature.params.len(),
+            _ => ARGS_REG.len(),
+        };
+        &args[in_registers.min(args.len())..]
+    }
+
+    fn gen_func(&self, module: &Module, index: usize, func: &Function) -> Assembly {
+        let outgoing_size = func
+            .blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .map(|inst| match inst {
+                Inst::Call { callee, args, .. } => self.stack_args(module, callee, args).len() * 8,
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        let frame = Frame::new(
+            func,
+            outgoing_size,
+            if self.allocates_registers {
+                &REGISTERS
+            } else {
+                &Registers::NONE
+            },
+        );
+        let assembly: Assembly = vec![
+            format!(".globl {}", self.with_prefix(&func.name)).into(),
+            "  .p2align 2".into(),
+            format!("{}:", self.with_prefix(&func.name)).into(),
             // prologue
-            Assembly::inst3(SUB, SP, SP, offset),
-            Assembly::inst3(STP, X29, X30, PtrAdd(SP, "#16".to_string())),
-            Assembly::inst2(MOV, X9, SP),
-            func.args
+            Assembly::inst3(STP, X29, X30, PreIndex(SP, -16)),
+            Assembly::inst2(MOV, X29, SP),
+            if frame.size < 4096 {
+                Assembly::inst3(SUB, SP, SP, frame.size)
+            } else {
+                vec![
+                    load_imm(X16, frame.size as i64),
+                    Assembly::inst3(SUB, SP, SP, X16),
+                ]
+                .into()
+            },
+            frame.save_registers(),
+            func.params
                 .iter()
+                .zip(&func.signature.params)
                 .enumerate()
-                .map(|(i, arg)| {
-                    if let NodeType::LocalVar = arg.nt {
-                        Assembly::inst2(
-                            STR,
-                            ARGS_REG[i],
-                            PtrAdd(SP, format!("#{}", arg.offset.unwrap())),
-                        )
-                    } else {
-                        self.error_logger.print_error_position(
-                            arg.token.as_ref().unwrap().pos,
-                            "ident expected",
-                        );
-                        unreachable!()
+                .map(|(i, (param, ty))| match frame.allocation.location(*param) {
+                    Location::Reg(reg) => Self::extend(reg, ARGS_REG[i], *ty),
+                    Location::Stack(_) => {
+                        vec![Self::extend(X8, ARGS_REG[i], *ty), frame.store(*param, X8)].into()
                     }
                 })
                 .collect::<Vec<Assembly>>()
                 .into(),
-            self.gen_node(
-                func.body.as_ref().unwrap(),
-                Options {
-                    offset,
-                    breakable_branch_num: 0,
-                    args_offset: 0,
-                },
-            ),
-            Assembly::inst2(MOV, X0, 0), // default return value
-            Self::epilogue(offset),
+            func.block_ids()
+                .map(|id| {
+                    let block = func.block(id);
+                    let tail_call = self
+                        .makes_tail_calls
+                        .then(|| tail_call::tail_call(module, func, id, ARGS_REG.len()))
+                        .flatten();
+                    vec![
+                        format!("{}:", self.block_label(index, id)).into(),
+                        block.insts[..tail_call.unwrap_or(block.insts.len())]
+                            .iter()
+                            .map(|inst| self.gen_inst(module, &frame, inst))
+                            .collect::<Vec<Assembly>>()
+                            .into(),
+                        match tail_call {
+                            Some(call) => self.gen_tail_call(&frame, &block.insts[call]),
+                            None => vec![
+                                Self::gen_phi_operands(&frame, func, id),
+                                self.gen_terminator(&frame, index, id, &block.term),
+                            ]
+                            .into(),
+                        },
+                    ]
+                    .into()
+                })
+                .collect::<Vec<Assembly>>()
+                .into(),
         ]
-        .into()
+        .into();
+        if self.optimizes_peepholes {
+            peephole::optimize(assembly)
+        } else {
+            assembly
+        }
     }
-    fn push<T1>(operand: T1) -> Assembly
-    where
-        T1: Into<InstOperand>,
-    {
+
+    fn gen_inst(&self, module: &Module, frame: &Frame, inst: &Inst) -> Assembly {
+        let Some(dst) = inst.dst() else {
+            let Inst::Store { ty, addr, value } = inst else {
+                unreachable!()
+            };
+            let (value_setup, reg) = frame.register(X0, value);
+            let (setup, memory) = frame.address(addr, ty.size());
+            return vec![
+                value_setup,
+                setup,
+                match ty {
+                    Ty::I8 => Assembly::inst2(STRB, reg.w(), memory),
+                    Ty::I32 => Assembly::inst2(STR, reg.w(), memory),
+                    Ty::Ptr => Assembly::inst2(STR, reg, memory),
+                },
+            ]
+            .into();
+        };
+        let reg = frame.result_register(dst);
         vec![
-            Assembly::inst3(SUB, X9, X9, 8),
-            Assembly::inst2(MOV, X8, operand),
-            Assembly::inst2(STR, X8, Ptr(X9, 8)),
+            match inst {
+                Inst::Copy { src, .. } => frame.load(reg, src),
+                Inst::Binary { op, lhs, rhs, .. } => {
+                    let (lhs_setup, lhs) = frame.register(X0, lhs);
+                    let (rhs_setup, rhs) = frame.register(X1, rhs);
+                    vec![
+                        lhs_setup,
+                        rhs_setup,
+                        match op {
+                            BinOp::Add => Assembly::inst3(ADD, reg, lhs, rhs),
+                            BinOp::Sub => Assembly::inst3(SUB, reg, lhs, rhs),
+                            BinOp::Mul => Assembly::inst3(MUL, reg, lhs, rhs),
+                            BinOp::Div => Assembly::inst3(SDIV, reg, lhs, rhs),
+                            BinOp::Mod => vec![
+                                Assembly::inst3(SDIV, X2, lhs, rhs),
+                                Assembly::inst4(MSUB, reg, X2, rhs, lhs),
+                            ]
+                            .into(),
+                            BinOp::Shl => Assembly::inst3(LSL, reg, lhs, rhs),
+                            BinOp::Shr => Assembly::inst3(ASR, reg, lhs, rhs),
+                            BinOp::And => Assembly::inst3(AND, reg, lhs, rhs),
+                            BinOp::Xor => Assembly::inst3(EOR, reg, lhs, rhs),
+                            BinOp::Or => Assembly::inst3(ORR, reg, lhs, rhs),
+                            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le => vec![
+                                Assembly::inst2(CMP, lhs, rhs),
+                                Assembly::inst2(
+                                    CSET,
+                                    reg,
+                                    match op {
+                                        BinOp::Eq => "eq",
+                                        BinOp::Ne => "ne",
+                                        BinOp::Lt => "lt",
+                                        _ => "le",
+                                    },
+                                ),
+                            ]
+                            .into(),
+                        },
+                    ]
+                    .into()
+                }
+                Inst::Not { src, .. } => {
+                    let (setup, src) = frame.register(X0, src);
+                    vec![setup, Assembly::inst2(MVN, reg, src)].into()
+                }
+                Inst::Extend { ty, src, .. } => {
+                    let (setup, src) = frame.register(X0, src);
+                    vec![setup, Self::extend(reg, src, *ty)].into()
+                }
+                Inst::SlotAddr { slot, .. } => {
+                    let offset = frame.slots[slot.0 as usize];
+                    if offset < 4096 {
+                        Assembly::inst3(ADD, reg, SP, offset)
+                    } else {
+                        vec![
+                            load_imm(reg, offset as i64),
+                            Assembly::inst3(ADD, reg, SP, reg),
+                        ]
+                        .into()
+                    }
+                }
+                Inst::SymbolAddr { symbol, .. } => {
+                    let label = self.symbol_label(symbol);
+                    match self.target_os {
+                        Os::MacOS => vec![
+                            Assembly::inst2(ADRP, reg, format!("{}@PAGE", label)),
+                            Assembly::inst3(ADD, reg, reg, format!("{}@PAGEOFF", label)),
+                        ],
+                        Os::Linux => vec![
+                            Assembly::inst2(ADRP, reg, label.clone()),
+                            Assembly::inst3(ADD, reg, reg, format!(":lo12:{}", label)),
+                        ],
+                    }
+                    .into()
+                }
+                Inst::Load { ty, addr, .. } => {
+                    let (setup, memory) = frame.address(addr, ty.size());
+                    vec![
+                        setup,
+                        match ty {
+                            Ty::I8 => Assembly::inst2(LDRSB, reg, memory),
+                            Ty::I32 => Assembly::inst2(LDRSW, reg, memory),
+                            Ty::Ptr => Assembly::inst2(LDR, reg, memory),
+                        },
+                    ]
+                    .into()
+                }
+                Inst::Phi { .. } => {
+                    let (setup, memory) = frame.phi_operand(dst);
+                    vec![setup, Assembly::inst2(LDR, reg, memory)].into()
+                }
+                Inst::Call { callee, args, .. } => {
+                    let ret = module.signature(callee).map_or(Ty::I32, |s| s.ret);
+                    let stack_args = self.stack_args(module, callee, args);
+                    let in_registers = args.len() - stack_args.len();
+                    vec![
+                        stack_args
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (_, arg))| {
+                                let (setup, arg) = frame.register(X8, arg);
+                                vec![setup, Assembly::inst2(STR, arg, Offset(SP, 8 * i))].into()
+                            })
+                            .collect::<Vec<Assembly>>()
+                            .into(),
+                        Self::pass_args(frame, &args[..in_registers]),
+                        Assembly::inst1(BL, self.with_prefix(callee)),
+                        Self::extend(reg, X0, ret),
+                    ]
+                    .into()
+                }
+                Inst::Store { .. } => unreachable!(),
+            },
+            frame.store(dst, reg),
         ]
         .into()
     }
-    fn pop<T1>(operand: T1) -> Assembly
-    where
-        T1: Into<InstOperand>,
-    {
+
+    /// Moves the arguments passed in the registers to them.
+    fn pass_args(frame: &Frame, args: &[(Ty, Operand)]) -> Assembly {
+        args.iter()
+            .enumerate()
+            .map(|(i, (_, arg))| frame.load(ARGS_REG[i], arg))
+            .collect::<Vec<Assembly>>()
+            .into()
+    }
+
+    /// Branches to the callee with the frame of the function freed, so that it returns to the caller.
+    fn gen_tail_call(&self, frame: &Frame, call: &Inst) -> Assembly {
+        let Inst::Call { callee, args, .. } = call else {
+            unreachable!("call expected")
+        };
         vec![
-            Assembly::inst2(LDR, operand, Ptr(X9, 8)),
-            Assembly::inst3(ADD, X9, X9, 8),
+            Self::pass_args(frame, args),
+            frame.leave(),
+            Assembly::inst1(B, self.with_prefix(callee)),
         ]
         .into()
     }
 
-    fn reset_stack(stack_size: usize) -> Assembly {
-        vec![Assembly::inst3(ADD, X9, X9, stack_size)].into()
-    }
-    fn epilogue(offset: usize) -> Assembly {
-        Assembly::Group(vec![
-            Assembly::inst3(LDP, X29, X30, PtrAdd(SP, "#16".to_string())),
-            Assembly::inst3(ADD, SP, SP, offset),
-            Assembly::inst0(RET),
-        ])
-    }
-
-    fn gen_node(&self, node: &Node, options: Options) -> Assembly {
-        match node.nt {
-            NodeType::DefVar => return self.gen_statements(&node.children, options),
-            NodeType::CallFunc => {
-                let fixed_args_len = reserved_functions()
-                    .get(node.global_name.as_str())
-                    .map(|v| {
-                        if let Os::Linux = self.target_os {
-                            return node.args.len().min(ARGS_REG.len());
-                        }
-                        let Identifier::Static(Type::Func(args, _)) = v else {
-                            unreachable!()
-                        };
-                        args.len()
+    /// Writes the operands given by the block to the phis of its successors.
+    fn gen_phi_operands(frame: &Frame, func: &Function, id: BlockId) -> Assembly {
+        let mut successors = func.block(id).term.successors();
+        successors.dedup();
+        successors
+            .into_iter()
+            .flat_map(|succ| func.block(succ).phis())
+            .filter_map(|phi| match phi {
+                Inst::Phi { dst, args } => {
+                    args.iter().find(|(pred, _)| *pred == id).map(|(_, arg)| {
+                        let (arg_setup, arg) = frame.register(X0, arg);
+                        let (setup, memory) = frame.phi_operand(*dst);
+                        vec![arg_setup, setup, Assembly::inst2(STR, arg, memory)].into()
                     })
-                    .unwrap_or(node.args.len());
-                let variadic_args_len = node.args.len() - fixed_args_len;
-                let args_offset = (options.args_offset + variadic_args_len * 8) % 16;
-                let options = Options {
-                    args_offset,
-                    ..options
-                };
-                return vec![
-                    Assembly::inst3(SUB, X9, X9, args_offset), // if the length of variadic args is odd, we need to align the stack
-                    node.args
-                        .iter()
-                        .map(|node| self.gen_node(node, options))
-                        .collect::<Vec<Assembly>>()
-                        .into(),
-                    ARGS_REG
-                        .iter()
-                        .take(fixed_args_len)
-                        .map(|op| Self::pop(*op))
-                        .collect::<Vec<Assembly>>()
-                        .into(),
-                    Assembly::inst2(MOV, SP, X9),
-                    Assembly::inst1(BL, self.with_prefix(&node.global_name)),
-                    Assembly::inst3(ADD, SP, SP, variadic_args_len * 8 + args_offset), // restore SP to the original value before calling the function
-                    Assembly::inst2(MOV, X9, SP),
-                    Self::push(X0), // push the return value
-                ]
-                .into();
-            }
-            NodeType::If => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                return vec![
-                    self.gen_node(node.cond.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    Assembly::inst2(CMP, X8, 0),
-                    Assembly::inst1(JE, ElseFlag(branch_num)),
-                    self.gen_node(node.then.as_ref().unwrap(), options),
-                    Assembly::inst1(JMP, EndFlag(branch_num)),
-                    format!("{}:", ElseFlag(branch_num)).into(),
-                    node.els
-                        .as_ref()
-                        .map(|node| self.gen_node(node, options))
-                        .unwrap_or_else(|| vec![].into()),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                ]
-                .into();
-            }
-            NodeType::While => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                let options = Options {
-                    breakable_branch_num: branch_num,
-                    ..options
-                };
-                let v = vec![
-                    format!("{}:", BeginFlag(branch_num)).into(),
-                    Self::reset_stack(options.offset),
-                    self.gen_node(node.cond.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    Assembly::inst2(CMP, X8, 0),
-                    Assembly::inst1(JE, EndFlag(branch_num)),
-                    self.gen_node(node.then.as_ref().unwrap(), options),
-                    Assembly::inst1(JMP, BeginFlag(branch_num)),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                ];
-                return v.into();
-            }
-            NodeType::For => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                let options = Options {
-                    breakable_branch_num: branch_num,
-                    ..options
-                };
-                let v = vec![
-                    node.ini.as_ref().map_or(Vec::new().into(), |node| {
-                        vec![self.gen_node(node, options), Self::pop(X8)].into()
-                    }),
-                    format!("{}:", BeginFlag(branch_num)).into(),
-                    Self::reset_stack(options.offset),
-                    node.cond
-                        .as_ref()
-                        .map_or(Assembly::inst2(MOV, X8, 1), |node| {
-                            vec![self.gen_node(node, options), Self::pop(X8)].into()
-                        }),
-                    Assembly::inst2(CMP, X8, 0),
-                    Assembly::inst1(JE, EndFlag(branch_num)),
-                    self.gen_node(node.then.as_ref().unwrap(), options),
-                    node.upd.as_ref().map_or(Vec::new().into(), |node| {
-                        vec![self.gen_node(node, options), Self::pop(X8)].into()
-                    }),
-                    Assembly::inst1(JMP, BeginFlag(branch_num)),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                ];
-                return v.into();
-            }
-            NodeType::Block => {
-                return self.gen_statements(&node.children, options);
-            }
-            NodeType::Break => {
-                if options.breakable_branch_num != 0 {
-                    return Assembly::inst1(JMP, EndFlag(options.breakable_branch_num));
-                } else {
-                    self.error_logger.print_error_position(
-                        node.token.as_ref().unwrap().pos,
-                        "unexpected break found",
-                    );
-                }
-            }
-            NodeType::Return => {
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), options),
-                    Self::pop(X0),
-                    Self::epilogue(options.offset),
-                ]
-                .into();
-            }
-            NodeType::Num => {
-                return Self::push(node.value.unwrap());
-            }
-            NodeType::LocalVar | NodeType::GlobalVar => {
-                return vec![
-                    self.gen_addr(node, options),
-                    if let Some(Type::Arr(_, _)) = node.resolve_type() {
-                        vec![].into()
-                    } else {
-                        vec![Self::pop(X8), self.deref(node), Self::push(X8)].into()
-                    },
-                ]
-                .into();
-            }
-            NodeType::Addr => {
-                return self.gen_addr(node.lhs.as_ref().unwrap(), options);
-            }
-            NodeType::Deref => {
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), options),
-                    if let Some(Type::Arr(..)) = node.lhs.as_ref().unwrap().dest_type() {
-                        vec![].into()
-                    } else {
-                        vec![Self::pop(X8), self.deref(node), Self::push(X8)].into()
-                    },
-                ]
-                .into();
-            }
-            NodeType::Assign => {
-                return vec![
-                    self.gen_addr(node.lhs.as_ref().unwrap(), options),
-                    self.gen_node(node.rhs.as_ref().unwrap(), options),
-                    Self::pop(X13),
-                    Self::pop(X8),
-                    Assembly::inst2(LDR, X13, Ptr(X8, 8)),
-                    //self.operation2rdi(node.lhs.as_ref().unwrap().resolve_type(), MOV, X8),
-                    Self::push(X13),
-                ]
-                .into();
-            }
-            NodeType::BitLeft | NodeType::BitRight => {
-                return vec![
-                    self.gen_node(node.rhs.as_ref().unwrap(), options),
-                    self.gen_node(node.lhs.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    Self::pop(X10),
-                    Assembly::inst2(
-                        match node.nt {
-                            NodeType::BitLeft => SHL,
-                            NodeType::BitRight => SAR,
-                            _ => {
-                                unreachable!()
-                            }
-                        },
-                        X8,
-                        CL,
-                    ),
-                    Self::push(X8),
-                ]
-                .into();
-            }
-            NodeType::BitNot => {
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    Assembly::inst1(NOT, X8),
-                    Self::push(X8),
-                ]
-                .into();
-            }
-            NodeType::LogicalAnd => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    Assembly::inst2(CMP, X8, 0),
-                    Assembly::inst1(JE, EndFlag(branch_num)),
-                    self.gen_node(node.rhs.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                    Self::push(X8),
-                ]
-                .into();
-            }
-            NodeType::LogicalOr => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    Assembly::inst2(CMP, X8, 0),
-                    Assembly::inst1(JNE, EndFlag(branch_num)),
-                    self.gen_node(node.rhs.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                    Self::push(X8),
-                ]
-                .into();
-            }
-            NodeType::SuffixIncr | NodeType::SuffixDecr => {
-                let op = if let NodeType::SuffixIncr = node.nt {
-                    ADD
-                } else {
-                    SUB
-                };
-                return vec![
-                    self.gen_addr(node.lhs.as_ref().unwrap(), options),
-                    Self::pop(X8),
-                    Assembly::inst2(MOV, X13, 1),
-                    if let Some(t) = node.lhs.as_ref().unwrap().dest_type() {
-                        Assembly::inst2(MUL, X13, t.size_of())
-                    } else {
-                        vec![].into()
-                    },
-                    Assembly::inst2(MOV, X11, X8),
-                    self.deref(node.lhs.as_ref().unwrap()),
-                    self.operation2rdi(node.lhs.as_ref().unwrap().resolve_type(), op, X11),
-                    Self::push(X8),
-                ]
-                .into();
-            }
-            _ => {}
-        }
-        vec![
-            self.gen_node(node.rhs.as_ref().unwrap(), options),
-            self.gen_node(node.lhs.as_ref().unwrap(), options),
-            Self::pop(X8),
-            Self::pop(X13),
-            match node.nt {
-                NodeType::Add => vec![
-                    if let Some(t) = node.lhs.as_ref().unwrap().dest_type() {
-                        Assembly::inst2(MUL, X13, t.size_of())
-                    } else {
-                        vec![].into()
-                    },
-                    Assembly::inst3(ADD, X8, X8, X13),
-                ]
-                .into(),
-                NodeType::Sub => vec![
-                    if let Some(t) = node.lhs.as_ref().unwrap().dest_type() {
-                        Assembly::inst2(MUL, X13, t.size_of())
-                    } else {
-                        vec![].into()
-                    },
-                    Assembly::inst3(SUB, X8, X8, X13),
-                ]
-                .into(),
-                NodeType::Mul => Assembly::inst3(MUL, X8, X8, X13),
-                NodeType::Div => Assembly::inst3(SDIV, X8, X8, X13),
-                NodeType::Mod => vec![
-                    Assembly::inst3(SDIV, X10, X8, X13),
-                    Assembly::inst4(MSUB, X8, X10, X13, X8),
-                ]
-                .into(),
-                NodeType::Eq | NodeType::Ne | NodeType::Lt | NodeType::Le => vec![
-                    Assembly::inst2(CMP, X8, X13),
-                    Assembly::inst1(
-                        match node.nt {
-                            NodeType::Eq => SETE,
-                            NodeType::Ne => SETNE,
-                            NodeType::Lt => SETL,
-                            NodeType::Le => SETLE,
-                            _ => unreachable!(),
-                        },
-                        AL,
-                    ),
-                    Assembly::inst2(MOVZX, X8, AL),
-                ]
-                .into(),
-                NodeType::BitAnd => Assembly::inst2(AND, X8, X13),
-                NodeType::BitXor => Assembly::inst2(XOR, X8, X13),
-                NodeType::BitOr => Assembly::inst2(OR, X8, X13),
-                _ => {
-                    self.error_logger
-                        .print_error_position(node.token.as_ref().unwrap().pos, "unexpected node");
-                    unreachable!();
                 }
-            },
-            Self::push(X8),
-        ]
-        .into()
+                _ => None,
+            })
+            .collect::<Vec<Assembly>>()
+            .into()
     }
 
-    fn gen_statements(&self, v: &[Node], options: Options) -> Assembly {
-        v.iter()
-            .map(|node| {
+    fn gen_terminator(
+        &self,
+        frame: &Frame,
+        index: usize,
+        id: BlockId,
+        term: &Terminator,
+    ) -> Assembly {
+        let next = BlockId(id.0 + 1);
+        let jump = |target: BlockId| {
+            if target == next {
+                vec![].into()
+            } else {
+                Assembly::inst1(B, self.block_label(index, target))
+            }
+        };
+        match term {
+            Terminator::Jump(target) => jump(*target),
+            Terminator::Branch { cond, then, els } => {
+                let (setup, cond) = frame.register(X0, cond);
                 vec![
-                    self.gen_node(node, options),
-                    Self::pop(X8),
-                    // Self::reset_stack(offset),
+                    setup,
+                    Assembly::inst2(CBNZ, cond, self.block_label(index, *then)),
+                    jump(*els),
                 ]
                 .into()
-            })
-            .collect::<Vec<Assembly>>()
-            .into()
+            }
+            Terminator::Return(value) => vec![frame.load(X0, value), frame.epilogue()].into(),
+        }
     }
 
-    fn gen_addr(&self, node: &Node, options: Options) -> Assembly {
-        match node.nt {
-            NodeType::GlobalVar => vec![
-                if !node.dest.is_empty() {
-                    vec![
-                        Assembly::inst2(
-                            ADRP,
-                            X8,
-                            match self.target_os {
-                                Os::MacOS => node.dest.clone().replace('@', "L_") + "@PAGE",
-                                Os::Linux => node.dest.clone().replace('@', "L_"),
-                            },
-                        ),
-                        Assembly::inst3(
-                            ADD,
-                            X8,
-                            X8,
-                            match self.target_os {
-                                Os::MacOS => node.dest.clone().replace('@', "L_") + "@PAGEOFF",
-                                Os::Linux => {
-                                    ":lo12:".to_string() + &node.dest.clone().replace('@', "L_")
-                                }
-                            },
-                        ),
-                    ]
-                    .into()
-                } else {
-                    vec![
-                        Assembly::inst2(
-                            ADRP,
-                            X8,
-                            match self.target_os {
-                                Os::MacOS => self.with_prefix(&node.global_name) + "@PAGE",
-                                Os::Linux => self.with_prefix(&node.global_name),
-                            },
-                        ),
-                        Assembly::inst3(
-                            ADD,
-                            X8,
-                            X8,
-                            match self.target_os {
-                                Os::MacOS => self.with_prefix(&node.global_name) + "@PAGEOFF",
-                                Os::Linux => {
-                                    ":lo12:".to_string()
-                                        + &self.with_prefix(&node.global_name).replace('@', "L_")
-                                }
-                            },
-                        ),
-                    ]
-                    .into()
-                },
-                Self::push(X8),
-            ]
-            .into(),
-            NodeType::LocalVar => vec![
-                Assembly::inst3(ADD, X8, SP, node.offset.unwrap()),
-                Self::push(X8),
-            ]
-            .into(),
-            NodeType::Deref => self.gen_node(node.lhs.as_ref().unwrap(), options),
-            _ => {
-                unreachable!();
-            }
+    /// Moves the low bits of `from` which fit the type to `to`, sign-extending them.
+    fn extend(to: Register, from: Register, ty: Ty) -> Assembly {
+        match ty {
+            Ty::I8 => Assembly::inst2(SXTB, to, from.w()),
+            Ty::I32 => Assembly::inst2(SXTW, to, from.w()),
+            Ty::Ptr if to == from => vec![].into(),
+            Ty::Ptr => Assembly::inst2(MOV, to, from),
         }
     }
 
-    fn operation2rdi(
-        &self,
-        c_type: Option<Type>,
-        operator: InstOperator,
-        from: Register,
-    ) -> Assembly {
-        match c_type {
-            Some(Type::I8) => Assembly::inst2(operator, Ptr(from, 1), X17),
-            Some(Type::I32) => Assembly::inst2(operator, Ptr(from, 4), X16),
-            _ => Assembly::inst2(operator, Ptr(from, 8), X13),
+    fn block_label(&self, index: usize, id: BlockId) -> String {
+        self.local_label(&format!("@BB{}_{}", index, id.0))
+    }
+
+    fn symbol_label(&self, symbol: &Symbol) -> String {
+        match symbol {
+            Symbol::Global(name) => self.with_prefix(name),
+            Symbol::Str(index) => self.local_label(&format!("@.str.{}", index)),
         }
     }
 
-    fn deref(&self, node: &Node) -> Assembly {
-        match node.resolve_type() {
-            Some(Type::I8) => Assembly::inst2(LDR, X8, Ptr(X8, 1)),
-            Some(Type::I32) => Assembly::inst2(LDR, X8, Ptr(X8, 4)),
-            _ => Assembly::inst2(LDR, X8, Ptr(X8, 8)),
+    /// Returns the name of a label which is not exported from the object file,
+    /// for a name starting with `@` such as string literals.
+    fn local_label(&self, name: &str) -> String {
+        match self.target_os {
+            Os::MacOS => name.replace('@', "L_"),
+            Os::Linux => name.replace('@', ".L"),
         }
     }
 
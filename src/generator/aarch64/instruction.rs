@@ -1,40 +1,37 @@
 use crate::generator::Os;
 
-#[derive(Default)]
+#[derive(Clone, Copy, PartialEq, Default)]
 pub enum InstOperator {
     MOV,
+    MOVZ,
+    MOVK,
     ADD,
     SUB,
-    RET,
-    BL,
-    SVC,
-    CQO,
-    JMP,
-    JE,
-    JNE,
     MUL,
     SDIV,
-    SHL,
-    SAR,
-    NOT,
-    SETE,
-    SETNE,
-    SETL,
-    SETLE,
-    LEA,
-    MOVSX,
-    MOVSXD,
-    MOVZX,
-    CMP,
+    MSUB,
+    LSL,
+    ASR,
     AND,
-    OR,
-    XOR,
+    ORR,
+    EOR,
+    MVN,
+    CMP,
+    CSET,
+    SXTB,
+    SXTW,
     LDR,
+    LDRSB,
+    LDRSW,
     STR,
+    STRB,
     ADRP,
     STP,
     LDP,
-    MSUB,
+    B,
+    BL,
+    CBNZ,
+    RET,
     #[default]
     NOP,
 }
@@ -43,50 +40,44 @@ use InstOperator::*;
 use std::fmt::{Debug, Display, Error, Formatter};
 
 impl InstOperator {
-    pub fn to_string(&self, target_os: Os) -> &str {
-        if matches!(target_os, Os::Linux) && matches!(self, MOVZX) {
-            return "movzb";
-        }
+    pub fn to_string(&self, _target_os: Os) -> &str {
         match self {
             MOV => "mov",
+            MOVZ => "movz",
+            MOVK => "movk",
             ADD => "add",
             SUB => "sub",
-            RET => "ret",
-            BL => "bl",
-            CQO => "cqo",
-            SVC => "svc",
-            JMP => "jmp",
-            JE => "je",
-            JNE => "jne",
             MUL => "mul",
             SDIV => "sdiv",
             MSUB => "msub",
-            SHL => "shl",
-            SAR => "sar",
-            NOT => "not",
-            SETE => "sete",
-            SETNE => "setne",
-            SETL => "setl",
-            SETLE => "setle",
-            LEA => "lea",
-            MOVSX => "movsx",
-            MOVSXD => "movsxd",
-            MOVZX => "movzx",
-            CMP => "cmp",
+            LSL => "lsl",
+            ASR => "asr",
             AND => "and",
-            OR => "or",
-            XOR => "xor",
+            ORR => "orr",
+            EOR => "eor",
+            MVN => "mvn",
+            CMP => "cmp",
+            CSET => "cset",
+            SXTB => "sxtb",
+            SXTW => "sxtw",
             LDR => "ldr",
+            LDRSB => "ldrsb",
+            LDRSW => "ldrsw",
             STR => "str",
+            STRB => "strb",
             ADRP => "adrp",
             STP => "stp",
             LDP => "ldp",
+            B => "b",
+            BL => "bl",
+            CBNZ => "cbnz",
+            RET => "ret",
             NOP => "nop",
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Register {
     X0,
     X1,
@@ -104,19 +95,121 @@ pub enum Register {
     X13,
     X14,
     X15,
+    X19,
+    X20,
+    X21,
+    X22,
+    X23,
+    X24,
+    X25,
+    X26,
+    X27,
+    X28,
     X16,
-    X17,
     X29,
     X30,
-    AL,
-    CL,
-    RIP,
-    R8,
-    R9,
+    W0,
+    W1,
+    W2,
+    W3,
+    W4,
+    W5,
+    W6,
+    W7,
+    W8,
+    W9,
+    W10,
+    W11,
+    W12,
+    W13,
+    W14,
+    W15,
+    W19,
+    W20,
+    W21,
+    W22,
+    W23,
+    W24,
+    W25,
+    W26,
+    W27,
+    W28,
     SP,
 }
 use Register::*;
 
+impl Register {
+    /// Returns the 32-bit register holding the low bits of the 64-bit register.
+    pub fn w(self) -> Register {
+        match self {
+            X0 => W0,
+            X1 => W1,
+            X2 => W2,
+            X3 => W3,
+            X4 => W4,
+            X5 => W5,
+            X6 => W6,
+            X7 => W7,
+            X8 => W8,
+            X9 => W9,
+            X10 => W10,
+            X11 => W11,
+            X12 => W12,
+            X13 => W13,
+            X14 => W14,
+            X15 => W15,
+            X19 => W19,
+            X20 => W20,
+            X21 => W21,
+            X22 => W22,
+            X23 => W23,
+            X24 => W24,
+            X25 => W25,
+            X26 => W26,
+            X27 => W27,
+            X28 => W28,
+            _ => unreachable!("no 32-bit register for {}", self),
+        }
+    }
+
+    /// Returns the 64-bit register which the register is the low half of.
+    pub fn x(self) -> Register {
+        match self {
+            W0 => X0,
+            W1 => X1,
+            W2 => X2,
+            W3 => X3,
+            W4 => X4,
+            W5 => X5,
+            W6 => X6,
+            W7 => X7,
+            W8 => X8,
+            W9 => X9,
+            W10 => X10,
+            W11 => X11,
+            W12 => X12,
+            W13 => X13,
+            W14 => X14,
+            W15 => X15,
+            W19 => X19,
+            W20 => X20,
+            W21 => X21,
+            W22 => X22,
+            W23 => X23,
+            W24 => X24,
+            W25 => X25,
+            W26 => X26,
+            W27 => X27,
+            W28 => X28,
+            _ => self,
+        }
+    }
+
+    pub fn is_32_bit(self) -> bool {
+        self.x() != self
+    }
+}
+
 impl Display for Register {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         f.write_str(match self {
@@ -136,31 +229,65 @@ impl Display for Register {
             X13 => "x13",
             X14 => "x14",
             X15 => "x15",
+            X19 => "x19",
+            X20 => "x20",
+            X21 => "x21",
+            X22 => "x22",
+            X23 => "x23",
+            X24 => "x24",
+            X25 => "x25",
+            X26 => "x26",
+            X27 => "x27",
+            X28 => "x28",
             X16 => "x16",
-            X17 => "x17",
             X29 => "x29",
             X30 => "x30",
-            AL => "al",
-            CL => "cl",
-            RIP => "rip",
-            R8 => "r8",
-            R9 => "r9",
+            W0 => "w0",
+            W1 => "w1",
+            W2 => "w2",
+            W3 => "w3",
+            W4 => "w4",
+            W5 => "w5",
+            W6 => "w6",
+            W7 => "w7",
+            W8 => "w8",
+            W9 => "w9",
+            W10 => "w10",
+            W11 => "w11",
+            W12 => "w12",
+            W13 => "w13",
+            W14 => "w14",
+            W15 => "w15",
+            W19 => "w19",
+            W20 => "w20",
+            W21 => "w21",
+            W22 => "w22",
+            W23 => "w23",
+            W24 => "w24",
+            W25 => "w25",
+            W26 => "w26",
+            W27 => "w27",
+            W28 => "w28",
             SP => "sp",
         })?;
         Ok(())
     }
 }
 
+#[derive(Clone, PartialEq)]
 pub enum InstOperand {
     Reg(Register),
     Num(usize),
+    Imm(i64),
     Label(String),
     Str(&'static str),
-    Ptr(Register, usize),
-    PtrAdd(Register, String),
-    ElseFlag(usize),
-    BeginFlag(usize),
-    EndFlag(usize),
+    Ptr(Register),
+    /// The address at an offset from the register.
+    Offset(Register, usize),
+    /// The address in the register, which is added the offset before the access.
+    PreIndex(Register, i64),
+    /// The address in the register, which is added the offset after the access.
+    PostIndex(Register, i64),
 }
 
 impl Display for InstOperand {
@@ -168,13 +295,13 @@ impl Display for InstOperand {
         f.write_str(&match self {
             InstOperand::Reg(r) => format!("{}", r),
             InstOperand::Num(i) => format!("#{}", i),
+            InstOperand::Imm(i) => format!("#{}", i),
             InstOperand::Label(l) => l.clone(),
             InstOperand::Str(s) => String::from(*s),
-            InstOperand::Ptr(r, _) => format!("[{}]", r),
-            InstOperand::ElseFlag(i) => format!(".Lelse{}", i),
-            InstOperand::BeginFlag(i) => format!(".Lbegin{}", i),
-            InstOperand::EndFlag(i) => format!(".Lend{}", i),
-            InstOperand::PtrAdd(s, r) => format!("[{}, {}]", s, r),
+            InstOperand::Ptr(r) => format!("[{}]", r),
+            InstOperand::Offset(r, offset) => format!("[{}, #{}]", r, offset),
+            InstOperand::PreIndex(r, offset) => format!("[{}, #{}]!", r, offset),
+            InstOperand::PostIndex(r, offset) => format!("[{}], #{}", r, offset),
         })
     }
 }
@@ -197,12 +324,18 @@ impl From<usize> for InstOperand {
     }
 }
 
+impl From<i64> for InstOperand {
+    fn from(val: i64) -> Self {
+        InstOperand::Imm(val)
+    }
+}
+
 impl From<Register> for InstOperand {
     fn from(val: Register) -> Self {
         InstOperand::Reg(val)
     }
 }
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Instruction {
     pub operator: InstOperator,
     pub operand1: Option<InstOperand>,
@@ -212,40 +345,37 @@ pub struct Instruction {
 }
 impl Instruction {
     pub fn to_string(&self, target_os: Os) -> String {
-        if self.operand4.is_some() {
-            return format!(
+        match (
+            &self.operand1,
+            &self.operand2,
+            &self.operand3,
+            &self.operand4,
+        ) {
+            (Some(operand1), Some(operand2), Some(operand3), Some(operand4)) => format!(
                 "  {} {}, {}, {}, {}",
                 self.operator.to_string(target_os),
-                &self.operand1.as_ref().unwrap().to_string()[..],
-                &self.operand2.as_ref().unwrap().to_string()[..],
-                &self.operand3.as_ref().unwrap().to_string()[..],
-                &self.operand4.as_ref().unwrap().to_string()[..],
-            );
-        }
-        if self.operand3.is_some() {
-            return format!(
+                operand1,
+                operand2,
+                operand3,
+                operand4,
+            ),
+            (Some(operand1), Some(operand2), Some(operand3), None) => format!(
                 "  {} {}, {}, {}",
                 self.operator.to_string(target_os),
-                &self.operand1.as_ref().unwrap().to_string()[..],
-                &self.operand2.as_ref().unwrap().to_string()[..],
-                &self.operand3.as_ref().unwrap().to_string()[..]
-            );
-        }
-        if self.operand2.is_some() {
-            return format!(
+                operand1,
+                operand2,
+                operand3
+            ),
+            (Some(operand1), Some(operand2), None, None) => format!(
                 "  {} {}, {}",
                 self.operator.to_string(target_os),
-                &self.operand1.as_ref().unwrap().to_string()[..],
-                &self.operand2.as_ref().unwrap().to_string()[..]
-            );
-        }
-        if self.operand1.is_some() {
-            return format!(
-                "  {} {}",
-                self.operator.to_string(target_os),
-                &self.operand1.as_ref().unwrap().to_string()[..],
-            );
+                operand1,
+                operand2
+            ),
+            (Some(operand1), None, None, None) => {
+                format!("  {} {}", self.operator.to_string(target_os), operand1)
+            }
+            _ => format!("  {}", self.operator.to_string(target_os)),
         }
-        format!("  {}", self.operator.to_string(target_os))
     }
 }
@@ -0,0 +1,315 @@
+//! Rewrites short sequences of the instructions generated for a function to fewer ones.
+//! A register is only assumed dead when it is written before being read
+//! in the instructions up to the next label, branch or call.
+
+use super::assembly::Assembly;
+use super::instruction::{
+    InstOperand::{self, *},
+    InstOperator::{self, *},
+    Instruction,
+    Register::{self, *},
+};
+use std::ops::Range;
+
+/// Rewrites the code of a function until no pattern is found.
+pub fn optimize(assembly: Assembly) -> Assembly {
+    let mut code = vec![];
+    flatten(assembly, &mut code);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut i = 0;
+        while i < code.len() {
+            match rewrite(&code[i..]) {
+                Some((range, replacement)) => {
+                    code.splice(i + range.start..i + range.end, replacement);
+                    changed = true;
+                }
+                None => i += 1,
+            }
+        }
+    }
+    code.into()
+}
+
+fn flatten(assembly: Assembly, code: &mut Vec<Assembly>) {
+    match assembly {
+        Assembly::Group(children) => children.into_iter().for_each(|child| flatten(child, code)),
+        _ => code.push(assembly),
+    }
+}
+
+fn instruction(assembly: Option<&Assembly>) -> Option<&Instruction> {
+    match assembly {
+        Some(Assembly::Inst(inst)) => Some(inst),
+        _ => None,
+    }
+}
+
+/// Returns the immediate of an arithmetic instruction, which is unsigned and 12 bits.
+fn immediate(operand: &InstOperand) -> Option<usize> {
+    let value = match operand {
+        Num(value) => *value,
+        Imm(value) => usize::try_from(*value).ok()?,
+        _ => return None,
+    };
+    (value < 4096).then_some(value)
+}
+
+/// Returns the instructions replacing the range of the code from its first instruction, if any pattern starts there.
+fn rewrite(code: &[Assembly]) -> Option<(Range<usize>, Vec<Assembly>)> {
+    let first = instruction(code.first())?;
+    let next = instruction(code.get(1));
+    match (
+        first.operator,
+        first.operand1.as_ref()?,
+        first.operand2.as_ref(),
+        first.operand3.as_ref(),
+    ) {
+        (MOV, Reg(to), Some(Reg(from)), None) if to == from && !to.is_32_bit() => {
+            Some((0..1, vec![]))
+        }
+        // a register written and never read
+        (_, Reg(reg), _, _) if overwrites(first) && is_dead(&code[1..], reg.x()) => {
+            Some((0..1, vec![]))
+        }
+        // moving back the value just moved
+        (MOV, Reg(to), Some(Reg(from)), None)
+            if next.is_some_and(|next| {
+                next.operator == MOV
+                    && next.operand1 == Some(Reg(*from))
+                    && next.operand2 == Some(Reg(*to))
+                    && next.operand3.is_none()
+            }) && !to.is_32_bit()
+                && !from.is_32_bit() =>
+        {
+            Some((1..2, vec![]))
+        }
+        // loading the value just stored
+        (STR, Reg(stored), Some(memory), None) if !stored.is_32_bit() => {
+            let next = next?;
+            match (next.operator, &next.operand1, &next.operand2) {
+                (LDR, Some(Reg(loaded)), Some(other)) if other == memory && !loaded.is_32_bit() => {
+                    Some((
+                        1..2,
+                        if loaded == stored {
+                            vec![]
+                        } else {
+                            vec![Assembly::inst2(MOV, *loaded, *stored)]
+                        },
+                    ))
+                }
+                _ => None,
+            }
+        }
+        // the constant moved to a register only to be an operand
+        (MOV, Reg(reg), Some(value), None) => {
+            let value = immediate(value)?;
+            let next = next?;
+            let folded = match (
+                next.operator,
+                &next.operand1,
+                &next.operand2,
+                &next.operand3,
+            ) {
+                (op @ (ADD | SUB), Some(Reg(dst)), Some(Reg(lhs)), Some(Reg(rhs)))
+                    if rhs == reg && lhs != reg =>
+                {
+                    Assembly::inst3(op, *dst, *lhs, value)
+                }
+                (CMP, Some(Reg(lhs)), Some(Reg(rhs)), None) if rhs == reg && lhs != reg => {
+                    Assembly::inst2(CMP, *lhs, value)
+                }
+                _ => return None,
+            };
+            (writes(next, reg.x()) || is_dead(&code[2..], reg.x())).then(|| (0..2, vec![folded]))
+        }
+        (ADD, Reg(reg), Some(Reg(base)), Some(value))
+            if reg != base && first.operand4.is_none() =>
+        {
+            let value = immediate(value)?;
+            let next = next?;
+            match (
+                next.operator,
+                &next.operand1,
+                &next.operand2,
+                &next.operand3,
+            ) {
+                // the constants added one after the other
+                (ADD, Some(Reg(dst)), Some(Reg(src)), Some(other)) if dst == reg && src == reg => {
+                    let value = immediate(&Num(value + immediate(other)?))?;
+                    Some((0..2, vec![Assembly::inst3(ADD, *reg, *base, value)]))
+                }
+                // the address accessed by the next instruction using the register
+                _ => {
+                    let reg = reg.x();
+                    let skips = |assembly: &Assembly| match assembly {
+                        Assembly::Inst(inst) => {
+                            !matches!(inst.operator, B | BL | CBNZ | RET)
+                                && !reads(inst, reg)
+                                && !writes(inst, reg)
+                                && !writes(inst, *base)
+                        }
+                        _ => false,
+                    };
+                    let user = 1 + code[1..].iter().position(|assembly| !skips(assembly))?;
+                    let inst = instruction(code.get(user))?;
+                    let (Some(Reg(data)), Some(Ptr(r) | Offset(r, _)), None) =
+                        (&inst.operand1, &inst.operand2, &inst.operand3)
+                    else {
+                        return None;
+                    };
+                    let offset = match inst.operand2 {
+                        Some(Offset(_, offset)) => value + offset,
+                        _ => value,
+                    };
+                    let size = access_size(inst.operator, *data)?;
+                    let is_load = matches!(inst.operator, LDR | LDRSB | LDRSW);
+                    if *r != reg
+                        || !offset.is_multiple_of(size)
+                        || offset / size >= 4096
+                        || (!is_load && data.x() == reg)
+                        || !((is_load && data.x() == reg) || is_dead(&code[user + 1..], reg))
+                    {
+                        return None;
+                    }
+                    Some((
+                        0..user + 1,
+                        code[1..user]
+                            .iter()
+                            .filter_map(|assembly| instruction(Some(assembly)))
+                            .map(|inst| inst.clone().into())
+                            .chain([Assembly::inst2(inst.operator, *data, Offset(*base, offset))])
+                            .collect(),
+                    ))
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the size of the memory loaded or stored by the instruction with the data register.
+fn access_size(op: InstOperator, data: Register) -> Option<usize> {
+    match op {
+        LDRSB | STRB => Some(1),
+        LDRSW => Some(4),
+        LDR | STR if data.is_32_bit() => Some(4),
+        LDR | STR => Some(8),
+        _ => None,
+    }
+}
+
+/// Returns whether the instruction writes its first operand, a register, without reading it.
+fn overwrites(inst: &Instruction) -> bool {
+    matches!(
+        inst.operator,
+        MOV | MOVZ
+            | ADD
+            | SUB
+            | MUL
+            | SDIV
+            | MSUB
+            | LSL
+            | ASR
+            | AND
+            | ORR
+            | EOR
+            | MVN
+            | CSET
+            | SXTB
+            | SXTW
+            | LDR
+            | LDRSB
+            | LDRSW
+            | ADRP
+    ) && matches!(inst.operand1, Some(Reg(_)))
+}
+
+fn reads(inst: &Instruction, reg: Register) -> bool {
+    let uses = |operand: &InstOperand| match operand {
+        Reg(r) => r.x() == reg,
+        Ptr(r) | Offset(r, _) | PreIndex(r, _) | PostIndex(r, _) => *r == reg,
+        _ => false,
+    };
+    let skip = if overwrites(inst) { 1 } else { 0 };
+    [
+        &inst.operand1,
+        &inst.operand2,
+        &inst.operand3,
+        &inst.operand4,
+    ]
+    .into_iter()
+    .skip(skip)
+    .flatten()
+    .any(uses)
+}
+
+fn writes(inst: &Instruction, reg: Register) -> bool {
+    overwrites(inst) && matches!(inst.operand1, Some(Reg(r)) if r.x() == reg)
+}
+
+/// Returns whether the register is written before being read by the code.
+fn is_dead(code: &[Assembly], reg: Register) -> bool {
+    for assembly in code {
+        let Assembly::Inst(inst) = assembly else {
+            return false;
+        };
+        match inst.operator {
+            // the return value and the callee-saved registers are read by the caller
+            RET => {
+                return !matches!(
+                    reg,
+                    X0 | X19 | X20 | X21 | X22 | X23 | X24 | X25 | X26 | X27 | X28 | X29 | X30 | SP
+                );
+            }
+            // the caller-saved registers not passing the arguments are overwritten by the callee
+            BL => return matches!(reg, X9 | X10 | X11 | X12 | X13 | X14 | X15 | X16),
+            B | CBNZ => return false,
+            _ if reads(inst, reg) => return false,
+            _ if writes(inst, reg) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{Assembly as _, Os};
+
+    #[test]
+    fn test_optimize() {
+        let code: Assembly = vec![
+            Assembly::inst3(ADD, X9, SP, 8usize),
+            Assembly::inst2(MOV, X1, 4i64),
+            Assembly::inst3(ADD, X9, X9, X1),
+            Assembly::inst2(MOV, X0, 5i64),
+            Assembly::inst2(STR, W0, Ptr(X9)),
+            Assembly::inst2(STR, X10, Offset(SP, 16)),
+            Assembly::inst2(LDR, X11, Offset(SP, 16)),
+            Assembly::inst2(MOV, X12, X11),
+            Assembly::inst2(MOV, X11, X12),
+            Assembly::inst2(MOV, X1, 7i64),
+            Assembly::inst2(CMP, X12, X1),
+            Assembly::inst2(MOV, X0, X12),
+            Assembly::inst0(RET),
+        ]
+        .into();
+        assert_eq!(
+            optimize(code).to_string(Os::Linux),
+            [
+                "  mov x0, #5",
+                "  str w0, [sp, #12]",
+                "  str x10, [sp, #16]",
+                "  mov x11, x10",
+                "  mov x12, x11",
+                "  cmp x12, #7",
+                "  mov x0, x12",
+                "  ret",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -1,62 +1,59 @@
-use std::{collections::HashMap, vec};
-
 use crate::{
-    ast::{Identifier, ProgramAst, reserved_functions},
     ctype::Type,
-    error,
-    func::Func,
+    generator::{Target, tail_call},
     global::{GlobalVariable, GlobalVariableData},
-    node::{Node, NodeType},
+    ir::opt::{OptOptions, Pass},
+    ir::{Address, BinOp, BlockId, Function, Inst, Module, Operand, Terminator, Ty, Value},
 };
 
-#[derive(Debug)]
-pub struct Options<'a> {
-    register_number: &'a mut usize,
-    register_map: &'a mut HashMap<usize, usize>,
-    register_queue: &'a mut Vec<String>,
+pub struct IrGenerator {
+    target: Target,
+    marks_tail_calls: bool,
 }
 
-impl Options<'_> {
-    fn new_register(&mut self) -> usize {
-        *self.register_number += 1;
-        self.register_queue
-            .push(format!("%{}", self.register_number));
-        *self.register_number
-    }
-    fn register_from_offset(&mut self, offset: usize) -> usize {
-        let entry = self.register_map.entry(offset);
-        let register = *entry.or_insert_with(|| *self.register_number + 1);
-        if register > *self.register_number {
-            *self.register_number = register;
+impl IrGenerator {
+    pub fn new(target: Target, options: &OptOptions) -> Self {
+        Self {
+            target,
+            marks_tail_calls: options.is_enabled(Pass::TailCall),
         }
-        register
     }
-}
-
-pub struct IrGenerator<'a> {
-    error_logger: &'a dyn error::ErrorLogger,
-}
 
-impl<'a> IrGenerator<'a> {
-    pub fn new(error_logger: &'a dyn error::ErrorLogger) -> Self {
-        Self { error_logger }
-    }
-    pub fn generate(&self, ast: ProgramAst) -> String {
+    pub fn generate(&self, module: &Module) -> String {
         [
-            self.gen_string_literals(ast.string_literals),
-            ast.global_variables
+            vec![
+                format!("target datalayout = \"{}\"", self.target.llvm_data_layout()),
+                format!("target triple = \"{}\"", self.target.llvm_triple()),
+            ],
+            self.gen_string_literals(&module.string_literals),
+            module
+                .global_variables
                 .iter()
                 .map(|(s, gv)| self.gen_global_variable(s, gv))
                 .collect::<Vec<String>>(),
-            ast.functions
+            module
+                .functions
                 .iter()
-                .map(|(name, f)| self.gen_func(name, f) + "\n")
+                .map(|f| self.gen_func(module, f) + "\n")
+                .collect::<Vec<String>>(),
+            module
+                .externs
+                .iter()
+                .map(|(name, signature)| {
+                    format!(
+                        "declare {} @{}({})",
+                        Self::ir_type(signature.ret),
+                        name,
+                        signature
+                            .params
+                            .iter()
+                            .map(|ty| format!("{} noundef", Self::ir_type(*ty)))
+                            .chain(signature.is_variadic.then(|| "...".to_string()))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )
+                })
                 .collect::<Vec<String>>(),
-            vec![
-                "declare i32 @printf(ptr noundef, ...)".to_string(),
-                "declare i32 @putchar(i8 noundef, ...)".to_string(),
-                "declare i32 @exit(i8 noundef, ...)".to_string(),
-            ],
         ]
         .concat()
         .join("\n")
@@ -121,15 +118,14 @@ impl<'a> IrGenerator<'a> {
                     format!(
                         "{} [{}]",
                         Self::gen_type(ty.clone()),
-                        v.iter()
-                            .enumerate()
-                            .filter(|(i, _)| i < size)
-                            .map(|(_, d)| { Self::gen_initializer_element(children_ty, Some(d)) })
+                        // the elements without initializers are zero
+                        (0..*size)
+                            .map(|i| Self::gen_initializer_element(children_ty, v.get(i)))
                             .collect::<Vec<String>>()
                             .join(", "),
                     )
                 } else {
-                    format!("{} null", Self::gen_type(ty.clone()))
+                    format!("{} zeroinitializer", Self::gen_type(ty.clone()))
                 }
             }
             Type::Ptr(_) => format!("{} null", Self::gen_type_with_opaque_ptr(ty.clone())),
@@ -146,437 +142,412 @@ impl<'a> IrGenerator<'a> {
         }
     }
 
-    fn gen_func(&self, name: &str, func: &Func) -> String {
-        if func.body.is_none() {
-            return String::new();
-        }
-        let Type::Func(_, return_ty) = &func.cty else {
-            todo!()
-        };
-        let options = &mut Options {
-            register_number: &mut func.args.len(),
-            register_map: &mut HashMap::<usize, usize>::new(),
-            register_queue: &mut vec!["?".to_string(); 10],
+    fn gen_func(&self, module: &Module, func: &Function) -> String {
+        let mut temps = Temps::default();
+        let params = func
+            .params
+            .iter()
+            .zip(&func.signature.params)
+            .map(|(param, ty)| {
+                let arg = format!("%a{}", param.0);
+                (
+                    format!("{} noundef {}", Self::ir_type(*ty), arg),
+                    Self::widen(&Self::value(*param), *ty, &arg),
+                )
+            })
+            .collect::<Vec<(String, String)>>();
+        let tail_calls = func
+            .block_ids()
+            .map(|id| {
+                self.marks_tail_calls
+                    .then(|| tail_call::tail_call(module, func, id, self.target.arg_registers()))
+                    .flatten()
+            })
+            .collect::<Vec<Option<usize>>>();
+        // the blocks jumping to a block which only returns a phi return after their tail calls
+        let returns_early = |pred: &BlockId| {
+            tail_calls[pred.0 as usize].is_some()
+                && matches!(func.block(*pred).term, Terminator::Jump(_))
         };
         [
             vec![format!(
-                "define {} @{}({}) {{",
-                Self::gen_type(*return_ty.clone()),
-                name,
-                func.args
+                "define {} @{}({}) {}{{",
+                Self::ir_type(func.signature.ret),
+                func.name,
+                params
                     .iter()
-                    .enumerate()
-                    .map(|(i, arg)| format!(
-                        "{} noundef %{}",
-                        Self::gen_type(arg.resolve_type().unwrap()),
-                        i
-                    ))
+                    .map(|(param, _)| param.clone())
                     .collect::<Vec<String>>()
-                    .join(", ")
+                    .join(", "),
+                func.inline
+                    .attribute()
+                    .map_or(String::new(), |attribute| format!("{} ", attribute))
             )],
-            func.args
-                .iter()
-                .enumerate()
-                .map(|(i, node)| {
-                    let register = options.new_register();
-                    options.register_map.insert(node.offset.unwrap(), register);
-                    format!(
-                        "  %{} = alloca {}, align {}",
-                        register,
-                        Self::gen_type(node.resolve_type().unwrap()),
-                        node.resolve_type().unwrap().size_of(),
-                    ) + "\n"
-                        + &format!(
-                            "  store {} %{}, ptr %{}, align {}",
-                            Self::gen_type(node.resolve_type().unwrap()),
-                            i,
-                            register,
-                            node.resolve_type().unwrap().size_of(),
-                        )
+            func.block_ids()
+                .flat_map(|id| {
+                    let block = func.block(id);
+                    let entry = if id == BlockId(0) {
+                        [
+                            func.slots
+                                .iter()
+                                .enumerate()
+                                .map(|(i, slot)| {
+                                    format!(
+                                        "  %s{} = alloca [{} x i8], align {}",
+                                        i, slot.size, slot.align
+                                    )
+                                })
+                                .collect::<Vec<String>>(),
+                            params.iter().map(|(_, widen)| widen.clone()).collect(),
+                        ]
+                        .concat()
+                    } else {
+                        vec![]
+                    };
+                    let (insts, rest) = block
+                        .insts
+                        .split_at(tail_calls[id.0 as usize].unwrap_or(block.insts.len()));
+                    [
+                        vec![format!("{}:", id)],
+                        entry,
+                        insts
+                            .iter()
+                            .flat_map(|inst| match inst {
+                                Inst::Phi { dst, args }
+                                    if args.iter().any(|(pred, _)| returns_early(pred)) =>
+                                {
+                                    let args = args
+                                        .iter()
+                                        .filter(|(pred, _)| !returns_early(pred))
+                                        .copied()
+                                        .collect::<Vec<_>>();
+                                    // no predecessor is left when all of them return early
+                                    let inst = if args.is_empty() {
+                                        Inst::Copy {
+                                            dst: *dst,
+                                            src: Operand::Const(0),
+                                        }
+                                    } else {
+                                        Inst::Phi { dst: *dst, args }
+                                    };
+                                    self.gen_inst(module, &mut temps, &inst)
+                                }
+                                _ => self.gen_inst(module, &mut temps, inst),
+                            })
+                            .collect(),
+                        if rest.is_empty() {
+                            Self::gen_terminator(func, &mut temps, &block.term)
+                        } else {
+                            let result = tail_call::returned_value(func, id)
+                                .expect("tail call returns its result");
+                            let term = Terminator::Return(Operand::Value(result));
+                            self.gen_tail_call(module, func, &mut temps, rest, &term)
+                        },
+                    ]
+                    .concat()
                 })
-                .collect::<Vec<String>>(),
-            self.gen_node(func.body.as_ref().unwrap(), options),
-            vec![
-                format!("  ret {} 0", Self::gen_type(*return_ty.clone())), // default return value
-                "}".to_string(),
-            ],
+                .collect(),
+            vec!["}".to_string()],
         ]
         .concat()
         .join("\n")
     }
 
-    fn gen_node(&self, node: &Node, options: &mut Options) -> Vec<String> {
-        match node.nt {
-            NodeType::DefVar => {
-                return node
-                    .children
-                    .iter()
-                    .map(|node| {
-                        [
-                            vec![format!(
-                                "  %{} = alloca {}, align {}",
-                                options.register_from_offset(
-                                    node.lhs
-                                        .as_ref()
-                                        .unwrap_or_else(|| {
-                                            self.error_logger.print_error_position(
-                                                node.token.as_ref().unwrap().pos,
-                                                &format!("lhs is None:\n{:?}", node),
-                                            );
-                                            unreachable!()
-                                        })
-                                        .offset
-                                        .unwrap()
-                                ),
-                                Self::gen_type(node.resolve_type().unwrap()),
-                                node.resolve_type().unwrap().size_of(),
-                            )],
-                            self.gen_node(node, options),
-                        ]
-                        .concat()
-                    })
-                    .collect::<Vec<Vec<String>>>()
-                    .concat();
-            }
-            NodeType::CallFunc => {
-                let Some(return_ty) = &node.cty else {
-                    panic!("{:?}", node.cty)
-                };
-                let args_types = reserved_functions()
-                    .get(node.global_name.as_str())
-                    .map(|v| {
-                        let Identifier::Static(Type::Func(args, _)) = v else {
-                            unreachable!()
-                        };
-                        args.iter()
-                            .map(|arg_type| Self::gen_type_with_opaque_ptr(arg_type.clone()))
-                            .collect::<Vec<String>>()
-                    })
-                    .unwrap_or(
-                        node.args
-                            .iter()
-                            .map(|arg| {
-                                Self::gen_type_with_opaque_ptr(arg.resolve_type().unwrap_or_else({
-                                    || {
-                                        self.error_logger.print_error_position(
-                                            arg.token.as_ref().unwrap().pos,
-                                            &format!("cannot resolve type: {:?}", arg),
-                                        );
-                                        unreachable!()
-                                    }
-                                }))
-                            })
-                            .collect::<Vec<String>>(),
-                    );
-                let args = node
-                    .args
-                    .iter()
-                    .map(|node| self.gen_node(node, options))
-                    .collect::<Vec<Vec<String>>>()
-                    .concat();
-                let args_passing = node
-                    .args
-                    .iter()
-                    .enumerate()
-                    .map(|(i, arg)| {
-                        format!(
-                            "{} noundef {}",
-                            args_types.get(node.args.len() - i - 1).unwrap_or(
-                                &Self::gen_type_with_opaque_ptr(arg.resolve_type().unwrap())
-                            ),
-                            options.register_queue.pop().unwrap(),
-                        )
-                    })
-                    .rev()
+    fn ir_type(ty: Ty) -> &'static str {
+        match ty {
+            Ty::I8 => "i8",
+            Ty::I32 => "i32",
+            Ty::Ptr => "ptr",
+        }
+    }
+
+    fn value(value: Value) -> String {
+        format!("%v{}", value.0)
+    }
+
+    fn operand(operand: &Operand) -> String {
+        match operand {
+            Operand::Value(value) => Self::value(*value),
+            Operand::Const(value) => value.to_string(),
+        }
+    }
+
+    /// Converts a value of the type to the 64-bit integer which values are kept as.
+    fn widen(dst: &str, ty: Ty, src: &str) -> String {
+        match ty {
+            Ty::Ptr => format!("  {} = ptrtoint ptr {} to i64", dst, src),
+            _ => format!("  {} = sext {} {} to i64", dst, Self::ir_type(ty), src),
+        }
+    }
+
+    /// Converts a 64-bit integer to the type, and returns the instructions and the converted value.
+    fn narrow(temps: &mut Temps, ty: Ty, operand: &Operand) -> (Vec<String>, String) {
+        let temp = temps.next();
+        let inst = match ty {
+            Ty::Ptr => format!(
+                "  {} = inttoptr i64 {} to ptr",
+                temp,
+                Self::operand(operand)
+            ),
+            _ => format!(
+                "  {} = trunc i64 {} to {}",
+                temp,
+                Self::operand(operand),
+                Self::ir_type(ty)
+            ),
+        };
+        (vec![inst], temp)
+    }
+
+    /// Returns the instructions computing the pointer of the address, and the pointer.
+    fn pointer(temps: &mut Temps, addr: &Address) -> (Vec<String>, String) {
+        match addr {
+            Address::Slot(slot) => (vec![], format!("%s{}", slot.0)),
+            Address::Ptr(operand) => Self::narrow(temps, Ty::Ptr, operand),
+        }
+    }
+
+    fn gen_inst(&self, module: &Module, temps: &mut Temps, inst: &Inst) -> Vec<String> {
+        match inst {
+            Inst::Phi { dst, args } => vec![format!(
+                "  {} = phi i64 {}",
+                Self::value(*dst),
+                args.iter()
+                    .map(|(pred, arg)| format!("[ {}, %{} ]", Self::operand(arg), pred))
                     .collect::<Vec<String>>()
-                    .join(", ");
-                return [
-                    args,
-                    vec![format!(
-                        "  %{} = call {} ({}) @{}({})",
-                        options.new_register(),
-                        Self::gen_type(return_ty.clone()),
-                        [args_types, vec!["...".to_string()]].concat().join(", "),
-                        node.global_name,
-                        args_passing,
-                    )],
-                    // push the return value
+                    .join(", ")
+            )],
+            Inst::Copy { dst, src } => vec![format!(
+                "  {} = add i64 {}, 0",
+                Self::value(*dst),
+                Self::operand(src)
+            )],
+            Inst::Binary { dst, op, lhs, rhs } if op.is_comparison() => {
+                let temp = temps.next();
+                vec![
+                    format!(
+                        "  {} = icmp {} i64 {}, {}",
+                        temp,
+                        match op {
+                            BinOp::Eq => "eq",
+                            BinOp::Ne => "ne",
+                            BinOp::Lt => "slt",
+                            _ => "sle",
+                        },
+                        Self::operand(lhs),
+                        Self::operand(rhs)
+                    ),
+                    format!("  {} = zext i1 {} to i64", Self::value(*dst), temp),
                 ]
-                .concat();
             }
-            NodeType::Block => {
-                return node
-                    .children
-                    .iter()
-                    .map(|node| self.gen_node(node, options))
-                    .collect::<Vec<Vec<String>>>()
-                    .concat();
+            Inst::Binary { dst, op, lhs, rhs } => vec![format!(
+                "  {} = {} i64 {}, {}",
+                Self::value(*dst),
+                match op {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "mul",
+                    BinOp::Div => "sdiv",
+                    BinOp::Mod => "srem",
+                    BinOp::Shl => "shl",
+                    BinOp::Shr => "ashr",
+                    BinOp::And => "and",
+                    BinOp::Xor => "xor",
+                    _ => "or",
+                },
+                Self::operand(lhs),
+                Self::operand(rhs)
+            )],
+            Inst::Not { dst, src } => vec![format!(
+                "  {} = xor i64 {}, -1",
+                Self::value(*dst),
+                Self::operand(src)
+            )],
+            Inst::Extend {
+                dst,
+                ty: Ty::Ptr,
+                src,
+            } => vec![format!(
+                "  {} = add i64 {}, 0",
+                Self::value(*dst),
+                Self::operand(src)
+            )],
+            Inst::Extend { dst, ty, src } => {
+                let (mut insts, narrowed) = Self::narrow(temps, *ty, src);
+                insts.push(Self::widen(&Self::value(*dst), *ty, &narrowed));
+                insts
             }
-            NodeType::Return => {
-                let lhs = self.gen_node(node.lhs.as_ref().unwrap(), options);
-                *options.register_number += 1;
-                return [
-                    lhs,
-                    vec![format!(
-                        "  ret {} {}",
-                        Self::gen_type(node.resolve_type().unwrap()),
-                        options.register_queue.pop().unwrap(),
-                    )],
-                ]
-                .concat();
+            Inst::SlotAddr { dst, slot } => vec![format!(
+                "  {} = ptrtoint ptr %s{} to i64",
+                Self::value(*dst),
+                slot.0
+            )],
+            Inst::SymbolAddr { dst, symbol } => vec![format!(
+                "  {} = ptrtoint ptr {} to i64",
+                Self::value(*dst),
+                symbol
+            )],
+            Inst::Load {
+                dst,
+                ty: Ty::Ptr,
+                addr,
+            } => {
+                let (mut insts, pointer) = Self::pointer(temps, addr);
+                insts.push(format!(
+                    "  {} = load i64, ptr {}, align 8",
+                    Self::value(*dst),
+                    pointer
+                ));
+                insts
             }
-            NodeType::Num => {
-                options.register_queue.push(node.value.unwrap().to_string());
-                return vec![];
+            Inst::Load { dst, ty, addr } => {
+                let (mut insts, pointer) = Self::pointer(temps, addr);
+                let temp = temps.next();
+                insts.push(format!(
+                    "  {} = load {}, ptr {}, align {}",
+                    temp,
+                    Self::ir_type(*ty),
+                    pointer,
+                    ty.size()
+                ));
+                insts.push(Self::widen(&Self::value(*dst), *ty, &temp));
+                insts
             }
-            NodeType::Assign => {
-                let rhs = self.gen_node(node.rhs.as_ref().unwrap(), options);
-                let lhs = match node.lhs.as_ref().unwrap().nt {
-                    NodeType::LocalVar => format!(
-                        "%{}",
-                        options.register_from_offset(
-                            node.lhs
-                                .as_ref()
-                                .unwrap()
-                                .offset
-                                .unwrap_or_else(|| panic!("{:?}", node)),
-                        )
-                    ),
-                    NodeType::GlobalVar => format!("@{}", node.lhs.as_ref().unwrap().global_name,),
-                    NodeType::Deref => {
-                        let lhs = self.gen_node(node.lhs.as_ref().unwrap(), options);
-                        return [
-                            rhs,
-                            lhs,
-                            vec![format!(
-                                "  store {} {}, ptr {}, align {}",
-                                Self::gen_type(node.rhs.as_ref().unwrap().resolve_type().unwrap()),
-                                options.register_queue.pop().unwrap(),
-                                options.register_queue.pop().unwrap(),
-                                node.lhs.as_ref().unwrap().resolve_type().unwrap().size_of(),
-                            )],
-                        ]
-                        .concat();
-                    }
+            Inst::Store { ty, addr, value } => {
+                let (mut insts, pointer) = Self::pointer(temps, addr);
+                let (ty_name, value) = match ty {
+                    Ty::Ptr => ("i64", Self::operand(value)),
                     _ => {
-                        self.error_logger.print_error_position(
-                            node.lhs.as_ref().unwrap().token.clone().unwrap().pos,
-                            &format!("unexpected node: {:?}", node.lhs.as_ref().unwrap().nt),
-                        );
-                        unreachable!()
+                        let (narrowing, narrowed) = Self::narrow(temps, *ty, value);
+                        insts.extend(narrowing);
+                        (Self::ir_type(*ty), narrowed)
                     }
                 };
-                return [
-                    rhs,
-                    vec![format!(
-                        "  store {} {}, ptr {}, align {}",
-                        Self::gen_type(node.rhs.as_ref().unwrap().resolve_type().unwrap()),
-                        options.register_queue.pop().unwrap(),
-                        lhs,
-                        node.lhs.as_ref().unwrap().resolve_type().unwrap().size_of(),
-                    )],
-                ]
-                .concat();
-            }
-            NodeType::Addr => {
-                options
-                    .register_queue
-                    .push(node.lhs.as_ref().unwrap().dest.to_string());
-                return vec![];
-            }
-            NodeType::Deref => match node.lhs.as_ref().unwrap().nt {
-                NodeType::Add => {
-                    let rhs =
-                        self.gen_node(node.lhs.as_ref().unwrap().rhs.as_ref().unwrap(), options);
-                    let lhs =
-                        self.gen_node(node.lhs.as_ref().unwrap().lhs.as_ref().unwrap(), options);
-                    let lhs_register = options.register_queue.pop().unwrap();
-                    let rhs_register = options.register_queue.pop().unwrap();
-                    return [
-                        rhs,
-                        lhs,
-                        vec![format!(
-                            "  %{} = getelementptr inbounds {}, {} {}, i64 0, i64 {}",
-                            options.new_register(),
-                            Self::gen_type(
-                                node.lhs
-                                    .as_ref()
-                                    .unwrap()
-                                    .lhs
-                                    .as_ref()
-                                    .unwrap()
-                                    .resolve_type()
-                                    .unwrap()
-                            ),
-                            Self::gen_type_with_opaque_ptr(
-                                node.lhs
-                                    .as_ref()
-                                    .unwrap()
-                                    .lhs
-                                    .as_ref()
-                                    .unwrap()
-                                    .resolve_type()
-                                    .unwrap()
-                            ),
-                            lhs_register,
-                            rhs_register,
-                        )],
-                    ]
-                    .concat();
-                }
-                NodeType::LocalVar => {
-                    return [
-                        self.gen_node(node.lhs.as_ref().unwrap(), options),
-                        vec![format!(
-                            "  %{} = getelementptr inbounds {}, ptr {}, i64 0, i64 0",
-                            options.new_register(),
-                            Self::gen_type(node.lhs.as_ref().unwrap().resolve_type().unwrap()),
-                            options.register_queue.pop().unwrap(),
-                        )],
-                    ]
-                    .concat();
-                }
-                _ => unreachable!(),
-            },
-            NodeType::LocalVar => {
-                return vec![format!(
-                    "  %{} = load {}, ptr %{}, align {}",
-                    options.new_register(),
-                    Self::gen_type(node.resolve_type().unwrap()),
-                    options.register_from_offset(node.offset.unwrap()),
-                    Self::align(&node.resolve_type().unwrap()),
-                )];
+                insts.push(format!(
+                    "  store {} {}, ptr {}, align {}",
+                    ty_name,
+                    value,
+                    pointer,
+                    ty.size()
+                ));
+                insts
             }
-            NodeType::GlobalVar => {
-                return vec![format!(
-                    "  %{} = load {}, ptr @{}, align {}",
-                    options.new_register(),
-                    Self::gen_type(node.resolve_type().unwrap()),
-                    node.global_name,
-                    node.resolve_type().unwrap().size_of(),
-                )];
+            Inst::Call { dst, callee, args } => {
+                let (mut insts, result) = Self::gen_call(module, temps, "", callee, args);
+                let ret = module.signature(callee).unwrap().ret;
+                insts.push(Self::widen(&Self::value(*dst), ret, &result));
+                insts
             }
-            NodeType::BitNot => {
-                let lhs = self.gen_node(node.lhs.as_ref().unwrap(), options);
-                return [
-                    lhs,
-                    vec![format!(
-                        "  %{} = xor {} {}, -1",
-                        options.new_register(),
-                        Self::gen_type(node.lhs.as_ref().unwrap().resolve_type().unwrap()),
-                        options.register_queue.pop().unwrap(),
-                    )],
+        }
+    }
+
+    /// Returns the instructions of the call, whose kind is such as `tail `,
+    /// and the result of the callee's return type.
+    fn gen_call(
+        module: &Module,
+        temps: &mut Temps,
+        kind: &str,
+        callee: &str,
+        args: &[(Ty, Operand)],
+    ) -> (Vec<String>, String) {
+        let signature = module.signature(callee).unwrap();
+        let mut insts = Vec::new();
+        let args = args
+            .iter()
+            .map(|(ty, arg)| {
+                let (narrowing, narrowed) = Self::narrow(temps, *ty, arg);
+                insts.extend(narrowing);
+                format!("{} noundef {}", Self::ir_type(*ty), narrowed)
+            })
+            .collect::<Vec<String>>();
+        let function_type = if signature.is_variadic {
+            format!(
+                "{} ({}) ",
+                Self::ir_type(signature.ret),
+                signature
+                    .params
+                    .iter()
+                    .map(|ty| Self::ir_type(*ty))
+                    .chain(["..."])
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )
+        } else {
+            format!("{} ", Self::ir_type(signature.ret))
+        };
+        let temp = temps.next();
+        insts.push(format!(
+            "  {} = {}call {}@{}({})",
+            temp,
+            kind,
+            function_type,
+            callee,
+            args.join(", ")
+        ));
+        (insts, temp)
+    }
+
+    /// Returns the call in tail position, which begins the rest of the block, and the return.
+    /// The result of the callee is returned as it is with `musttail` when the types of the call
+    /// are those of the function, or converted by the rest of the block with `tail` otherwise.
+    fn gen_tail_call(
+        &self,
+        module: &Module,
+        func: &Function,
+        temps: &mut Temps,
+        rest: &[Inst],
+        term: &Terminator,
+    ) -> Vec<String> {
+        let Some((Inst::Call { dst, callee, args }, after)) = rest.split_first() else {
+            unreachable!("call expected")
+        };
+        let ret = module.signature(callee).unwrap().ret;
+        let is_exact = ret == func.signature.ret
+            && args
+                .iter()
+                .map(|(ty, _)| *ty)
+                .eq(func.signature.params.iter().copied());
+        let kind = if is_exact { "musttail " } else { "tail " };
+        let (mut insts, result) = Self::gen_call(module, temps, kind, callee, args);
+        if is_exact {
+            insts.push(format!("  ret {} {}", Self::ir_type(ret), result));
+            return insts;
+        }
+        insts.push(Self::widen(&Self::value(*dst), ret, &result));
+        for inst in after {
+            insts.extend(self.gen_inst(module, temps, inst));
+        }
+        insts.extend(Self::gen_terminator(func, temps, term));
+        insts
+    }
+
+    fn gen_terminator(func: &Function, temps: &mut Temps, term: &Terminator) -> Vec<String> {
+        match term {
+            Terminator::Jump(target) => vec![format!("  br label %{}", target)],
+            Terminator::Branch { cond, then, els } => {
+                let temp = temps.next();
+                vec![
+                    format!("  {} = icmp ne i64 {}, 0", temp, Self::operand(cond)),
+                    format!("  br i1 {}, label %{}, label %{}", temp, then, els),
                 ]
-                .concat();
             }
-            NodeType::If => {
-                let cond = self.gen_node(node.cond.as_ref().unwrap(), options);
-                let cond_result_register = options.register_queue.pop().unwrap();
-                let then_register = options.new_register();
-                let then = self.gen_node(node.then.as_ref().unwrap(), options);
-                let else_register = options.new_register();
-                let els = node
-                    .els
-                    .as_ref()
-                    .map(|n| self.gen_node(n, options))
-                    .unwrap_or_default();
-                let end_register = options.new_register();
-                return [
-                    cond,
-                    vec![format!(
-                        "  br i1 {}, label %{}, label %{}",
-                        cond_result_register, then_register, else_register
-                    )],
-                    vec![format!("\n{}:", then_register)],
-                    then,
-                    vec![format!("  br label %{}", end_register)],
-                    vec![format!("\n{}:", else_register)],
-                    els,
-                    vec![format!("  br label %{}", end_register)],
-                    vec![format!("\n{}:", end_register)],
-                ]
-                .concat();
+            Terminator::Return(value) => {
+                let ty = func.signature.ret;
+                let (mut insts, narrowed) = Self::narrow(temps, ty, value);
+                insts.push(format!("  ret {} {}", Self::ir_type(ty), narrowed));
+                insts
             }
-            _ => {}
         }
+    }
+}
 
-        let lhs = self.gen_node(
-            node.rhs.as_ref().unwrap_or_else(|| panic!("{:?}", node)),
-            options,
-        );
-        let rhs = self.gen_node(
-            node.lhs.as_ref().unwrap_or_else(|| panic!("{:?}", node)),
-            options,
-        );
-
-        let operation = match node.nt {
-            NodeType::Add => "add nsw",
-            NodeType::Sub => "sub nsw",
-            NodeType::Mul => "mul nsw",
-            NodeType::Div => "sdiv",
-            NodeType::Mod => "srem",
-            NodeType::Eq => "icmp eq",
-            NodeType::Ne => "icmp ne",
-            NodeType::Lt => "icmp slt",
-            NodeType::Le => "icmp sle",
-            NodeType::BitAnd => "and",
-            NodeType::BitXor => "xor",
-            NodeType::BitOr => "or",
-            NodeType::BitLeft => "shl",
-            NodeType::BitRight => "shr",
-            _ => "unknown",
-        };
-        let rhs_register = options
-            .register_queue
-            .pop()
-            .expect("register queue is empty");
-        let lhs_register = options
-            .register_queue
-            .pop()
-            .expect("register queue is empty");
-        [
-            lhs,
-            rhs,
-            vec![match node.nt {
-                NodeType::Add
-                | NodeType::Sub
-                | NodeType::Mul
-                | NodeType::Div
-                | NodeType::Mod
-                | NodeType::Eq
-                | NodeType::Ne
-                | NodeType::Lt
-                | NodeType::Le
-                | NodeType::BitAnd
-                | NodeType::BitXor
-                | NodeType::BitOr
-                | NodeType::BitLeft
-                | NodeType::BitRight => {
-                    format!(
-                        "  %{} = {} {} {}, {}",
-                        options.new_register(),
-                        operation,
-                        Self::gen_type(node.resolve_type().unwrap_or_else(|| {
-                            self.error_logger.print_error_position(
-                                node.token.as_ref().unwrap().pos,
-                                &format!("cannot resolve type: {:?}", node.lhs),
-                            );
-                            unreachable!()
-                        })),
-                        rhs_register,
-                        lhs_register,
-                    )
-                }
+/// Names the temporaries of a function, which hold the values converted from and to 64 bits.
+#[derive(Default)]
+struct Temps(usize);
 
-                _ => {
-                    self.error_logger.print_error_position(
-                        node.token.as_ref().unwrap().pos,
-                        &format!("unexpected node: {:?}", node.nt),
-                    );
-                    unreachable!();
-                }
-            }],
-        ]
-        .concat()
+impl Temps {
+    fn next(&mut self) -> String {
+        self.0 += 1;
+        format!("%t{}", self.0)
     }
 }
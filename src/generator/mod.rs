@@ -1,41 +1,138 @@
 pub mod aarch64;
 pub mod llvm;
+pub mod regalloc;
+pub mod tail_call;
 pub mod x86_64;
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Os {
     Linux,
     MacOS,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Arch {
     Aarch64,
     X86_64,
 }
 
-use crate::{ast::ProgramAst, error::ErrorPrinter};
+/// The machine to generate code for, given by `--target`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub arch: Arch,
+    pub os: Os,
+}
+
+impl Target {
+    pub const TRIPLES: [&'static str; 4] = [
+        "x86_64-linux-gnu",
+        "aarch64-linux-gnu",
+        "x86_64-apple-darwin",
+        "aarch64-apple-darwin",
+    ];
+
+    pub fn from_triple(triple: &str) -> Option<Self> {
+        let (arch, os) = match triple {
+            "x86_64-linux-gnu" => (Arch::X86_64, Os::Linux),
+            "aarch64-linux-gnu" => (Arch::Aarch64, Os::Linux),
+            "x86_64-apple-darwin" => (Arch::X86_64, Os::MacOS),
+            "aarch64-apple-darwin" => (Arch::Aarch64, Os::MacOS),
+            _ => return None,
+        };
+        Some(Self { arch, os })
+    }
+
+    /// Returns the target this compiler is running on.
+    pub fn host() -> Self {
+        #[cfg(target_os = "linux")]
+        let os = Os::Linux;
+        #[cfg(target_os = "macos")]
+        let os = Os::MacOS;
+        #[cfg(target_arch = "x86_64")]
+        let arch = Arch::X86_64;
+        #[cfg(target_arch = "aarch64")]
+        let arch = Arch::Aarch64;
+        Self { arch, os }
+    }
+
+    /// Returns the name accepted by `--target`, which is also the prefix of the gcc cross toolchain.
+    pub fn triple(&self) -> &'static str {
+        match (self.arch, self.os) {
+            (Arch::X86_64, Os::Linux) => "x86_64-linux-gnu",
+            (Arch::Aarch64, Os::Linux) => "aarch64-linux-gnu",
+            (Arch::X86_64, Os::MacOS) => "x86_64-apple-darwin",
+            (Arch::Aarch64, Os::MacOS) => "aarch64-apple-darwin",
+        }
+    }
+
+    pub fn llvm_triple(&self) -> &'static str {
+        match (self.arch, self.os) {
+            (Arch::X86_64, Os::Linux) => "x86_64-unknown-linux-gnu",
+            (Arch::Aarch64, Os::Linux) => "aarch64-unknown-linux-gnu",
+            (Arch::X86_64, Os::MacOS) => "x86_64-apple-macosx",
+            (Arch::Aarch64, Os::MacOS) => "arm64-apple-macosx",
+        }
+    }
+
+    /// Returns the number of the integer arguments passed in registers.
+    pub fn arg_registers(&self) -> usize {
+        match self.arch {
+            Arch::X86_64 => 6,
+            Arch::Aarch64 => 8,
+        }
+    }
+
+    pub fn llvm_data_layout(&self) -> &'static str {
+        match (self.arch, self.os) {
+            (Arch::X86_64, Os::Linux) => {
+                "e-m:e-p270:32:32-p271:32:32-p272:64:64-i64:64-i128:128-f80:128-n8:16:32:64-S128"
+            }
+            (Arch::X86_64, Os::MacOS) => {
+                "e-m:o-p270:32:32-p271:32:32-p272:64:64-i64:64-i128:128-f80:128-n8:16:32:64-S128"
+            }
+            (Arch::Aarch64, Os::Linux) => "e-m:e-i8:8:32-i16:16:32-i64:64-i128:128-n32:64-S128",
+            (Arch::Aarch64, Os::MacOS) => "e-m:o-i64:64-i128:128-n32:64-S128",
+        }
+    }
+}
+
+use crate::ir::Module;
+use crate::ir::opt::OptOptions;
 
 pub trait Assembly {
     fn to_string(&self, target_os: Os) -> String;
 }
 
+/// Generates the native code of a program lowered to the mid-level IR.
 pub trait Generator {
-    fn generate(&self, ast: ProgramAst) -> Box<dyn Assembly>;
-}
-
-pub fn new<'a>(
-    target_arch: Arch,
-    target_os: Os,
-    error_printer: &'a ErrorPrinter,
-) -> Box<dyn Generator + 'a> {
-    match target_arch {
-        Arch::Aarch64 => Box::new(aarch64::generator::AsmGenerator::new(
-            error_printer,
-            target_os,
-        )),
-        Arch::X86_64 => Box::new(x86_64::generator::AsmGenerator::new(
-            error_printer,
-            target_os,
-        )),
+    fn generate(&self, module: &Module) -> Box<dyn Assembly>;
+}
+
+/// Returns the generator of the target, which runs the backend passes enabled in the options.
+pub fn new(target: Target, options: &OptOptions) -> Box<dyn Generator> {
+    let target_os = target.os;
+    match target.arch {
+        Arch::Aarch64 => Box::new(aarch64::generator::AsmGenerator::new(target_os, options)),
+        Arch::X86_64 => Box::new(x86_64::generator::AsmGenerator::new(target_os, options)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_triples() {
+        for triple in Target::TRIPLES {
+            assert_eq!(Target::from_triple(triple).unwrap().triple(), triple);
+        }
+        assert_eq!(
+            Target::from_triple("aarch64-linux-gnu"),
+            Some(Target {
+                arch: Arch::Aarch64,
+                os: Os::Linux
+            })
+        );
+        assert_eq!(Target::from_triple("riscv64-linux-gnu"), None);
     }
 }
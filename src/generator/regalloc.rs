@@ -0,0 +1,331 @@
+//! Linear-scan register allocation over the live intervals of the values of a function,
+//! shared by the native backends, which keep the values given no register in stack homes.
+
+use crate::ir::{BlockId, Function, Inst, Value};
+use std::collections::HashSet;
+
+/// Where a value is kept for its whole lifetime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location<R> {
+    Reg(R),
+    /// The index of the 8-byte home of a spilled value.
+    Stack(usize),
+}
+
+/// The registers which the allocator can give to the values, apart from the scratch
+/// registers the backend computes in. The values living across calls get callee-saved ones.
+pub struct Registers<'a, R> {
+    pub caller_saved: &'a [R],
+    pub callee_saved: &'a [R],
+}
+
+impl<R: 'static> Registers<'_, R> {
+    /// No registers, to keep every value on the stack.
+    pub const NONE: Registers<'static, R> = Registers {
+        caller_saved: &[],
+        callee_saved: &[],
+    };
+}
+
+#[derive(Debug)]
+pub struct Allocation<R> {
+    locations: Vec<Option<Location<R>>>,
+    /// The number of stack homes of the values spilled.
+    pub spill_count: usize,
+    /// The callee-saved registers given to any value, which the prologue has to save.
+    pub callee_saved: Vec<R>,
+}
+
+impl<R: Copy> Allocation<R> {
+    /// Returns the location of a value, which is defined for every value defined in the function.
+    pub fn location(&self, value: Value) -> Location<R> {
+        self.locations[value.0 as usize].unwrap()
+    }
+}
+
+/// The positions in the function between which a value is live, both included.
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    value: Value,
+    start: usize,
+    end: usize,
+    crosses_call: bool,
+}
+
+/// The position where the instruction numbered `index` reads its operands.
+fn use_position(index: usize) -> usize {
+    2 * index
+}
+
+/// The position where the instruction numbered `index` defines its result, after reading
+/// the operands, so that the result can take the register of an operand used for the last time.
+fn def_position(index: usize) -> usize {
+    2 * index + 1
+}
+
+/// Numbers the instructions in the order the blocks are emitted,
+/// and returns the interval covering all the live ranges of each value.
+/// The parameters are defined at 0, then a block starts with its phis, and ends with
+/// the operands given to the phis of its successors and its terminator.
+fn live_intervals(func: &Function) -> Vec<Interval> {
+    let mut starts = vec![0; func.blocks.len()];
+    let mut ends = vec![0; func.blocks.len()];
+    let mut calls = vec![];
+    let mut index = 0;
+    for id in func.block_ids() {
+        index += 1;
+        starts[id.0 as usize] = index;
+        for inst in func.block(id).insts.iter().filter(|inst| !inst.is_phi()) {
+            index += 1;
+            if let Inst::Call { .. } = inst {
+                calls.push(index);
+            }
+        }
+        index += 1;
+        ends[id.0 as usize] = index;
+    }
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; func.value_count as usize];
+    let mut cover = |value: Value, position: usize| {
+        let range = &mut ranges[value.0 as usize];
+        *range = Some(match *range {
+            Some((start, end)) => (start.min(position), end.max(position)),
+            None => (position, position),
+        });
+    };
+    for param in &func.params {
+        cover(*param, def_position(0));
+    }
+    for id in func.block_ids() {
+        let block = func.block(id);
+        let mut index = starts[id.0 as usize];
+        for inst in &block.insts {
+            if inst.is_phi() {
+                cover(inst.dst().unwrap(), def_position(starts[id.0 as usize]));
+                continue;
+            }
+            index += 1;
+            for value in inst.operands().iter().filter_map(|op| op.as_value()) {
+                cover(value, use_position(index));
+            }
+            if let Some(dst) = inst.dst() {
+                cover(dst, def_position(index));
+            }
+        }
+        for value in phi_uses(func, id)
+            .chain(block.term.operands())
+            .filter_map(|op| op.as_value())
+        {
+            cover(value, use_position(ends[id.0 as usize]));
+        }
+    }
+    for (id, (live_in, live_out)) in live_sets(func).into_iter().enumerate() {
+        for value in live_in {
+            cover(value, use_position(starts[id]));
+        }
+        for value in live_out {
+            cover(value, use_position(ends[id]));
+        }
+    }
+    ranges
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, range)| {
+            range.map(|(start, end)| Interval {
+                value: Value(i as u32),
+                start,
+                end,
+                // the registers of the values read by a call are not needed after it
+                crosses_call: calls
+                    .iter()
+                    .any(|call| start < use_position(*call) && def_position(*call) < end),
+            })
+        })
+        .collect()
+}
+
+/// Returns the operands which the block gives to the phis of its successors.
+fn phi_uses(func: &Function, id: BlockId) -> impl Iterator<Item = crate::ir::Operand> + '_ {
+    let mut successors = func.block(id).term.successors();
+    successors.dedup();
+    successors
+        .into_iter()
+        .flat_map(|succ| func.block(succ).phis())
+        .filter_map(move |phi| match phi {
+            Inst::Phi { args, .. } => args
+                .iter()
+                .find(|(pred, _)| *pred == id)
+                .map(|(_, arg)| *arg),
+            _ => None,
+        })
+}
+
+/// Returns the values live at the start and at the end of each block.
+fn live_sets(func: &Function) -> Vec<(HashSet<Value>, HashSet<Value>)> {
+    // the values used before being defined in each block, and the values defined in it
+    let (uses, defs): (Vec<HashSet<Value>>, Vec<HashSet<Value>>) = func
+        .block_ids()
+        .map(|id| {
+            let block = func.block(id);
+            let mut uses = HashSet::new();
+            let mut defs = HashSet::new();
+            for inst in &block.insts {
+                if !inst.is_phi() {
+                    uses.extend(
+                        inst.operands()
+                            .iter()
+                            .filter_map(|op| op.as_value())
+                            .filter(|value| !defs.contains(value)),
+                    );
+                }
+                defs.extend(inst.dst());
+            }
+            uses.extend(
+                phi_uses(func, id)
+                    .chain(block.term.operands())
+                    .filter_map(|op| op.as_value())
+                    .filter(|value| !defs.contains(value)),
+            );
+            (uses, defs)
+        })
+        .unzip();
+    let mut live = vec![(HashSet::new(), HashSet::new()); func.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in func.block_ids().collect::<Vec<_>>().into_iter().rev() {
+            let i = id.0 as usize;
+            let live_out = func
+                .block(id)
+                .term
+                .successors()
+                .into_iter()
+                .flat_map(|succ| {
+                    let succ = succ.0 as usize;
+                    // the phis are defined on entry to the successor
+                    live[succ]
+                        .0
+                        .difference(&defs[succ])
+                        .copied()
+                        .collect::<Vec<_>>()
+                })
+                .collect::<HashSet<Value>>();
+            let live_in = uses[i]
+                .iter()
+                .copied()
+                .chain(live_out.difference(&defs[i]).copied())
+                .collect::<HashSet<Value>>();
+            if live_in != live[i].0 || live_out != live[i].1 {
+                live[i] = (live_in, live_out);
+                changed = true;
+            }
+        }
+    }
+    live
+}
+
+/// Gives each value a register of the set, or a stack home if none is free during its interval,
+/// spilling the value whose interval ends last.
+pub fn allocate<R: Copy + PartialEq>(func: &Function, registers: &Registers<R>) -> Allocation<R> {
+    let mut intervals = live_intervals(func);
+    intervals.sort_by_key(|interval| (interval.start, interval.value));
+    let mut allocation = Allocation {
+        locations: vec![None; func.value_count as usize],
+        spill_count: 0,
+        callee_saved: vec![],
+    };
+    let spill = |allocation: &mut Allocation<R>, value: Value| {
+        allocation.locations[value.0 as usize] = Some(Location::Stack(allocation.spill_count));
+        allocation.spill_count += 1;
+    };
+    let mut active: Vec<(Interval, R)> = vec![];
+    for interval in intervals {
+        active.retain(|(other, _)| other.end >= interval.start);
+        // the caller-saved registers are preferred, which the prologue does not have to save
+        let candidates = if interval.crosses_call {
+            registers.callee_saved.to_vec()
+        } else {
+            [registers.caller_saved, registers.callee_saved].concat()
+        };
+        let free = candidates
+            .iter()
+            .find(|reg| active.iter().all(|(_, used)| used != *reg));
+        let reg = if let Some(reg) = free {
+            *reg
+        } else if let Some(i) = (0..active.len())
+            .filter(|i| candidates.contains(&active[*i].1))
+            .max_by_key(|i| active[*i].0.end)
+            .filter(|i| active[*i].0.end > interval.end)
+        {
+            let (spilled, reg) = active.remove(i);
+            spill(&mut allocation, spilled.value);
+            reg
+        } else {
+            spill(&mut allocation, interval.value);
+            continue;
+        };
+        allocation.locations[interval.value.0 as usize] = Some(Location::Reg(reg));
+        if registers.callee_saved.contains(&reg) && !allocation.callee_saved.contains(&reg) {
+            allocation.callee_saved.push(reg);
+        }
+        active.push((interval, reg));
+    }
+    allocation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompileOptions, Compiler};
+
+    fn lower(code: &str) -> Function {
+        let compiler = Compiler::new(code, CompileOptions::default());
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        compiler.lower(&ast).functions.remove(0)
+    }
+
+    #[test]
+    fn test_allocate() {
+        let func = lower(
+            "int f(int a, int b) { int s; s = 0; while (a < b) { s = s + g(a); a = a + 1; } return s; } int g(int x) { return x; }",
+        );
+        let registers = Registers {
+            caller_saved: &["r10"],
+            callee_saved: &["rbx", "r12"],
+        };
+        let allocation = allocate(&func, &registers);
+        let intervals = live_intervals(&func);
+        // `a`, `b` and `s` live across the call, and only two callee-saved registers are left
+        assert_eq!(
+            intervals.iter().filter(|i| i.crosses_call).count(),
+            3,
+            "{}",
+            func
+        );
+        assert_eq!(allocation.spill_count, 1);
+        assert_eq!(allocation.callee_saved, vec!["rbx", "r12"]);
+        for a in &intervals {
+            let location = allocation.location(a.value);
+            if a.crosses_call {
+                assert_ne!(location, Location::Reg("r10"), "{:?}", a);
+            }
+            // no two values live at once share a register
+            for b in intervals.iter().filter(|b| b.value != a.value) {
+                if a.start <= b.end && b.start <= a.end {
+                    assert!(
+                        !matches!(location, Location::Reg(_))
+                            || location != allocation.location(b.value),
+                        "{:?} and {:?}",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+
+        let allocation = allocate::<&str>(&func, &Registers::NONE);
+        assert_eq!(allocation.spill_count, live_intervals(&func).len());
+        assert!(allocation.callee_saved.is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! Finds the calls in tail position, which the backends make by jumping to the callee
+//! in place of the caller's frame, so that the recursion in them does not grow the stack.
+
+use crate::ir::{Block, BlockId, Function, Inst, Module, Operand, Terminator, Value};
+
+/// Returns the index of the call in the block which can be a tail call. It is one when
+/// - the block returns its result, directly or extended to the return type of the caller
+///   which is not wider than the callee's, or jumps to a block only returning it as a phi,
+///   as the blocks of an inlined function return,
+/// - the callee takes a fixed number of arguments, all passed in the registers,
+/// - no slot of the caller has its address taken, so the callee cannot access the frame
+///   it replaces.
+pub fn tail_call(
+    module: &Module,
+    func: &Function,
+    id: BlockId,
+    arg_registers: usize,
+) -> Option<usize> {
+    let block = func.block(id);
+    let result = returned_value(func, id)?;
+    let index = match block.insts.as_slice() {
+        [.., Inst::Call { dst, .. }] if *dst == result => block.insts.len() - 1,
+        [
+            ..,
+            Inst::Call { dst, .. },
+            Inst::Extend {
+                dst: extended,
+                ty,
+                src,
+            },
+        ] if *extended == result && *ty == func.signature.ret && *src == Operand::Value(*dst) => {
+            block.insts.len() - 2
+        }
+        _ => return None,
+    };
+    let Inst::Call { callee, args, .. } = &block.insts[index] else {
+        unreachable!("call expected")
+    };
+    let signature = module.signature(callee)?;
+    let takes_address = func
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .any(|inst| matches!(inst, Inst::SlotAddr { .. }));
+    (!signature.is_variadic
+        && args.len() <= arg_registers
+        && func.signature.ret.size() <= signature.ret.size()
+        && !takes_address)
+        .then_some(index)
+}
+
+/// Returns the value which the block returns, or gives to a phi which its successor only returns.
+pub fn returned_value(func: &Function, id: BlockId) -> Option<Value> {
+    match func.block(id).term {
+        Terminator::Return(Operand::Value(value)) => Some(value),
+        Terminator::Jump(target) => match func.block(target) {
+            Block {
+                insts,
+                term: Terminator::Return(Operand::Value(result)),
+            } if let [Inst::Phi { dst, args }] = insts.as_slice()
+                && dst == result =>
+            {
+                args.iter()
+                    .find(|(pred, _)| *pred == id)
+                    .and_then(|(_, arg)| arg.as_value())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompileOptions, Compiler};
+
+    /// Returns the callees of the tail calls of each function.
+    fn tail_calls(code: &str) -> Vec<Vec<String>> {
+        let compiler = Compiler::new(code, CompileOptions::default());
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        let module = compiler.lower(&ast);
+        module
+            .functions
+            .iter()
+            .map(|func| {
+                func.block_ids()
+                    .filter_map(|id| {
+                        let index = tail_call(&module, func, id, 6)?;
+                        match &func.block(id).insts[index] {
+                            Inst::Call { callee, .. } => Some(callee.clone()),
+                            _ => None,
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tail_call() {
+        assert_eq!(
+            tail_calls(
+                "int even(int n) { if (n == 0) return 1; return odd(n - 1); }
+int odd(int n) { if (n == 0) return 0; return even(n - 1); }
+int sum(int n, int s) { if (n == 0) return s; return sum(n - 1, s + n); }
+char narrow(int n) { return sum(n, 0); }
+int wide(char c) { return narrow(c); }
+int not_last(int n) { return sum(n, 0) + 1; }
+int variadic(int n) { return printf(\"%d\", n); }
+int address(int n) { int x; x = n; return sum(*&x, 0); }"
+            ),
+            [
+                vec!["odd"],
+                vec!["even"],
+                vec!["sum"],
+                vec!["sum"],
+                vec![],
+                vec![],
+                vec![],
+                vec![],
+            ]
+        );
+    }
+}
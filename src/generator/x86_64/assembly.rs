@@ -71,20 +71,16 @@ impl Assembly {
             operand2: Some(operand2.into()),
         })
     }
-    pub fn reset_stack(stack_size: usize) -> Assembly {
-        vec![
-            Assembly::inst2(MOV, RSP, RBP),
-            Assembly::inst2(SUB, RSP, stack_size),
-        ]
-        .into()
-    }
-    pub fn epilogue() -> Assembly {
+    /// Frees the frame and restores the frame pointer of the caller.
+    pub fn leave() -> Assembly {
         Assembly::Group(vec![
             Assembly::inst2(MOV, RSP, RBP),
             Assembly::inst1(POP, RBP),
-            Assembly::inst0(RET),
         ])
     }
+    pub fn epilogue() -> Assembly {
+        Assembly::Group(vec![Assembly::leave(), Assembly::inst0(RET)])
+    }
 }
 
 impl crate::generator::Assembly for Assembly {
@@ -1,40 +1,211 @@
 use super::assembly::Assembly;
 use super::instruction::{
-    InstOperand::*,
-    InstOperator::{self, *},
+    InstOperand::{self, *},
+    InstOperator::*,
     Register::{self, *},
 };
-use crate::ast::ProgramAst;
+use super::peephole;
 use crate::ctype::Type;
-use crate::error;
-use crate::func::Func;
 use crate::generator::Os;
+use crate::generator::regalloc::{self, Allocation, Location, Registers};
+use crate::generator::tail_call;
 use crate::global::{GlobalVariable, GlobalVariableData};
-use crate::node::{Node, NodeType};
+use crate::ir::opt::{OptOptions, Pass};
+use crate::ir::{
+    Address, BinOp, BlockId, Function, Inst, Module, Operand, SlotId, Symbol, Terminator, Ty, Value,
+};
+use std::collections::HashMap;
 use std::fmt::Display;
 
-pub struct AsmGenerator<'a> {
-    error_logger: &'a dyn error::ErrorLogger,
+pub struct AsmGenerator {
     target_os: Os,
+    allocates_registers: bool,
+    makes_tail_calls: bool,
+    optimizes_peepholes: bool,
 }
 
 const ARGS_REG: [Register; 6] = [RDI, RSI, RDX, RCX, R8, R9];
 
-impl crate::generator::Generator for AsmGenerator<'_> {
-    fn generate(&self, ast: ProgramAst) -> Box<dyn crate::generator::Assembly> {
-        self.generate(ast)
+/// The registers given to the values. `rax`, `rcx`, `rdx` and `rdi` are left for computing,
+/// and the other argument registers for passing the arguments.
+const REGISTERS: Registers<Register> = Registers {
+    caller_saved: &[R10, R11],
+    callee_saved: &[RBX, R12, R13, R14, R15],
+};
+
+impl crate::generator::Generator for AsmGenerator {
+    fn generate(&self, module: &Module) -> Box<dyn crate::generator::Assembly> {
+        self.generate(module)
+    }
+}
+
+/// Where the slots and the values of a function are kept.
+struct Frame {
+    /// The offset of each slot from the frame pointer.
+    slots: Vec<i64>,
+    allocation: Allocation<Register>,
+    /// The callee-saved registers used, with the offset each is saved at.
+    saved_registers: Vec<(Register, i64)>,
+    /// The offset below which the homes of the spilled values are.
+    spills_offset: i64,
+    /// The offset of the place each phi takes its operand from,
+    /// written at the end of the predecessors so that the phis are evaluated at once.
+    phi_operands: HashMap<Value, i64>,
+    /// The size of the frame, aligned to 16 bytes.
+    size: usize,
+}
+
+impl Frame {
+    fn new(func: &Function, registers: &Registers<Register>) -> Self {
+        let allocation = regalloc::allocate(func, registers);
+        let mut size = 0;
+        let slots = func
+            .slots
+            .iter()
+            .map(|slot| {
+                size = (size + slot.size).next_multiple_of(slot.align.max(1));
+                -(size as i64)
+            })
+            .collect();
+        size = size.next_multiple_of(8);
+        let saved_registers = allocation
+            .callee_saved
+            .iter()
+            .map(|reg| {
+                size += 8;
+                (*reg, -(size as i64))
+            })
+            .collect();
+        let spills_offset = size as i64;
+        size += allocation.spill_count * 8;
+        let phi_operands = func
+            .blocks
+            .iter()
+            .flat_map(|block| block.phis())
+            .map(|phi| {
+                size += 8;
+                (phi.dst().unwrap(), -(size as i64))
+            })
+            .collect();
+        Self {
+            slots,
+            allocation,
+            saved_registers,
+            spills_offset,
+            phi_operands,
+            size: size.next_multiple_of(16),
+        }
+    }
+
+    fn phi_operand(&self, phi: Value) -> InstOperand {
+        Mem(RBP, self.phi_operands[&phi], 8)
+    }
+
+    fn value(&self, value: Value) -> InstOperand {
+        match self.allocation.location(value) {
+            Location::Reg(reg) => Reg(reg),
+            Location::Stack(i) => Mem(RBP, -(self.spills_offset + 8 * (i as i64 + 1)), 8),
+        }
+    }
+
+    fn slot(&self, slot: SlotId, size: usize) -> InstOperand {
+        Mem(RBP, self.slots[slot.0 as usize], size)
+    }
+
+    fn load(&self, reg: Register, operand: &Operand) -> Assembly {
+        match operand {
+            Operand::Const(value) => Assembly::inst2(MOV, reg, *value),
+            Operand::Value(value) if self.allocation.location(*value) == Location::Reg(reg) => {
+                vec![].into()
+            }
+            Operand::Value(value) => Assembly::inst2(MOV, reg, self.value(*value)),
+        }
+    }
+
+    /// Returns the register to compute the value in, which is its own register
+    /// unless the operand read after writing it is there, or `rax` for a spilled value.
+    fn result_register(&self, dst: Value, operand: Option<&Operand>) -> Register {
+        match self.allocation.location(dst) {
+            Location::Reg(reg)
+                if operand.is_none_or(|operand| {
+                    operand
+                        .as_value()
+                        .is_none_or(|value| self.allocation.location(value) != Location::Reg(reg))
+                }) =>
+            {
+                reg
+            }
+            _ => RAX,
+        }
+    }
+
+    /// Moves the value computed in the register to its location, if it is not there.
+    fn store_result(&self, dst: Value, reg: Register) -> Assembly {
+        if self.allocation.location(dst) == Location::Reg(reg) {
+            vec![].into()
+        } else {
+            Assembly::inst2(MOV, self.value(dst), reg)
+        }
+    }
+
+    /// Returns the operand as a register, a memory access or a 32-bit immediate,
+    /// with the instructions loading it to the scratch register if it is none of them.
+    fn operand(&self, scratch: Register, operand: &Operand) -> (Assembly, InstOperand) {
+        match operand {
+            Operand::Const(value) if i32::try_from(*value).is_ok() => (vec![].into(), Imm(*value)),
+            Operand::Const(_) => (self.load(scratch, operand), Reg(scratch)),
+            Operand::Value(value) => (vec![].into(), self.value(*value)),
+        }
+    }
+
+    /// Returns the operand as a register or a memory access, loading a constant to the scratch register.
+    fn register_or_memory(&self, scratch: Register, operand: &Operand) -> (Assembly, InstOperand) {
+        match operand {
+            Operand::Const(_) => (self.load(scratch, operand), Reg(scratch)),
+            Operand::Value(value) => (vec![].into(), self.value(*value)),
+        }
+    }
+
+    /// Returns the memory accessed with the size, and the instructions computing its address in `rax`.
+    fn address(&self, addr: &Address, size: usize) -> (Assembly, InstOperand) {
+        match addr {
+            Address::Slot(slot) => (vec![].into(), self.slot(*slot, size)),
+            Address::Ptr(Operand::Value(value)) if let Reg(reg) = self.value(*value) => {
+                (vec![].into(), Ptr(reg, size))
+            }
+            Address::Ptr(operand) => (self.load(RAX, operand), Ptr(RAX, size)),
+        }
+    }
+
+    fn save_registers(&self) -> Assembly {
+        self.saved_registers
+            .iter()
+            .map(|(reg, offset)| Assembly::inst2(MOV, Mem(RBP, *offset, 8), *reg))
+            .collect::<Vec<Assembly>>()
+            .into()
+    }
+
+    fn restore_registers(&self) -> Assembly {
+        self.saved_registers
+            .iter()
+            .map(|(reg, offset)| Assembly::inst2(MOV, *reg, Mem(RBP, *offset, 8)))
+            .collect::<Vec<Assembly>>()
+            .into()
     }
 }
 
-impl<'a> AsmGenerator<'a> {
-    pub fn new(error_logger: &'a dyn error::ErrorLogger, target_os: Os) -> Self {
+impl AsmGenerator {
+    /// Without allocating registers, every value is kept on the stack.
+    pub fn new(target_os: Os, options: &OptOptions) -> Self {
         Self {
-            error_logger,
             target_os,
+            allocates_registers: options.is_enabled(Pass::RegAlloc),
+            makes_tail_calls: options.is_enabled(Pass::TailCall),
+            optimizes_peepholes: options.is_enabled(Pass::Peephole),
         }
     }
 
-    fn generate(&self, ast: ProgramAst) -> Box<dyn crate::generator::Assembly> {
+    fn generate(&self, module: &Module) -> Box<dyn crate::generator::Assembly> {
         Box::<Assembly>::new(
             vec![
                 ".intel_syntax noprefix".into(),
@@ -44,14 +215,12 @@ impl<'a> AsmGenerator<'a> {
                     ".section .text"
                 }
                 .into(),
-                ast.functions
+                module
+                    .functions
                     .iter()
-                    .fold((0, vec![]), |(last_offset, mut last_vec), (name, f)| {
-                        let stack_offset = last_offset + f.offset_size;
-                        last_vec.push(self.gen_func(name, f, stack_offset));
-                        (stack_offset, last_vec)
-                    })
-                    .1
+                    .enumerate()
+                    .map(|(i, f)| self.gen_func(module, i, f))
+                    .collect::<Vec<Assembly>>()
                     .into(),
                 if let Os::MacOS = self.target_os {
                     ".section __DATA,__data"
@@ -59,12 +228,19 @@ impl<'a> AsmGenerator<'a> {
                     ".section .data"
                 }
                 .into(),
-                ast.global_variables
+                module
+                    .global_variables
                     .iter()
                     .map(|(s, gv)| self.gen_global_variable(s, gv))
                     .collect::<Vec<Assembly>>()
                     .into(),
-                self.gen_string_literals(ast.string_literals),
+                self.gen_string_literals(&module.string_literals),
+                if let Os::Linux = self.target_os {
+                    // marks the stack as non-executable for the linker
+                    ".section .note.GNU-stack,\"\",@progbits".into()
+                } else {
+                    vec![].into()
+                },
             ]
             .into(),
         )
@@ -85,7 +261,7 @@ impl<'a> AsmGenerator<'a> {
                     .enumerate()
                     .map(|(i, str)| {
                         vec![
-                            format!("L_.str.{}:", i).into(),
+                            format!("{}:", self.local_label(&format!("@.str.{}", i))).into(),
                             format!("  .asciz \"{}\"", str).into(),
                         ]
                         .into()
@@ -100,12 +276,12 @@ impl<'a> AsmGenerator<'a> {
     fn gen_global_variable(&self, name: &str, gv: &GlobalVariable) -> Assembly {
         vec![
             format!("{}:", self.with_prefix(name)).into(),
-            Self::gen_initializer_element(&gv.ty, gv.data.as_ref()),
+            self.gen_initializer_element(&gv.ty, gv.data.as_ref()),
         ]
         .into()
     }
 
-    fn gen_initializer_element(ty: &Type, data: Option<&GlobalVariableData>) -> Assembly {
+    fn gen_initializer_element(&self, ty: &Type, data: Option<&GlobalVariableData>) -> Assembly {
         match ty {
             Type::Arr(children_ty, size) => {
                 if let Some(GlobalVariableData::Arr(v)) = data {
@@ -114,7 +290,7 @@ impl<'a> AsmGenerator<'a> {
                             .enumerate()
                             .filter(|(i, _)| i < size)
                             .map(|(_, d)| {
-                                Self::gen_initializer_element(children_ty.as_ref(), Some(d))
+                                self.gen_initializer_element(children_ty.as_ref(), Some(d))
                             })
                             .collect::<Vec<Assembly>>()
                             .into(),
@@ -154,7 +330,7 @@ impl<'a> AsmGenerator<'a> {
             _ => format!(
                 "  .8byte {}",
                 if let Some(GlobalVariableData::Elem(s)) = data {
-                    s.replace('@', "L_")
+                    self.local_label(s)
                 } else {
                     "0".to_string()
                 }
@@ -163,415 +339,345 @@ impl<'a> AsmGenerator<'a> {
         }
     }
 
-    fn gen_func(&self, name: &str, func: &Func, offset: usize) -> Assembly {
-        if func.body.is_none() {
-            return vec![].into();
-        }
-        vec![
-            format!(".globl {}", self.with_prefix(name)).into(),
-            format!("{}:", self.with_prefix(name)).into(),
+    fn gen_func(&self, module: &Module, index: usize, func: &Function) -> Assembly {
+        let frame = Frame::new(
+            func,
+            if self.allocates_registers {
+                &REGISTERS
+            } else {
+                &Registers::NONE
+            },
+        );
+        let assembly: Assembly = vec![
+            format!(".globl {}", self.with_prefix(&func.name)).into(),
+            format!("{}:", self.with_prefix(&func.name)).into(),
             // prologue
             Assembly::inst1(PUSH, RBP),
             Assembly::inst2(MOV, RBP, RSP),
-            Assembly::inst2(SUB, RSP, func.offset_size),
-            func.args
+            Assembly::inst2(SUB, RSP, frame.size),
+            frame.save_registers(),
+            func.params
                 .iter()
+                .zip(&func.signature.params)
                 .enumerate()
-                .map(|(i, arg)| {
-                    if let NodeType::LocalVar = arg.nt {
-                        vec![
-                            Assembly::inst2(MOV, RAX, RBP),
-                            Assembly::inst2(SUB, RAX, arg.offset.unwrap()),
-                            Assembly::inst2(MOV, Ptr(RAX, 8), ARGS_REG[i]),
-                        ]
-                        .into()
-                    } else {
-                        self.error_logger.print_error_position(
-                            arg.token.as_ref().unwrap().pos,
-                            "ident expected",
-                        );
-                        unreachable!()
-                    }
+                .map(|(i, (param, ty))| match frame.value(*param) {
+                    Reg(reg) => Self::extend(reg, ARGS_REG[i], *ty),
+                    home => vec![
+                        Self::extend(RAX, ARGS_REG[i], *ty),
+                        Assembly::inst2(MOV, home, RAX),
+                    ]
+                    .into(),
+                })
+                .collect::<Vec<Assembly>>()
+                .into(),
+            func.block_ids()
+                .map(|id| {
+                    let block = func.block(id);
+                    let tail_call = self
+                        .makes_tail_calls
+                        .then(|| tail_call::tail_call(module, func, id, ARGS_REG.len()))
+                        .flatten();
+                    vec![
+                        format!("{}:", self.block_label(index, id)).into(),
+                        block.insts[..tail_call.unwrap_or(block.insts.len())]
+                            .iter()
+                            .map(|inst| self.gen_inst(module, &frame, inst))
+                            .collect::<Vec<Assembly>>()
+                            .into(),
+                        match tail_call {
+                            Some(call) => self.gen_tail_call(&frame, &block.insts[call]),
+                            None => vec![
+                                Self::gen_phi_operands(&frame, func, id),
+                                self.gen_terminator(&frame, index, id, &block.term),
+                            ]
+                            .into(),
+                        },
+                    ]
+                    .into()
                 })
                 .collect::<Vec<Assembly>>()
                 .into(),
-            self.gen_node(func.body.as_ref().unwrap(), offset, 0),
-            Assembly::inst2(MOV, RAX, 0), // default return value
-            Assembly::epilogue(),
         ]
-        .into()
+        .into();
+        if self.optimizes_peepholes {
+            peephole::optimize(assembly)
+        } else {
+            assembly
+        }
     }
 
-    fn gen_node(&self, node: &Node, offset: usize, breakable_branch_num: usize) -> Assembly {
-        match node.nt {
-            NodeType::DefVar => {
-                return self.gen_statements(&node.children, offset, breakable_branch_num);
+    fn gen_inst(&self, module: &Module, frame: &Frame, inst: &Inst) -> Assembly {
+        match inst {
+            Inst::Copy { dst, src } => {
+                let reg = frame.result_register(*dst, None);
+                vec![frame.load(reg, src), frame.store_result(*dst, reg)].into()
             }
-            NodeType::CallFunc => {
-                return vec![
-                    Assembly::inst2(MOV, RAX, RSP),
-                    Assembly::inst2(ADD, RAX, 8),
-                    Assembly::inst2(MOV, RDI, 16),
-                    Assembly::inst0(CQO),
-                    Assembly::inst1(IDIV, RDI),
-                    Assembly::inst2(SUB, RSP, RDX),
-                    Assembly::inst1(PUSH, RDX),
-                    node.args
-                        .iter()
-                        .map(|node| self.gen_node(node, offset, breakable_branch_num))
-                        .collect::<Vec<Assembly>>()
-                        .into(),
-                    ARGS_REG
-                        .iter()
-                        .take(node.args.len())
-                        .map(|op| Assembly::inst1(POP, *op))
-                        .collect::<Vec<Assembly>>()
-                        .into(),
-                    Assembly::inst1(CALL, self.with_prefix(&node.global_name)),
-                    Assembly::inst1(POP, RDI),
-                    Assembly::inst2(ADD, RSP, RDI),
-                    Assembly::inst1(PUSH, RAX),
-                ]
-                .into();
-            }
-            NodeType::If => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                return vec![
-                    self.gen_node(node.cond.as_ref().unwrap(), offset, breakable_branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::inst2(CMP, RAX, 0),
-                    Assembly::inst1(JE, ElseFlag(branch_num)),
-                    self.gen_node(node.then.as_ref().unwrap(), offset, breakable_branch_num),
-                    Assembly::inst1(JMP, EndFlag(branch_num)),
-                    format!("{}:", ElseFlag(branch_num)).into(),
-                    node.els
-                        .as_ref()
-                        .map(|node| self.gen_node(node, offset, breakable_branch_num))
-                        .unwrap_or_else(|| vec![].into()),
-                    format!("{}:", EndFlag(branch_num)).into(),
+            Inst::Binary {
+                dst,
+                op: op @ (BinOp::Shl | BinOp::Shr),
+                lhs,
+                rhs,
+            } => {
+                let reg = frame.result_register(*dst, Some(rhs));
+                let operator = match op {
+                    BinOp::Shl => SHL,
+                    _ => SAR,
+                };
+                vec![
+                    frame.load(reg, lhs),
+                    match rhs {
+                        // the count is masked as the processor does
+                        Operand::Const(count) => {
+                            Assembly::inst2(operator, reg, (count & 63) as usize)
+                        }
+                        _ => vec![frame.load(RCX, rhs), Assembly::inst2(operator, reg, CL)].into(),
+                    },
+                    frame.store_result(*dst, reg),
                 ]
-                .into();
-            }
-            NodeType::While => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                let v = vec![
-                    format!("{}:", BeginFlag(branch_num)).into(),
-                    Assembly::reset_stack(offset),
-                    self.gen_node(node.cond.as_ref().unwrap(), offset, branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::inst2(CMP, RAX, 0),
-                    Assembly::inst1(JE, EndFlag(branch_num)),
-                    self.gen_node(node.then.as_ref().unwrap(), offset, branch_num),
-                    Assembly::inst1(JMP, BeginFlag(branch_num)),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                ];
-                return v.into();
-            }
-            NodeType::For => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                let v = vec![
-                    node.ini.as_ref().map_or(Vec::new().into(), |node| {
-                        vec![
-                            self.gen_node(node, offset, branch_num),
-                            Assembly::inst1(POP, RAX),
-                        ]
-                        .into()
-                    }),
-                    format!("{}:", BeginFlag(branch_num)).into(),
-                    Assembly::reset_stack(offset),
-                    node.cond
-                        .as_ref()
-                        .map_or(Assembly::inst2(MOV, RAX, 1), |node| {
-                            vec![
-                                self.gen_node(node, offset, branch_num),
-                                Assembly::inst1(POP, RAX),
-                            ]
-                            .into()
-                        }),
-                    Assembly::inst2(CMP, RAX, 0),
-                    Assembly::inst1(JE, EndFlag(branch_num)),
-                    self.gen_node(node.then.as_ref().unwrap(), offset, branch_num),
-                    node.upd.as_ref().map_or(Vec::new().into(), |node| {
-                        vec![
-                            self.gen_node(node, offset, branch_num),
-                            Assembly::inst1(POP, RAX),
-                        ]
-                        .into()
-                    }),
-                    Assembly::inst1(JMP, BeginFlag(branch_num)),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                ];
-                return v.into();
-            }
-            NodeType::Block => {
-                return self.gen_statements(&node.children, offset, breakable_branch_num);
-            }
-            NodeType::Break => {
-                if breakable_branch_num != 0 {
-                    return Assembly::inst1(JMP, EndFlag(breakable_branch_num));
-                } else {
-                    self.error_logger.print_error_position(
-                        node.token.as_ref().unwrap().pos,
-                        "unexpected break found",
-                    );
-                }
+                .into()
             }
-            NodeType::Return => {
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), offset, breakable_branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::epilogue(),
+            Inst::Binary {
+                dst,
+                op: op @ (BinOp::Div | BinOp::Mod),
+                lhs,
+                rhs,
+            } => {
+                let (setup, rhs) = frame.register_or_memory(RDI, rhs);
+                vec![
+                    frame.load(RAX, lhs),
+                    setup,
+                    Assembly::inst0(CQO),
+                    Assembly::inst1(IDIV, rhs),
+                    Assembly::inst2(
+                        MOV,
+                        frame.value(*dst),
+                        if *op == BinOp::Div { RAX } else { RDX },
+                    ),
                 ]
-                .into();
-            }
-            NodeType::Num => {
-                return Assembly::inst1(PUSH, node.value.unwrap());
+                .into()
             }
-            NodeType::LocalVar | NodeType::GlobalVar => {
-                return vec![
-                    self.gen_addr(node, offset, breakable_branch_num),
-                    if let Some(Type::Arr(_, _)) = node.resolve_type() {
-                        vec![].into()
-                    } else {
-                        vec![
-                            Assembly::inst1(POP, RAX),
-                            self.deref_rax(node),
-                            Assembly::inst1(PUSH, RAX),
+            Inst::Binary { dst, op, lhs, rhs } => {
+                let reg = frame.result_register(*dst, Some(rhs));
+                let (setup, rhs) = frame.operand(RDI, rhs);
+                vec![
+                    frame.load(reg, lhs),
+                    setup,
+                    match op {
+                        BinOp::Add => Assembly::inst2(ADD, reg, rhs),
+                        BinOp::Sub => Assembly::inst2(SUB, reg, rhs),
+                        BinOp::Mul => Assembly::inst2(IMUL, reg, rhs),
+                        BinOp::And => Assembly::inst2(AND, reg, rhs),
+                        BinOp::Xor => Assembly::inst2(XOR, reg, rhs),
+                        BinOp::Or => Assembly::inst2(OR, reg, rhs),
+                        BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le => vec![
+                            Assembly::inst2(CMP, reg, rhs),
+                            Assembly::inst1(
+                                match op {
+                                    BinOp::Eq => SETE,
+                                    BinOp::Ne => SETNE,
+                                    BinOp::Lt => SETL,
+                                    _ => SETLE,
+                                },
+                                AL,
+                            ),
+                            Assembly::inst2(MOVZX, reg, AL),
                         ]
-                        .into()
+                        .into(),
+                        BinOp::Div | BinOp::Mod | BinOp::Shl | BinOp::Shr => unreachable!(),
                     },
+                    frame.store_result(*dst, reg),
                 ]
-                .into();
+                .into()
             }
-            NodeType::Addr => {
-                return self.gen_addr(node.lhs.as_ref().unwrap(), offset, breakable_branch_num);
+            Inst::Not { dst, src } => {
+                let reg = frame.result_register(*dst, None);
+                vec![
+                    frame.load(reg, src),
+                    Assembly::inst1(NOT, reg),
+                    frame.store_result(*dst, reg),
+                ]
+                .into()
             }
-            NodeType::Deref => {
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), offset, breakable_branch_num),
-                    if let Some(Type::Arr(..)) = node.lhs.as_ref().unwrap().dest_type() {
-                        vec![].into()
-                    } else {
-                        vec![
-                            Assembly::inst1(POP, RAX),
-                            self.deref_rax(node),
-                            Assembly::inst1(PUSH, RAX),
-                        ]
-                        .into()
-                    },
+            Inst::Extend { dst, ty, src } => {
+                let reg = frame.result_register(*dst, None);
+                vec![
+                    frame.load(RAX, src),
+                    Self::extend(reg, RAX, *ty),
+                    frame.store_result(*dst, reg),
                 ]
-                .into();
+                .into()
             }
-            NodeType::Assign => {
-                return vec![
-                    self.gen_addr(node.lhs.as_ref().unwrap(), offset, breakable_branch_num),
-                    self.gen_node(node.rhs.as_ref().unwrap(), offset, breakable_branch_num),
-                    Assembly::inst1(POP, RDI),
-                    Assembly::inst1(POP, RAX),
-                    self.operation2rdi(node.lhs.as_ref().unwrap().resolve_type(), MOV, RAX),
-                    Assembly::inst1(PUSH, RDI),
+            Inst::SlotAddr { dst, slot } => {
+                let reg = frame.result_register(*dst, None);
+                vec![
+                    Assembly::inst2(LEA, reg, frame.slot(*slot, 8)),
+                    frame.store_result(*dst, reg),
                 ]
-                .into();
+                .into()
             }
-            NodeType::BitLeft | NodeType::BitRight => {
-                return vec![
-                    self.gen_node(node.rhs.as_ref().unwrap(), offset, breakable_branch_num),
-                    self.gen_node(node.lhs.as_ref().unwrap(), offset, breakable_branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::inst1(POP, RCX),
-                    Assembly::inst2(
-                        match node.nt {
-                            NodeType::BitLeft => SHL,
-                            NodeType::BitRight => SAR,
-                            _ => {
-                                unreachable!()
-                            }
-                        },
-                        RAX,
-                        CL,
-                    ),
-                    Assembly::inst1(PUSH, RAX),
+            Inst::SymbolAddr { dst, symbol } => {
+                let reg = frame.result_register(*dst, None);
+                vec![
+                    Assembly::inst2(LEA, reg, PtrAdd(RIP, self.symbol_label(symbol))),
+                    frame.store_result(*dst, reg),
                 ]
-                .into();
+                .into()
             }
-            NodeType::BitNot => {
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), offset, breakable_branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::inst1(NOT, RAX),
-                    Assembly::inst1(PUSH, RAX),
+            Inst::Load { dst, ty, addr } => {
+                let reg = frame.result_register(*dst, None);
+                let (setup, memory) = frame.address(addr, ty.size());
+                vec![
+                    setup,
+                    match ty {
+                        Ty::I8 => Assembly::inst2(MOVSX, reg, memory),
+                        Ty::I32 => Assembly::inst2(MOVSXD, reg, memory),
+                        Ty::Ptr => Assembly::inst2(MOV, reg, memory),
+                    },
+                    frame.store_result(*dst, reg),
                 ]
-                .into();
+                .into()
             }
-            NodeType::LogicalAnd => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), offset, branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::inst2(CMP, RAX, 0),
-                    Assembly::inst1(JE, EndFlag(branch_num)),
-                    self.gen_node(node.rhs.as_ref().unwrap(), offset, branch_num),
-                    Assembly::inst1(POP, RAX),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                    Assembly::inst1(PUSH, RAX),
+            Inst::Store { ty, addr, value } => {
+                let (setup, memory) = frame.address(addr, ty.size());
+                vec![
+                    setup,
+                    frame.load(RDI, value),
+                    Assembly::inst2(MOV, memory, RDI.sized(ty.size())),
                 ]
-                .into();
+                .into()
             }
-            NodeType::LogicalOr => {
-                let branch_num = node.token.as_ref().unwrap().pos;
-                return vec![
-                    self.gen_node(node.lhs.as_ref().unwrap(), offset, branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::inst2(CMP, RAX, 0),
-                    Assembly::inst1(JNE, EndFlag(branch_num)),
-                    self.gen_node(node.rhs.as_ref().unwrap(), offset, branch_num),
-                    Assembly::inst1(POP, RAX),
-                    format!("{}:", EndFlag(branch_num)).into(),
-                    Assembly::inst1(PUSH, RAX),
+            Inst::Phi { dst, .. } => {
+                let reg = frame.result_register(*dst, None);
+                vec![
+                    Assembly::inst2(MOV, reg, frame.phi_operand(*dst)),
+                    frame.store_result(*dst, reg),
                 ]
-                .into();
+                .into()
             }
-            NodeType::SuffixIncr | NodeType::SuffixDecr => {
-                let op = if let NodeType::SuffixIncr = node.nt {
-                    ADD
-                } else {
-                    SUB
-                };
-                return vec![
-                    self.gen_addr(node.lhs.as_ref().unwrap(), offset, breakable_branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::inst2(MOV, RDI, 1),
-                    if let Some(t) = node.lhs.as_ref().unwrap().dest_type() {
-                        Assembly::inst2(IMUL, RDI, t.size_of())
-                    } else {
-                        vec![].into()
-                    },
-                    Assembly::inst2(MOV, RDX, RAX),
-                    self.deref_rax(node.lhs.as_ref().unwrap()),
-                    self.operation2rdi(node.lhs.as_ref().unwrap().resolve_type(), op, RDX),
-                    Assembly::inst1(PUSH, RAX),
+            Inst::Call { dst, callee, args } => {
+                let ret = module.signature(callee).map_or(Ty::I32, |s| s.ret);
+                let reg = frame.result_register(*dst, None);
+                vec![
+                    Self::pass_args(frame, args),
+                    // no vector registers are used by variadic arguments
+                    Assembly::inst2(MOV, EAX, 0usize),
+                    Assembly::inst1(CALL, self.with_prefix(callee)),
+                    Self::extend(reg, RAX, ret),
+                    frame.store_result(*dst, reg),
                 ]
-                .into();
+                .into()
             }
-            _ => {}
         }
+    }
+
+    /// Moves the arguments to the registers passing them.
+    fn pass_args(frame: &Frame, args: &[(Ty, Operand)]) -> Assembly {
+        args.iter()
+            .enumerate()
+            .map(|(i, (_, arg))| frame.load(ARGS_REG[i], arg))
+            .collect::<Vec<Assembly>>()
+            .into()
+    }
+
+    /// Jumps to the callee with the frame of the function freed, so that it returns to the caller.
+    fn gen_tail_call(&self, frame: &Frame, call: &Inst) -> Assembly {
+        let Inst::Call { callee, args, .. } = call else {
+            unreachable!("call expected")
+        };
         vec![
-            self.gen_node(node.rhs.as_ref().unwrap(), offset, breakable_branch_num),
-            self.gen_node(node.lhs.as_ref().unwrap(), offset, breakable_branch_num),
-            Assembly::inst1(POP, RAX),
-            Assembly::inst1(POP, RDI),
-            match node.nt {
-                NodeType::Add => vec![
-                    if let Some(t) = node.lhs.as_ref().unwrap().dest_type() {
-                        Assembly::inst2(IMUL, RDI, t.size_of())
-                    } else {
-                        vec![].into()
-                    },
-                    Assembly::inst2(ADD, RAX, RDI),
-                ]
-                .into(),
-                NodeType::Sub => vec![
-                    if let Some(t) = node.lhs.as_ref().unwrap().dest_type() {
-                        Assembly::inst2(IMUL, RDI, t.size_of())
-                    } else {
-                        vec![].into()
-                    },
-                    Assembly::inst2(SUB, RAX, RDI),
-                ]
-                .into(),
-                NodeType::Mul => Assembly::inst2(IMUL, RAX, RDI),
-                NodeType::Div => vec![Assembly::inst0(CQO), Assembly::inst1(IDIV, RDI)].into(),
-                NodeType::Mod => vec![
-                    Assembly::inst0(CQO),
-                    Assembly::inst1(IDIV, RDI),
-                    Assembly::inst2(MOV, RAX, RDX),
-                ]
-                .into(),
-                NodeType::Eq | NodeType::Ne | NodeType::Lt | NodeType::Le => vec![
-                    Assembly::inst2(CMP, RAX, RDI),
-                    Assembly::inst1(
-                        match node.nt {
-                            NodeType::Eq => SETE,
-                            NodeType::Ne => SETNE,
-                            NodeType::Lt => SETL,
-                            NodeType::Le => SETLE,
-                            _ => unreachable!(),
-                        },
-                        AL,
-                    ),
-                    Assembly::inst2(MOVZX, RAX, AL),
-                ]
-                .into(),
-                NodeType::BitAnd => Assembly::inst2(AND, RAX, RDI),
-                NodeType::BitXor => Assembly::inst2(XOR, RAX, RDI),
-                NodeType::BitOr => Assembly::inst2(OR, RAX, RDI),
-                _ => {
-                    self.error_logger
-                        .print_error_position(node.token.as_ref().unwrap().pos, "unexpected node");
-                    unreachable!();
-                }
-            },
-            Assembly::inst1(PUSH, RAX),
+            Self::pass_args(frame, args),
+            frame.restore_registers(),
+            Assembly::leave(),
+            Assembly::inst1(JMP, self.with_prefix(callee)),
         ]
         .into()
     }
 
-    fn gen_statements(&self, v: &[Node], offset: usize, branch_num: usize) -> Assembly {
-        v.iter()
-            .map(|node| {
-                vec![
-                    self.gen_node(node, offset, branch_num),
-                    Assembly::inst1(POP, RAX),
-                    Assembly::reset_stack(offset),
-                ]
-                .into()
+    /// Writes the operands given by the block to the phis of its successors.
+    fn gen_phi_operands(frame: &Frame, func: &Function, id: BlockId) -> Assembly {
+        let mut successors = func.block(id).term.successors();
+        successors.dedup();
+        successors
+            .into_iter()
+            .flat_map(|succ| func.block(succ).phis())
+            .filter_map(|phi| match phi {
+                Inst::Phi { dst, args } => {
+                    args.iter().find(|(pred, _)| *pred == id).map(|(_, arg)| {
+                        vec![
+                            frame.load(RAX, arg),
+                            Assembly::inst2(MOV, frame.phi_operand(*dst), RAX),
+                        ]
+                        .into()
+                    })
+                }
+                _ => None,
             })
             .collect::<Vec<Assembly>>()
             .into()
     }
 
-    fn gen_addr(&self, node: &Node, offset: usize, branch_num: usize) -> Assembly {
-        match node.nt {
-            NodeType::GlobalVar => vec![
-                if !node.dest.is_empty() {
-                    Assembly::inst2(LEA, RAX, PtrAdd(RIP, node.dest.clone().replace('@', "L_")))
-                } else {
-                    Assembly::inst2(LEA, RAX, PtrAdd(RIP, self.with_prefix(&node.global_name)))
+    fn gen_terminator(
+        &self,
+        frame: &Frame,
+        index: usize,
+        id: BlockId,
+        term: &Terminator,
+    ) -> Assembly {
+        let next = BlockId(id.0 + 1);
+        let jump = |target: BlockId| {
+            if target == next {
+                vec![].into()
+            } else {
+                Assembly::inst1(JMP, self.block_label(index, target))
+            }
+        };
+        match term {
+            Terminator::Jump(target) => jump(*target),
+            Terminator::Branch { cond, then, els } => vec![
+                match cond {
+                    Operand::Value(value) => Assembly::inst2(CMP, frame.value(*value), 0usize),
+                    _ => vec![frame.load(RAX, cond), Assembly::inst2(CMP, RAX, 0usize)].into(),
                 },
-                Assembly::inst1(PUSH, RAX),
+                Assembly::inst1(JNE, self.block_label(index, *then)),
+                jump(*els),
             ]
             .into(),
-            NodeType::LocalVar => vec![
-                Assembly::inst2(MOV, RAX, RBP),
-                Assembly::inst2(SUB, RAX, node.offset.unwrap()),
-                Assembly::inst1(PUSH, RAX),
+            Terminator::Return(value) => vec![
+                frame.load(RAX, value),
+                frame.restore_registers(),
+                Assembly::epilogue(),
             ]
             .into(),
-            NodeType::Deref => self.gen_node(node.lhs.as_ref().unwrap(), offset, branch_num),
-            _ => {
-                unreachable!();
-            }
         }
     }
 
-    fn operation2rdi(
-        &self,
-        c_type: Option<Type>,
-        operator: InstOperator,
-        from: Register,
-    ) -> Assembly {
-        match c_type {
-            Some(Type::I8) => Assembly::inst2(operator, Ptr(from, 1), DIL),
-            Some(Type::I32) => Assembly::inst2(operator, Ptr(from, 4), EDI),
-            _ => Assembly::inst2(operator, Ptr(from, 8), RDI),
+    /// Moves the low bits of `from` which fit the type to `to`, sign-extending them.
+    fn extend(to: Register, from: Register, ty: Ty) -> Assembly {
+        match ty {
+            Ty::I8 => Assembly::inst2(MOVSX, to, from.sized(1)),
+            Ty::I32 => Assembly::inst2(MOVSXD, to, from.sized(4)),
+            Ty::Ptr if to == from => vec![].into(),
+            Ty::Ptr => Assembly::inst2(MOV, to, from),
+        }
+    }
+
+    fn block_label(&self, index: usize, id: BlockId) -> String {
+        self.local_label(&format!("@BB{}_{}", index, id.0))
+    }
+
+    fn symbol_label(&self, symbol: &Symbol) -> String {
+        match symbol {
+            Symbol::Global(name) => self.with_prefix(name),
+            Symbol::Str(index) => self.local_label(&format!("@.str.{}", index)),
         }
     }
 
-    fn deref_rax(&self, node: &Node) -> Assembly {
-        match node.resolve_type() {
-            Some(Type::I32) => Assembly::inst2(MOVSXD, RAX, Ptr(RAX, 4)),
-            Some(Type::I8) => Assembly::inst2(MOVSX, RAX, Ptr(RAX, 1)),
-            _ => Assembly::inst2(MOV, RAX, Ptr(RAX, 8)),
+    /// Returns the name of a label which is not exported from the object file,
+    /// for a name starting with `@` such as string literals.
+    fn local_label(&self, name: &str) -> String {
+        match self.target_os {
+            Os::MacOS => name.replace('@', "L_"),
+            Os::Linux => name.replace('@', ".L"),
         }
     }
 
@@ -1,5 +1,6 @@
 use crate::generator::Os;
 
+#[derive(Clone, Copy, PartialEq)]
 pub enum InstOperator {
     PUSH,
     POP,
@@ -72,7 +73,7 @@ impl InstOperator {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Register {
     RAX,
     RBX,
@@ -82,13 +83,29 @@ pub enum Register {
     RDI,
     RBP,
     RSP,
+    EAX,
+    ECX,
+    EDX,
+    ESI,
     EDI,
+    R8D,
+    R9D,
+    SIL,
     DIL,
     AL,
     CL,
+    DL,
+    R8B,
+    R9B,
     RIP,
     R8,
     R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
 }
 use Register::*;
 
@@ -103,28 +120,101 @@ impl Display for Register {
             RDI => "rdi",
             RBP => "rbp",
             RSP => "rsp",
+            EAX => "eax",
+            ECX => "ecx",
+            EDX => "edx",
+            ESI => "esi",
             EDI => "edi",
+            R8D => "r8d",
+            R9D => "r9d",
+            SIL => "sil",
             DIL => "dil",
             AL => "al",
             CL => "cl",
+            DL => "dl",
+            R8B => "r8b",
+            R9B => "r9b",
             RIP => "rip",
             R8 => "r8",
             R9 => "r9",
+            R10 => "r10",
+            R11 => "r11",
+            R12 => "r12",
+            R13 => "r13",
+            R14 => "r14",
+            R15 => "r15",
         })?;
         Ok(())
     }
 }
 
+impl Register {
+    /// Returns the register holding the low bits of the 64-bit register, for a value of the size.
+    pub fn sized(self, size: usize) -> Register {
+        match (self, size) {
+            (_, 8) => self,
+            (RAX, 4) => EAX,
+            (RCX, 4) => ECX,
+            (RDX, 4) => EDX,
+            (RSI, 4) => ESI,
+            (RDI, 4) => EDI,
+            (R8, 4) => R8D,
+            (R9, 4) => R9D,
+            (RAX, 1) => AL,
+            (RCX, 1) => CL,
+            (RDX, 1) => DL,
+            (RSI, 1) => SIL,
+            (RDI, 1) => DIL,
+            (R8, 1) => R8B,
+            (R9, 1) => R9B,
+            _ => unreachable!("no {}-byte register for {}", size, self),
+        }
+    }
+
+    /// Returns the 64-bit register which the register holds the low bits of.
+    pub fn full(self) -> Register {
+        match self {
+            EAX | AL => RAX,
+            ECX | CL => RCX,
+            EDX | DL => RDX,
+            ESI | SIL => RSI,
+            EDI | DIL => RDI,
+            R8D | R8B => R8,
+            R9D | R9B => R9,
+            _ => self,
+        }
+    }
+
+    /// Returns the size of the register in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            EAX | ECX | EDX | ESI | EDI | R8D | R9D => 4,
+            SIL | DIL | AL | CL | DL | R8B | R9B => 1,
+            _ => 8,
+        }
+    }
+}
+
+fn ptr_size(size: usize) -> &'static str {
+    match size {
+        1 => "byte ptr",
+        4 => "dword ptr",
+        8 => "qword ptr",
+        _ => unreachable!(),
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub enum InstOperand {
     Reg(Register),
     Num(usize),
+    Imm(i64),
     Label(String),
     Str(&'static str),
     Ptr(Register, usize),
     PtrAdd(Register, String),
-    ElseFlag(usize),
-    BeginFlag(usize),
-    EndFlag(usize),
+    /// A memory access of the size at an offset from the register.
+    Mem(Register, i64, usize),
 }
 
 impl Display for InstOperand {
@@ -134,16 +224,15 @@ impl Display for InstOperand {
             InstOperand::Num(i) => format!("{}", i),
             InstOperand::Label(l) => l.clone(),
             InstOperand::Str(s) => String::from(*s),
-            InstOperand::Ptr(r, i) => match i {
-                1 => format!("byte ptr[{}]", r),
-                4 => format!("dword ptr[{}]", r),
-                8 => format!("qword ptr[{}]", r),
-                _ => unreachable!(),
-            },
-            InstOperand::ElseFlag(i) => format!(".Lelse{}", i),
-            InstOperand::BeginFlag(i) => format!(".Lbegin{}", i),
-            InstOperand::EndFlag(i) => format!(".Lend{}", i),
+            InstOperand::Imm(i) => format!("{}", i),
+            InstOperand::Ptr(r, size) => format!("{}[{}]", ptr_size(*size), r),
             InstOperand::PtrAdd(s, r) => format!("[{} + {}]", s, r),
+            InstOperand::Mem(r, offset, size) if *offset < 0 => {
+                format!("{} [{} - {}]", ptr_size(*size), r, -offset)
+            }
+            InstOperand::Mem(r, offset, size) => {
+                format!("{} [{} + {}]", ptr_size(*size), r, offset)
+            }
         })
     }
 }
@@ -166,12 +255,19 @@ impl From<usize> for InstOperand {
     }
 }
 
+impl From<i64> for InstOperand {
+    fn from(val: i64) -> Self {
+        InstOperand::Imm(val)
+    }
+}
+
 impl From<Register> for InstOperand {
     fn from(val: Register) -> Self {
         InstOperand::Reg(val)
     }
 }
 
+#[derive(Clone)]
 pub struct Instruction {
     pub operator: InstOperator,
     pub operand1: Option<InstOperand>,
@@ -179,21 +275,17 @@ pub struct Instruction {
 }
 impl Instruction {
     pub fn to_string(&self, target_os: Os) -> String {
-        if self.operand2.is_some() {
-            return format!(
+        match (&self.operand1, &self.operand2) {
+            (Some(operand1), Some(operand2)) => format!(
                 "  {} {}, {}",
                 self.operator.to_string(target_os),
-                &self.operand1.as_ref().unwrap().to_string()[..],
-                &self.operand2.as_ref().unwrap().to_string()[..]
-            );
-        }
-        if self.operand1.is_some() {
-            return format!(
-                "  {} {}",
-                self.operator.to_string(target_os),
-                &self.operand1.as_ref().unwrap().to_string()[..],
-            );
+                operand1,
+                operand2
+            ),
+            (Some(operand1), None) => {
+                format!("  {} {}", self.operator.to_string(target_os), operand1)
+            }
+            _ => format!("  {}", self.operator.to_string(target_os)),
         }
-        format!("  {}", self.operator.to_string(target_os))
     }
 }
@@ -1,3 +1,4 @@
 pub mod assembly;
 pub mod generator;
 pub mod instruction;
+pub mod peephole;
@@ -0,0 +1,346 @@
+//! Rewrites short sequences of the instructions generated for a function to fewer or cheaper ones.
+//! A register or the flags are only assumed dead when they are written before being read
+//! in the instructions up to the next label, jump or call.
+
+use super::assembly::Assembly;
+use super::instruction::{
+    InstOperand::{self, *},
+    InstOperator::*,
+    Instruction,
+    Register::{self, *},
+};
+use std::ops::Range;
+
+/// Rewrites the code of a function until no pattern is found.
+pub fn optimize(assembly: Assembly) -> Assembly {
+    let mut code = vec![];
+    flatten(assembly, &mut code);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut i = 0;
+        while i < code.len() {
+            match rewrite(&code[i..]) {
+                Some((range, replacement)) => {
+                    code.splice(i + range.start..i + range.end, replacement);
+                    changed = true;
+                }
+                None => i += 1,
+            }
+        }
+    }
+    code.into()
+}
+
+fn flatten(assembly: Assembly, code: &mut Vec<Assembly>) {
+    match assembly {
+        Assembly::Group(children) => children.into_iter().for_each(|child| flatten(child, code)),
+        _ => code.push(assembly),
+    }
+}
+
+fn instruction(assembly: Option<&Assembly>) -> Option<&Instruction> {
+    match assembly {
+        Some(Assembly::Inst(inst)) => Some(inst),
+        _ => None,
+    }
+}
+
+/// Returns the instructions replacing the range of the code from its first instruction, if any pattern starts there.
+fn rewrite(code: &[Assembly]) -> Option<(Range<usize>, Vec<Assembly>)> {
+    let first = instruction(code.first())?;
+    let next = instruction(code.get(1));
+    match (first.operator, first.operand1.as_ref()?, &first.operand2) {
+        // a 32-bit move clears the upper bits
+        (MOV, to, Some(from)) if to == from && !matches!(to, Reg(r) if r.size() == 4) => {
+            Some((0..1, vec![]))
+        }
+        // a register written and never read
+        (op, Reg(reg), _) if op != POP && overwrites(first) && is_dead(&code[1..], reg.full()) => {
+            Some((0..1, vec![]))
+        }
+        (MOV, Reg(reg), Some(Imm(0) | Num(0))) if flags_dead(&code[1..]) => {
+            Some((0..1, vec![Assembly::inst2(XOR, *reg, *reg)]))
+        }
+        // moving back the value just moved, unless its address is changed by the first move
+        (MOV, to, Some(from))
+            if next.is_some_and(|next| {
+                next.operator == MOV
+                    && next.operand1.as_ref() == Some(from)
+                    && next.operand2.as_ref() == Some(to)
+            }) && !matches!(to, Reg(reg) if addresses(from, reg.full())) =>
+        {
+            Some((1..2, vec![]))
+        }
+        // the value computed in a register only to be moved to another one
+        (_, Reg(reg), Some(src))
+            if overwrites(first)
+                && reg.size() == 8
+                && next.is_some_and(|next| {
+                    next.operator == MOV
+                        && matches!(next.operand1, Some(Reg(dst)) if dst.size() == 8 && dst != *reg)
+                        && next.operand2 == Some(Reg(*reg))
+                })
+                && is_dead(&code[2..], *reg) =>
+        {
+            Some((
+                0..2,
+                vec![Assembly::inst2(
+                    first.operator,
+                    next?.operand1.clone()?,
+                    src.clone(),
+                )],
+            ))
+        }
+        // the register copied only to be read once
+        (MOV, Reg(reg), Some(Reg(src))) if reg.size() == 8 && src.size() == 8 => {
+            let next = next?;
+            let is_reg = |operand: &Option<InstOperand>| operand == &Some(Reg(*reg));
+            let (operand1, operand2) = if is_reg(&next.operand2) {
+                (next.operand1.clone(), Some(Reg(*src)))
+            } else if next.operator == CMP && is_reg(&next.operand1) {
+                (Some(Reg(*src)), next.operand2.clone())
+            } else {
+                return None;
+            };
+            let forwarded = Instruction {
+                operator: next.operator,
+                operand1,
+                operand2,
+            };
+            (!reads(&forwarded, *reg) && (writes(&forwarded, *reg) || is_dead(&code[2..], *reg)))
+                .then(|| (0..2, vec![forwarded.into()]))
+        }
+        (PUSH, pushed, None) => match (pushed, next?) {
+            (
+                pushed,
+                Instruction {
+                    operator: POP,
+                    operand1: Some(popped),
+                    ..
+                },
+            ) if pushed == popped => Some((0..2, vec![])),
+            (
+                Reg(pushed),
+                Instruction {
+                    operator: POP,
+                    operand1: Some(Reg(popped)),
+                    ..
+                },
+            ) => Some((0..2, vec![Assembly::inst2(MOV, *popped, *pushed)])),
+            _ => None,
+        },
+        (LEA, Reg(reg), Some(Mem(base, offset, size))) => {
+            let next = next?;
+            match (next.operator, &next.operand1, &next.operand2) {
+                // the constant added to the address computed
+                (op @ (ADD | SUB), Some(Reg(dst)), Some(Imm(value)))
+                    if dst == reg && flags_dead(&code[2..]) =>
+                {
+                    let offset = if op == ADD {
+                        offset.checked_add(*value)?
+                    } else {
+                        offset.checked_sub(*value)?
+                    };
+                    i32::try_from(offset).ok()?;
+                    Some((
+                        0..2,
+                        vec![Assembly::inst2(LEA, *reg, Mem(*base, offset, *size))],
+                    ))
+                }
+                // the address accessed by the next instruction using the register
+                _ if *base != reg.full() => {
+                    let reg = reg.full();
+                    let skips = |assembly: &Assembly| match assembly {
+                        Assembly::Inst(inst) => {
+                            !matches!(inst.operator, CALL | JMP | JE | JNE | RET)
+                                && !reads(inst, reg)
+                                && !writes(inst, reg)
+                        }
+                        _ => false,
+                    };
+                    let user = 1 + code[1..].iter().position(|assembly| !skips(assembly))?;
+                    let inst = instruction(code.get(user))?;
+                    let operand1 = inst.operand1.as_ref()?;
+                    let operand2 = inst.operand2.as_ref();
+                    let (operand1, operand2) = if addresses(operand1, reg) {
+                        (fold_address(operand1, *base, *offset)?, operand2.cloned())
+                    } else if let Some(operand2) = operand2.filter(|op| addresses(op, reg)) {
+                        (
+                            operand1.clone(),
+                            Some(fold_address(operand2, *base, *offset)?),
+                        )
+                    } else {
+                        return None;
+                    };
+                    let folded = Instruction {
+                        operator: inst.operator,
+                        operand1: Some(operand1),
+                        operand2,
+                    };
+                    (!reads(&folded, reg)
+                        && (writes(&folded, reg) || is_dead(&code[user + 1..], reg)))
+                    .then(|| {
+                        (
+                            0..user + 1,
+                            code[1..user]
+                                .iter()
+                                .filter_map(|assembly| instruction(Some(assembly)))
+                                .map(|inst| inst.clone().into())
+                                .chain([folded.into()])
+                                .collect(),
+                        )
+                    })
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the operand is a memory access at an address in the register.
+fn addresses(operand: &InstOperand, reg: Register) -> bool {
+    matches!(operand, Ptr(r, _) | Mem(r, _, _) | PtrAdd(r, _) if r.full() == reg)
+}
+
+/// Returns the memory access at the offset from the base register,
+/// instead of the register holding that address.
+fn fold_address(operand: &InstOperand, base: Register, offset: i64) -> Option<InstOperand> {
+    match operand {
+        Ptr(_, size) => Some(Mem(base, offset, *size)),
+        Mem(_, other, size) => {
+            let offset = offset.checked_add(*other)?;
+            i32::try_from(offset).ok()?;
+            Some(Mem(base, offset, *size))
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the instruction writes its first operand, a whole 64-bit register, without reading it.
+fn overwrites(inst: &Instruction) -> bool {
+    matches!(inst.operator, MOV | LEA | MOVSX | MOVSXD | MOVZX | POP)
+        && matches!(inst.operand1, Some(Reg(r)) if r.size() >= 4)
+}
+
+fn reads(inst: &Instruction, reg: Register) -> bool {
+    let is_reg = |operand: &InstOperand| matches!(operand, Reg(r) if r.full() == reg);
+    let implicit = match inst.operator {
+        CQO => reg == RAX,
+        IDIV => matches!(reg, RAX | RDX),
+        _ => false,
+    };
+    implicit
+        || inst
+            .operand1
+            .iter()
+            .chain(&inst.operand2)
+            .any(|operand| addresses(operand, reg))
+        || inst.operand2.as_ref().is_some_and(is_reg)
+        || (inst.operand1.as_ref().is_some_and(is_reg) && !overwrites(inst))
+}
+
+fn writes(inst: &Instruction, reg: Register) -> bool {
+    (overwrites(inst) && matches!(inst.operand1, Some(Reg(r)) if r.full() == reg))
+        || (inst.operator == CQO && reg == RDX)
+}
+
+/// Returns whether the register is written before being read by the code.
+fn is_dead(code: &[Assembly], reg: Register) -> bool {
+    for assembly in code {
+        let Assembly::Inst(inst) = assembly else {
+            return false;
+        };
+        match inst.operator {
+            // the return value and the callee-saved registers are read by the caller
+            RET => return !matches!(reg, RAX | RBX | RBP | RSP | R12 | R13 | R14 | R15),
+            // the caller-saved registers not passing the arguments are overwritten by the callee
+            CALL => return matches!(reg, R10 | R11),
+            JMP | JE | JNE => return false,
+            _ if reads(inst, reg) => return false,
+            _ if writes(inst, reg) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Returns whether the flags are written before being read by the code.
+fn flags_dead(code: &[Assembly]) -> bool {
+    for assembly in code {
+        let Assembly::Inst(inst) = assembly else {
+            return false;
+        };
+        match inst.operator {
+            SETE | SETNE | SETL | SETLE | JE | JNE | JMP => return false,
+            CMP | ADD | SUB | AND | OR | XOR | IMUL | CALL | RET => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{Assembly as _, Os};
+
+    fn optimize_lines(code: Vec<Assembly>) -> String {
+        optimize(code.into()).to_string(Os::Linux)
+    }
+
+    #[test]
+    fn test_optimize() {
+        assert_eq!(
+            optimize_lines(vec![
+                Assembly::inst1(PUSH, RBX),
+                Assembly::inst1(POP, RBX),
+                Assembly::inst1(PUSH, RAX),
+                Assembly::inst1(POP, RDI),
+                Assembly::inst2(MOV, Mem(RBP, -8, 8), RDI),
+                Assembly::inst2(MOV, RDI, Mem(RBP, -8, 8)),
+                Assembly::inst2(LEA, R10, Mem(RBP, -16, 8)),
+                Assembly::inst2(ADD, R10, 4i64),
+                Assembly::inst2(MOVSXD, R10, Ptr(R10, 4)),
+                Assembly::inst2(MOV, RAX, 0i64),
+                Assembly::inst2(ADD, RAX, R10),
+                Assembly::inst2(ADD, RAX, RDI),
+                Assembly::inst0(RET),
+            ]),
+            [
+                "  mov qword ptr [rbp - 8], rax",
+                "  mov rdi, qword ptr [rbp - 8]",
+                "  movsxd r10, dword ptr [rbp - 12]",
+                "  xor rax, rax",
+                "  add rax, r10",
+                "  add rax, rdi",
+                "  ret",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_optimize_keeps_live_registers_and_flags() {
+        let code = vec![
+            Assembly::inst2(LEA, RAX, Mem(RBP, -16, 8)),
+            Assembly::inst2(MOV, RDI, Ptr(RAX, 8)),
+            Assembly::inst2(CMP, RDI, 0i64),
+            Assembly::inst2(MOV, RDI, 0i64),
+            Assembly::inst1(SETE, AL),
+            Assembly::inst2(MOV, RAX, Ptr(RAX, 8)),
+            Assembly::inst2(MOV, Ptr(RAX, 8), RAX),
+            Assembly::inst2(ADD, RAX, RDI),
+            Assembly::inst0(RET),
+        ];
+        let expected = Assembly::from(
+            code.iter()
+                .filter_map(|assembly| instruction(Some(assembly)))
+                .map(|inst| inst.clone().into())
+                .collect::<Vec<Assembly>>(),
+        )
+        .to_string(Os::Linux);
+        assert_eq!(optimize_lines(code), expected);
+    }
+}
@@ -1,10 +1,10 @@
 use crate::ctype::Type;
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GlobalVariable {
     pub ty: Type,
     pub data: Option<GlobalVariableData>,
 }
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum GlobalVariableData {
     Elem(String),
     Arr(Vec<GlobalVariableData>),
@@ -0,0 +1,172 @@
+//! Constant folding and propagation: computes the instructions whose operands are constants,
+//! replaces their uses with the results, and turns the branches on constants into jumps.
+//! The instructions whose results are given by algebraic identities, such as `x + 0`,
+//! and the sign extensions of values which are already extended are replaced with their operands too.
+
+use super::{BinOp, Function, Inst, Operand, Terminator, Ty, Value};
+use std::collections::HashMap;
+
+/// Returns whether the function is changed.
+pub fn const_fold(func: &mut Function) -> bool {
+    let mut replacements = HashMap::<Value, Operand>::new();
+    // the values known to be sign-extended from the types, as the arguments are on entry
+    let mut extended = func
+        .params
+        .iter()
+        .copied()
+        .zip(func.signature.params.iter().copied())
+        .collect::<HashMap<Value, Ty>>();
+    let mut changed = false;
+    // the definitions are visited before their uses, except the operands of phis along back edges
+    for id in func.reverse_postorder() {
+        let block = func.block_mut(id);
+        for inst in &mut block.insts {
+            propagate(inst.operands_mut(), &replacements);
+            if let Some(folded) = fold(inst).or_else(|| redundant_extend(inst, &extended)) {
+                replacements.insert(inst.dst().unwrap(), folded);
+            } else if let Some((dst, ty)) = extended_type(inst) {
+                extended.insert(dst, ty);
+            }
+        }
+        propagate(block.term.operands_mut(), &replacements);
+        if let Terminator::Branch {
+            cond: Operand::Const(cond),
+            then,
+            els,
+        } = block.term
+        {
+            let (target, dropped) = if cond != 0 { (then, els) } else { (els, then) };
+            block.term = Terminator::Jump(target);
+            if dropped != target {
+                func.block_mut(dropped).remove_phi_operands(id);
+            }
+            changed = true;
+        }
+    }
+    if !replacements.is_empty() {
+        changed = true;
+        for block in &mut func.blocks {
+            block.insts.retain(|inst| {
+                inst.dst()
+                    .is_none_or(|dst| !replacements.contains_key(&dst))
+            });
+        }
+        func.replace_uses(&replacements);
+    }
+    func.remove_unreachable_blocks();
+    changed
+}
+
+fn propagate(operands: Vec<&mut Operand>, replacements: &HashMap<Value, Operand>) {
+    for operand in operands {
+        if let Operand::Value(value) = operand
+            && let Some(replacement) = replacements.get(value)
+        {
+            *operand = *replacement;
+        }
+    }
+}
+
+/// Returns the constant computed by the instruction if its operands are constants,
+/// or the operand it always results in.
+fn fold(inst: &Inst) -> Option<Operand> {
+    if let Inst::Binary { op, lhs, rhs, .. } = inst
+        && let Some(operand) = identity(*op, *lhs, *rhs)
+    {
+        return Some(operand);
+    }
+    let constant = match inst {
+        Inst::Copy {
+            src: Operand::Const(c),
+            ..
+        } => *c,
+        Inst::Not {
+            src: Operand::Const(c),
+            ..
+        } => !c,
+        Inst::Extend {
+            ty,
+            src: Operand::Const(c),
+            ..
+        } => ty.wrap(*c),
+        Inst::Binary {
+            op,
+            lhs: Operand::Const(lhs),
+            rhs: Operand::Const(rhs),
+            ..
+        } => binary(*op, *lhs, *rhs)?,
+        Inst::Phi { args, .. } => {
+            let (_, first) = args.first()?;
+            let Operand::Const(c) = first else {
+                return None;
+            };
+            if !args.iter().all(|(_, arg)| arg == first) {
+                return None;
+            }
+            *c
+        }
+        _ => return None,
+    };
+    Some(Operand::Const(constant))
+}
+
+fn redundant_extend(inst: &Inst, extended: &HashMap<Value, Ty>) -> Option<Operand> {
+    match inst {
+        Inst::Extend {
+            ty,
+            src: src @ Operand::Value(value),
+            ..
+        } if extended.get(value)?.size() <= ty.size() => Some(*src),
+        _ => None,
+    }
+}
+
+/// Returns the result of the instruction and the type it is sign-extended from.
+fn extended_type(inst: &Inst) -> Option<(Value, Ty)> {
+    match inst {
+        Inst::Load { dst, ty, .. } | Inst::Extend { dst, ty, .. } => Some((*dst, *ty)),
+        // 0 or 1
+        Inst::Binary { dst, op, .. } if op.is_comparison() => Some((*dst, Ty::I8)),
+        _ => None,
+    }
+}
+
+/// Returns the result of the operation which does not depend on the values of the operands.
+fn identity(op: BinOp, lhs: Operand, rhs: Operand) -> Option<Operand> {
+    use Operand::Const;
+    match (op, lhs, rhs) {
+        (BinOp::Add | BinOp::Or | BinOp::Xor, x, Const(0))
+        | (BinOp::Add | BinOp::Or | BinOp::Xor, Const(0), x)
+        | (BinOp::Sub | BinOp::Shl | BinOp::Shr, x, Const(0))
+        | (BinOp::Mul | BinOp::Div, x, Const(1))
+        | (BinOp::Mul, Const(1), x) => Some(x),
+        (BinOp::Mul | BinOp::And, _, Const(0)) | (BinOp::Mul | BinOp::And, Const(0), _) => {
+            Some(Const(0))
+        }
+        (BinOp::Sub | BinOp::Xor | BinOp::Ne | BinOp::Lt, x, y) if x == y => Some(Const(0)),
+        (BinOp::Eq | BinOp::Le, x, y) if x == y => Some(Const(1)),
+        (BinOp::And | BinOp::Or, x, y) if x == y => Some(x),
+        _ => None,
+    }
+}
+
+/// Computes the operation as the backends do, except for the division overflowing or by 0,
+/// which is left to the program.
+pub fn binary(op: BinOp, lhs: i64, rhs: i64) -> Option<i64> {
+    Some(match op {
+        BinOp::Add => lhs.wrapping_add(rhs),
+        BinOp::Sub => lhs.wrapping_sub(rhs),
+        BinOp::Mul => lhs.wrapping_mul(rhs),
+        BinOp::Div => lhs.checked_div(rhs)?,
+        BinOp::Mod => lhs.checked_rem(rhs)?,
+        BinOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinOp::Shr => lhs.wrapping_shr(rhs as u32),
+        BinOp::And => lhs & rhs,
+        BinOp::Xor => lhs ^ rhs,
+        BinOp::Or => lhs | rhs,
+        BinOp::Eq => (lhs == rhs) as i64,
+        BinOp::Ne => (lhs != rhs) as i64,
+        BinOp::Lt => (lhs < rhs) as i64,
+        BinOp::Le => (lhs <= rhs) as i64,
+    })
+}
@@ -0,0 +1,26 @@
+//! Copy propagation: replaces the uses of copies with their sources,
+//! and the phis whose operands are all the same with that operand.
+
+use super::mem2reg::simplify_phis;
+use super::{Function, Inst};
+use std::collections::HashMap;
+
+/// Returns whether the function is changed.
+pub fn copy_prop(func: &mut Function) -> bool {
+    let replacements = func
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match inst {
+            Inst::Copy { dst, src } => Some((*dst, *src)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    for block in &mut func.blocks {
+        block
+            .insts
+            .retain(|inst| !matches!(inst, Inst::Copy { .. }));
+    }
+    func.replace_uses(&replacements);
+    simplify_phis(func) || !replacements.is_empty()
+}
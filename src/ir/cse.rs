@@ -0,0 +1,109 @@
+//! Common subexpression elimination: replaces the pure instructions computing
+//! the same operation as an instruction dominating them with the result of that one.
+
+use super::dominators::DominatorTree;
+use super::{BinOp, BlockId, Function, Inst, Operand, Value};
+use std::collections::HashMap;
+
+/// Returns whether the function is changed.
+pub fn cse(func: &mut Function) -> bool {
+    let mut elimination = Elimination {
+        children: DominatorTree::new(func).children(),
+        available: HashMap::new(),
+        replacements: HashMap::new(),
+    };
+    elimination.visit(func, BlockId(0));
+    let replacements = elimination.replacements;
+    if replacements.is_empty() {
+        return false;
+    }
+    for block in &mut func.blocks {
+        block.insts.retain(|inst| {
+            inst.dst()
+                .is_none_or(|dst| !replacements.contains_key(&dst))
+        });
+    }
+    func.replace_uses(&replacements);
+    true
+}
+
+struct Elimination {
+    children: Vec<Vec<BlockId>>,
+    /// The result of each operation computed in the blocks dominating the one visited.
+    available: HashMap<Inst, Value>,
+    replacements: HashMap<Value, Operand>,
+}
+
+impl Elimination {
+    fn visit(&mut self, func: &Function, id: BlockId) {
+        let mut added = Vec::new();
+        for inst in &func.block(id).insts {
+            let mut inst = inst.clone();
+            for operand in inst.operands_mut() {
+                if let Operand::Value(value) = operand
+                    && let Some(replacement) = self.replacements.get(value)
+                {
+                    *operand = *replacement;
+                }
+            }
+            let dst = inst.dst();
+            let Some(key) = expression(inst) else {
+                continue;
+            };
+            match self.available.get(&key) {
+                Some(value) => {
+                    self.replacements
+                        .insert(dst.unwrap(), Operand::Value(*value));
+                }
+                None => {
+                    self.available.insert(key.clone(), dst.unwrap());
+                    added.push(key);
+                }
+            }
+        }
+        for child in self.children[id.0 as usize].clone() {
+            self.visit(func, child);
+        }
+        for key in added {
+            self.available.remove(&key);
+        }
+    }
+}
+
+/// Returns the operation of a pure instruction without its result, with the operands
+/// of a commutative operation in a fixed order.
+fn expression(inst: Inst) -> Option<Inst> {
+    let dst = Value(u32::MAX);
+    Some(match inst {
+        Inst::Binary { op, lhs, rhs, .. } => {
+            let is_commutative = matches!(
+                op,
+                BinOp::Add
+                    | BinOp::Mul
+                    | BinOp::And
+                    | BinOp::Xor
+                    | BinOp::Or
+                    | BinOp::Eq
+                    | BinOp::Ne
+            );
+            let (lhs, rhs) = if is_commutative && rank(rhs) < rank(lhs) {
+                (rhs, lhs)
+            } else {
+                (lhs, rhs)
+            };
+            Inst::Binary { dst, op, lhs, rhs }
+        }
+        Inst::Not { src, .. } => Inst::Not { dst, src },
+        Inst::Extend { ty, src, .. } => Inst::Extend { dst, ty, src },
+        Inst::SlotAddr { slot, .. } => Inst::SlotAddr { dst, slot },
+        Inst::SymbolAddr { symbol, .. } => Inst::SymbolAddr { dst, symbol },
+        _ => return None,
+    })
+}
+
+fn rank(operand: Operand) -> (bool, i64) {
+    match operand {
+        Operand::Value(value) => (false, value.0 as i64),
+        Operand::Const(c) => (true, c),
+    }
+}
@@ -0,0 +1,47 @@
+//! Dead code elimination: removes the instructions whose results are not used
+//! by a store, a call, a terminator, or another instruction which is kept.
+
+use super::{Function, Inst};
+
+/// Returns whether the function is changed.
+pub fn dce(func: &mut Function) -> bool {
+    let mut is_live = vec![false; func.value_count as usize];
+    let mut worklist = Vec::new();
+    let mut definitions = vec![None; func.value_count as usize];
+    for (b, block) in func.blocks.iter().enumerate() {
+        for (i, inst) in block.insts.iter().enumerate() {
+            if let Some(dst) = inst.dst() {
+                definitions[dst.0 as usize] = Some((b, i));
+            }
+            if has_side_effects(inst) {
+                worklist.extend(inst.operands());
+            }
+        }
+        worklist.extend(block.term.operands());
+    }
+    while let Some(operand) = worklist.pop() {
+        let Some(value) = operand.as_value() else {
+            continue;
+        };
+        if is_live[value.0 as usize] {
+            continue;
+        }
+        is_live[value.0 as usize] = true;
+        if let Some((b, i)) = definitions[value.0 as usize] {
+            worklist.extend(func.blocks[b].insts[i].operands());
+        }
+    }
+    let mut changed = false;
+    for block in &mut func.blocks {
+        let count = block.insts.len();
+        block.insts.retain(|inst| {
+            has_side_effects(inst) || inst.dst().is_some_and(|dst| is_live[dst.0 as usize])
+        });
+        changed |= block.insts.len() != count;
+    }
+    changed
+}
+
+fn has_side_effects(inst: &Inst) -> bool {
+    matches!(inst, Inst::Store { .. } | Inst::Call { .. })
+}
@@ -0,0 +1,113 @@
+use super::{BlockId, Function};
+
+/// The immediate dominator of each block reachable from the entry,
+/// computed by the iterative algorithm of Cooper, Harvey and Kennedy.
+pub struct DominatorTree {
+    idom: Vec<Option<BlockId>>,
+    /// The position of each block in the reverse postorder.
+    order: Vec<usize>,
+}
+
+impl DominatorTree {
+    pub fn new(func: &Function) -> Self {
+        let rpo = func.reverse_postorder();
+        let predecessors = func.predecessors();
+        let mut order = vec![usize::MAX; func.blocks.len()];
+        for (i, id) in rpo.iter().enumerate() {
+            order[id.0 as usize] = i;
+        }
+        let mut idom = vec![None; func.blocks.len()];
+        idom[0] = Some(BlockId(0));
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for id in rpo.iter().skip(1) {
+                let new_idom = predecessors[id.0 as usize]
+                    .iter()
+                    .filter(|pred| idom[pred.0 as usize].is_some())
+                    .fold(None, |new_idom, pred| match new_idom {
+                        None => Some(*pred),
+                        Some(other) => Some(Self::intersect(&idom, &order, *pred, other)),
+                    });
+                if idom[id.0 as usize] != new_idom {
+                    idom[id.0 as usize] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        Self { idom, order }
+    }
+
+    fn intersect(
+        idom: &[Option<BlockId>],
+        order: &[usize],
+        mut a: BlockId,
+        mut b: BlockId,
+    ) -> BlockId {
+        while a != b {
+            while order[a.0 as usize] > order[b.0 as usize] {
+                a = idom[a.0 as usize].unwrap();
+            }
+            while order[b.0 as usize] > order[a.0 as usize] {
+                b = idom[b.0 as usize].unwrap();
+            }
+        }
+        a
+    }
+
+    /// Returns the immediate dominator of the block, `None` for the entry and unreachable blocks.
+    pub fn idom(&self, id: BlockId) -> Option<BlockId> {
+        self.idom[id.0 as usize].filter(|idom| *idom != id)
+    }
+
+    pub fn is_reachable(&self, id: BlockId) -> bool {
+        self.idom[id.0 as usize].is_some()
+    }
+
+    /// Returns whether every path from the entry to `b` goes through `a`.
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+        while self.order[b.0 as usize] > self.order[a.0 as usize] {
+            b = self.idom[b.0 as usize].unwrap();
+        }
+        a == b
+    }
+
+    /// Returns the children of each block in the tree.
+    pub fn children(&self) -> Vec<Vec<BlockId>> {
+        let mut children = vec![Vec::new(); self.idom.len()];
+        for (i, idom) in self.idom.iter().enumerate() {
+            let id = BlockId(i as u32);
+            if let Some(idom) = idom
+                && *idom != id
+            {
+                children[idom.0 as usize].push(id);
+            }
+        }
+        children
+    }
+
+    /// Returns the dominance frontier of each block: the blocks where its dominance ends,
+    /// which are the places of the phis for the definitions in it.
+    pub fn frontiers(&self, func: &Function) -> Vec<Vec<BlockId>> {
+        let mut frontiers = vec![Vec::new(); self.idom.len()];
+        for (id, predecessors) in func.predecessors().iter().enumerate() {
+            let id = BlockId(id as u32);
+            if predecessors.len() < 2 || !self.is_reachable(id) {
+                continue;
+            }
+            for pred in predecessors.iter().filter(|pred| self.is_reachable(**pred)) {
+                let mut runner = *pred;
+                while Some(runner) != self.idom(id) {
+                    if !frontiers[runner.0 as usize].contains(&id) {
+                        frontiers[runner.0 as usize].push(id);
+                    }
+                    runner = self.idom[runner.0 as usize].unwrap();
+                }
+            }
+        }
+        frontiers
+    }
+}
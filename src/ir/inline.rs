@@ -0,0 +1,299 @@
+//! Function inlining: replaces the calls of small functions, and of those declared `inline`
+//! or `__attribute__((always_inline))`, with copies of their blocks.
+//! The slots of the callee are added to the frame of the caller, and its values renumbered after the caller's.
+
+use super::{Address, BlockId, Function, Inst, Module, Operand, SlotId, Terminator, Ty, Value};
+use crate::func::InlineHint;
+
+/// The largest function inlined without a hint, in instructions and terminators.
+const INLINE_SIZE: usize = 12;
+/// The largest function declared `inline` which is inlined.
+const HINTED_INLINE_SIZE: usize = 48;
+
+/// Returns the indices of the functions of the module, each after the functions it calls
+/// except along cycles of calls, so that the callees are inlined into before their callers.
+pub fn bottom_up_order(module: &Module) -> Vec<usize> {
+    let calls = module
+        .functions
+        .iter()
+        .map(|func| {
+            func.blocks
+                .iter()
+                .flat_map(|block| &block.insts)
+                .filter_map(|inst| match inst {
+                    Inst::Call { callee, .. } => {
+                        module.functions.iter().position(|f| &f.name == callee)
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<usize>>()
+        })
+        .collect::<Vec<Vec<usize>>>();
+    let mut visited = vec![false; module.functions.len()];
+    let mut order = Vec::new();
+    for root in 0..module.functions.len() {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        // each entry is a function and the number of its calls already visited
+        let mut stack = vec![(root, 0)];
+        while let Some((i, next)) = stack.pop() {
+            if let Some(callee) = calls[i].get(next) {
+                stack.push((i, next + 1));
+                if !visited[*callee] {
+                    visited[*callee] = true;
+                    stack.push((*callee, 0));
+                }
+            } else {
+                order.push(i);
+            }
+        }
+    }
+    order
+}
+
+/// Returns the number of instructions of the function, counting the terminators but not the phis.
+fn size(func: &Function) -> usize {
+    func.blocks
+        .iter()
+        .map(|block| block.insts.len() - block.phis().len() + 1)
+        .sum()
+}
+
+/// Returns whether the calls of the function are inlined, where only the `always_inline` ones are
+/// unless `by_size`. The recursive functions are never inlined.
+fn should_inline(callee: &Function, by_size: bool) -> bool {
+    let is_recursive = callee
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .any(|inst| matches!(inst, Inst::Call { callee: name, .. } if *name == callee.name));
+    if callee.blocks.is_empty() || is_recursive {
+        return false;
+    }
+    match callee.inline {
+        InlineHint::Always => true,
+        InlineHint::Never => false,
+        _ if !by_size => false,
+        InlineHint::Inline => size(callee) <= HINTED_INLINE_SIZE,
+        InlineHint::None => size(callee) <= INLINE_SIZE,
+    }
+}
+
+/// Inlines the calls of the function to the other functions of the module which should be,
+/// and returns whether the function is changed.
+pub fn inline(func: &mut Function, module: &Module, by_size: bool) -> bool {
+    let name = func.name.clone();
+    let callee_of = |inst: &Inst| match inst {
+        Inst::Call { callee, args, .. } if *callee != name => module
+            .functions
+            .iter()
+            .find(|f| f.name == *callee)
+            .filter(|f| f.params.len() == args.len() && should_inline(f, by_size)),
+        _ => None,
+    };
+    let mut changed = false;
+    // the blocks copied from the callees are not searched, so that the cycles of calls are inlined once
+    let mut worklist = func.block_ids().collect::<Vec<BlockId>>();
+    while let Some(id) = worklist.pop() {
+        let Some((index, callee)) = func
+            .block(id)
+            .insts
+            .iter()
+            .enumerate()
+            .find_map(|(i, inst)| callee_of(inst).map(|callee| (i, callee)))
+        else {
+            continue;
+        };
+        worklist.push(inline_call(func, id, index, callee));
+        changed = true;
+    }
+    if changed {
+        // the continuations of the callees which never return
+        func.remove_unreachable_blocks();
+    }
+    changed
+}
+
+/// Replaces the call at the index of the block with the blocks of the callee, and returns
+/// the block continuing after the call, which takes the instructions following it.
+fn inline_call(func: &mut Function, id: BlockId, index: usize, callee: &Function) -> BlockId {
+    let continuation = BlockId(func.blocks.len() as u32);
+    let first_block = continuation.0 + 1;
+    let first_value = func.value_count;
+    let first_slot = func.slots.len() as u32;
+    let value = |value: Value| Value(value.0 + first_value);
+    let block_id = |id: BlockId| BlockId(id.0 + first_block);
+    func.value_count += callee.value_count;
+    func.slots.extend(&callee.slots);
+
+    let block = func.block_mut(id);
+    let rest = block.insts.split_off(index + 1);
+    let Some(Inst::Call { dst, args, .. }) = block.insts.pop() else {
+        unreachable!("call expected")
+    };
+    // the arguments are converted to the types of the parameters as the callee does on entry
+    let params = callee.params.iter().zip(&callee.signature.params);
+    for ((param, ty), (arg_ty, arg)) in params.zip(args) {
+        let dst = value(*param);
+        let inst = if *ty != Ty::Ptr && ty.size() < arg_ty.size() {
+            Inst::Extend {
+                dst,
+                ty: *ty,
+                src: arg,
+            }
+        } else {
+            Inst::Copy { dst, src: arg }
+        };
+        block.insts.push(inst);
+    }
+    let term = std::mem::replace(&mut block.term, Terminator::Jump(block_id(BlockId(0))));
+    for succ in term.successors() {
+        for inst in &mut func.block_mut(succ).insts {
+            if let Inst::Phi { args, .. } = inst {
+                for (pred, _) in args.iter_mut().filter(|(pred, _)| *pred == id) {
+                    *pred = continuation;
+                }
+            }
+        }
+    }
+
+    let mut returns = Vec::new();
+    let blocks = callee.blocks.iter().enumerate().map(|(i, block)| {
+        let mut block = block.clone();
+        for inst in &mut block.insts {
+            if let Some(dst) = inst.dst_mut() {
+                *dst = value(*dst);
+            }
+            for operand in inst.operands_mut() {
+                if let Operand::Value(v) = operand {
+                    *v = value(*v);
+                }
+            }
+            match inst {
+                Inst::SlotAddr { slot, .. }
+                | Inst::Load {
+                    addr: Address::Slot(slot),
+                    ..
+                }
+                | Inst::Store {
+                    addr: Address::Slot(slot),
+                    ..
+                } => *slot = SlotId(slot.0 + first_slot),
+                Inst::Phi { args, .. } => {
+                    for (pred, _) in args {
+                        *pred = block_id(*pred);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for operand in block.term.operands_mut() {
+            if let Operand::Value(v) = operand {
+                *v = value(*v);
+            }
+        }
+        for succ in block.term.successors_mut() {
+            *succ = block_id(*succ);
+        }
+        if let Terminator::Return(result) = block.term {
+            returns.push((block_id(BlockId(i as u32)), result));
+            block.term = Terminator::Jump(continuation);
+        }
+        block
+    });
+    let blocks = blocks.collect::<Vec<_>>();
+
+    let result = match returns.as_slice() {
+        [(_, result)] => Inst::Copy { dst, src: *result },
+        _ => Inst::Phi { dst, args: returns },
+    };
+    func.blocks.push(super::Block {
+        insts: [result].into_iter().chain(rest).collect(),
+        term,
+    });
+    func.blocks.extend(blocks);
+    continuation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::opt::{OptLevel, OptOptions};
+    use crate::{CompileOptions, Compiler};
+
+    fn lower(code: &str, opt: OptOptions) -> Module {
+        let compiler = Compiler::new(code, CompileOptions::default().with_opt(opt));
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        compiler.lower(&ast)
+    }
+
+    fn calls(func: &Function) -> Vec<&str> {
+        func.blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .filter_map(|inst| match inst {
+                Inst::Call { callee, .. } => Some(callee.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_inline() {
+        let code = "int sq(int x) { return x * x; }
+int max(int a, int b) { if (a < b) return b; return a; }
+int main() { return max(sq(3), 2) + sq(4); }";
+        let module = lower(code, OptOptions::new(OptLevel::O2));
+        assert_eq!(
+            module.functions[2].to_string(),
+            "define i32 @main() {
+bb0:
+  ret 25
+}"
+        );
+        // the callees are kept for the other translation units
+        assert_eq!(module.functions.len(), 3);
+    }
+
+    #[test]
+    fn test_inline_hints() {
+        let code = "int __attribute__((noinline)) one() { return 1; }
+__attribute__((always_inline)) int two() { return 2; }
+int fact(int n) { if (n < 2) return 1; return n * fact(n - 1); }
+int main() { return one() + two() + fact(3); }";
+        let module = lower(code, OptOptions::new(OptLevel::O2));
+        assert_eq!(calls(&module.functions[3]), ["fact", "one"]);
+        // `always_inline` is honored without the pass
+        let module = lower(code, OptOptions::new(OptLevel::O0));
+        assert_eq!(calls(&module.functions[3]), ["fact", "one"]);
+        let mut options = OptOptions::new(OptLevel::O2);
+        options.apply_flag("no-inline");
+        let module = lower(code, options);
+        assert_eq!(calls(&module.functions[3]), ["fact", "one"]);
+    }
+
+    #[test]
+    fn test_inline_remaps_the_slots() {
+        let code = "inline int get(int i) { int a[2]; a[0] = 5; a[1] = i; return a[i]; }
+int main() { int b[3]; b[2] = get(1); return b[2]; }";
+        let mut options = OptOptions::new(OptLevel::O0);
+        options.apply_flag("inline");
+        let module = lower(code, options);
+        let main = &module.functions[1];
+        assert!(calls(main).is_empty());
+        assert_eq!(
+            main.slots.iter().map(|slot| slot.size).collect::<Vec<_>>(),
+            [12, 8]
+        );
+        assert!(
+            main.blocks
+                .iter()
+                .flat_map(|block| &block.insts)
+                .any(|inst| matches!(inst, Inst::SlotAddr { slot, .. } if *slot == SlotId(1)))
+        );
+    }
+}
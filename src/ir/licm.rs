@@ -0,0 +1,159 @@
+//! Loop-invariant code motion: moves the pure instructions of each loop whose operands
+//! are not computed in it to the preheader of the loop, so that they are evaluated once
+//! before it instead of on every iteration.
+
+use super::loops::{self, Loop};
+use super::{BinOp, Function, Inst, Operand, Value};
+use std::collections::HashSet;
+
+/// Returns whether the function is changed.
+pub fn licm(func: &mut Function) -> bool {
+    let mut loops = loops::find_loops(func);
+    let mut changed = false;
+    // the inner loops come first, so that what they hoist can be hoisted again from the outer ones
+    for i in 0..loops.len() {
+        let invariants = invariants(func, &loops[i]);
+        if invariants.is_empty() {
+            continue;
+        }
+        let preheader = loops::preheader(func, &mut loops, i);
+        let mut hoisted = Vec::new();
+        for id in &loops[i].blocks {
+            let (moved, kept) = std::mem::take(&mut func.block_mut(*id).insts)
+                .into_iter()
+                .partition(|inst| inst.dst().is_some_and(|dst| invariants.contains(&dst)));
+            func.block_mut(*id).insts = kept;
+            hoisted.extend::<Vec<Inst>>(moved);
+        }
+        func.block_mut(preheader).insts.extend(hoisted);
+        changed = true;
+    }
+    changed
+}
+
+/// Returns the results of the instructions of the loop which can be hoisted out of it.
+fn invariants(func: &Function, lp: &Loop) -> HashSet<Value> {
+    let defined = lp
+        .blocks
+        .iter()
+        .flat_map(|id| &func.block(*id).insts)
+        .filter_map(|inst| inst.dst())
+        .collect::<HashSet<Value>>();
+    let mut invariants = HashSet::new();
+    // the operands are defined before their uses in the reverse postorder, except in phis
+    for inst in lp.blocks.iter().flat_map(|id| &func.block(*id).insts) {
+        let is_invariant = inst.operands().iter().all(|operand| match operand {
+            Operand::Value(value) => !defined.contains(value) || invariants.contains(value),
+            Operand::Const(_) => true,
+        });
+        if is_invariant && can_hoist(inst) {
+            invariants.insert(inst.dst().unwrap());
+        }
+    }
+    invariants
+}
+
+/// Returns whether the instruction can be evaluated where it may not have been,
+/// when the loop is not entered or not on the path through its body.
+fn can_hoist(inst: &Inst) -> bool {
+    match inst {
+        // a division can trap, unless by a constant which is neither 0 nor -1
+        Inst::Binary {
+            op: BinOp::Div | BinOp::Mod,
+            rhs,
+            ..
+        } => matches!(rhs, Operand::Const(c) if *c != 0 && *c != -1),
+        Inst::Copy { .. }
+        | Inst::Binary { .. }
+        | Inst::Not { .. }
+        | Inst::Extend { .. }
+        | Inst::SlotAddr { .. }
+        | Inst::SymbolAddr { .. } => true,
+        Inst::Load { .. } | Inst::Store { .. } | Inst::Call { .. } | Inst::Phi { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::opt::{OptLevel, OptOptions};
+    use crate::ir::{BlockId, Module};
+    use crate::{CompileOptions, Compiler};
+
+    fn lower(code: &str, flags: &[&str]) -> Module {
+        let mut options = OptOptions::new(OptLevel::O1);
+        for flag in flags {
+            options.apply_flag(flag);
+        }
+        let compiler = Compiler::new(code, CompileOptions::default().with_opt(options));
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        compiler.lower(&ast)
+    }
+
+    /// Returns the number of instructions of the function in its loops.
+    fn insts_in_loops(func: &Function) -> usize {
+        loops::find_loops(func)
+            .iter()
+            .flat_map(|lp| &lp.blocks)
+            .collect::<HashSet<&BlockId>>()
+            .iter()
+            .map(|id| func.block(**id).insts.len())
+            .sum()
+    }
+
+    #[test]
+    fn test_licm() {
+        let code = "int f(int n, int k) { int i; int j; int s; s = 0;
+for (i = 0; i < n; i = i + 1) for (j = 0; j < n; j = j + 1) s = s + (k * 3 + n) / 4 + i * k;
+return s; }";
+        let func = &lower(code, &["licm"]).functions[0];
+        let without = &lower(code, &[]).functions[0];
+        // `k * 3 + n` and its quotient leave both loops, and `i * k` the inner one
+        assert_eq!(insts_in_loops(without) - insts_in_loops(func), 3);
+        let hoisted = func
+            .blocks
+            .iter()
+            .position(|block| {
+                block.insts.iter().any(|inst| {
+                    matches!(
+                        inst,
+                        Inst::Binary {
+                            op: BinOp::Div,
+                            rhs: Operand::Const(4),
+                            ..
+                        }
+                    )
+                })
+            })
+            .unwrap();
+        assert!(
+            loops::find_loops(func)
+                .iter()
+                .all(|lp| !lp.contains(BlockId(hoisted as u32)))
+        );
+    }
+
+    #[test]
+    fn test_licm_keeps_what_can_trap() {
+        let code = "int a[4];
+int f(int n, int d) { int i; int s; s = 0; for (i = 0; i < n; i = i + 1) s = s + n / d + a[d] + g(d); return s; }";
+        let func = &lower(code, &["licm"]).functions[0];
+        let without = &lower(code, &[]).functions[0];
+        // only the address of `a[d]` is computed before the loop
+        assert_eq!(insts_in_loops(without) - insts_in_loops(func), 3);
+        for lp in loops::find_loops(func) {
+            let insts = lp.blocks.iter().flat_map(|id| &func.block(*id).insts);
+            assert_eq!(
+                insts
+                    .filter(|inst| matches!(
+                        inst,
+                        Inst::Binary { op: BinOp::Div, .. } | Inst::Load { .. } | Inst::Call { .. }
+                    ))
+                    .count(),
+                3
+            );
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Natural loops: each made of a header dominating the blocks which branch back to it,
+//! and of the blocks from which those reach the header without going through it.
+
+use super::dominators::DominatorTree;
+use super::{Block, BlockId, Function, Inst, Terminator};
+
+pub struct Loop {
+    pub header: BlockId,
+    /// The blocks of the loop in reverse postorder, starting with the header.
+    pub blocks: Vec<BlockId>,
+    /// The blocks branching back to the header.
+    pub latches: Vec<BlockId>,
+}
+
+impl Loop {
+    pub fn contains(&self, id: BlockId) -> bool {
+        self.blocks.contains(&id)
+    }
+}
+
+/// Returns the natural loops of the function, each before the loops containing it.
+/// The back edges to the same header make one loop.
+pub fn find_loops(func: &Function) -> Vec<Loop> {
+    let dominators = DominatorTree::new(func);
+    let predecessors = func.predecessors();
+    let rpo = func.reverse_postorder();
+    let mut order = vec![usize::MAX; func.blocks.len()];
+    for (i, id) in rpo.iter().enumerate() {
+        order[id.0 as usize] = i;
+    }
+    let mut loops = Vec::new();
+    for header in &rpo {
+        let latches = predecessors[header.0 as usize]
+            .iter()
+            .copied()
+            .filter(|pred| dominators.dominates(*header, *pred))
+            .collect::<Vec<BlockId>>();
+        if latches.is_empty() {
+            continue;
+        }
+        let mut blocks = vec![*header];
+        let mut worklist = latches.clone();
+        while let Some(id) = worklist.pop() {
+            if blocks.contains(&id) {
+                continue;
+            }
+            blocks.push(id);
+            worklist.extend(
+                predecessors[id.0 as usize]
+                    .iter()
+                    .filter(|pred| dominators.is_reachable(**pred)),
+            );
+        }
+        blocks.sort_by_key(|id| order[id.0 as usize]);
+        loops.push(Loop {
+            header: *header,
+            blocks,
+            latches,
+        });
+    }
+    // a loop inside another has fewer blocks
+    loops.sort_by_key(|l| l.blocks.len());
+    loops
+}
+
+/// Returns the preheader of the loop, the block outside it which alone enters the header,
+/// and creates one if the header is entered from other blocks. The new preheader takes
+/// the operands of the header's phis given for those blocks, and is added to the loops
+/// after this one which contain it.
+pub fn preheader(func: &mut Function, loops: &mut [Loop], index: usize) -> BlockId {
+    let header = loops[index].header;
+    let entries = func.predecessors()[header.0 as usize]
+        .iter()
+        .copied()
+        .filter(|pred| !loops[index].contains(*pred))
+        .collect::<Vec<BlockId>>();
+    if let [entry] = entries[..]
+        && func.block(entry).term == Terminator::Jump(header)
+    {
+        return entry;
+    }
+    let preheader = BlockId(func.blocks.len() as u32);
+    for entry in &entries {
+        for succ in func.block_mut(*entry).term.successors_mut() {
+            if *succ == header {
+                *succ = preheader;
+            }
+        }
+    }
+    let mut phis = Vec::new();
+    for i in 0..func.block(header).phis().len() {
+        let Inst::Phi { args, .. } = &func.block(header).insts[i] else {
+            unreachable!("phi expected")
+        };
+        let (outside, mut inside): (Vec<_>, Vec<_>) =
+            args.iter().partition(|(pred, _)| entries.contains(pred));
+        let arg = match outside[..] {
+            [(_, arg)] => arg,
+            _ => {
+                let dst = func.new_value();
+                phis.push(Inst::Phi { dst, args: outside });
+                dst.into()
+            }
+        };
+        inside.push((preheader, arg));
+        let Inst::Phi { args, .. } = &mut func.block_mut(header).insts[i] else {
+            unreachable!("phi expected")
+        };
+        *args = inside;
+    }
+    func.blocks.push(Block {
+        insts: phis,
+        term: Terminator::Jump(header),
+    });
+    for outer in loops[index + 1..].iter_mut() {
+        if let Some(position) = outer.blocks.iter().position(|id| *id == header) {
+            outer.blocks.insert(position, preheader);
+        }
+    }
+    preheader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::opt::{OptLevel, OptOptions};
+    use crate::{CompileOptions, Compiler};
+
+    #[test]
+    fn test_find_loops() {
+        let code = "int f(int n) { int i; int j;
+for (i = 0; i < n; i = i + 1) { j = 0; while (j < i) { j = j + 1; if (j == 3) g(j); else h(j); } }
+return 0; }";
+        let mut options = OptOptions::new(OptLevel::O0);
+        options.apply_flag("simplify-cfg");
+        let compiler = Compiler::new(code, CompileOptions::default().with_opt(options));
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        let mut func = compiler.lower(&ast).functions.remove(0);
+        let mut loops = find_loops(&func);
+        assert_eq!(loops.len(), 2);
+        let (inner, outer) = (&loops[0], &loops[1]);
+        assert!(inner.blocks.iter().all(|id| outer.contains(*id)));
+        assert!(outer.blocks.len() > inner.blocks.len());
+        // both branches of the `if` go back to the inner header, once their join is threaded
+        assert_eq!(inner.latches.len(), 2);
+        assert_eq!(outer.latches.len(), 1);
+        assert_eq!(inner.blocks[0], inner.header);
+
+        // the inner loop is entered by a branch, so a preheader is made for it
+        let header = loops[0].header;
+        let block_count = func.blocks.len();
+        let made = preheader(&mut func, &mut loops, 0);
+        assert_eq!(made, BlockId(block_count as u32));
+        assert_eq!(func.block(made).term, Terminator::Jump(header));
+        assert!(loops[1].contains(made));
+        let predecessors = func.predecessors();
+        assert!(
+            predecessors[header.0 as usize]
+                .iter()
+                .all(|pred| *pred == made || loops[0].contains(*pred))
+        );
+        assert_eq!(preheader(&mut func, &mut loops, 0), made);
+        // the outer loop is entered by a jump from the entry
+        assert_eq!(preheader(&mut func, &mut loops, 1), BlockId(0));
+    }
+}
@@ -0,0 +1,588 @@
+use super::{
+    Address, BinOp, Block, BlockId, Function, Inst, Module, Operand, Signature, Slot, SlotId,
+    Symbol, Terminator, Ty,
+};
+use crate::ast::{Identifier, ProgramAst, reserved_functions};
+use crate::ctype::Type;
+use crate::func::Func;
+use crate::node::{BinaryOp, NodeArena, NodeId, NodeKind, UnaryOp};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+
+/// Lowers an analyzed program, which must have no errors, to the mid-level IR.
+pub fn lower(ast: &ProgramAst) -> Module {
+    let functions = ast
+        .functions
+        .iter()
+        .filter_map(|(name, func)| {
+            func.body
+                .map(|body| FunctionLowering::new(&ast.nodes, name, func).lower(&func.args, body))
+        })
+        .collect::<Vec<Function>>();
+    let reserved = reserved_functions();
+    let mut externs = IndexMap::new();
+    for callee in functions.iter().flat_map(callees) {
+        if functions.iter().any(|f| f.name == callee) || externs.contains_key(&callee) {
+            continue;
+        }
+        let signature = match (ast.functions.get(&callee), reserved.get(&callee)) {
+            (Some(func), _) => {
+                let signature = signature_of(&func.cty);
+                Signature {
+                    // declared as `int name()`, without a prototype
+                    is_variadic: signature.params.is_empty(),
+                    ..signature
+                }
+            }
+            (None, Some(Identifier::Static(ty))) => Signature {
+                is_variadic: true,
+                ..signature_of(ty)
+            },
+            _ => unreachable!("undeclared function '{}'", callee),
+        };
+        externs.insert(callee, signature);
+    }
+    Module {
+        functions,
+        externs,
+        global_variables: ast.global_variables.clone(),
+        string_literals: ast.string_literals.clone(),
+    }
+}
+
+fn signature_of(ty: &Type) -> Signature {
+    let Type::Func(params, ret) = ty else {
+        unreachable!("not a function type: {}", ty)
+    };
+    Signature {
+        params: params.iter().map(Ty::of).collect(),
+        ret: Ty::of(ret),
+        is_variadic: false,
+    }
+}
+
+fn callees(func: &Function) -> Vec<String> {
+    func.blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match inst {
+            Inst::Call { callee, .. } => Some(callee.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn align_of(ty: &Type) -> usize {
+    match ty {
+        Type::Arr(elem, _) => align_of(elem),
+        _ => ty.size_of(),
+    }
+}
+
+/// A block whose terminator has not been lowered yet.
+struct PartialBlock {
+    insts: Vec<Inst>,
+    term: Option<Terminator>,
+}
+
+struct FunctionLowering<'a> {
+    nodes: &'a NodeArena,
+    func: Function,
+    blocks: Vec<PartialBlock>,
+    current: BlockId,
+    /// The slot of each local variable, by its offset.
+    slots: HashMap<usize, SlotId>,
+    /// The blocks which `break` goes to, innermost last.
+    break_targets: Vec<BlockId>,
+}
+
+impl<'a> FunctionLowering<'a> {
+    fn new(nodes: &'a NodeArena, name: &str, func: &Func) -> Self {
+        Self {
+            nodes,
+            func: Function {
+                name: name.to_string(),
+                signature: signature_of(&func.cty),
+                params: Vec::new(),
+                slots: Vec::new(),
+                blocks: Vec::new(),
+                value_count: 0,
+                inline: func.inline,
+            },
+            blocks: vec![PartialBlock {
+                insts: Vec::new(),
+                term: None,
+            }],
+            current: BlockId(0),
+            slots: HashMap::new(),
+            break_targets: Vec::new(),
+        }
+    }
+
+    fn lower(mut self, args: &[NodeId], body: NodeId) -> Function {
+        for (arg, ty) in args.iter().zip(self.func.signature.params.clone()) {
+            let param = self.func.new_value();
+            self.func.params.push(param);
+            let addr = self.address(*arg);
+            self.emit(Inst::Store {
+                ty,
+                addr,
+                value: param.into(),
+            });
+        }
+        self.stmt(body);
+        let mut func = self.func;
+        func.blocks = self
+            .blocks
+            .into_iter()
+            .map(|block| Block {
+                insts: block.insts,
+                // falling off the end returns 0, as `main` does
+                term: block.term.unwrap_or(Terminator::Return(Operand::Const(0))),
+            })
+            .collect();
+        func.remove_unreachable_blocks();
+        func
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(PartialBlock {
+            insts: Vec::new(),
+            term: None,
+        });
+        BlockId(self.blocks.len() as u32 - 1)
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.blocks[self.current.0 as usize].insts.push(inst);
+    }
+
+    /// Ends the current block, and continues in a new one, which is unreachable unless jumped to.
+    fn terminate(&mut self, term: Terminator) {
+        self.blocks[self.current.0 as usize].term = Some(term);
+        self.current = self.new_block();
+    }
+
+    /// Ends the current block with a jump to the block, and continues in it.
+    fn jump_to(&mut self, target: BlockId) {
+        self.blocks[self.current.0 as usize].term = Some(Terminator::Jump(target));
+        self.current = target;
+    }
+
+    fn ty(&self, node: NodeId) -> Type {
+        self.nodes.resolve_type(node).unwrap_or_default()
+    }
+
+    fn slot(&mut self, offset: usize, ty: &Type) -> SlotId {
+        if let Some(slot) = self.slots.get(&offset) {
+            return *slot;
+        }
+        let slot = self.new_slot(ty);
+        self.slots.insert(offset, slot);
+        slot
+    }
+
+    fn new_slot(&mut self, ty: &Type) -> SlotId {
+        self.func.slots.push(Slot {
+            size: ty.size_of(),
+            align: align_of(ty),
+        });
+        SlotId(self.func.slots.len() as u32 - 1)
+    }
+
+    fn binary(&mut self, op: BinOp, lhs: Operand, rhs: Operand) -> Operand {
+        let dst = self.func.new_value();
+        self.emit(Inst::Binary { dst, op, lhs, rhs });
+        dst.into()
+    }
+
+    fn load(&mut self, ty: Ty, addr: Address) -> Operand {
+        let dst = self.func.new_value();
+        self.emit(Inst::Load { dst, ty, addr });
+        dst.into()
+    }
+
+    fn stmt(&mut self, node: NodeId) {
+        let nodes = self.nodes;
+        match &nodes[node].kind {
+            NodeKind::Block(children) | NodeKind::DefVar(children) => {
+                children.iter().for_each(|child| self.stmt(*child));
+            }
+            NodeKind::If { cond, then, els } => {
+                let cond = self.expr(*cond);
+                let then_block = self.new_block();
+                let else_block = self.new_block();
+                let end_block = els.map_or(else_block, |_| self.new_block());
+                self.blocks[self.current.0 as usize].term = Some(Terminator::Branch {
+                    cond,
+                    then: then_block,
+                    els: else_block,
+                });
+                self.current = then_block;
+                self.stmt(*then);
+                self.jump_to(end_block);
+                if let Some(els) = els {
+                    self.current = else_block;
+                    self.stmt(*els);
+                    self.jump_to(end_block);
+                }
+            }
+            NodeKind::While { cond, body } => self.lower_loop(Some(*cond), None, *body),
+            NodeKind::For {
+                ini,
+                cond,
+                upd,
+                body,
+            } => {
+                if let Some(ini) = ini {
+                    self.stmt(*ini);
+                }
+                self.lower_loop(*cond, *upd, *body);
+            }
+            NodeKind::Break => {
+                let target = *self.break_targets.last().unwrap();
+                self.terminate(Terminator::Jump(target));
+            }
+            NodeKind::Return(value) => {
+                let value = self.expr(*value);
+                self.terminate(Terminator::Return(value));
+            }
+            _ => {
+                self.expr(node);
+            }
+        }
+    }
+
+    fn lower_loop(&mut self, cond: Option<NodeId>, upd: Option<NodeId>, body: NodeId) {
+        let cond_block = self.new_block();
+        let body_block = self.new_block();
+        let end_block = self.new_block();
+        self.jump_to(cond_block);
+        match cond {
+            Some(cond) => {
+                let cond = self.expr(cond);
+                self.blocks[self.current.0 as usize].term = Some(Terminator::Branch {
+                    cond,
+                    then: body_block,
+                    els: end_block,
+                });
+                self.current = body_block;
+            }
+            None => self.jump_to(body_block),
+        }
+        self.break_targets.push(end_block);
+        self.stmt(body);
+        self.break_targets.pop();
+        if let Some(upd) = upd {
+            self.expr(upd);
+        }
+        self.jump_to(cond_block);
+        self.current = end_block;
+    }
+
+    /// Returns where the value of an lvalue is.
+    fn address(&mut self, node: NodeId) -> Address {
+        let nodes = self.nodes;
+        match &nodes[node].kind {
+            NodeKind::LocalVar(offset) => {
+                let ty = self.ty(node);
+                Address::Slot(self.slot(*offset, &ty))
+            }
+            NodeKind::GlobalVar(name) => {
+                Address::Ptr(self.symbol_addr(Symbol::Global(name.clone())))
+            }
+            NodeKind::StringLiteral(index) => Address::Ptr(self.symbol_addr(Symbol::Str(*index))),
+            NodeKind::Unary(UnaryOp::Deref, pointer) => Address::Ptr(self.expr(*pointer)),
+            kind => unreachable!("not an lvalue: {}", kind.name()),
+        }
+    }
+
+    fn address_value(&mut self, node: NodeId) -> Operand {
+        match self.address(node) {
+            Address::Slot(slot) => {
+                let dst = self.func.new_value();
+                self.emit(Inst::SlotAddr { dst, slot });
+                dst.into()
+            }
+            Address::Ptr(addr) => addr,
+        }
+    }
+
+    fn symbol_addr(&mut self, symbol: Symbol) -> Operand {
+        let dst = self.func.new_value();
+        self.emit(Inst::SymbolAddr { dst, symbol });
+        dst.into()
+    }
+
+    /// Loads the value of an lvalue, or takes the address of an array.
+    fn load_lvalue(&mut self, node: NodeId) -> Operand {
+        match self.ty(node) {
+            Type::Arr(..) => self.address_value(node),
+            ty => {
+                let addr = self.address(node);
+                self.load(Ty::of(&ty), addr)
+            }
+        }
+    }
+
+    fn expr(&mut self, node: NodeId) -> Operand {
+        let nodes = self.nodes;
+        match &nodes[node].kind {
+            NodeKind::Num(value) => Operand::Const(*value as i64),
+            NodeKind::LocalVar(_)
+            | NodeKind::GlobalVar(_)
+            | NodeKind::StringLiteral(_)
+            | NodeKind::Unary(UnaryOp::Deref, _) => self.load_lvalue(node),
+            NodeKind::Unary(UnaryOp::Addr, operand) => self.address_value(*operand),
+            NodeKind::Assign(lhs, rhs) => {
+                let addr = self.address(*lhs);
+                let value = self.expr(*rhs);
+                let ty = Ty::of(&self.ty(*lhs));
+                self.emit(Inst::Store { ty, addr, value });
+                value
+            }
+            NodeKind::Binary(op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), lhs, rhs) => {
+                self.logical(*op, *lhs, *rhs)
+            }
+            NodeKind::Binary(op, lhs, rhs) => {
+                // the right operand is evaluated first
+                let rhs_value = self.expr(*rhs);
+                let lhs_value = self.expr(*lhs);
+                self.arithmetic(*op, *lhs, *rhs, lhs_value, rhs_value)
+            }
+            NodeKind::Unary(UnaryOp::BitNot, operand) => {
+                let src = self.expr(*operand);
+                let dst = self.func.new_value();
+                self.emit(Inst::Not { dst, src });
+                dst.into()
+            }
+            NodeKind::Unary(UnaryOp::Cast, operand) => {
+                let src = self.expr(*operand);
+                let from = self.ty(*operand);
+                match self.ty(node) {
+                    ty @ (Type::I8 | Type::I32)
+                        if !(from.is_integer() && from.size_of() <= ty.size_of()) =>
+                    {
+                        let dst = self.func.new_value();
+                        self.emit(Inst::Extend {
+                            dst,
+                            ty: Ty::of(&ty),
+                            src,
+                        });
+                        dst.into()
+                    }
+                    _ => src,
+                }
+            }
+            NodeKind::Unary(op @ (UnaryOp::SuffixIncr | UnaryOp::SuffixDecr), operand) => {
+                let addr = self.address(*operand);
+                let ty = self.ty(*operand);
+                let old = self.load(Ty::of(&ty), addr);
+                let step = ty.dest_type().map_or(1, |elem| elem.size_of()) as i64;
+                let op = match op {
+                    UnaryOp::SuffixIncr => BinOp::Add,
+                    _ => BinOp::Sub,
+                };
+                let new = self.binary(op, old, Operand::Const(step));
+                self.emit(Inst::Store {
+                    ty: Ty::of(&ty),
+                    addr,
+                    value: new,
+                });
+                old
+            }
+            NodeKind::CallFunc { callee, args } => {
+                let NodeKind::GlobalVar(callee) = &nodes[*callee].kind else {
+                    unreachable!("function name expected")
+                };
+                // arguments are stored and evaluated from right to left
+                let mut args = args
+                    .iter()
+                    .map(|arg| (Ty::of(&self.ty(*arg)), self.expr(*arg)))
+                    .collect::<Vec<(Ty, Operand)>>();
+                args.reverse();
+                let dst = self.func.new_value();
+                self.emit(Inst::Call {
+                    dst,
+                    callee: callee.clone(),
+                    args,
+                });
+                dst.into()
+            }
+            NodeKind::Conditional { cond, then, els } => {
+                let ty = self.ty(node);
+                let slot = self.new_slot(&ty);
+                let cond = self.expr(*cond);
+                let then_block = self.new_block();
+                let else_block = self.new_block();
+                let end_block = self.new_block();
+                self.blocks[self.current.0 as usize].term = Some(Terminator::Branch {
+                    cond,
+                    then: then_block,
+                    els: else_block,
+                });
+                for (block, value) in [(then_block, *then), (else_block, *els)] {
+                    self.current = block;
+                    let value = self.expr(value);
+                    self.emit(Inst::Store {
+                        ty: Ty::of(&ty),
+                        addr: Address::Slot(slot),
+                        value,
+                    });
+                    self.jump_to(end_block);
+                }
+                self.current = end_block;
+                self.load(Ty::of(&ty), Address::Slot(slot))
+            }
+            kind => unreachable!("not an expression: {}", kind.name()),
+        }
+    }
+
+    /// Lowers `&&` and `||`, which give 0 or 1 and evaluate the right operand only if needed.
+    fn logical(&mut self, op: BinaryOp, lhs: NodeId, rhs: NodeId) -> Operand {
+        let slot = self.new_slot(&Type::I32);
+        let short_circuit = match op {
+            BinaryOp::LogicalAnd => 0,
+            _ => 1,
+        };
+        self.emit(Inst::Store {
+            ty: Ty::I32,
+            addr: Address::Slot(slot),
+            value: Operand::Const(short_circuit),
+        });
+        let cond = self.expr(lhs);
+        let rhs_block = self.new_block();
+        let end_block = self.new_block();
+        let (then, els) = match op {
+            BinaryOp::LogicalAnd => (rhs_block, end_block),
+            _ => (end_block, rhs_block),
+        };
+        self.blocks[self.current.0 as usize].term = Some(Terminator::Branch { cond, then, els });
+        self.current = rhs_block;
+        let value = self.expr(rhs);
+        let value = self.binary(BinOp::Ne, value, Operand::Const(0));
+        self.emit(Inst::Store {
+            ty: Ty::I32,
+            addr: Address::Slot(slot),
+            value,
+        });
+        self.jump_to(end_block);
+        self.load(Ty::I32, Address::Slot(slot))
+    }
+
+    /// Lowers a binary operator other than `&&` and `||`, scaling the pointer arithmetic.
+    fn arithmetic(
+        &mut self,
+        op: BinaryOp,
+        lhs: NodeId,
+        rhs: NodeId,
+        lhs_value: Operand,
+        rhs_value: Operand,
+    ) -> Operand {
+        let op = match op {
+            BinaryOp::Add => BinOp::Add,
+            BinaryOp::Sub => BinOp::Sub,
+            BinaryOp::Mul => BinOp::Mul,
+            BinaryOp::Div => BinOp::Div,
+            BinaryOp::Mod => BinOp::Mod,
+            BinaryOp::Eq => BinOp::Eq,
+            BinaryOp::Ne => BinOp::Ne,
+            BinaryOp::Lt => BinOp::Lt,
+            BinaryOp::Le => BinOp::Le,
+            BinaryOp::BitLeft => BinOp::Shl,
+            BinaryOp::BitRight => BinOp::Shr,
+            BinaryOp::BitAnd => BinOp::And,
+            BinaryOp::BitXor => BinOp::Xor,
+            BinaryOp::BitOr => BinOp::Or,
+            BinaryOp::LogicalAnd | BinaryOp::LogicalOr => unreachable!(),
+        };
+        let elem_size = match op {
+            BinOp::Add | BinOp::Sub => self.ty(lhs).dest_type().map(|elem| elem.size_of()),
+            _ => None,
+        };
+        match elem_size {
+            // the difference of two pointers is in elements
+            Some(size) if self.ty(rhs).dest_type().is_some() => {
+                let bytes = self.binary(op, lhs_value, rhs_value);
+                self.scale(BinOp::Div, bytes, size)
+            }
+            Some(size) => {
+                let offset = self.scale(BinOp::Mul, rhs_value, size);
+                self.binary(op, lhs_value, offset)
+            }
+            None => self.binary(op, lhs_value, rhs_value),
+        }
+    }
+
+    fn scale(&mut self, op: BinOp, value: Operand, size: usize) -> Operand {
+        if size == 1 {
+            return value;
+        }
+        self.binary(op, value, Operand::Const(size as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::verify::verify;
+    use crate::{CompileOptions, Compiler};
+
+    fn lower(code: &str) -> super::Module {
+        let compiler = Compiler::new(code, CompileOptions::default());
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        super::lower(&ast)
+    }
+
+    #[test]
+    fn test_lower_loop() {
+        let module =
+            lower("int main() { int i; int s; s = 0; for (i = 0; i < 3; i++) s += i; return s; }");
+        assert_eq!(verify(&module), Ok(()));
+        assert_eq!(
+            module.to_string(),
+            "define i32 @main() {
+  $0 = slot 4, align 4
+  $1 = slot 4, align 4
+bb0:
+  store i32 [$0], 0
+  store i32 [$1], 0
+  jmp bb1
+bb1:
+  %0 = load i32 [$1]
+  %1 = lt %0, 3
+  br %1, bb2, bb3
+bb2:
+  %2 = load i32 [$1]
+  %3 = load i32 [$0]
+  %4 = add %3, %2
+  store i32 [$0], %4
+  %5 = load i32 [$1]
+  %6 = add %5, 1
+  store i32 [$1], %6
+  jmp bb1
+bb3:
+  %7 = load i32 [$0]
+  ret %7
+}"
+        );
+    }
+
+    #[test]
+    fn test_lower_call_and_pointer() {
+        let module = lower(
+            "int f(int *p, char c) { return p[1] + c; } int main() { int a[2]; return f(a, 3) && 1; }",
+        );
+        let text = module.to_string();
+        assert!(
+            text.starts_with("define i32 @f(ptr %0, i8 %1) {\n"),
+            "{}",
+            text
+        );
+        // the index is scaled by the size of int
+        assert!(text.contains("= mul 1, 4\n"), "{}", text);
+        assert!(text.contains("= call @f(ptr %"), "{}", text);
+        assert!(module.externs.is_empty());
+    }
+}
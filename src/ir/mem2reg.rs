@@ -0,0 +1,319 @@
+//! Promotes the slots of scalar locals whose addresses are not taken to SSA values,
+//! with phis where different definitions reach a block.
+
+use super::dominators::DominatorTree;
+use super::{Address, BlockId, Function, Inst, Operand, SlotId, Ty, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Replaces the loads and the stores of the promotable slots with the values stored,
+/// and removes the slots. A slot is promotable if it is only loaded and stored as a whole
+/// with a single type, and its address is never taken.
+pub fn mem2reg(func: &mut Function) {
+    let promoted = promotable_slots(func);
+    if promoted.iter().all(|ty| ty.is_none()) {
+        return;
+    }
+    let dominators = DominatorTree::new(func);
+    let phi_slots = insert_phis(func, &dominators, &promoted);
+    let mut renaming = Renaming {
+        promoted: &promoted,
+        phi_slots: &phi_slots,
+        slot_numbers: renumber_slots(func, &promoted),
+        replacements: HashMap::new(),
+        children: dominators.children(),
+    };
+    // reading a variable before assigning it is undefined, and gives 0
+    let current = vec![Operand::Const(0); promoted.len()];
+    renaming.rename(func, BlockId(0), current);
+    func.slots = func
+        .slots
+        .iter()
+        .zip(&promoted)
+        .filter(|(_, ty)| ty.is_none())
+        .map(|(slot, _)| *slot)
+        .collect();
+    simplify_phis(func);
+}
+
+/// Returns the type of each promotable slot, which is `I32` for the slots never accessed.
+fn promotable_slots(func: &Function) -> Vec<Option<Ty>> {
+    let mut is_promotable = vec![true; func.slots.len()];
+    let mut tys = vec![None; func.slots.len()];
+    for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+        match inst {
+            Inst::SlotAddr { slot, .. } => is_promotable[slot.0 as usize] = false,
+            Inst::Load {
+                ty,
+                addr: Address::Slot(slot),
+                ..
+            }
+            | Inst::Store {
+                ty,
+                addr: Address::Slot(slot),
+                ..
+            } => {
+                let i = slot.0 as usize;
+                if func.slots[i].size != ty.size() || tys[i].is_some_and(|t| t != *ty) {
+                    is_promotable[i] = false;
+                }
+                tys[i] = Some(*ty);
+            }
+            _ => {}
+        }
+    }
+    is_promotable
+        .into_iter()
+        .zip(tys)
+        .map(|(is_promotable, ty)| is_promotable.then(|| ty.unwrap_or(Ty::I32)))
+        .collect()
+}
+
+/// Returns the new number of each slot which is not promoted.
+fn renumber_slots(func: &Function, promoted: &[Option<Ty>]) -> Vec<Option<SlotId>> {
+    let mut count = 0;
+    (0..func.slots.len())
+        .map(|i| {
+            promoted[i].is_none().then(|| {
+                count += 1;
+                SlotId(count - 1)
+            })
+        })
+        .collect()
+}
+
+/// Places empty phis for each promoted slot in the iterated dominance frontier of its stores,
+/// and returns the slot of each phi.
+fn insert_phis(
+    func: &mut Function,
+    dominators: &DominatorTree,
+    promoted: &[Option<Ty>],
+) -> HashMap<Value, usize> {
+    let frontiers = dominators.frontiers(func);
+    let mut phi_slots = HashMap::new();
+    for (slot, _) in promoted.iter().enumerate().filter(|(_, ty)| ty.is_some()) {
+        let mut worklist = func
+            .block_ids()
+            .filter(|id| {
+                func.block(*id).insts.iter().any(|inst| {
+                    matches!(inst, Inst::Store { addr: Address::Slot(s), .. } if s.0 as usize == slot)
+                })
+            })
+            .collect::<Vec<BlockId>>();
+        let mut has_phi = vec![false; func.blocks.len()];
+        while let Some(id) = worklist.pop() {
+            for frontier in &frontiers[id.0 as usize] {
+                if has_phi[frontier.0 as usize] {
+                    continue;
+                }
+                has_phi[frontier.0 as usize] = true;
+                let dst = func.new_value();
+                let block = func.block_mut(*frontier);
+                let position = block.phis().len();
+                block
+                    .insts
+                    .insert(position, Inst::Phi { dst, args: vec![] });
+                phi_slots.insert(dst, slot);
+                // the phi is a new definition of the slot
+                worklist.push(*frontier);
+            }
+        }
+    }
+    phi_slots
+}
+
+struct Renaming<'a> {
+    promoted: &'a [Option<Ty>],
+    phi_slots: &'a HashMap<Value, usize>,
+    slot_numbers: Vec<Option<SlotId>>,
+    /// The operands replacing the values of the loads removed.
+    replacements: HashMap<Value, Operand>,
+    children: Vec<Vec<BlockId>>,
+}
+
+impl Renaming<'_> {
+    /// Rewrites the block with the operands held by the slots when it is entered,
+    /// then fills the phis of its successors and goes down the dominator tree.
+    fn rename(&mut self, func: &mut Function, id: BlockId, mut current: Vec<Operand>) {
+        let insts = std::mem::take(&mut func.block_mut(id).insts);
+        let mut renamed = Vec::with_capacity(insts.len());
+        for mut inst in insts {
+            if !inst.is_phi() {
+                for operand in inst.operands_mut() {
+                    self.replace(operand);
+                }
+            }
+            match &mut inst {
+                Inst::Phi { dst, .. } => {
+                    if let Some(slot) = self.phi_slots.get(dst) {
+                        current[*slot] = Operand::Value(*dst);
+                    }
+                }
+                Inst::Load {
+                    dst,
+                    addr: Address::Slot(slot),
+                    ..
+                } if self.promoted[slot.0 as usize].is_some() => {
+                    self.replacements.insert(*dst, current[slot.0 as usize]);
+                    continue;
+                }
+                Inst::Store {
+                    ty,
+                    addr: Address::Slot(slot),
+                    value,
+                } if self.promoted[slot.0 as usize].is_some() => {
+                    // the value is truncated by the store, and sign-extended by the loads
+                    current[slot.0 as usize] = match (*ty, *value) {
+                        (Ty::Ptr, value) => value,
+                        (ty, Operand::Const(c)) => Operand::Const(ty.wrap(c)),
+                        (ty, src) => {
+                            let dst = func.new_value();
+                            renamed.push(Inst::Extend { dst, ty, src });
+                            Operand::Value(dst)
+                        }
+                    };
+                    continue;
+                }
+                Inst::SlotAddr { slot, .. }
+                | Inst::Load {
+                    addr: Address::Slot(slot),
+                    ..
+                }
+                | Inst::Store {
+                    addr: Address::Slot(slot),
+                    ..
+                } => *slot = self.slot_numbers[slot.0 as usize].unwrap(),
+                _ => {}
+            }
+            renamed.push(inst);
+        }
+        let block = func.block_mut(id);
+        block.insts = renamed;
+        for operand in block.term.operands_mut() {
+            self.replace(operand);
+        }
+        let mut successors = block.term.successors();
+        successors.dedup();
+        for succ in successors {
+            for inst in &mut func.block_mut(succ).insts {
+                if let Inst::Phi { dst, args } = inst
+                    && let Some(slot) = self.phi_slots.get(dst)
+                {
+                    args.push((id, current[*slot]));
+                }
+            }
+        }
+        for child in self.children[id.0 as usize].clone() {
+            self.rename(func, child, current.clone());
+        }
+    }
+
+    fn replace(&self, operand: &mut Operand) {
+        if let Operand::Value(value) = operand
+            && let Some(replacement) = self.replacements.get(value)
+        {
+            *operand = *replacement;
+        }
+    }
+}
+
+/// Removes the phis whose results are unused, and the phis taking a single operand
+/// other than themselves, which are replaced by it. Returns whether any phi is removed.
+pub fn simplify_phis(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let uses = func.use_counts();
+        let mut removed = HashSet::new();
+        let mut replacements = HashMap::new();
+        for inst in func.blocks.iter().flat_map(|block| block.phis()) {
+            let Inst::Phi { dst, args } = inst else {
+                unreachable!()
+            };
+            let mut others = args
+                .iter()
+                .map(|(_, arg)| *arg)
+                .filter(|arg| *arg != Operand::Value(*dst));
+            let others_count = others.clone().count();
+            let first = others.next();
+            if uses[dst.0 as usize] == args.len() - others_count {
+                removed.insert(*dst);
+            } else if let Some(first) = first
+                && others.all(|arg| arg == first)
+            {
+                removed.insert(*dst);
+                replacements.insert(*dst, first);
+            }
+        }
+        if removed.is_empty() {
+            return changed;
+        }
+        changed = true;
+        for block in &mut func.blocks {
+            block
+                .insts
+                .retain(|inst| !(inst.is_phi() && removed.contains(&inst.dst().unwrap())));
+        }
+        // a phi may be replaced by another one replaced in the same round
+        func.replace_uses(&replacements);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::verify::verify;
+    use crate::{CompileOptions, Compiler};
+
+    fn lower(code: &str) -> crate::ir::Module {
+        let compiler = Compiler::new(code, CompileOptions::default());
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        let mut module = crate::ir::lower::lower(&ast);
+        for func in &mut module.functions {
+            super::mem2reg(func);
+        }
+        assert_eq!(verify(&module), Ok(()), "{}", module);
+        module
+    }
+
+    #[test]
+    fn test_mem2reg_loop() {
+        let module =
+            lower("int main() { int i; int s; s = 0; for (i = 0; i < 3; i++) s += i; return s; }");
+        assert_eq!(
+            module.to_string(),
+            "define i32 @main() {
+bb0:
+  jmp bb1
+bb1:
+  %8 = phi [bb0: 0], [bb2: %10]
+  %9 = phi [bb0: 0], [bb2: %11]
+  %1 = lt %9, 3
+  br %1, bb2, bb3
+bb2:
+  %4 = add %8, %9
+  %10 = extend i32 %4
+  %6 = add %9, 1
+  %11 = extend i32 %6
+  jmp bb1
+bb3:
+  ret %8
+}"
+        );
+    }
+
+    #[test]
+    fn test_mem2reg_keeps_slots_whose_address_is_taken() {
+        let module = lower(
+            "int main() { int a; int b; int c[2]; int *p; p = &a; c[1] = 3; if (c[1]) a = 1; else b = 2; return a + b; }",
+        );
+        let text = module.to_string();
+        // `a` and `c` stay in memory, and `b` merges in a phi
+        assert!(
+            text.contains("  $0 = slot 4, align 4\n  $1 = slot 8, align 4\nbb0:\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains(" = phi [bb1: 0], [bb2: 2]\n"), "{}", text);
+        assert!(!text.contains("[$2]"), "{}", text);
+    }
+}
@@ -0,0 +1,523 @@
+//! The mid-level IR: a three-address code in basic blocks, lowered from the AST
+//! and shared by all the backends.
+
+pub mod const_fold;
+pub mod copy_prop;
+pub mod cse;
+pub mod dce;
+pub mod dominators;
+pub mod inline;
+pub mod licm;
+pub mod loops;
+pub mod lower;
+pub mod mem2reg;
+pub mod opt;
+pub mod print;
+pub mod simplify_cfg;
+pub mod strength_reduce;
+pub mod verify;
+
+use crate::ctype::Type;
+use crate::func::InlineHint;
+use crate::global::GlobalVariable;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+
+/// A virtual register, defined by exactly one instruction or parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub u32);
+
+/// The index of a basic block in its function. Block 0 is the entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+/// The index of a stack slot in its function, which holds a local variable or a temporary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlotId(pub u32);
+
+/// The type of a value in memory, or passed to and returned from a function.
+/// Values themselves are 64-bit words, sign-extended when loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty {
+    I8,
+    I32,
+    Ptr,
+}
+
+impl Ty {
+    /// Arrays and functions are passed by their addresses.
+    pub fn of(ty: &Type) -> Self {
+        match ty {
+            Type::I8 => Ty::I8,
+            Type::I32 => Ty::I32,
+            _ => Ty::Ptr,
+        }
+    }
+    pub fn size(&self) -> usize {
+        match self {
+            Ty::I8 => 1,
+            Ty::I32 => 4,
+            Ty::Ptr => 8,
+        }
+    }
+
+    /// Returns the constant stored to and loaded back from memory of the type.
+    pub fn wrap(&self, value: i64) -> i64 {
+        match self {
+            Ty::I8 => value as i8 as i64,
+            Ty::I32 => value as i32 as i64,
+            Ty::Ptr => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Value(Value),
+    Const(i64),
+}
+
+impl From<Value> for Operand {
+    fn from(value: Value) -> Self {
+        Operand::Value(value)
+    }
+}
+
+impl Operand {
+    pub fn as_value(&self) -> Option<Value> {
+        match self {
+            Operand::Value(v) => Some(*v),
+            Operand::Const(_) => None,
+        }
+    }
+}
+
+/// Where a load or a store accesses: a stack slot directly, or a computed address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Address {
+    Slot(SlotId),
+    Ptr(Operand),
+}
+
+/// A label defined in the data sections.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Symbol {
+    Global(String),
+    /// The string literal of the number.
+    Str(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Shl,
+    /// Arithmetic shift to the right.
+    Shr,
+    And,
+    Xor,
+    Or,
+    /// The comparisons are signed, and give 0 or 1.
+    Eq,
+    Ne,
+    Lt,
+    Le,
+}
+
+impl BinOp {
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Inst {
+    Copy {
+        dst: Value,
+        src: Operand,
+    },
+    Binary {
+        dst: Value,
+        op: BinOp,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    /// Bitwise not.
+    Not {
+        dst: Value,
+        src: Operand,
+    },
+    /// Sign-extends the low bits of the source which fit the type, as a conversion to it does.
+    Extend {
+        dst: Value,
+        ty: Ty,
+        src: Operand,
+    },
+    SlotAddr {
+        dst: Value,
+        slot: SlotId,
+    },
+    SymbolAddr {
+        dst: Value,
+        symbol: Symbol,
+    },
+    Load {
+        dst: Value,
+        ty: Ty,
+        addr: Address,
+    },
+    Store {
+        ty: Ty,
+        addr: Address,
+        value: Operand,
+    },
+    /// The arguments are given with the types they are passed as.
+    Call {
+        dst: Value,
+        callee: String,
+        args: Vec<(Ty, Operand)>,
+    },
+    /// Takes the operand given for the predecessor the block is entered from.
+    /// Phis come before the other instructions of a block, and are evaluated at once.
+    Phi {
+        dst: Value,
+        args: Vec<(BlockId, Operand)>,
+    },
+}
+
+impl Inst {
+    pub fn dst(&self) -> Option<Value> {
+        match self {
+            Inst::Copy { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Not { dst, .. }
+            | Inst::Extend { dst, .. }
+            | Inst::SlotAddr { dst, .. }
+            | Inst::SymbolAddr { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::Call { dst, .. }
+            | Inst::Phi { dst, .. } => Some(*dst),
+            Inst::Store { .. } => None,
+        }
+    }
+
+    pub fn dst_mut(&mut self) -> Option<&mut Value> {
+        match self {
+            Inst::Copy { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Not { dst, .. }
+            | Inst::Extend { dst, .. }
+            | Inst::SlotAddr { dst, .. }
+            | Inst::SymbolAddr { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::Call { dst, .. }
+            | Inst::Phi { dst, .. } => Some(dst),
+            Inst::Store { .. } => None,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Operand> {
+        match self {
+            Inst::Copy { src, .. } | Inst::Not { src, .. } | Inst::Extend { src, .. } => {
+                vec![*src]
+            }
+            Inst::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Inst::SlotAddr { .. } | Inst::SymbolAddr { .. } => vec![],
+            Inst::Load { addr, .. } => addr_operand(addr).into_iter().collect(),
+            Inst::Store { addr, value, .. } => {
+                addr_operand(addr).into_iter().chain([*value]).collect()
+            }
+            Inst::Call { args, .. } => args.iter().map(|(_, arg)| *arg).collect(),
+            Inst::Phi { args, .. } => args.iter().map(|(_, arg)| *arg).collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Inst::Copy { src, .. } | Inst::Not { src, .. } | Inst::Extend { src, .. } => {
+                vec![src]
+            }
+            Inst::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Inst::SlotAddr { .. } | Inst::SymbolAddr { .. } => vec![],
+            Inst::Load { addr, .. } => addr_operand_mut(addr).into_iter().collect(),
+            Inst::Store { addr, value, .. } => {
+                addr_operand_mut(addr).into_iter().chain([value]).collect()
+            }
+            Inst::Call { args, .. } => args.iter_mut().map(|(_, arg)| arg).collect(),
+            Inst::Phi { args, .. } => args.iter_mut().map(|(_, arg)| arg).collect(),
+        }
+    }
+
+    pub fn is_phi(&self) -> bool {
+        matches!(self, Inst::Phi { .. })
+    }
+}
+
+fn addr_operand(addr: &Address) -> Option<Operand> {
+    match addr {
+        Address::Slot(_) => None,
+        Address::Ptr(operand) => Some(*operand),
+    }
+}
+
+fn addr_operand_mut(addr: &mut Address) -> Option<&mut Operand> {
+    match addr {
+        Address::Slot(_) => None,
+        Address::Ptr(operand) => Some(operand),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Goes to `then` if the condition is not 0.
+    Branch {
+        cond: Operand,
+        then: BlockId,
+        els: BlockId,
+    },
+    Return(Operand),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch { then, els, .. } => vec![*then, *els],
+            Terminator::Return(_) => vec![],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch { then, els, .. } => vec![then, els],
+            Terminator::Return(_) => vec![],
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Operand> {
+        match self {
+            Terminator::Jump(_) => vec![],
+            Terminator::Branch { cond, .. } => vec![*cond],
+            Terminator::Return(value) => vec![*value],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::Jump(_) => vec![],
+            Terminator::Branch { cond, .. } => vec![cond],
+            Terminator::Return(value) => vec![value],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+impl Block {
+    /// Returns the phis at the start of the block.
+    pub fn phis(&self) -> &[Inst] {
+        let count = self.insts.iter().take_while(|inst| inst.is_phi()).count();
+        &self.insts[..count]
+    }
+
+    /// Removes the operands given for the predecessor from the phis,
+    /// when the block is no longer entered from it.
+    pub fn remove_phi_operands(&mut self, pred: BlockId) {
+        for inst in &mut self.insts {
+            if let Inst::Phi { args, .. } = inst {
+                args.retain(|(p, _)| *p != pred);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub size: usize,
+    pub align: usize,
+}
+
+/// The types of the parameters and of the return value of a function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Ty>,
+    pub ret: Ty,
+    /// Takes more arguments than the parameters, or is declared without a prototype.
+    pub is_variadic: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub signature: Signature,
+    /// The values holding the arguments when the function is entered.
+    pub params: Vec<Value>,
+    pub slots: Vec<Slot>,
+    pub blocks: Vec<Block>,
+    /// The number of values, which are numbered from 0.
+    pub value_count: u32,
+    pub inline: InlineHint,
+}
+
+impl Function {
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0 as usize]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.blocks[id.0 as usize]
+    }
+
+    pub fn block_ids(&self) -> impl Iterator<Item = BlockId> + use<> {
+        (0..self.blocks.len() as u32).map(BlockId)
+    }
+
+    pub fn new_value(&mut self) -> Value {
+        self.value_count += 1;
+        Value(self.value_count - 1)
+    }
+
+    /// Replaces the uses of the values with the operands, following the replacements
+    /// of the operands themselves.
+    pub fn replace_uses(&mut self, replacements: &HashMap<Value, Operand>) {
+        if replacements.is_empty() {
+            return;
+        }
+        for block in &mut self.blocks {
+            let operands = block
+                .insts
+                .iter_mut()
+                .flat_map(|inst| inst.operands_mut())
+                .chain(block.term.operands_mut());
+            for operand in operands {
+                while let Operand::Value(value) = operand
+                    && let Some(replacement) = replacements.get(value)
+                {
+                    *operand = *replacement;
+                }
+            }
+        }
+    }
+
+    /// Returns how many times each value is used.
+    pub fn use_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.value_count as usize];
+        for block in &self.blocks {
+            let operands = block
+                .insts
+                .iter()
+                .flat_map(|inst| inst.operands())
+                .chain(block.term.operands());
+            for operand in operands {
+                if let Some(value) = operand.as_value() {
+                    counts[value.0 as usize] += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Returns the predecessors of each block, in the order of the blocks.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for id in self.block_ids() {
+            for succ in self.block(id).term.successors() {
+                if !predecessors[succ.0 as usize].contains(&id) {
+                    predecessors[succ.0 as usize].push(id);
+                }
+            }
+        }
+        predecessors
+    }
+
+    /// Returns the blocks reachable from the entry, each before its successors except along back edges.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::new();
+        // each entry is a block and the number of its successors already visited
+        let mut stack = vec![(BlockId(0), 0)];
+        visited[0] = true;
+        while let Some((id, next)) = stack.pop() {
+            let successors = self.block(id).term.successors();
+            if let Some(succ) = successors.get(next) {
+                stack.push((id, next + 1));
+                if !visited[succ.0 as usize] {
+                    visited[succ.0 as usize] = true;
+                    stack.push((*succ, 0));
+                }
+            } else {
+                postorder.push(id);
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// Removes the blocks unreachable from the entry, and renumbers the others in their order.
+    /// The phis drop the operands given for the removed blocks.
+    pub fn remove_unreachable_blocks(&mut self) {
+        let mut reachable = vec![false; self.blocks.len()];
+        for id in self.reverse_postorder() {
+            reachable[id.0 as usize] = true;
+        }
+        let mut numbers = vec![None; self.blocks.len()];
+        let mut count = 0;
+        for (i, is_reachable) in reachable.iter().enumerate() {
+            if *is_reachable {
+                numbers[i] = Some(BlockId(count));
+                count += 1;
+            }
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        self.blocks = blocks
+            .into_iter()
+            .zip(reachable)
+            .filter(|(_, is_reachable)| *is_reachable)
+            .map(|(mut block, _)| {
+                for succ in block.term.successors_mut() {
+                    *succ = numbers[succ.0 as usize].unwrap();
+                }
+                for inst in &mut block.insts {
+                    if let Inst::Phi { args, .. } = inst {
+                        args.retain(|(pred, _)| numbers[pred.0 as usize].is_some());
+                        for (pred, _) in args {
+                            *pred = numbers[pred.0 as usize].unwrap();
+                        }
+                    }
+                }
+                block
+            })
+            .collect();
+    }
+}
+
+/// A program in the mid-level IR.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    /// The functions defined, in the order of declaration.
+    pub functions: Vec<Function>,
+    /// The functions called but not defined.
+    pub externs: IndexMap<String, Signature>,
+    pub global_variables: IndexMap<String, GlobalVariable>,
+    pub string_literals: Vec<String>,
+}
+
+impl Module {
+    /// Returns the signature of a function defined or declared.
+    pub fn signature(&self, name: &str) -> Option<&Signature> {
+        self.functions
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| &f.signature)
+            .or_else(|| self.externs.get(name))
+    }
+}
@@ -0,0 +1,262 @@
+//! The optimization pipeline run on the mid-level IR, selected by `-O<level>`,
+//! in which each pass can be enabled by `-f<pass>` or disabled by `-fno-<pass>`.
+
+use super::{
+    Function, const_fold, copy_prop, cse, dce, licm, mem2reg, simplify_cfg, strength_reduce,
+};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pass {
+    Mem2Reg,
+    ConstFold,
+    CopyProp,
+    Cse,
+    Licm,
+    StrengthReduce,
+    Dce,
+    SimplifyCfg,
+    /// Inlining of the calls of small functions, run on the whole module by `Compiler::lower`.
+    Inline,
+    /// Register allocation, run by the native backends instead of on the IR.
+    RegAlloc,
+    /// Jumping to the callees of the calls in tail position, run by the native backends
+    /// and marking those calls in the LLVM IR.
+    TailCall,
+    /// Rewriting of short instruction sequences, run by the native backends on the assembly.
+    Peephole,
+}
+
+impl Pass {
+    /// The passes in the order they run.
+    pub const ALL: [Pass; 12] = [
+        Pass::Mem2Reg,
+        Pass::ConstFold,
+        Pass::CopyProp,
+        Pass::Cse,
+        Pass::Licm,
+        Pass::StrengthReduce,
+        Pass::Dce,
+        Pass::SimplifyCfg,
+        Pass::Inline,
+        Pass::RegAlloc,
+        Pass::TailCall,
+        Pass::Peephole,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::Mem2Reg => "mem2reg",
+            Pass::ConstFold => "const-fold",
+            Pass::CopyProp => "copy-prop",
+            Pass::Cse => "cse",
+            Pass::Licm => "licm",
+            Pass::StrengthReduce => "strength-reduce",
+            Pass::Dce => "dce",
+            Pass::SimplifyCfg => "simplify-cfg",
+            Pass::Inline => "inline",
+            Pass::RegAlloc => "regalloc",
+            Pass::TailCall => "tail-call",
+            Pass::Peephole => "peephole",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+    /// The lowest level running the pass.
+    fn level(&self) -> OptLevel {
+        match self {
+            Pass::Mem2Reg | Pass::RegAlloc => OptLevel::O0,
+            Pass::ConstFold
+            | Pass::CopyProp
+            | Pass::Dce
+            | Pass::SimplifyCfg
+            | Pass::TailCall
+            | Pass::Peephole => OptLevel::O1,
+            Pass::Cse | Pass::Licm | Pass::StrengthReduce | Pass::Inline => OptLevel::O2,
+        }
+    }
+    /// Runs the pass, and returns whether the function is changed.
+    pub fn run(&self, func: &mut Function) -> bool {
+        match self {
+            Pass::Mem2Reg => {
+                let slot_count = func.slots.len();
+                mem2reg::mem2reg(func);
+                func.slots.len() != slot_count
+            }
+            Pass::ConstFold => const_fold::const_fold(func),
+            Pass::CopyProp => copy_prop::copy_prop(func),
+            Pass::Cse => cse::cse(func),
+            Pass::Licm => licm::licm(func),
+            Pass::StrengthReduce => strength_reduce::strength_reduce(func),
+            Pass::Dce => dce::dce(func),
+            Pass::SimplifyCfg => simplify_cfg::simplify_cfg(func),
+            Pass::Inline | Pass::RegAlloc | Pass::TailCall | Pass::Peephole => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// Only promotes the local variables to SSA values.
+    #[default]
+    O0,
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    /// Returns the level given as `-O<name>`, where `-O` is `-O1`. The levels of other compilers
+    /// above `-O2`, and those optimizing for size, are `-O2`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "0" => Some(OptLevel::O0),
+            "" | "1" => Some(OptLevel::O1),
+            "2" | "3" | "s" | "z" | "fast" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
+
+/// Which passes are run, set by `-O<level>`, `-f<pass>` and `-fno-<pass>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptOptions {
+    enabled: HashSet<Pass>,
+}
+
+impl Default for OptOptions {
+    fn default() -> Self {
+        Self::new(OptLevel::default())
+    }
+}
+
+impl OptOptions {
+    pub fn new(level: OptLevel) -> Self {
+        Self {
+            enabled: Pass::ALL
+                .into_iter()
+                .filter(|pass| pass.level() <= level)
+                .collect(),
+        }
+    }
+    pub fn is_enabled(&self, pass: Pass) -> bool {
+        self.enabled.contains(&pass)
+    }
+    /// Applies a flag given as `-f<flag>`, returning `None` if it is not the name of a pass.
+    pub fn apply_flag(&mut self, flag: &str) -> Option<()> {
+        let (enable, name) = match flag.strip_prefix("no-") {
+            Some(name) => (false, name),
+            None => (true, flag),
+        };
+        let pass = Pass::from_name(name)?;
+        if enable {
+            self.enabled.insert(pass);
+        } else {
+            self.enabled.remove(&pass);
+        }
+        Some(())
+    }
+    /// Returns the passes enabled, in the order they run.
+    pub fn passes(&self) -> Vec<Pass> {
+        Pass::ALL
+            .into_iter()
+            .filter(|pass| self.is_enabled(*pass))
+            .collect()
+    }
+}
+
+/// The most times the passes after `mem2reg` are repeated while they change the function.
+const MAX_ROUNDS: usize = 4;
+
+/// Runs the passes enabled on the function, calling `after_pass` after each of them.
+pub fn optimize(
+    func: &mut Function,
+    options: &OptOptions,
+    mut after_pass: impl FnMut(Pass, &Function),
+) {
+    let passes = options.passes();
+    if let Some(Pass::Mem2Reg) = passes.first() {
+        Pass::Mem2Reg.run(func);
+        after_pass(Pass::Mem2Reg, func);
+    }
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for pass in passes.iter().filter(|pass| {
+            !matches!(
+                pass,
+                Pass::Mem2Reg | Pass::Inline | Pass::RegAlloc | Pass::TailCall | Pass::Peephole
+            )
+        }) {
+            changed |= pass.run(func);
+            after_pass(*pass, func);
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompileOptions, Compiler};
+
+    fn lower(code: &str, opt: OptOptions) -> String {
+        let compiler = Compiler::new(code, CompileOptions::default().with_opt(opt));
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        compiler.lower(&ast).to_string()
+    }
+
+    #[test]
+    fn test_opt_options() {
+        assert_eq!(
+            OptOptions::new(OptLevel::O0).passes(),
+            vec![Pass::Mem2Reg, Pass::RegAlloc]
+        );
+        assert_eq!(OptOptions::new(OptLevel::O2).passes(), Pass::ALL.to_vec());
+        let mut options = OptOptions::new(OptLevel::O1);
+        assert!(!options.is_enabled(Pass::Cse));
+        assert!(options.is_enabled(Pass::Peephole));
+        assert_eq!(options.apply_flag("cse"), Some(()));
+        assert_eq!(options.apply_flag("no-dce"), Some(()));
+        assert_eq!(options.apply_flag("no-such-pass"), None);
+        assert!(options.is_enabled(Pass::Cse));
+        assert!(!options.is_enabled(Pass::Dce));
+        assert_eq!(OptLevel::from_name(""), Some(OptLevel::O1));
+        assert_eq!(OptLevel::from_name("s"), Some(OptLevel::O2));
+        assert_eq!(OptLevel::from_name("4"), None);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let code = "int f(int a, int b) { int x; x = a * b + 0; if (b - b == 0) x = x + a * b; else x = 3; return x; }";
+        assert_eq!(
+            lower(code, OptOptions::new(OptLevel::O2)),
+            "define i32 @f(i32 %0, i32 %1) {
+bb0:
+  %4 = mul %0, %1
+  %19 = extend i32 %4
+  %14 = add %19, %4
+  %20 = extend i32 %14
+  ret %20
+}"
+        );
+    }
+
+    #[test]
+    fn test_each_pass_keeps_the_ir_valid() {
+        let code = std::fs::read_to_string("./tests/c/many_functions.c").unwrap();
+        for pass in Pass::ALL {
+            for with_mem2reg in [false, true] {
+                let mut options = OptOptions::new(OptLevel::O0);
+                options.apply_flag(pass.name());
+                if !with_mem2reg {
+                    options.apply_flag("no-mem2reg");
+                }
+                // the IR is verified after each pass
+                lower(&code, options);
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! The text form of the IR, written by `--emit=ir`.
+
+use super::{
+    Address, BinOp, BlockId, Function, Inst, Module, Operand, Signature, SlotId, Symbol,
+    Terminator, Ty, Value,
+};
+use std::fmt::{Display, Formatter, Result};
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl Display for BlockId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl Display for SlotId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "${}", self.0)
+    }
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str(match self {
+            Ty::I8 => "i8",
+            Ty::I32 => "i32",
+            Ty::Ptr => "ptr",
+        })
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Operand::Value(value) => write!(f, "{}", value),
+            Operand::Const(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Address::Slot(slot) => write!(f, "[{}]", slot),
+            Address::Ptr(operand) => write!(f, "[{}]", operand),
+        }
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Symbol::Global(name) => write!(f, "@{}", name),
+            Symbol::Str(index) => write!(f, "@.str.{}", index),
+        }
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str(match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div => "div",
+            BinOp::Mod => "mod",
+            BinOp::Shl => "shl",
+            BinOp::Shr => "shr",
+            BinOp::And => "and",
+            BinOp::Xor => "xor",
+            BinOp::Or => "or",
+            BinOp::Eq => "eq",
+            BinOp::Ne => "ne",
+            BinOp::Lt => "lt",
+            BinOp::Le => "le",
+        })
+    }
+}
+
+impl Display for Inst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Inst::Copy { dst, src } => write!(f, "{} = copy {}", dst, src),
+            Inst::Binary { dst, op, lhs, rhs } => write!(f, "{} = {} {}, {}", dst, op, lhs, rhs),
+            Inst::Not { dst, src } => write!(f, "{} = not {}", dst, src),
+            Inst::Extend { dst, ty, src } => write!(f, "{} = extend {} {}", dst, ty, src),
+            Inst::SlotAddr { dst, slot } => write!(f, "{} = addr {}", dst, slot),
+            Inst::SymbolAddr { dst, symbol } => write!(f, "{} = addr {}", dst, symbol),
+            Inst::Load { dst, ty, addr } => write!(f, "{} = load {} {}", dst, ty, addr),
+            Inst::Store { ty, addr, value } => write!(f, "store {} {}, {}", ty, addr, value),
+            Inst::Call { dst, callee, args } => write!(
+                f,
+                "{} = call @{}({})",
+                dst,
+                callee,
+                args.iter()
+                    .map(|(ty, arg)| format!("{} {}", ty, arg))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Inst::Phi { dst, args } => write!(
+                f,
+                "{} = phi {}",
+                dst,
+                args.iter()
+                    .map(|(pred, arg)| format!("[{}: {}]", pred, arg))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl Display for Terminator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jmp {}", target),
+            Terminator::Branch { cond, then, els } => write!(f, "br {}, {}, {}", cond, then, els),
+            Terminator::Return(value) => write!(f, "ret {}", value),
+        }
+    }
+}
+
+/// Writes the types of the parameters, with their values if given.
+fn params(signature: &Signature, values: Option<&[Value]>) -> String {
+    signature
+        .params
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| match values {
+            Some(values) => format!("{} {}", ty, values[i]),
+            None => ty.to_string(),
+        })
+        .chain(signature.is_variadic.then(|| "...".to_string()))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        writeln!(
+            f,
+            "define {} @{}({}) {}{{",
+            self.signature.ret,
+            self.name,
+            params(&self.signature, Some(&self.params)),
+            self.inline
+                .attribute()
+                .map_or(String::new(), |attribute| format!("{} ", attribute))
+        )?;
+        for (i, slot) in self.slots.iter().enumerate() {
+            writeln!(
+                f,
+                "  {} = slot {}, align {}",
+                SlotId(i as u32),
+                slot.size,
+                slot.align
+            )?;
+        }
+        for id in self.block_ids() {
+            let block = self.block(id);
+            writeln!(f, "{}:", id)?;
+            for inst in &block.insts {
+                writeln!(f, "  {}", inst)?;
+            }
+            writeln!(f, "  {}", block.term)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let header = self
+            .global_variables
+            .iter()
+            .map(|(name, gv)| format!("@{} = global {}", name, gv.ty))
+            .chain(
+                self.string_literals
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| format!("@.str.{} = string \"{}\"", i, s)),
+            )
+            .chain(self.externs.iter().map(|(name, signature)| {
+                format!(
+                    "declare {} @{}({})",
+                    signature.ret,
+                    name,
+                    params(signature, None)
+                )
+            }))
+            .collect::<Vec<String>>();
+        let functions = self
+            .functions
+            .iter()
+            .map(|func| func.to_string())
+            .collect::<Vec<String>>();
+        f.write_str(
+            &[header.join("\n")]
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .chain(functions)
+                .collect::<Vec<String>>()
+                .join("\n\n"),
+        )
+    }
+}
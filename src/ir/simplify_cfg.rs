@@ -0,0 +1,112 @@
+//! Control-flow graph simplification: turns the branches to a single block into jumps,
+//! threads the jumps through empty blocks, merges the blocks into their only predecessors
+//! which jump to them, and removes the blocks which become unreachable.
+
+use super::{Function, Inst, Operand, Terminator};
+use std::collections::HashMap;
+
+/// Returns whether the function is changed.
+pub fn simplify_cfg(func: &mut Function) -> bool {
+    let mut changed = false;
+    while fold_branches(func) | thread_jumps(func) | merge_blocks(func) {
+        func.remove_unreachable_blocks();
+        changed = true;
+    }
+    changed
+}
+
+fn fold_branches(func: &mut Function) -> bool {
+    let mut changed = false;
+    for block in &mut func.blocks {
+        if let Terminator::Branch { then, els, .. } = block.term
+            && then == els
+        {
+            block.term = Terminator::Jump(then);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Makes the predecessors of each block which only jumps to another go there directly.
+fn thread_jumps(func: &mut Function) -> bool {
+    let mut changed = false;
+    for id in func.block_ids().skip(1) {
+        let block = func.block(id);
+        let Terminator::Jump(target) = block.term else {
+            continue;
+        };
+        if !block.insts.is_empty() || target == id {
+            continue;
+        }
+        let predecessors = func.predecessors();
+        for pred in &predecessors[id.0 as usize] {
+            let target_block = func.block_mut(target);
+            if !target_block.phis().is_empty() {
+                // the phis cannot take different operands for the same predecessor
+                if predecessors[target.0 as usize].contains(pred) {
+                    continue;
+                }
+                for inst in &mut target_block.insts {
+                    if let Inst::Phi { args, .. } = inst {
+                        let (_, arg) = *args.iter().find(|(p, _)| *p == id).unwrap();
+                        args.push((*pred, arg));
+                    }
+                }
+            }
+            for succ in func.block_mut(*pred).term.successors_mut() {
+                if *succ == id {
+                    *succ = target;
+                }
+            }
+            changed = true;
+        }
+        if func.predecessors()[id.0 as usize].is_empty() {
+            func.block_mut(target).remove_phi_operands(id);
+        }
+    }
+    changed
+}
+
+/// Appends each block to its only predecessor if the predecessor only jumps to it.
+fn merge_blocks(func: &mut Function) -> bool {
+    let mut changed = false;
+    let mut replacements = HashMap::<_, Operand>::new();
+    for id in func.block_ids().skip(1) {
+        let predecessors = func.predecessors();
+        let [pred] = predecessors[id.0 as usize][..] else {
+            continue;
+        };
+        if pred == id || func.block(pred).term != Terminator::Jump(id) {
+            continue;
+        }
+        let block = func.block_mut(id);
+        let insts = std::mem::take(&mut block.insts);
+        // leaves the block unreachable, to be removed
+        let term = std::mem::replace(&mut block.term, Terminator::Return(Operand::Const(0)));
+        for succ in term.successors() {
+            for inst in &mut func.block_mut(succ).insts {
+                if let Inst::Phi { args, .. } = inst {
+                    for (p, _) in args {
+                        if *p == id {
+                            *p = pred;
+                        }
+                    }
+                }
+            }
+        }
+        let pred_block = func.block_mut(pred);
+        for inst in insts {
+            match inst {
+                Inst::Phi { dst, args } => {
+                    replacements.insert(dst, args[0].1);
+                }
+                inst => pred_block.insts.push(inst),
+            }
+        }
+        pred_block.term = term;
+        changed = true;
+    }
+    func.replace_uses(&replacements);
+    changed
+}
@@ -0,0 +1,260 @@
+//! Strength reduction of induction variables: replaces the sums computed in a loop of an
+//! invariant base and a constant multiple of a variable stepped by a constant on each
+//! iteration, as the address of `a[i]` is, with a value stepped along with the variable,
+//! so that the multiplication is replaced with an addition.
+
+use super::loops::{self, Loop};
+use super::{BinOp, Function, Inst, Operand, Value};
+use std::collections::{HashMap, HashSet};
+
+/// A variable taking a value before the loop, and stepped on each iteration.
+struct Induction {
+    init: Operand,
+    step: i64,
+}
+
+/// The sum of `base` and `iv * factor` which becomes a variable stepped along with `iv`.
+struct Candidate {
+    dst: Value,
+    base: Operand,
+    iv: Value,
+    factor: i64,
+}
+
+/// Returns whether the function is changed.
+pub fn strength_reduce(func: &mut Function) -> bool {
+    let mut loops = loops::find_loops(func);
+    let mut changed = false;
+    for i in 0..loops.len() {
+        let inductions = inductions(func, &loops[i]);
+        let candidates = candidates(func, &loops[i], &inductions);
+        if candidates.is_empty() {
+            continue;
+        }
+        let preheader = loops::preheader(func, &mut loops, i);
+        let mut replacements = HashMap::new();
+        for candidate in candidates {
+            let induction = &inductions[&candidate.iv];
+            let (offset, init) = (func.new_value(), func.new_value());
+            func.block_mut(preheader).insts.extend([
+                Inst::Binary {
+                    dst: offset,
+                    op: BinOp::Mul,
+                    lhs: induction.init,
+                    rhs: Operand::Const(candidate.factor),
+                },
+                Inst::Binary {
+                    dst: init,
+                    op: BinOp::Add,
+                    lhs: candidate.base,
+                    rhs: offset.into(),
+                },
+            ]);
+            let reduced = func.new_value();
+            let mut args = vec![(preheader, init.into())];
+            for latch in &loops[i].latches {
+                let next = func.new_value();
+                func.block_mut(*latch).insts.push(Inst::Binary {
+                    dst: next,
+                    op: BinOp::Add,
+                    lhs: reduced.into(),
+                    rhs: Operand::Const(induction.step.wrapping_mul(candidate.factor)),
+                });
+                args.push((*latch, next.into()));
+            }
+            func.block_mut(loops[i].header)
+                .insts
+                .insert(0, Inst::Phi { dst: reduced, args });
+            replacements.insert(candidate.dst, Operand::Value(reduced));
+        }
+        for id in &loops[i].blocks {
+            func.block_mut(*id).insts.retain(|inst| {
+                inst.dst()
+                    .is_none_or(|dst| !replacements.contains_key(&dst))
+            });
+        }
+        func.replace_uses(&replacements);
+        changed = true;
+    }
+    changed
+}
+
+/// Returns the phis of the loop header which are induction variables: taking one operand
+/// from outside the loop, and from each block branching back the phi plus the same constant.
+/// The sum may be extended to the type of the variable, whose overflow is undefined.
+fn inductions(func: &Function, lp: &Loop) -> HashMap<Value, Induction> {
+    let definitions = definitions(func, lp);
+    let step = |iv: Value, operand: &Operand| {
+        let mut inst = definitions.get(&operand.as_value()?)?;
+        if let Inst::Extend {
+            src: Operand::Value(src),
+            ..
+        } = inst
+        {
+            inst = definitions.get(src)?;
+        }
+        match inst {
+            Inst::Binary {
+                op: BinOp::Add,
+                lhs: Operand::Value(value),
+                rhs: Operand::Const(step),
+                ..
+            }
+            | Inst::Binary {
+                op: BinOp::Add,
+                lhs: Operand::Const(step),
+                rhs: Operand::Value(value),
+                ..
+            } if *value == iv => Some(*step),
+            _ => None,
+        }
+    };
+    let mut inductions = HashMap::new();
+    for inst in func.block(lp.header).phis() {
+        let Inst::Phi { dst, args } = inst else {
+            unreachable!("phi expected")
+        };
+        let (outside, inside): (Vec<_>, Vec<_>) =
+            args.iter().partition(|(pred, _)| !lp.contains(*pred));
+        let [(_, init)] = outside[..] else {
+            continue;
+        };
+        let steps = inside
+            .iter()
+            .map(|(_, arg)| step(*dst, arg))
+            .collect::<Option<HashSet<i64>>>();
+        if let Some(steps) = steps
+            && let [step] = steps.into_iter().collect::<Vec<i64>>()[..]
+        {
+            inductions.insert(*dst, Induction { init, step });
+        }
+    }
+    inductions
+}
+
+/// Returns the sums in the loop of an invariant base and a multiple of an induction variable.
+fn candidates(
+    func: &Function,
+    lp: &Loop,
+    inductions: &HashMap<Value, Induction>,
+) -> Vec<Candidate> {
+    let definitions = definitions(func, lp);
+    let multiple = |operand: &Operand| match definitions.get(&operand.as_value()?)? {
+        Inst::Binary {
+            op: BinOp::Mul,
+            lhs: Operand::Value(iv),
+            rhs: Operand::Const(factor),
+            ..
+        }
+        | Inst::Binary {
+            op: BinOp::Mul,
+            lhs: Operand::Const(factor),
+            rhs: Operand::Value(iv),
+            ..
+        } if inductions.contains_key(iv) => Some((*iv, *factor)),
+        _ => None,
+    };
+    let is_invariant = |operand: &Operand| {
+        operand
+            .as_value()
+            .is_none_or(|value| !definitions.contains_key(&value))
+    };
+    lp.blocks
+        .iter()
+        .flat_map(|id| &func.block(*id).insts)
+        .filter_map(|inst| {
+            let Inst::Binary {
+                dst,
+                op: BinOp::Add,
+                lhs,
+                rhs,
+            } = inst
+            else {
+                return None;
+            };
+            let ((iv, factor), base) = match (multiple(lhs), multiple(rhs)) {
+                (Some(multiple), _) if is_invariant(rhs) => (multiple, *rhs),
+                (_, Some(multiple)) if is_invariant(lhs) => (multiple, *lhs),
+                _ => return None,
+            };
+            Some(Candidate {
+                dst: *dst,
+                base,
+                iv,
+                factor,
+            })
+        })
+        .collect()
+}
+
+/// Returns the instructions of the loop by their results.
+fn definitions<'a>(func: &'a Function, lp: &Loop) -> HashMap<Value, &'a Inst> {
+    lp.blocks
+        .iter()
+        .flat_map(|id| &func.block(*id).insts)
+        .filter_map(|inst| Some((inst.dst()?, inst)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::opt::{OptLevel, OptOptions};
+    use crate::{CompileOptions, Compiler};
+
+    fn lower(code: &str, flags: &[&str]) -> Function {
+        let mut options = OptOptions::new(OptLevel::O1);
+        for flag in flags {
+            options.apply_flag(flag);
+        }
+        let compiler = Compiler::new(code, CompileOptions::default().with_opt(options));
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        compiler.analyze(&mut ast).unwrap();
+        compiler.lower(&ast).functions.remove(0)
+    }
+
+    fn multiplications(func: &Function) -> usize {
+        func.blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .filter(|inst| matches!(inst, Inst::Binary { op: BinOp::Mul, .. }))
+            .count()
+    }
+
+    #[test]
+    fn test_strength_reduce() {
+        let code =
+            "int fill(int *p, int n) { int i; for (i = 2; i < n; i = i + 3) p[i] = i; return 0; }";
+        let func = lower(code, &["strength-reduce"]);
+        assert_eq!(multiplications(&lower(code, &[])), 1);
+        assert_eq!(multiplications(&func), 0);
+        assert_eq!(
+            func.to_string(),
+            "define i32 @fill(ptr %0, i32 %1) {
+bb0:
+  %16 = add %0, 8
+  jmp bb1
+bb1:
+  %17 = phi [bb0: %16], [bb2: %18]
+  %12 = phi [bb0: 2], [bb2: %14]
+  %4 = lt %12, %1
+  br %4, bb2, bb3
+bb2:
+  store i32 [%17], %12
+  %11 = add %12, 3
+  %14 = extend i32 %11
+  %18 = add %17, 12
+  jmp bb1
+bb3:
+  ret 0
+}"
+        );
+    }
+
+    #[test]
+    fn test_strength_reduce_needs_a_constant_step() {
+        let code = "int fill(int *p, int n) { int i; for (i = 0; i < n; i = i * 2 + 1) p[i] = p[n * i]; return 0; }";
+        assert_eq!(multiplications(&lower(code, &["strength-reduce"])), 4);
+    }
+}
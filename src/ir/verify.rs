@@ -0,0 +1,310 @@
+//! Checks the invariants of the IR, which the lowering and the passes must keep.
+
+use super::dominators::DominatorTree;
+use super::{Address, BlockId, Function, Inst, Module, Operand, Value};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub function: String,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid IR in function '{}': {}",
+            self.function, self.message
+        )
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+pub fn verify(module: &Module) -> Result<(), VerifyError> {
+    module
+        .functions
+        .iter()
+        .try_for_each(|func| verify_function(module, func))
+}
+
+/// Checks that
+/// - every block ends with a terminator going to blocks of the function, and is reachable from the entry,
+/// - the entry has no predecessors,
+/// - every value is defined once, before its uses and in a block dominating them,
+/// - the phis are at the start of their blocks, with an operand for each predecessor
+///   which is available at its end,
+/// - the slots exist, and the functions called are declared with enough parameters.
+pub fn verify_function(module: &Module, func: &Function) -> Result<(), VerifyError> {
+    let error = |message: String| VerifyError {
+        function: func.name.clone(),
+        message,
+    };
+    if func.blocks.is_empty() {
+        return Err(error("no entry block".to_string()));
+    }
+    for id in func.block_ids() {
+        for succ in func.block(id).term.successors() {
+            if succ.0 as usize >= func.blocks.len() {
+                return Err(error(format!("{} jumps to undefined {}", id, succ)));
+            }
+            if succ == BlockId(0) {
+                return Err(error(format!("{} jumps to the entry block", id)));
+            }
+        }
+    }
+    let dominators = DominatorTree::new(func);
+    if let Some(id) = func.block_ids().find(|id| !dominators.is_reachable(*id)) {
+        return Err(error(format!("{} is unreachable", id)));
+    }
+
+    // where each value is defined, as the block and the position in it
+    let mut definitions = vec![None; func.value_count as usize];
+    let mut define =
+        |value: Value, position: (BlockId, usize)| match definitions.get_mut(value.0 as usize) {
+            None => Err(error(format!(
+                "{} is out of the {} values",
+                value, func.value_count
+            ))),
+            Some(Some(_)) => Err(error(format!("{} is defined more than once", value))),
+            Some(definition) => {
+                *definition = Some(position);
+                Ok(())
+            }
+        };
+    for param in &func.params {
+        define(*param, (BlockId(0), 0))?;
+    }
+    for id in func.block_ids() {
+        for (i, inst) in func.block(id).insts.iter().enumerate() {
+            if let Some(dst) = inst.dst() {
+                define(dst, (id, i + 1))?;
+            }
+        }
+    }
+    if func.params.len() != func.signature.params.len() {
+        return Err(error(format!(
+            "{} parameters for {} types",
+            func.params.len(),
+            func.signature.params.len()
+        )));
+    }
+
+    let check_use = |operand: &Operand, position: (BlockId, usize)| {
+        let Some(value) = operand.as_value() else {
+            return Ok(());
+        };
+        let Some(Some((block, index))) = definitions.get(value.0 as usize) else {
+            return Err(error(format!("{} is used but not defined", value)));
+        };
+        let is_dominated = if *block == position.0 {
+            *index < position.1
+        } else {
+            dominators.dominates(*block, position.0)
+        };
+        if !is_dominated {
+            return Err(error(format!(
+                "{} is used in {} before its definition",
+                value, position.0
+            )));
+        }
+        Ok(())
+    };
+    let predecessors = func.predecessors();
+    for id in func.block_ids() {
+        let block = func.block(id);
+        for (i, inst) in block.insts.iter().enumerate() {
+            if let Inst::Phi { dst, args } = inst {
+                if i >= block.phis().len() {
+                    return Err(error(format!("{} is after other instructions", dst)));
+                }
+                let mut preds = args.iter().map(|(pred, _)| *pred).collect::<Vec<BlockId>>();
+                preds.sort();
+                let mut expected = predecessors[id.0 as usize].clone();
+                expected.sort();
+                if preds != expected {
+                    return Err(error(format!(
+                        "{} does not have an operand for each predecessor of {}",
+                        dst, id
+                    )));
+                }
+                // an operand of a phi is used when the predecessor is left
+                for (pred, arg) in args {
+                    check_use(arg, (*pred, func.block(*pred).insts.len() + 1))?;
+                }
+                continue;
+            }
+            for operand in inst.operands() {
+                check_use(&operand, (id, i + 1))?;
+            }
+            match inst {
+                Inst::SlotAddr { slot, .. }
+                | Inst::Load {
+                    addr: Address::Slot(slot),
+                    ..
+                }
+                | Inst::Store {
+                    addr: Address::Slot(slot),
+                    ..
+                } if slot.0 as usize >= func.slots.len() => {
+                    return Err(error(format!("{} is not a slot of the function", slot)));
+                }
+                Inst::Call { callee, args, .. } => match module.signature(callee) {
+                    None => return Err(error(format!("@{} is not declared", callee))),
+                    Some(signature)
+                        if args.len() < signature.params.len()
+                            || (args.len() > signature.params.len() && !signature.is_variadic) =>
+                    {
+                        return Err(error(format!(
+                            "@{} is called with {} arguments",
+                            callee,
+                            args.len()
+                        )));
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        for operand in block.term.operands() {
+            check_use(&operand, (id, block.insts.len() + 1))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::func::InlineHint;
+    use crate::ir::{BinOp, Block, Signature, Terminator, Ty};
+    use indexmap::IndexMap;
+
+    fn module(blocks: Vec<Block>, value_count: u32) -> Module {
+        Module {
+            functions: vec![Function {
+                name: "main".to_string(),
+                signature: Signature {
+                    params: vec![],
+                    ret: Ty::I32,
+                    is_variadic: false,
+                },
+                params: vec![],
+                slots: vec![],
+                blocks,
+                value_count,
+                inline: InlineHint::None,
+            }],
+            externs: IndexMap::new(),
+            global_variables: IndexMap::new(),
+            string_literals: vec![],
+        }
+    }
+
+    fn add(dst: u32, lhs: Operand) -> Inst {
+        Inst::Binary {
+            dst: Value(dst),
+            op: BinOp::Add,
+            lhs,
+            rhs: Operand::Const(1),
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let branch = Terminator::Branch {
+            cond: Operand::Const(1),
+            then: BlockId(1),
+            els: BlockId(2),
+        };
+        let ret = |value: u32| Terminator::Return(Operand::Value(Value(value)));
+        let valid = module(
+            vec![
+                Block {
+                    insts: vec![add(0, Operand::Const(0))],
+                    term: branch.clone(),
+                },
+                Block {
+                    insts: vec![add(1, Operand::Value(Value(0)))],
+                    term: ret(1),
+                },
+                Block {
+                    insts: vec![],
+                    term: ret(0),
+                },
+            ],
+            2,
+        );
+        assert_eq!(verify(&valid), Ok(()));
+
+        // %1 is defined in bb1, which does not dominate bb2
+        let mut invalid = valid.clone();
+        invalid.functions[0].blocks[2].term = ret(1);
+        assert_eq!(
+            verify(&invalid).map_err(|e| e.to_string()),
+            Err(
+                "invalid IR in function 'main': %1 is used in bb2 before its definition"
+                    .to_string()
+            )
+        );
+
+        let mut invalid = valid.clone();
+        invalid.functions[0].blocks[2].insts = vec![add(0, Operand::Const(2))];
+        assert_eq!(
+            verify(&invalid).map_err(|e| e.message),
+            Err("%0 is defined more than once".to_string())
+        );
+
+        let mut invalid = valid;
+        invalid.functions[0].blocks[1].term = Terminator::Jump(BlockId(0));
+        assert_eq!(
+            verify(&invalid).map_err(|e| e.message),
+            Err("bb1 jumps to the entry block".to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_phis() {
+        let phi = |args: Vec<(u32, i64)>| Inst::Phi {
+            dst: Value(0),
+            args: args
+                .into_iter()
+                .map(|(pred, c)| (BlockId(pred), Operand::Const(c)))
+                .collect(),
+        };
+        let blocks = |phi: Inst| {
+            vec![
+                Block {
+                    insts: vec![],
+                    term: Terminator::Branch {
+                        cond: Operand::Const(1),
+                        then: BlockId(1),
+                        els: BlockId(2),
+                    },
+                },
+                Block {
+                    insts: vec![],
+                    term: Terminator::Jump(BlockId(2)),
+                },
+                Block {
+                    insts: vec![add(1, Operand::Const(0)), phi],
+                    term: Terminator::Return(Operand::Value(Value(0))),
+                },
+            ]
+        };
+        let mut valid = module(blocks(phi(vec![(1, 1), (0, 2)])), 2);
+        valid.functions[0].blocks[2].insts.reverse();
+        assert_eq!(verify(&valid), Ok(()));
+        assert_eq!(
+            verify(&module(blocks(phi(vec![(1, 1), (0, 2)])), 2)).map_err(|e| e.message),
+            Err("%0 is after other instructions".to_string())
+        );
+        let mut invalid = module(blocks(phi(vec![(1, 1)])), 2);
+        invalid.functions[0].blocks[2].insts.reverse();
+        assert_eq!(
+            verify(&invalid).map_err(|e| e.message),
+            Err("%0 does not have an operand for each predecessor of bb2".to_string())
+        );
+    }
+}
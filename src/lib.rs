@@ -1,45 +1,109 @@
 pub mod ast;
+pub mod compiler;
 pub mod ctype;
+pub mod dot;
+pub mod driver;
+pub mod emit;
 pub mod error;
+pub mod fold;
 pub mod func;
 pub mod generator;
 pub mod global;
+pub mod ir;
+pub mod json;
 pub mod node;
+pub mod sema;
 pub mod token;
 pub mod tokenizer;
 pub mod trie;
+pub mod warning;
 
-use ast::{AstBuilder, ProgramAst};
-use generator::{Arch, Os};
+pub use compiler::{CompileError, CompileOptions, Compiler, OutputKind};
+pub use generator::{Arch, Os, Target};
 
-pub fn generate(code: &str, output_option: &str, is_debug: bool) -> String {
-    let error_printer = error::ErrorPrinter::new(code);
-    let tokens = tokenizer::Tokenizer::tokenize(code, is_debug).unwrap_or_else(|e| {
-        error::ErrorLogger::print_syntax_error_position(&error_printer, e);
-        std::process::exit(1)
-    });
-    let mut builder = ast::AstBuilderImpl::new(&error_printer, &tokens);
-    let ast = builder.build(is_debug);
-    match output_option {
-        "asm" => gen_asm(ast, &error_printer),
-        _ => gen_llvm_ir(ast, &error_printer),
-    }
+use emit::{Emit, EmitFormat, Emitter};
+
+/// Compiles the code to assembly with `"asm"` or to LLVM IR with `"llvm"`, printing the warnings.
+/// Returns an error for an unknown output kind, or the rendered diagnostics if it fails.
+pub fn generate(code: &str, output_option: &str, is_debug: bool) -> Result<String, String> {
+    let output = OutputKind::from_name(output_option)
+        .ok_or_else(|| format!("unknown output kind '{}'", output_option))?;
+    generate_with_options(
+        code,
+        &CompileOptions::default()
+            .with_debug(is_debug)
+            .with_output(output),
+    )
+    .map_err(|e| e.to_string())
 }
 
-fn gen_asm(ast: ProgramAst, error_printer: &error::ErrorPrinter) -> String {
-    #[cfg(target_os = "linux")]
-    let target_os = Os::Linux;
-    #[cfg(target_os = "macos")]
-    let target_os = Os::MacOS;
-    #[cfg(target_arch = "x86_64")]
-    let target_arch = Arch::X86_64;
-    #[cfg(target_arch = "aarch64")]
-    let target_arch = Arch::Aarch64;
-    generator::new(target_arch, target_os, error_printer)
-        .generate(ast)
-        .to_string(target_os)
+/// Compiles the code with the options, printing the warnings,
+/// and returns the diagnostics if it fails.
+pub fn generate_with_options(code: &str, options: &CompileOptions) -> Result<String, CompileError> {
+    let emit = match options.output() {
+        OutputKind::Assembly => Emit::Asm,
+        OutputKind::LlvmIr => Emit::Llvm,
+    };
+    Ok(emit_with_options(code, &[emit], EmitFormat::Text, options)?.remove(0))
 }
 
-fn gen_llvm_ir(ast: ProgramAst, error_printer: &error::ErrorPrinter) -> String {
-    generator::llvm::generator::IrGenerator::new(error_printer).generate(ast)
+/// Compiles the code, and returns each of the requested representations in the same order.
+/// Prints the warnings, and returns the diagnostics if it fails.
+pub fn emit_with_options(
+    code: &str,
+    emits: &[Emit],
+    format: EmitFormat,
+    options: &CompileOptions,
+) -> Result<Vec<String>, CompileError> {
+    let compiler = Compiler::new(code, options.clone());
+    let emitter = Emitter::new(code, format);
+    let mut outputs = Vec::<(Emit, String)>::new();
+    let tokens = compiler.tokenize()?;
+    if emits.contains(&Emit::Tokens) {
+        outputs.push((Emit::Tokens, emitter.tokens(&tokens)));
+    }
+    let mut ast = compiler.parse(&tokens);
+    if emits.contains(&Emit::Ast) {
+        outputs.push((Emit::Ast, emitter.program(&ast)));
+    }
+    compiler.analyze(&mut ast)?;
+    compiler.print_diagnostics();
+    for emit in [Emit::TypedAst, Emit::AstDot] {
+        if !emits.contains(&emit) {
+            continue;
+        }
+        let text = match emit {
+            Emit::AstDot => dot::ast_to_dot(code, &ast),
+            _ => emitter.program(&ast),
+        };
+        outputs.push((emit, text));
+    }
+    if emits
+        .iter()
+        .any(|emit| matches!(emit, Emit::Ir | Emit::CfgDot | Emit::Asm | Emit::Llvm))
+    {
+        let module = compiler.lower(&ast);
+        for emit in [Emit::Ir, Emit::CfgDot, Emit::Asm, Emit::Llvm] {
+            if !emits.contains(&emit) {
+                continue;
+            }
+            let text = match emit {
+                Emit::CfgDot => dot::cfg_to_dot(&module),
+                Emit::Asm => compiler.codegen_as(&module, OutputKind::Assembly),
+                Emit::Llvm => compiler.codegen_as(&module, OutputKind::LlvmIr),
+                _ => module.to_string(),
+            };
+            outputs.push((emit, text));
+        }
+    }
+    Ok(emits
+        .iter()
+        .map(|emit| {
+            outputs
+                .iter()
+                .find(|(e, _)| e == emit)
+                .map(|(_, output)| output.clone())
+                .unwrap()
+        })
+        .collect())
 }
@@ -1,51 +1,260 @@
-use dynamite_compiler::generate;
+use dynamite_compiler::driver::{Driver, DriverError, Language, Stage};
+use dynamite_compiler::emit::{Emit, EmitFormat};
+use dynamite_compiler::generator::Target;
+use dynamite_compiler::ir::opt::{OptLevel, OptOptions};
+use dynamite_compiler::{CompileOptions, OutputKind, error, warning};
 use getopts::Options;
 use std::env;
-use std::fs::File;
-use std::io::Read;
 extern crate getopts;
 
 fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options] FILE", program);
+    let brief = format!("Usage: {} [options] FILE...", program);
     print!("{}", opts.usage(&brief));
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    // accept the single-dash spelling of gcc and clang
+    let args: Vec<String> = env::args()
+        .map(|arg| match arg.as_str() {
+            "-emit-llvm" => "--emit-llvm".to_string(),
+            _ if arg.starts_with("-std=") => format!("-{}", arg),
+            _ => arg,
+        })
+        .collect();
+    // `-O` only takes a level attached to it, so that it never takes the next argument
+    let (opt_args, args): (Vec<String>, Vec<String>) =
+        args.into_iter().partition(|arg| arg.starts_with("-O"));
     let program = args[0].clone();
     let mut opts = Options::new();
     opts.optflag("", "debug", "print debug info");
     opts.optflag("h", "help", "print this help menu");
+    opts.optflag("S", "", "only compile, writing assembly");
+    opts.optflag("c", "", "compile and assemble, but do not link");
+    opts.optopt("o", "", "write output to FILE", "FILE");
+    opts.optopt(
+        "x",
+        "",
+        "treat the inputs as LANGUAGE (c, assembler, none)",
+        "LANGUAGE",
+    );
+    opts.optflag("", "emit-llvm", "use LLVM IR instead of native assembly");
+    // accepted so that the driver can be used as `CC`, but there is no preprocessor,
+    // debug information or other language standard
+    opts.optmulti(
+        "I",
+        "",
+        "add DIR to the include search path (ignored)",
+        "DIR",
+    );
+    opts.optmulti("D", "", "define MACRO (ignored)", "MACRO");
+    opts.optmulti("U", "", "undefine MACRO (ignored)", "MACRO");
+    opts.optflagopt("g", "", "generate debug information (ignored)", "LEVEL");
+    opts.optopt("", "std", "language standard (ignored)", "STANDARD");
+    opts.optopt(
+        "",
+        "target",
+        &format!("generate code for TRIPLE ({})", Target::TRIPLES.join(", ")),
+        "TRIPLE",
+    );
+    opts.optmulti(
+        "",
+        "emit",
+        "write the representations in KINDS (tokens, ast, typed-ast, ir, asm, llvm, ast-dot, cfg-dot) instead of compiling",
+        "KINDS",
+    );
+    opts.optflag(
+        "",
+        "dump-ast-dot",
+        "write the AST for Graphviz, same as --emit=ast-dot",
+    );
+    opts.optflag(
+        "",
+        "dump-cfg-dot",
+        "write the control-flow graphs for Graphviz, same as --emit=cfg-dot",
+    );
+    opts.optopt(
+        "",
+        "emit-format",
+        "format of tokens and ASTs written by --emit (text, json)",
+        "FORMAT",
+    );
+    opts.optflagopt(
+        "O",
+        "",
+        "optimization level (0: only promote local variables to registers and allocate them, 1, 2, where 3, s, z and fast are 2)",
+        "LEVEL",
+    );
+    opts.optmulti("l", "", "link with LIBRARY", "LIBRARY");
+    opts.optmulti("L", "", "add DIR to the library search path", "DIR");
     opts.opt(
-        "o",
-        "output",
-        "output format (asm, llvm)",
+        "f",
+        "",
+        "compiler flags (error-limit=N: stop after N errors, 0 for no limit), or an optimization pass to enable, or disable with no- prefix (mem2reg, const-fold, copy-prop, cse, licm, strength-reduce, dce, simplify-cfg, inline, regalloc, tail-call, peephole)",
+        "FLAG",
+        getopts::HasArg::Yes,
+        getopts::Occur::Multi,
+    );
+    opts.opt(
+        "W",
+        "",
+        "enable a warning (all, extra, error, or its name), or disable it with no- prefix, or pass OPTIONS to the linker with l,OPTIONS",
+        "WARNING",
+        getopts::HasArg::Yes,
+        getopts::Occur::Multi,
+    );
+    opts.opt(
+        "",
+        "diagnostics-format",
+        "format of diagnostics (text, json, sarif)",
         "FORMAT",
         getopts::HasArg::Yes,
         getopts::Occur::Optional,
     );
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => m,
-        Err(f) => {
-            panic!("{}", f.to_string())
-        }
-    };
+    let matches = opts.parse(&args[1..]).unwrap_or_else(|f| {
+        error::error(&f.to_string());
+        unreachable!()
+    });
     if matches.opt_present("help") {
         print_usage(&program, opts);
         return;
     }
-    let output_option = matches.opt_str("output").unwrap_or("llvm".to_string());
     let is_debug = matches.opt_present("debug");
-    let path = if !matches.free.is_empty() {
-        matches.free[0].clone()
-    } else {
+    let mut error_limit = error::DEFAULT_ERROR_LIMIT;
+    let opt_level = opt_args
+        .last()
+        .map(|arg| {
+            let level = &arg["-O".len()..];
+            OptLevel::from_name(level).unwrap_or_else(|| {
+                error::error(&format!("invalid value '{}' in '-O'", level));
+                unreachable!()
+            })
+        })
+        .unwrap_or_default();
+    let mut opt = OptOptions::new(opt_level);
+    for flag in matches.opt_strs("f") {
+        match flag.split_once('=') {
+            Some(("error-limit", n)) => {
+                error_limit = n.parse().unwrap_or_else(|_| {
+                    error::error(&format!("invalid value '{}' in '-ferror-limit='", n));
+                    unreachable!()
+                })
+            }
+            _ => {
+                if opt.apply_flag(&flag).is_none() {
+                    error::error(&format!("unknown argument: '-f{}'", flag))
+                }
+            }
+        }
+    }
+    let mut warnings = warning::WarningOptions::default();
+    let mut linker_options = Vec::new();
+    for flag in matches.opt_strs("W") {
+        if let Some(options) = flag.strip_prefix("l,") {
+            linker_options.extend(options.split(',').map(|option| option.to_string()));
+            continue;
+        }
+        // unknown warnings are not errors, as in clang, so that flags for other compilers pass
+        if let Err(msg) = warnings.apply_flag(&flag) {
+            error::warning(&msg);
+        }
+    }
+    let diagnostics_format = matches
+        .opt_str("diagnostics-format")
+        .map(|format| {
+            error::DiagnosticsFormat::from_name(&format).unwrap_or_else(|| {
+                error::error(&format!(
+                    "invalid value '{}' in '--diagnostics-format'",
+                    format
+                ));
+                unreachable!()
+            })
+        })
+        .unwrap_or_default();
+    let target = matches
+        .opt_str("target")
+        .map(|triple| {
+            Target::from_triple(&triple).unwrap_or_else(|| {
+                error::error(&format!("unknown target triple '{}'", triple));
+                unreachable!()
+            })
+        })
+        .unwrap_or_else(Target::host);
+    if matches.free.is_empty() {
         print_usage(&program, opts);
         std::process::exit(1)
+    }
+    let mut driver = Driver::new(
+        CompileOptions::default()
+            .with_debug(is_debug)
+            .with_error_limit(error_limit)
+            .with_warnings(warnings)
+            .with_diagnostics_format(diagnostics_format)
+            .with_target(target)
+            .with_opt(opt)
+            .with_output(if matches.opt_present("emit-llvm") {
+                OutputKind::LlvmIr
+            } else {
+                OutputKind::Assembly
+            }),
+    );
+    driver.stage = if matches.opt_present("S") {
+        Stage::Assembly
+    } else if matches.opt_present("c") {
+        Stage::Object
+    } else {
+        Stage::Executable
     };
-    let mut code = String::new();
-    File::open(&path)
-        .unwrap_or_else(|e| panic!("file \"{}\" not found: {}", path, e))
-        .read_to_string(&mut code)
-        .unwrap_or_else(|e| panic!("failed to read file \"{}\": {}", path, e));
-    println!("{}", generate(&code, &output_option, is_debug));
+    driver.output = matches.opt_str("o");
+    driver.language = matches
+        .opt_str("x")
+        .filter(|language| language != "none")
+        .map(|language| {
+            Language::from_name(&language).unwrap_or_else(|| {
+                error::error(&format!("language not recognized: '{}'", language));
+                unreachable!()
+            })
+        });
+    driver.emits = matches
+        .opt_strs("emit")
+        .iter()
+        .flat_map(|kinds| kinds.split(','))
+        .map(|kind| {
+            Emit::from_name(kind).unwrap_or_else(|| {
+                error::error(&format!("invalid value '{}' in '--emit'", kind));
+                unreachable!()
+            })
+        })
+        .chain(
+            [
+                ("dump-ast-dot", Emit::AstDot),
+                ("dump-cfg-dot", Emit::CfgDot),
+            ]
+            .into_iter()
+            .filter(|(flag, _)| matches.opt_present(flag))
+            .map(|(_, emit)| emit),
+        )
+        .collect();
+    driver.emit_format = matches
+        .opt_str("emit-format")
+        .map(|format| {
+            EmitFormat::from_name(&format).unwrap_or_else(|| {
+                error::error(&format!("invalid value '{}' in '--emit-format'", format));
+                unreachable!()
+            })
+        })
+        .unwrap_or_default();
+    driver.linker_args = matches
+        .opt_strs("L")
+        .iter()
+        .map(|dir| format!("-L{}", dir))
+        .chain(matches.opt_strs("l").iter().map(|lib| format!("-l{}", lib)))
+        .chain((!linker_options.is_empty()).then(|| format!("-Wl,{}", linker_options.join(","))))
+        .collect();
+    driver.run(&matches.free).unwrap_or_else(|e| match e {
+        DriverError::Compile(e) => {
+            eprint!("{}", e);
+            std::process::exit(1)
+        }
+        DriverError::Message(msg) => error::error(&msg),
+    });
 }
@@ -1,12 +1,12 @@
 use crate::ctype::Type;
-use crate::token::Token;
-use std::mem::swap;
+use std::ops::{Index, IndexMut};
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum NodeType {
-    Assign,
-    LocalVar,
-    GlobalVar,
+/// A handle of a node in the `NodeArena` of a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
     Add,
     Sub,
     Mul,
@@ -21,283 +21,428 @@ pub enum NodeType {
     BitAnd,
     BitXor,
     BitOr,
-    BitNot,
     LogicalAnd,
     LogicalOr,
-    Num,
-    Return,
-    If,
-    While,
-    For,
-    Break,
-    Block,
-    CallFunc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    BitNot,
     Addr,
     Deref,
-    DefVar,
     SuffixIncr,
     SuffixDecr,
+    /// An implicit conversion to the type of the node.
+    Cast,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Num(usize),
+    /// A local variable at the offset from the base pointer.
+    LocalVar(usize),
+    /// A global variable or a function.
+    GlobalVar(String),
+    /// The array of a string literal, numbered in `ProgramAst::string_literals`.
+    StringLiteral(usize),
+    Assign(NodeId, NodeId),
+    Binary(BinaryOp, NodeId, NodeId),
+    Unary(UnaryOp, NodeId),
+    Return(NodeId),
+    If {
+        cond: NodeId,
+        then: NodeId,
+        els: Option<NodeId>,
+    },
+    /// The conditional operator `?:`, which has a value unlike `if`.
+    Conditional {
+        cond: NodeId,
+        then: NodeId,
+        els: NodeId,
+    },
+    While {
+        cond: NodeId,
+        body: NodeId,
+    },
+    For {
+        ini: Option<NodeId>,
+        cond: Option<NodeId>,
+        upd: Option<NodeId>,
+        body: NodeId,
+    },
+    Break,
+    Block(Vec<NodeId>),
+    /// The initializers of the local variables defined in a declaration.
+    DefVar(Vec<NodeId>),
+    /// A function call, whose arguments are stored from right to left.
+    CallFunc {
+        callee: NodeId,
+        args: Vec<NodeId>,
+    },
 }
 
-impl Default for NodeType {
-    fn default() -> Self {
-        Self::Num
+impl NodeKind {
+    /// Returns the name of the kind shown in the dumps of the AST.
+    pub fn name(&self) -> String {
+        match self {
+            NodeKind::Num(_) => String::from("Num"),
+            NodeKind::LocalVar(_) => String::from("LocalVar"),
+            NodeKind::GlobalVar(_) => String::from("GlobalVar"),
+            NodeKind::StringLiteral(_) => String::from("StringLiteral"),
+            NodeKind::Assign(..) => String::from("Assign"),
+            NodeKind::Binary(op, ..) => format!("{:?}", op),
+            NodeKind::Unary(op, _) => format!("{:?}", op),
+            NodeKind::Return(_) => String::from("Return"),
+            NodeKind::If { .. } => String::from("If"),
+            NodeKind::Conditional { .. } => String::from("Conditional"),
+            NodeKind::While { .. } => String::from("While"),
+            NodeKind::For { .. } => String::from("For"),
+            NodeKind::Break => String::from("Break"),
+            NodeKind::Block(_) => String::from("Block"),
+            NodeKind::DefVar(_) => String::from("DefVar"),
+            NodeKind::CallFunc { .. } => String::from("CallFunc"),
+        }
     }
+    /// Returns whether the node is a statement, which has no value and so no type.
+    pub fn is_statement(&self) -> bool {
+        matches!(
+            self,
+            NodeKind::Return(_)
+                | NodeKind::If { .. }
+                | NodeKind::While { .. }
+                | NodeKind::For { .. }
+                | NodeKind::Break
+                | NodeKind::Block(_)
+                | NodeKind::DefVar(_)
+        )
+    }
+}
+
+/// Why an expression cannot be evaluated at compile time, with the node which stops it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalError {
+    NotConstant(NodeId),
+    DivisionByZero(NodeId),
+    /// The result does not fit the type of the operation, whose overflow is undefined.
+    Overflow(NodeId),
+    /// The shift count is negative, or not less than the width of the type.
+    InvalidShift(NodeId),
+}
+
+/// Whether an expression designates an object, set by the semantic analysis.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ValueCategory {
+    LValue,
+    RValue,
 }
 
-#[derive(Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct Node {
-    pub nt: NodeType,
+    pub kind: NodeKind,
+    /// The position of the token which the node is made from, such as the operator.
+    pub pos: Option<usize>,
     pub cty: Option<Type>,
-    pub token: Option<Token>,
-    pub lhs: Option<Box<Node>>,
-    pub rhs: Option<Box<Node>>,
-    // for number
-    pub value: Option<usize>,
-    // for "if", "for", "while" statement
-    pub cond: Option<Box<Node>>,
-    pub then: Option<Box<Node>>,
-    // for "if" statement
-    pub els: Option<Box<Node>>,
-    // for "for" statement
-    pub ini: Option<Box<Node>>,
-    pub upd: Option<Box<Node>>,
-    // for block, definition of local variables
-    pub children: Vec<Node>,
-    // flag of global variables
-    pub global_name: String,
-    // flag of string literal
-    pub dest: String,
-    // for calling function
-    pub args: Vec<Node>,
-    // for declaration of local variables
-    pub offset: Option<usize>,
+    pub category: Option<ValueCategory>,
 }
 
 impl Node {
-    pub fn new_with_op(token: Option<Token>, nt: NodeType, lhs: Node, rhs: Node) -> Self {
-        let (mut lhs, mut rhs) = (lhs, rhs);
-        if let NodeType::Add = nt {
-            if lhs.dest_type().is_none() && rhs.dest_type().is_some() {
-                swap(&mut lhs, &mut rhs);
-            }
-        }
+    pub fn new(kind: NodeKind, pos: Option<usize>) -> Self {
         Self {
-            token,
-            nt,
-            lhs: Some(Box::new(lhs)),
-            rhs: Some(Box::new(rhs)),
-            ..Self::default()
+            kind,
+            pos,
+            cty: None,
+            category: None,
         }
     }
-    pub fn new_with_op_and_lhs(token: Option<Token>, nt: NodeType, lhs: Node) -> Self {
+    pub fn with_type(self, cty: Type) -> Self {
         Self {
-            token,
-            nt,
-            lhs: Some(Box::new(lhs)),
-            ..Self::default()
+            cty: Some(cty),
+            ..self
         }
     }
-    pub fn new_with_num(token: Option<Token>, value: usize) -> Self {
-        Self {
-            token,
-            nt: NodeType::Num,
-            value: Some(value),
-            ..Self::default()
-        }
+}
+
+/// Owns all the nodes of a program, which refer to each other by `NodeId`.
+#[derive(Debug, Clone, Default)]
+pub struct NodeArena {
+    nodes: Vec<Node>,
+}
+
+impl Index<NodeId> for NodeArena {
+    type Output = Node;
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0 as usize]
     }
-    pub fn new_if_node(token: Option<Token>, cond: Node, then: Node, els: Option<Node>) -> Self {
-        Self {
-            token,
-            nt: NodeType::If,
-            cond: Some(Box::new(cond)),
-            then: Some(Box::new(then)),
-            els: els.map(Box::new),
-            ..Self::default()
-        }
+}
+
+impl IndexMut<NodeId> for NodeArena {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0 as usize]
     }
-    pub fn new_while_node(token: Option<Token>, cond: Node, then: Node) -> Self {
-        Self {
-            token,
-            nt: NodeType::While,
-            cond: Some(Box::new(cond)),
-            then: Some(Box::new(then)),
-            ..Self::default()
-        }
+}
+
+impl NodeArena {
+    pub fn alloc(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        NodeId((self.nodes.len() - 1) as u32)
     }
-    pub fn new_for_node(
-        token: Option<Token>,
-        ini: Option<Node>,
-        cond: Option<Node>,
-        upd: Option<Node>,
-        then: Node,
-    ) -> Self {
-        Self {
-            token,
-            nt: NodeType::For,
-            ini: ini.map(Box::new),
-            cond: cond.map(Box::new),
-            upd: upd.map(Box::new),
-            then: Some(Box::new(then)),
-            ..Self::default()
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    /// Copies the node and its descendants, so that the copy can be annotated on its own.
+    pub fn duplicate(&mut self, id: NodeId) -> NodeId {
+        let mut node = self[id].clone();
+        let mut dup = |child: &mut NodeId| *child = self.duplicate(*child);
+        match &mut node.kind {
+            NodeKind::Num(_)
+            | NodeKind::LocalVar(_)
+            | NodeKind::GlobalVar(_)
+            | NodeKind::StringLiteral(_)
+            | NodeKind::Break => {}
+            NodeKind::Assign(lhs, rhs) | NodeKind::Binary(_, lhs, rhs) => {
+                dup(lhs);
+                dup(rhs);
+            }
+            NodeKind::Unary(_, lhs) | NodeKind::Return(lhs) => dup(lhs),
+            NodeKind::If { cond, then, els } => {
+                dup(cond);
+                dup(then);
+                els.iter_mut().for_each(&mut dup);
+            }
+            NodeKind::Conditional { cond, then, els } => {
+                dup(cond);
+                dup(then);
+                dup(els);
+            }
+            NodeKind::While { cond, body } => {
+                dup(cond);
+                dup(body);
+            }
+            NodeKind::For {
+                ini,
+                cond,
+                upd,
+                body,
+            } => {
+                [ini, cond, upd].into_iter().flatten().for_each(&mut dup);
+                dup(body);
+            }
+            NodeKind::Block(children) | NodeKind::DefVar(children) => {
+                children.iter_mut().for_each(&mut dup)
+            }
+            NodeKind::CallFunc { callee, args } => {
+                dup(callee);
+                args.iter_mut().for_each(&mut dup);
+            }
         }
+        self.alloc(node)
     }
-    pub fn resolve_type(&self) -> Option<Type> {
-        match self.nt {
-            NodeType::LocalVar | NodeType::Num | NodeType::CallFunc | NodeType::GlobalVar => {
-                self.cty.clone()
+    /// Returns the type annotated by the semantic analysis, or infers it from the children
+    /// while the AST is being built.
+    pub fn resolve_type(&self, id: NodeId) -> Option<Type> {
+        let node = &self[id];
+        if node.cty.is_some() {
+            return node.cty.clone();
+        }
+        match &node.kind {
+            NodeKind::Unary(UnaryOp::Addr, lhs) => {
+                self.resolve_type(*lhs).map(|ty| Type::Ptr(Box::new(ty)))
+            }
+            NodeKind::Unary(UnaryOp::Deref, lhs) => self.dest_type(*lhs),
+            NodeKind::Assign(lhs, rhs) | NodeKind::Binary(_, lhs, rhs) => {
+                self.resolve_type(*lhs).or_else(|| self.resolve_type(*rhs))
             }
-            NodeType::Addr => {
-                if let Some(ty) = self.lhs.as_ref().unwrap().resolve_type() {
-                    Some(Type::Ptr(Box::new(ty)))
+            NodeKind::Unary(_, lhs) | NodeKind::Return(lhs) => self.resolve_type(*lhs),
+            NodeKind::If { then: body, .. }
+            | NodeKind::Conditional { then: body, .. }
+            | NodeKind::While { body, .. }
+            | NodeKind::For { body, .. } => self.resolve_type(*body),
+            _ => None,
+        }
+    }
+    /// Evaluates a constant expression, wrapping each result to the type of its node.
+    /// The operand of `&&` and `||` and the branch of `?:` which are not taken are not evaluated.
+    pub fn eval(&self, id: NodeId) -> Result<i64, EvalError> {
+        let value = match self[id].kind {
+            NodeKind::Num(value) => value as i64,
+            NodeKind::Unary(UnaryOp::Cast, operand) => self.eval(operand)?,
+            NodeKind::Unary(UnaryOp::BitNot, operand) => !self.eval(operand)?,
+            NodeKind::Binary(op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), lhs, rhs) => {
+                let lhs = self.eval(lhs)? != 0;
+                if lhs == (op == BinaryOp::LogicalOr) {
+                    lhs as i64
                 } else {
-                    self.cty.clone()
+                    (self.eval(rhs)? != 0) as i64
                 }
             }
-            NodeType::Deref => self.lhs.as_ref().unwrap().dest_type(),
-            _ => {
-                if let Some(node) = self.lhs.as_ref() {
-                    if let Some(ty) = node.resolve_type() {
-                        return Some(ty);
-                    }
-                }
-                if let Some(node) = self.rhs.as_ref() {
-                    if let Some(ty) = node.resolve_type() {
-                        return Some(ty);
+            NodeKind::Binary(op, lhs, rhs) => {
+                // the operands of both integer types are promoted to `int`, the width the operation is computed at
+                let (lhs, rhs) = (self.eval(lhs)? as i32, self.eval(rhs)? as i32);
+                let value = match op {
+                    BinaryOp::Div | BinaryOp::Mod if rhs == 0 => {
+                        return Err(EvalError::DivisionByZero(id));
                     }
-                }
-                if let Some(node) = self.then.as_ref() {
-                    if let Some(ty) = node.resolve_type() {
-                        return Some(ty);
+                    BinaryOp::BitLeft | BinaryOp::BitRight
+                        if !(0..i32::BITS as i32).contains(&rhs) =>
+                    {
+                        return Err(EvalError::InvalidShift(id));
                     }
-                }
-                None
+                    BinaryOp::Add => lhs.checked_add(rhs),
+                    BinaryOp::Sub => lhs.checked_sub(rhs),
+                    BinaryOp::Mul => lhs.checked_mul(rhs),
+                    BinaryOp::Div => lhs.checked_div(rhs),
+                    BinaryOp::Mod => lhs.checked_rem(rhs),
+                    BinaryOp::Eq => Some((lhs == rhs) as i32),
+                    BinaryOp::Ne => Some((lhs != rhs) as i32),
+                    BinaryOp::Lt => Some((lhs < rhs) as i32),
+                    BinaryOp::Le => Some((lhs <= rhs) as i32),
+                    BinaryOp::BitLeft => i32::try_from((lhs as i64) << rhs).ok(),
+                    BinaryOp::BitRight => Some(lhs >> rhs),
+                    BinaryOp::BitAnd => Some(lhs & rhs),
+                    BinaryOp::BitXor => Some(lhs ^ rhs),
+                    BinaryOp::BitOr => Some(lhs | rhs),
+                    BinaryOp::LogicalAnd | BinaryOp::LogicalOr => unreachable!(),
+                };
+                value.ok_or(EvalError::Overflow(id))? as i64
+            }
+            NodeKind::Conditional { cond, then, els } => {
+                self.eval(if self.eval(cond)? != 0 { then } else { els })?
             }
+            _ => return Err(EvalError::NotConstant(id)),
+        };
+        match self.resolve_type(id).unwrap_or_default() {
+            Type::I8 => Ok(value as i8 as i64),
+            Type::I32 => Ok(value as i32 as i64),
+            _ => Err(EvalError::NotConstant(id)),
         }
     }
-    pub fn dest_type(&self) -> Option<Type> {
-        if let Some(t) = self.resolve_type() {
-            t.dest_type()
-        } else {
-            None
+    pub fn dest_type(&self, id: NodeId) -> Option<Type> {
+        self.resolve_type(id).and_then(|ty| ty.dest_type())
+    }
+    /// Returns whether the expression designates an object, which can be assigned or addressed.
+    pub fn is_lvalue(&self, id: NodeId) -> bool {
+        match self[id].kind {
+            NodeKind::LocalVar(_) | NodeKind::Unary(UnaryOp::Deref, _) => true,
+            NodeKind::GlobalVar(_) => !matches!(self[id].cty, Some(Type::Func(..))),
+            _ => false,
         }
     }
-    pub fn to_debug_string(&self, indent: usize) -> String {
-        match self.nt {
-            NodeType::LocalVar => {
+    /// Returns the position of the leftmost token of the node.
+    pub fn first_pos(&self, id: NodeId) -> Option<usize> {
+        self.edges(id)
+            .into_iter()
+            .filter_map(|(_, child)| self.first_pos(child))
+            .chain(self[id].pos)
+            .min()
+    }
+    /// Returns the position of the rightmost token of the node.
+    pub fn last_pos(&self, id: NodeId) -> Option<usize> {
+        self.edges(id)
+            .into_iter()
+            .filter_map(|(_, child)| self.last_pos(child))
+            .chain(self[id].pos)
+            .max()
+    }
+    /// Returns the children with the name of each edge, in the order of the source.
+    pub fn edges(&self, id: NodeId) -> Vec<(&'static str, NodeId)> {
+        match &self[id].kind {
+            NodeKind::Num(_)
+            | NodeKind::LocalVar(_)
+            | NodeKind::GlobalVar(_)
+            | NodeKind::StringLiteral(_)
+            | NodeKind::Break => vec![],
+            NodeKind::Assign(lhs, rhs) | NodeKind::Binary(_, lhs, rhs) => {
+                vec![("lhs", *lhs), ("rhs", *rhs)]
+            }
+            NodeKind::Unary(_, lhs) | NodeKind::Return(lhs) => vec![("lhs", *lhs)],
+            NodeKind::If { cond, then, els } => [("cond", *cond), ("then", *then)]
+                .into_iter()
+                .chain(els.map(|els| ("else", els)))
+                .collect(),
+            NodeKind::Conditional { cond, then, els } => {
+                vec![("cond", *cond), ("then", *then), ("else", *els)]
+            }
+            NodeKind::While { cond, body } => vec![("cond", *cond), ("then", *body)],
+            NodeKind::For {
+                ini,
+                cond,
+                upd,
+                body,
+            } => [("init", *ini), ("cond", *cond), ("update", *upd)]
+                .into_iter()
+                .filter_map(|(edge, child)| child.map(|child| (edge, child)))
+                .chain([("then", *body)])
+                .collect(),
+            NodeKind::Block(children) | NodeKind::DefVar(children) => {
+                children.iter().map(|child| ("children", *child)).collect()
+            }
+            NodeKind::CallFunc { callee, args } => [("lhs", *callee)]
+                .into_iter()
+                .chain(args.iter().rev().map(|arg| ("args", *arg)))
+                .collect(),
+        }
+    }
+    pub fn to_debug_string(&self, id: NodeId, indent: usize) -> String {
+        let node = &self[id];
+        match &node.kind {
+            NodeKind::LocalVar(offset) => {
                 format!(
                     "LocalVar: {{ type: {:?}, offset: {} }}",
-                    self.cty.as_ref().unwrap(),
-                    self.offset.unwrap()
+                    node.cty.as_ref().unwrap(),
+                    offset
                 )
             }
-            NodeType::GlobalVar => {
-                format!("GlobalVar: {{ name: {}{} }}", &self.global_name, &self.dest)
-            }
-            NodeType::Num => {
-                format!("Num: {}", self.value.unwrap())
-            }
-            _ => [
-                vec![format!("{:?}:", self.nt)],
-                self.ini
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}ini->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-                self.cond
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}cond->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-                self.upd
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}upd->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-                self.then
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}then->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-                self.els
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}els->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-                self.lhs
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}lhs->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-                self.rhs
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}rhs->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-                self.children
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}children->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-                self.args
-                    .iter()
-                    .map(|c| {
-                        format!(
-                            "{}args->{}",
-                            " ".repeat(indent),
-                            c.to_debug_string(indent + 2)
-                        )
-                    })
-                    .collect::<Vec<String>>(),
-            ]
-            .concat()
-            .join("\n"),
+            NodeKind::GlobalVar(name) => format!("GlobalVar: {{ name: {} }}", name),
+            NodeKind::StringLiteral(index) => format!("StringLiteral: {{ index: {} }}", index),
+            NodeKind::Num(value) => format!("Num: {}", *value as i64),
+            kind => [format!("{}:", kind.name())]
+                .into_iter()
+                .chain(self.edges(id).into_iter().map(|(edge, child)| {
+                    format!(
+                        "{}{}->{}",
+                        " ".repeat(indent),
+                        edge,
+                        self.to_debug_string(child, indent + 2)
+                    )
+                }))
+                .collect::<Vec<String>>()
+                .join("\n"),
         }
     }
 }
 
-impl std::fmt::Debug for Node {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.to_debug_string(2))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_arena() {
+        let mut nodes = NodeArena::default();
+        let lhs = nodes.alloc(Node::new(NodeKind::LocalVar(8), Some(4)).with_type(Type::I8));
+        let rhs = nodes.alloc(Node::new(NodeKind::Num(1), Some(8)));
+        let add = nodes.alloc(Node::new(
+            NodeKind::Binary(BinaryOp::Add, lhs, rhs),
+            Some(6),
+        ));
+        assert_eq!(nodes.resolve_type(add), Some(Type::I8));
+        assert_eq!(
+            (nodes.first_pos(add), nodes.last_pos(add)),
+            (Some(4), Some(8))
+        );
+        let dup = nodes.duplicate(add);
+        assert_ne!(dup, add);
+        assert_eq!(nodes.len(), 6);
+        let NodeKind::Binary(_, dup_lhs, _) = nodes[dup].kind else {
+            panic!("not a binary operation")
+        };
+        assert_ne!(dup_lhs, lhs);
+        assert_eq!(nodes[dup_lhs].kind, NodeKind::LocalVar(8));
+        assert_eq!(
+            nodes.to_debug_string(add, 2),
+            "Add:\n  lhs->LocalVar: { type: I8, offset: 8 }\n  rhs->Num: 1"
+        );
     }
 }
@@ -0,0 +1,399 @@
+use crate::ast::ProgramAst;
+use crate::ctype::Type;
+use crate::error::{ErrorCode, ErrorLogger, SyntaxError};
+use crate::node::{BinaryOp, Node, NodeArena, NodeId, NodeKind, UnaryOp, ValueCategory};
+
+/// Where a value is implicitly converted, to describe it in diagnostics.
+#[derive(Clone, Copy)]
+enum Conversion {
+    Assigning,
+    Passing,
+    Returning,
+}
+
+/// Annotates every expression with its type and value category,
+/// inserts the implicit conversions and reports the type errors.
+pub struct SemanticAnalyzer<'a> {
+    error_logger: &'a dyn ErrorLogger,
+}
+
+impl<'a> SemanticAnalyzer<'a> {
+    pub fn new(error_logger: &'a dyn ErrorLogger) -> Self {
+        Self { error_logger }
+    }
+
+    pub fn analyze(&self, ast: &mut ProgramAst) {
+        let nodes = &mut ast.nodes;
+        ast.functions.values().for_each(|f| {
+            if let Type::Func(_, return_type) = &f.cty
+                && let Some(body) = f.body
+            {
+                self.stmt(nodes, body, return_type);
+            }
+        })
+    }
+
+    fn report(&self, code: ErrorCode, nodes: &NodeArena, node: NodeId, msg: &str) {
+        self.error_logger
+            .report(SyntaxError::new(code, nodes[node].pos.unwrap_or(0), msg));
+    }
+
+    fn stmt(&self, nodes: &mut NodeArena, node: NodeId, return_type: &Type) {
+        match nodes[node].kind {
+            NodeKind::Block(ref children) | NodeKind::DefVar(ref children) => children
+                .clone()
+                .into_iter()
+                .for_each(|child| self.stmt(nodes, child, return_type)),
+            NodeKind::If { cond, then, els } => {
+                self.expr(nodes, cond);
+                self.stmt(nodes, then, return_type);
+                if let Some(els) = els {
+                    self.stmt(nodes, els, return_type);
+                }
+            }
+            NodeKind::While { cond, body } => {
+                self.expr(nodes, cond);
+                self.stmt(nodes, body, return_type);
+            }
+            NodeKind::For {
+                ini,
+                cond,
+                upd,
+                body,
+            } => {
+                if let Some(ini) = ini {
+                    self.stmt(nodes, ini, return_type);
+                }
+                [cond, upd]
+                    .into_iter()
+                    .flatten()
+                    .for_each(|n| self.expr(nodes, n));
+                self.stmt(nodes, body, return_type);
+            }
+            NodeKind::Return(value) => {
+                self.expr(nodes, value);
+                self.convert(nodes, value, return_type, Conversion::Returning);
+            }
+            NodeKind::Break => {}
+            _ => self.expr(nodes, node),
+        }
+    }
+
+    fn ty(nodes: &NodeArena, node: NodeId) -> Type {
+        nodes[node].cty.clone().unwrap_or_default()
+    }
+
+    fn expr(&self, nodes: &mut NodeArena, node: NodeId) {
+        let (ty, category) = match nodes[node].kind {
+            NodeKind::Unary(UnaryOp::Cast, _) => return,
+            NodeKind::Num(_) => (Type::I32, ValueCategory::RValue),
+            NodeKind::LocalVar(_) => (Self::ty(nodes, node), ValueCategory::LValue),
+            NodeKind::GlobalVar(_) | NodeKind::StringLiteral(_) => match Self::ty(nodes, node) {
+                ty @ Type::Func(..) => (ty, ValueCategory::RValue),
+                ty => (ty, ValueCategory::LValue),
+            },
+            NodeKind::Unary(UnaryOp::Addr, operand) => {
+                self.expr(nodes, operand);
+                let ty = nodes[node]
+                    .cty
+                    .clone()
+                    .unwrap_or_else(|| Type::Ptr(Box::new(Self::ty(nodes, operand))));
+                (ty, ValueCategory::RValue)
+            }
+            NodeKind::Unary(UnaryOp::Deref, operand) => {
+                self.expr(nodes, operand);
+                let operand_ty = Self::ty(nodes, operand);
+                let ty = operand_ty.dest_type().unwrap_or_else(|| {
+                    self.report(
+                        ErrorCode::InvalidIndirection,
+                        nodes,
+                        node,
+                        &format!(
+                            "indirection requires pointer operand ('{}' invalid)",
+                            operand_ty
+                        ),
+                    );
+                    Type::I32
+                });
+                (ty, ValueCategory::LValue)
+            }
+            NodeKind::Assign(lhs, rhs) => {
+                self.operands(nodes, lhs, rhs);
+                let ty = Self::ty(nodes, lhs);
+                self.convert(nodes, rhs, &ty, Conversion::Assigning);
+                (ty, ValueCategory::RValue)
+            }
+            NodeKind::Binary(op @ (BinaryOp::Add | BinaryOp::Sub), lhs, rhs) => {
+                let (lhs_ty, rhs_ty) = self.operands(nodes, lhs, rhs);
+                let ty = match (lhs_ty.dest_type(), rhs_ty.dest_type()) {
+                    _ if lhs_ty.is_integer() && rhs_ty.is_integer() => {
+                        Self::promote_operands(nodes, lhs, rhs);
+                        Type::I32
+                    }
+                    (Some(elem), _) if rhs_ty.is_integer() => {
+                        Self::promote(nodes, rhs);
+                        Type::Ptr(Box::new(elem))
+                    }
+                    (Some(_), Some(_)) if op == BinaryOp::Sub => Type::I32,
+                    _ => {
+                        self.report_invalid_operands(nodes, node, &lhs_ty, &rhs_ty);
+                        Type::I32
+                    }
+                };
+                (ty, ValueCategory::RValue)
+            }
+            NodeKind::Binary(
+                BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Mod
+                | BinaryOp::BitAnd
+                | BinaryOp::BitXor
+                | BinaryOp::BitOr
+                | BinaryOp::BitLeft
+                | BinaryOp::BitRight,
+                lhs,
+                rhs,
+            ) => {
+                let (lhs_ty, rhs_ty) = self.operands(nodes, lhs, rhs);
+                if lhs_ty.is_integer() && rhs_ty.is_integer() {
+                    Self::promote_operands(nodes, lhs, rhs);
+                } else {
+                    self.report_invalid_operands(nodes, node, &lhs_ty, &rhs_ty);
+                }
+                (Type::I32, ValueCategory::RValue)
+            }
+            NodeKind::Binary(
+                BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le,
+                lhs,
+                rhs,
+            ) => {
+                let (lhs_ty, rhs_ty) = self.operands(nodes, lhs, rhs);
+                if lhs_ty.is_integer() && rhs_ty.is_integer() {
+                    Self::promote_operands(nodes, lhs, rhs);
+                } else if matches!(lhs_ty, Type::Func(..)) || matches!(rhs_ty, Type::Func(..)) {
+                    self.report_invalid_operands(nodes, node, &lhs_ty, &rhs_ty);
+                }
+                (Type::I32, ValueCategory::RValue)
+            }
+            NodeKind::Binary(BinaryOp::LogicalAnd | BinaryOp::LogicalOr, lhs, rhs) => {
+                self.operands(nodes, lhs, rhs);
+                (Type::I32, ValueCategory::RValue)
+            }
+            NodeKind::Unary(UnaryOp::BitNot, operand) => {
+                self.expr(nodes, operand);
+                let operand_ty = Self::ty(nodes, operand);
+                if operand_ty.is_integer() {
+                    Self::promote(nodes, operand);
+                } else {
+                    self.report(
+                        ErrorCode::InvalidOperands,
+                        nodes,
+                        node,
+                        &format!("invalid argument type '{}' to unary expression", operand_ty),
+                    );
+                }
+                (Type::I32, ValueCategory::RValue)
+            }
+            NodeKind::Unary(UnaryOp::SuffixIncr | UnaryOp::SuffixDecr, operand) => {
+                self.expr(nodes, operand);
+                (Self::ty(nodes, operand), ValueCategory::RValue)
+            }
+            NodeKind::CallFunc { callee, ref args } => {
+                let args = args.clone();
+                self.expr(nodes, callee);
+                args.iter().for_each(|arg| self.expr(nodes, *arg));
+                let ty = match Self::ty(nodes, callee) {
+                    Type::Func(params, return_type) => {
+                        // arguments are stored from right to left
+                        let len = args.len();
+                        args.iter().enumerate().for_each(|(i, arg)| {
+                            match params.get(len - i - 1) {
+                                Some(param) => {
+                                    self.convert(nodes, *arg, param, Conversion::Passing)
+                                }
+                                None => Self::promote(nodes, *arg),
+                            }
+                        });
+                        *return_type
+                    }
+                    ty => {
+                        self.report(
+                            ErrorCode::NotAFunction,
+                            nodes,
+                            node,
+                            &format!(
+                                "called object type '{}' is not a function or function pointer",
+                                ty
+                            ),
+                        );
+                        Type::I32
+                    }
+                };
+                (ty, ValueCategory::RValue)
+            }
+            NodeKind::Conditional { cond, then, els } => {
+                [cond, then, els]
+                    .into_iter()
+                    .for_each(|n| self.expr(nodes, n));
+                (Self::ty(nodes, then), ValueCategory::RValue)
+            }
+            _ => return,
+        };
+        nodes[node].cty = Some(ty);
+        nodes[node].category = Some(category);
+    }
+
+    /// Analyzes both operands and returns their types.
+    fn operands(&self, nodes: &mut NodeArena, lhs: NodeId, rhs: NodeId) -> (Type, Type) {
+        self.expr(nodes, lhs);
+        self.expr(nodes, rhs);
+        (Self::ty(nodes, lhs), Self::ty(nodes, rhs))
+    }
+
+    fn report_invalid_operands(
+        &self,
+        nodes: &NodeArena,
+        node: NodeId,
+        lhs_ty: &Type,
+        rhs_ty: &Type,
+    ) {
+        self.report(
+            ErrorCode::InvalidOperands,
+            nodes,
+            node,
+            &format!(
+                "invalid operands to binary expression ('{}' and '{}')",
+                lhs_ty, rhs_ty
+            ),
+        );
+    }
+
+    /// Wraps the node with an implicit conversion to the type, keeping its handle.
+    fn cast(nodes: &mut NodeArena, node: NodeId, ty: &Type) {
+        let pos = nodes[node].pos;
+        let operand = nodes.alloc(nodes[node].clone());
+        nodes[node] = Node {
+            category: Some(ValueCategory::RValue),
+            ..Node::new(NodeKind::Unary(UnaryOp::Cast, operand), pos).with_type(ty.clone())
+        };
+    }
+
+    /// Converts a `char` operand to `int`, as the integer promotions do.
+    fn promote(nodes: &mut NodeArena, node: NodeId) {
+        if let Some(Type::I8) = nodes[node].cty {
+            Self::cast(nodes, node, &Type::I32);
+        }
+    }
+
+    fn promote_operands(nodes: &mut NodeArena, lhs: NodeId, rhs: NodeId) {
+        Self::promote(nodes, lhs);
+        Self::promote(nodes, rhs);
+    }
+
+    /// Converts the value to the type as if by assignment.
+    fn convert(&self, nodes: &mut NodeArena, node: NodeId, ty: &Type, conversion: Conversion) {
+        let from = Self::ty(nodes, node);
+        if from == *ty {
+            return;
+        }
+        let is_null = nodes[node].kind == NodeKind::Num(0);
+        let kind = match ty {
+            _ if from.is_integer() && ty.is_integer() => return Self::cast(nodes, node, ty),
+            Type::Ptr(_) if from.is_integer() && !is_null => "integer to pointer",
+            _ if ty.is_integer() && from.dest_type().is_some() => "pointer to integer",
+            _ => return,
+        };
+        let context = match conversion {
+            Conversion::Assigning => format!("assigning to '{}' from '{}'", ty, from),
+            Conversion::Passing => format!("passing '{}' to parameter of type '{}'", from, ty),
+            Conversion::Returning => format!(
+                "returning '{}' from a function with result type '{}'",
+                from, ty
+            ),
+        };
+        self.report(
+            ErrorCode::IncompatibleTypes,
+            nodes,
+            node,
+            &format!("incompatible {} conversion {}", kind, context),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{AstBuilder, AstBuilderImpl};
+    use crate::error::ErrorPrinter;
+    use crate::tokenizer::Tokenizer;
+
+    #[test]
+    fn test_semantic_analyzer_annotates_types() {
+        let code = "int main() { char c; int *p; c = 1; return *p + c; }";
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        let mut ast_builder = AstBuilderImpl::new(&error_printer, &tokens);
+        let mut ast = ast_builder.build(false);
+        SemanticAnalyzer::new(&error_printer).analyze(&mut ast);
+        assert_eq!(error_printer.error_count(), 0);
+        let nodes = &ast.nodes;
+        let body = ast.functions["main"].body.unwrap();
+        let NodeKind::Block(children) = &nodes[body].kind else {
+            panic!("not a block")
+        };
+        let NodeKind::Assign(_, rhs) = nodes[children[2]].kind else {
+            panic!("not an assignment")
+        };
+        assert_eq!(nodes[children[2]].cty, Some(Type::I8));
+        assert!(matches!(nodes[rhs].kind, NodeKind::Unary(UnaryOp::Cast, _)));
+        let NodeKind::Return(add) = nodes[children[3]].kind else {
+            panic!("not a return statement")
+        };
+        assert_eq!(nodes[add].cty, Some(Type::I32));
+        assert_eq!(nodes[add].category, Some(ValueCategory::RValue));
+        let NodeKind::Binary(BinaryOp::Add, deref, cast) = nodes[add].kind else {
+            panic!("not an addition")
+        };
+        assert_eq!(nodes[deref].cty, Some(Type::I32));
+        assert_eq!(nodes[deref].category, Some(ValueCategory::LValue));
+        let NodeKind::Unary(UnaryOp::Cast, operand) = nodes[cast].kind else {
+            panic!("not a cast")
+        };
+        assert_eq!(nodes[operand].cty, Some(Type::I8));
+    }
+
+    #[test]
+    fn test_semantic_analyzer_reports_type_errors() {
+        let code = "int main() { int a; int *p; a = p; p = 0; p = 1; a(); return *a; }";
+        let error_printer = ErrorPrinter::new(code);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        let mut ast_builder = AstBuilderImpl::new(&error_printer, &tokens);
+        let mut ast = ast_builder.build(false);
+        SemanticAnalyzer::new(&error_printer).analyze(&mut ast);
+        assert_eq!(
+            error_printer
+                .errors()
+                .iter()
+                .map(|e| (e.code, e.msg.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    ErrorCode::IncompatibleTypes,
+                    "incompatible pointer to integer conversion assigning to 'int' from 'int *'"
+                ),
+                (
+                    ErrorCode::IncompatibleTypes,
+                    "incompatible integer to pointer conversion assigning to 'int *' from 'int'"
+                ),
+                (
+                    ErrorCode::NotAFunction,
+                    "called object type 'int' is not a function or function pointer"
+                ),
+                (
+                    ErrorCode::InvalidIndirection,
+                    "indirection requires pointer operand ('int' invalid)"
+                ),
+            ]
+        );
+    }
+}
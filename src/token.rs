@@ -1,18 +1,13 @@
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum TokenType {
     Reserved,
     Ident,
+    #[default]
     Num,
     Str,
     EOF,
 }
 
-impl Default for TokenType {
-    fn default() -> Self {
-        Self::Num
-    }
-}
-
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct Token {
     pub tt: TokenType,
@@ -4,7 +4,19 @@ use crate::trie::Trie;
 use std::collections::HashSet;
 
 pub const TYPES: [&str; 2] = ["int", "char"];
-const RESERVED_WORDS: [&str; 7] = ["return", "if", "else", "while", "for", "break", "sizeof"];
+/// The words which may begin a function definition before its return type.
+pub const FUNCTION_SPECIFIERS: [&str; 2] = ["inline", "__attribute__"];
+const RESERVED_WORDS: [&str; 9] = [
+    "return",
+    "if",
+    "else",
+    "while",
+    "for",
+    "break",
+    "sizeof",
+    "inline",
+    "__attribute__",
+];
 const RESERVED_SYMBOLS: [&str; 48] = [
     "=", "+", "-", "*", "/", "%", "<", ">", "==", "!=", "+=", "-=", "*=", "/=", "%=", "<=", ">=",
     "&", "^", "|", "&&", "||", "<<", ">>", "{", "}", "(", ")", "[", "]", ",", ";", "/*", "//",
@@ -118,7 +130,8 @@ impl Tokenizer {
                                             "//" | "#" => break,
                                             _ => {
                                                 return Err(error::SyntaxError::new(
-                                                    i,
+                                                    error::ErrorCode::UnterminatedLiteral,
+                                                    code.len(),
                                                     "unexpected EOF",
                                                 ));
                                             }
@@ -145,6 +158,7 @@ impl Tokenizer {
                                 if &code[pos..pos + match_size] == "'" {
                                     if i - pos == 2 {
                                         return Err(error::SyntaxError::new(
+                                            error::ErrorCode::UnexpectedCharacter,
                                             pos + 1,
                                             "unexpected character",
                                         ));
@@ -157,6 +171,7 @@ impl Tokenizer {
                                             "\\n" => '\n',
                                             _ => {
                                                 return Err(error::SyntaxError::new(
+                                                    error::ErrorCode::MultiCharacterConstant,
                                                     pos,
                                                     "multi-character character constant",
                                                 ));
@@ -186,7 +201,11 @@ impl Tokenizer {
                             }
                         }
                     } else if i < chars.len() {
-                        return Err(error::SyntaxError::new(pos, "unexpected character"));
+                        return Err(error::SyntaxError::new(
+                            error::ErrorCode::UnexpectedCharacter,
+                            pos,
+                            "unexpected character",
+                        ));
                     }
                 }
             }
@@ -199,6 +218,32 @@ impl Tokenizer {
     pub fn print_tokens(tokens: &[Token]) {
         tokens.iter().for_each(|t| t.print())
     }
+    /// Returns the length in bytes of the token at the beginning of `code`,
+    /// including the quotes of literals, or of its first character if it does not start a token.
+    pub fn token_length(code: &str) -> usize {
+        if let Some(quote @ ('"' | '\'')) = code.chars().next() {
+            let mut escaped = false;
+            for (i, c) in code.char_indices().skip(1) {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    c if c == quote => return i + 1,
+                    _ => {}
+                }
+            }
+            return code.len();
+        }
+        let word_length = code
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(code.len());
+        if word_length > 0 {
+            return word_length;
+        }
+        match Trie::new(&RESERVED_SYMBOLS).matched_length(code) {
+            0 => code.chars().next().map_or(0, char::len_utf8),
+            n => n,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,365 @@
+use crate::ast::ProgramAst;
+use crate::ctype::Type;
+use crate::error::ErrorLogger;
+use crate::node::{BinaryOp, NodeArena, NodeId, NodeKind};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Warning {
+    UnusedVariable,
+    UnusedParameter,
+    ImplicitFunctionDeclaration,
+    ReturnType,
+    /// Reserved for comparisons of signed and unsigned integers, which cannot be made
+    /// until unsigned types exist. It is accepted by `-Wsign-compare` but never reported.
+    SignCompare,
+    PointerIntegerCompare,
+    Parentheses,
+    UnreachableCode,
+    DivisionByZero,
+    IntegerOverflow,
+    ShiftCountOverflow,
+    UnknownAttributes,
+}
+
+impl Warning {
+    pub const ALL: [Warning; 12] = [
+        Warning::UnusedVariable,
+        Warning::UnusedParameter,
+        Warning::ImplicitFunctionDeclaration,
+        Warning::ReturnType,
+        Warning::SignCompare,
+        Warning::PointerIntegerCompare,
+        Warning::Parentheses,
+        Warning::UnreachableCode,
+        Warning::DivisionByZero,
+        Warning::IntegerOverflow,
+        Warning::ShiftCountOverflow,
+        Warning::UnknownAttributes,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::UnusedVariable => "unused-variable",
+            Warning::UnusedParameter => "unused-parameter",
+            Warning::ImplicitFunctionDeclaration => "implicit-function-declaration",
+            Warning::ReturnType => "return-type",
+            Warning::SignCompare => "sign-compare",
+            Warning::PointerIntegerCompare => "pointer-integer-compare",
+            Warning::Parentheses => "parentheses",
+            Warning::UnreachableCode => "unreachable-code",
+            Warning::DivisionByZero => "division-by-zero",
+            Warning::IntegerOverflow => "integer-overflow",
+            Warning::ShiftCountOverflow => "shift-count-overflow",
+            Warning::UnknownAttributes => "unknown-attributes",
+        }
+    }
+    /// Returns the stable code which identifies the warning in machine-readable diagnostics.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Warning::UnusedVariable => "W0001",
+            Warning::UnusedParameter => "W0002",
+            Warning::ImplicitFunctionDeclaration => "W0003",
+            Warning::ReturnType => "W0004",
+            Warning::SignCompare => "W0005",
+            Warning::Parentheses => "W0006",
+            Warning::UnreachableCode => "W0007",
+            Warning::DivisionByZero => "W0008",
+            Warning::UnknownAttributes => "W0009",
+            Warning::IntegerOverflow => "W0010",
+            Warning::ShiftCountOverflow => "W0011",
+            Warning::PointerIntegerCompare => "W0012",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|w| w.name() == name)
+    }
+    fn groups(&self) -> &'static [&'static str] {
+        match self {
+            Warning::UnusedVariable | Warning::Parentheses => &["all"],
+            Warning::ImplicitFunctionDeclaration
+            | Warning::ReturnType
+            | Warning::PointerIntegerCompare
+            | Warning::DivisionByZero
+            | Warning::IntegerOverflow
+            | Warning::ShiftCountOverflow
+            | Warning::UnknownAttributes => &["default", "all"],
+            Warning::UnusedParameter => &["extra"],
+            Warning::SignCompare | Warning::UnreachableCode => &[],
+        }
+    }
+}
+
+/// Which warnings are reported, set by `-W<name>`, `-Wno-<name>`, `-Wall`, `-Wextra` and `-Werror`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarningOptions {
+    enabled: HashSet<Warning>,
+    pub werror: bool,
+}
+
+impl Default for WarningOptions {
+    fn default() -> Self {
+        Self {
+            enabled: Self::group("default"),
+            werror: false,
+        }
+    }
+}
+
+impl WarningOptions {
+    fn group(group: &str) -> HashSet<Warning> {
+        Warning::ALL
+            .into_iter()
+            .filter(|w| w.groups().contains(&group))
+            .collect()
+    }
+    pub fn is_enabled(&self, warning: Warning) -> bool {
+        self.enabled.contains(&warning)
+    }
+    /// Applies a flag given as `-W<flag>`.
+    pub fn apply_flag(&mut self, flag: &str) -> Result<(), String> {
+        let (enable, name) = match flag.strip_prefix("no-") {
+            Some(name) => (false, name),
+            None => (true, flag),
+        };
+        let warnings = match name {
+            "error" => {
+                self.werror = enable;
+                return Ok(());
+            }
+            "all" | "extra" => Self::group(name),
+            _ => Warning::from_name(name)
+                .map(|w| HashSet::from([w]))
+                .ok_or_else(|| format!("unknown warning option '-W{}'", flag))?,
+        };
+        if enable {
+            self.enabled.extend(warnings);
+        } else {
+            self.enabled.retain(|w| !warnings.contains(w));
+        }
+        Ok(())
+    }
+}
+
+/// Reports the warnings which need a whole function body to be decided.
+pub struct WarningChecker<'a> {
+    error_logger: &'a dyn ErrorLogger,
+}
+
+impl<'a> WarningChecker<'a> {
+    pub fn new(error_logger: &'a dyn ErrorLogger) -> Self {
+        Self { error_logger }
+    }
+
+    pub fn check(&self, ast: &ProgramAst) {
+        ast.functions
+            .iter()
+            .filter_map(|(name, f)| f.body.map(|body| (name, f, body)))
+            .for_each(|(name, f, body)| {
+                if self.can_complete(&ast.nodes, body) && name != "main" {
+                    self.error_logger.warn(
+                        Warning::ReturnType,
+                        f.token.as_ref().unwrap().pos,
+                        "non-void function does not return a value in all control paths",
+                    );
+                }
+                self.check_comparisons(&ast.nodes, body);
+            })
+    }
+
+    /// Returns whether the control can reach the end of the statement,
+    /// reporting the first statement of each block which follows one that cannot.
+    fn can_complete(&self, nodes: &NodeArena, node: NodeId) -> bool {
+        match &nodes[node].kind {
+            NodeKind::Return(_) | NodeKind::Break => false,
+            NodeKind::Block(children) => {
+                let mut reachable = true;
+                for child in children {
+                    if !reachable {
+                        if let Some(pos) = nodes.first_pos(*child) {
+                            self.error_logger.warn(
+                                Warning::UnreachableCode,
+                                pos,
+                                "code will never be executed",
+                            );
+                        }
+                        return false;
+                    }
+                    reachable = self.can_complete(nodes, *child);
+                }
+                reachable
+            }
+            NodeKind::If { then, els, .. } => {
+                let then = self.can_complete(nodes, *then);
+                let els = els.is_none_or(|els| self.can_complete(nodes, els));
+                then || els
+            }
+            NodeKind::While { cond, body } => self.loop_can_complete(nodes, Some(*cond), *body),
+            NodeKind::For { cond, body, .. } => self.loop_can_complete(nodes, *cond, *body),
+            _ => true,
+        }
+    }
+
+    fn loop_can_complete(&self, nodes: &NodeArena, cond: Option<NodeId>, body: NodeId) -> bool {
+        self.can_complete(nodes, body);
+        let is_infinite =
+            cond.is_none_or(|cond| matches!(nodes[cond].kind, NodeKind::Num(value) if value != 0));
+        !is_infinite || Self::has_break(nodes, body)
+    }
+
+    /// Returns whether the statement contains `break` for the enclosing loop.
+    fn has_break(nodes: &NodeArena, node: NodeId) -> bool {
+        match &nodes[node].kind {
+            NodeKind::Break => true,
+            NodeKind::If { then, els, .. } => [Some(*then), *els]
+                .into_iter()
+                .flatten()
+                .any(|n| Self::has_break(nodes, n)),
+            NodeKind::Block(children) => children.iter().any(|n| Self::has_break(nodes, *n)),
+            _ => false,
+        }
+    }
+
+    fn check_comparisons(&self, nodes: &NodeArena, node: NodeId) {
+        if let NodeKind::Binary(BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le, lhs, rhs) =
+            nodes[node].kind
+            && let (Some(lhs_ty), Some(rhs_ty)) = (nodes.resolve_type(lhs), nodes.resolve_type(rhs))
+        {
+            let is_null = |n: NodeId| nodes[n].kind == NodeKind::Num(0);
+            let is_pointer = |ty: &Type| matches!(ty, Type::Ptr(_) | Type::Arr(..));
+            if (is_pointer(&lhs_ty) && rhs_ty.is_integer() && !is_null(rhs))
+                || (lhs_ty.is_integer() && is_pointer(&rhs_ty) && !is_null(lhs))
+            {
+                self.error_logger.warn(
+                    Warning::PointerIntegerCompare,
+                    nodes[node].pos.unwrap_or(0),
+                    &format!(
+                        "comparison between pointer and integer ('{}' and '{}')",
+                        lhs_ty, rhs_ty
+                    ),
+                );
+            }
+        }
+        nodes
+            .edges(node)
+            .into_iter()
+            .for_each(|(_, n)| self.check_comparisons(nodes, n));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{AstBuilder, AstBuilderImpl};
+    use crate::error::ErrorPrinter;
+    use crate::tokenizer::Tokenizer;
+    use crate::{CompileOptions, Compiler};
+
+    fn warnings(code: &str, flags: &[&str]) -> Vec<(Warning, String)> {
+        let mut options = WarningOptions::default();
+        flags
+            .iter()
+            .for_each(|flag| options.apply_flag(flag).unwrap());
+        let error_printer = ErrorPrinter::new(code).with_warning_options(options);
+        let tokens = Tokenizer::tokenize(code, false).unwrap();
+        let mut ast_builder = AstBuilderImpl::new(&error_printer, &tokens);
+        let ast = ast_builder.build(false);
+        WarningChecker::new(&error_printer).check(&ast);
+        error_printer
+            .diagnostics()
+            .into_iter()
+            .map(|d| (d.warning().unwrap(), d.msg))
+            .collect()
+    }
+
+    #[test]
+    fn test_warning_options() {
+        let mut options = WarningOptions::default();
+        assert!(options.is_enabled(Warning::ReturnType));
+        assert!(!options.is_enabled(Warning::UnusedVariable));
+        options.apply_flag("all").unwrap();
+        options.apply_flag("no-return-type").unwrap();
+        options.apply_flag("error").unwrap();
+        assert!(options.is_enabled(Warning::UnusedVariable));
+        assert!(!options.is_enabled(Warning::ReturnType));
+        assert!(options.werror);
+        // the reserved warning is accepted, but not enabled by the groups
+        options.apply_flag("extra").unwrap();
+        assert!(!options.is_enabled(Warning::SignCompare));
+        assert_eq!(options.apply_flag("sign-compare"), Ok(()));
+        assert_eq!(
+            options.apply_flag("bogus"),
+            Err("unknown warning option '-Wbogus'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_comparison_warnings() {
+        let code = "int f(int *p, int a[2]) { return (p == 5) + (3 < p) + (p == 0) + (a == p) + (1 == 'a'); }";
+        assert_eq!(
+            warnings(code, &["all", "extra"]),
+            vec![
+                (
+                    Warning::PointerIntegerCompare,
+                    "comparison between pointer and integer ('int *' and 'int')".to_string()
+                ),
+                (
+                    Warning::PointerIntegerCompare,
+                    "comparison between pointer and integer ('int' and 'int *')".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warning_checker() {
+        let code =
+            "int f(int a) { int x; if (a = 1) return 1; }\nint main() { return g(); return 0; }";
+        assert_eq!(
+            warnings(code, &[]),
+            vec![
+                (
+                    Warning::ReturnType,
+                    "non-void function does not return a value in all control paths".to_string()
+                ),
+                (
+                    Warning::ImplicitFunctionDeclaration,
+                    "implicit declaration of function 'g'".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            warnings(
+                code,
+                &["all", "extra", "unreachable-code", "no-return-type"]
+            )
+            .into_iter()
+            .map(|(w, _)| w)
+            .collect::<Vec<_>>(),
+            vec![
+                Warning::UnusedVariable,
+                Warning::Parentheses,
+                Warning::ImplicitFunctionDeclaration,
+                Warning::UnreachableCode,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warning_checker_skips_recovered_code() {
+        let code = "int g() { return 2 }\nint main() { return g(); }";
+        let compiler = Compiler::new(
+            code,
+            CompileOptions::default().with_warnings({
+                let mut options = WarningOptions::default();
+                options.apply_flag("unreachable-code").unwrap();
+                options
+            }),
+        );
+        let tokens = compiler.tokenize().unwrap();
+        let mut ast = compiler.parse(&tokens);
+        assert!(compiler.analyze(&mut ast).is_err());
+        let diagnostics = compiler.diagnostics();
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics.iter().all(|d| d.warning().is_none()));
+    }
+}
@@ -0,0 +1,67 @@
+#include <stdio.h>
+
+int squares[64];
+char letters[27];
+
+int fill(int *p, int from, int to, int step) {
+    int i;
+    for (i = from; i < to; i = i + step) {
+        p[i] = i * i;
+    }
+    return 0;
+}
+
+int sum(int *p, int n, int k) {
+    int i;
+    int s;
+    s = 0;
+    for (i = 0; i < n; i = i + 1) {
+        s = s + p[i] * (k * 3 + n);
+    }
+    return s;
+}
+
+// the division is only reached when the loop is entered
+int divide_each(int n, int d) {
+    int i;
+    int s;
+    s = 0;
+    i = 0;
+    while (i < n) {
+        s = s + 100 / d + i;
+        i = i + 1;
+    }
+    return s;
+}
+
+int matrix(int n) {
+    int m[36];
+    int i;
+    int j;
+    int s;
+    for (i = 0; i < n; i = i + 1) {
+        for (j = 0; j < n; j = j + 1) {
+            m[i * n + j] = i * 10 + j;
+        }
+    }
+    s = 0;
+    for (j = 0; j < n; j = j + 1) {
+        for (i = j; i < n; i = i + 1) {
+            s = s + m[i * n + j] * (n + 1);
+        }
+    }
+    return s;
+}
+
+int main() {
+    int i;
+    fill(squares, 0, 64, 1);
+    fill(squares, 3, 64, 7);
+    printf("%d, %d, %d\n", sum(squares, 64, 2), sum(squares, 0, 5), squares[63]);
+    printf("%d, %d\n", divide_each(10, 3), divide_each(0, 0));
+    for (i = 0; i < 26; i = i + 1) {
+        letters[25 - i] = 'a' + i;
+    }
+    printf("%s, %d\n", letters, matrix(6));
+    return 0;
+}
@@ -0,0 +1,17 @@
+int sum(int n) {
+    int i;
+    int s = 0;
+    for (i = 1; i <= n; i++) {
+        if (i % 2) {
+            s += i;
+        } else {
+            s += 2 * i;
+        }
+    }
+    return s;
+}
+
+int greet(char *name) {
+    printf("hello from %s\n", name);
+    return 0;
+}
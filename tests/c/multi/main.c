@@ -0,0 +1,15 @@
+int sum(int n);
+int greet(char *);
+
+int main() {
+    int i;
+    for (i = 1; i <= 3; i++) {
+        if (i == 2) {
+            printf("%s, ", "two");
+        } else {
+            printf("%d, ", sum(i));
+        }
+    }
+    greet("main");
+    return 0;
+}
@@ -0,0 +1,20 @@
+#include <stdio.h>
+int count(int n, int acc) {
+    if (n == 0)
+        return acc;
+    return count(n - 1, acc + 1);
+}
+int is_even(int n) {
+    if (n == 0)
+        return 1;
+    return is_odd(n - 1);
+}
+int is_odd(int n) {
+    if (n == 0)
+        return 0;
+    return is_even(n - 1);
+}
+int main() {
+    printf("%d, %d, %d\n", count(10000000, 0), is_even(10000000), is_odd(10000001));
+    return 0;
+}
@@ -1,10 +1,12 @@
 use std::{
     fs::{self, remove_file},
     io::Write,
-    process::Command,
+    process::{Command, Stdio},
 };
 extern crate dynamite_compiler;
 extern crate rand;
+use dynamite_compiler::ir::opt::{OptLevel, OptOptions};
+use dynamite_compiler::{CompileOptions, OutputKind};
 use rand::distributions::{Alphanumeric, DistString};
 use test_case::test_matrix;
 
@@ -49,33 +51,302 @@ fn it_compiles_string_c(output_option: &str) {
 fn it_compiles_simple_expr_c(output_option: &str) {
     let code = &fs::read_to_string("./tests/c/simple_expr.c").unwrap();
     let got = compile_and_get_stdout(code, output_option);
-    got.split("\n")
-        .filter(|s| !s.is_empty() && !s.ends_with("OK"))
-        .for_each(|s| panic!("assertion failed:\n  {}\n", s));
+    if let Some(s) = got
+        .split("\n")
+        .find(|s| !s.is_empty() && !s.ends_with("OK"))
+    {
+        panic!("assertion failed:\n  {}\n", s);
+    }
 }
 
 #[cfg(target_arch = "x86_64")]
-#[test_matrix(["asm"])]
+#[test_matrix(["asm", "llvm"])]
 fn it_compiles_expr_c(output_option: &str) {
     let code = &fs::read_to_string("./tests/c/expr.c").unwrap();
     let got = compile_and_get_stdout(code, output_option);
-    got.split("\n")
-        .filter(|s| !s.is_empty() && !s.ends_with("OK"))
-        .for_each(|s| panic!("assertion failed:\n  {}\n", s));
+    if let Some(s) = got
+        .split("\n")
+        .find(|s| !s.is_empty() && !s.ends_with("OK"))
+    {
+        panic!("assertion failed:\n  {}\n", s);
+    }
 }
 
 #[cfg(target_arch = "x86_64")]
-#[test_matrix(["asm"])]
+#[test_matrix(["asm", "llvm"])]
 fn it_compiles_many_functions_c(output_option: &str) {
     let code = &fs::read_to_string("./tests/c/many_functions.c").unwrap();
     let got = compile_and_get_stdout(code, output_option);
-    got.split("\n")
-        .filter(|s| !s.is_empty() && !s.ends_with("OK"))
-        .for_each(|s| panic!("assertion failed:\n  {}\n", s));
+    if let Some(s) = got
+        .split("\n")
+        .find(|s| !s.is_empty() && !s.ends_with("OK"))
+    {
+        panic!("assertion failed:\n  {}\n", s);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test_matrix(
+    ["asm", "llvm"],
+    ["simple_expr.c", "expr.c", "many_functions.c", "string.c", "loops.c"]
+)]
+fn it_compiles_the_same_with_optimizations(output_option: &str, file: &str) {
+    let code = &fs::read_to_string(format!("./tests/c/{}", file)).unwrap();
+    let options = CompileOptions::default()
+        .with_output(OutputKind::from_name(output_option).unwrap())
+        .with_opt(OptOptions::new(OptLevel::O2));
+    let assembly = dynamite_compiler::generate_with_options(code, &options).unwrap();
+    assert_eq!(
+        run(&assembly, output_option),
+        compile_and_get_stdout(code, output_option)
+    );
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test_matrix(["asm", "llvm"])]
+fn it_runs_deep_recursion_in_tail_calls(output_option: &str) {
+    let code = &fs::read_to_string("./tests/c/tail_call.c").unwrap();
+    // the recursion is deeper than the stack could hold with a frame for each call
+    // and at -O2 the inlined calls of the mutual recursion return through a phi
+    for level in [OptLevel::O1, OptLevel::O2] {
+        let options = CompileOptions::default()
+            .with_output(OutputKind::from_name(output_option).unwrap())
+            .with_opt(OptOptions::new(level));
+        let assembly = dynamite_compiler::generate_with_options(code, &options).unwrap();
+        assert_eq!(run(&assembly, output_option), "10000000, 1, 1\n");
+    }
+}
+
+#[test_matrix(["asm", "llvm"])]
+fn it_generates_identical_output_in_declaration_order(output_option: &str) {
+    let code = "int x; int b; int a;
+int second(int n) { return n * 2; }
+int first(int n) { return second(n) + 1; }
+int main() { printf(\"%d\\n\", first(a + b + x)); return 0; }";
+    let output = dynamite_compiler::generate(code, output_option, false).unwrap();
+    for _ in 0..8 {
+        assert_eq!(
+            dynamite_compiler::generate(code, output_option, false).unwrap(),
+            output
+        );
+    }
+    let (global_label, function_label) = match output_option {
+        "asm" => ("\n{}:", "\n{}:"),
+        _ => ("\n@{} =", "@{}("),
+    };
+    for (names, label) in [
+        (vec!["x", "b", "a"], global_label),
+        (vec!["second", "first", "main"], function_label),
+    ] {
+        let positions = names
+            .iter()
+            .map(|name| output.find(&label.replace("{}", name)).unwrap())
+            .collect::<Vec<usize>>();
+        assert!(positions.is_sorted(), "{:?}", positions);
+    }
+}
+
+#[test]
+fn it_returns_errors_from_generate() {
+    let code = "int main() { return 0; }";
+    assert_eq!(
+        dynamite_compiler::generate(code, "bogus", false),
+        Err("unknown output kind 'bogus'".to_string())
+    );
+    let error = dynamite_compiler::generate("int main() { return x; }", "asm", false).unwrap_err();
+    assert!(error.contains("undefined variable"));
+}
+
+#[test]
+fn it_builds_an_executable_with_the_driver() {
+    let mut rng = rand::thread_rng();
+    fs::create_dir_all("./tests/temp").unwrap();
+    let binary_name = &format!("./tests/temp/{}", Alphanumeric.sample_string(&mut rng, 32));
+    let status = Command::new(env!("CARGO_BIN_EXE_dynamite_compiler"))
+        .args(["-o", binary_name, "./tests/c/simple.c"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = Command::new(binary_name).output().unwrap();
+    remove_file(binary_name).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "12, 2, 35, 5, 7\n")
+}
+
+#[test]
+fn it_takes_the_optimization_level_only_attached() {
+    let compile = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_dynamite_compiler"))
+            .args(["-S", "-o", "-"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    // a bare `-O` is `-O1`, and does not take the input file after it
+    let output = compile(&["-O", "./tests/c/simple.c"]);
+    assert!(output.status.success());
+    assert_eq!(
+        output.stdout,
+        compile(&["-O1", "./tests/c/simple.c"]).stdout
+    );
+    for level in ["-O3", "-Os", "-Oz", "-Ofast"] {
+        let output = compile(&[level, "./tests/c/simple.c"]);
+        assert!(output.status.success());
+        assert_eq!(
+            output.stdout,
+            compile(&["-O2", "./tests/c/simple.c"]).stdout
+        );
+    }
+    assert!(!compile(&["-O4", "./tests/c/simple.c"]).status.success());
+}
+
+#[test]
+fn it_accepts_the_options_of_build_systems() {
+    let compile = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_dynamite_compiler"))
+            .args(args)
+            .args(["-S", "-o", "-", "./tests/c/simple.c"])
+            .output()
+            .unwrap()
+    };
+    let output = compile(&[
+        "-g",
+        "-I",
+        "include",
+        "-Iinclude",
+        "-DNDEBUG",
+        "-std=c99",
+        "-Wall",
+        "-Wshadow",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, compile(&[]).stdout);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown warning option '-Wshadow'"));
+    let output = compile(&["--bogus"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unrecognized option: 'bogus'"));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("panicked"));
+}
+
+#[test]
+fn it_links_multiple_translation_units() {
+    let mut rng = rand::thread_rng();
+    fs::create_dir_all("./tests/temp").unwrap();
+    let binary_name = &format!("./tests/temp/{}", Alphanumeric.sample_string(&mut rng, 32));
+    let output = Command::new(env!("CARGO_BIN_EXE_dynamite_compiler"))
+        .args([
+            "-o",
+            binary_name,
+            "./tests/c/multi/main.c",
+            "./tests/c/multi/lib.c",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    // the functions of the other unit are declared, not implicitly
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    let output = Command::new(binary_name).output().unwrap();
+    remove_file(binary_name).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "1, two, 8, hello from main\n"
+    )
+}
+
+#[test]
+fn it_generates_code_for_another_target() {
+    let compile = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_dynamite_compiler"))
+            .args(args)
+            .args(["-S", "-o", "-", "./tests/c/simple.c"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    let assembly = compile(&["--target", "aarch64-apple-darwin"]);
+    assert!(assembly.contains("_main:"));
+    assert!(assembly.contains("stp x29, x30"));
+    let ir = compile(&["--target", "aarch64-linux-gnu", "-emit-llvm"]);
+    assert!(ir.contains("target triple = \"aarch64-unknown-linux-gnu\""));
+}
+
+#[test]
+fn it_allocates_registers_for_aarch64() {
+    let compile = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_dynamite_compiler"))
+            .args(["--target", "aarch64-linux-gnu"])
+            .args(args)
+            .args(["-S", "-o", "-", "./tests/c/many_functions.c"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    let assembly = compile(&[]);
+    // the callee-saved registers are saved in the prologue and restored in the epilogue
+    assert!(assembly.contains("str x19, [sp, #"));
+    assert!(assembly.contains("ldr x19, [sp, #"));
+    let spilled = compile(&["-fno-regalloc"]);
+    assert!(!spilled.contains("x9") && !spilled.contains("x19"));
+    assert!(assembly.lines().count() < spilled.lines().count());
+}
+
+#[test]
+fn it_reads_the_source_from_stdin() {
+    let compile = |code: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_dynamite_compiler"))
+            .args(["-x", "c", "--diagnostics-format=json", "-S", "-o", "-", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(code.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    };
+    let output = compile("int main() { return 0; }");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("main:"));
+    let output = compile("int main() { return x; }");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(r#""file":"<stdin>""#));
+}
+
+#[test]
+fn it_emits_intermediate_representations() {
+    let emit = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_dynamite_compiler"))
+            .args(args)
+            .args(["-o", "-", "./tests/c/simple.c"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    assert!(emit(&["--emit=tokens"]).starts_with("2:1 reserved int\n2:5 identifier main\n"));
+    assert!(emit(&["--emit=ast", "--emit-format=json"]).starts_with(
+        r#"{"globals":[],"string_literals":["%d, %d, %d, %d, %d\\n"],"functions":[{"name":"main","#
+    ));
+    assert!(emit(&["--emit=typed-ast"]).contains("\n      lhs: GlobalVar printf : int (char *)\n"));
+    assert!(emit(&["--dump-ast-dot"]).starts_with("digraph ast {\n"));
+    assert!(emit(&["--dump-cfg-dot"]).contains("\n  subgraph \"cluster_main\" {\n"));
+    assert!(emit(&["--emit=ir"]).contains("\ndefine i32 @main() {\nbb0:\n"));
 }
 
 fn compile_and_get_stdout(code: &str, output_option: &str) -> String {
-    let assembly = dynamite_compiler::generate(code, output_option, false);
+    run(
+        &dynamite_compiler::generate(code, output_option, false).unwrap(),
+        output_option,
+    )
+}
+
+/// Builds an executable from the output of the compiler, and returns what it prints.
+fn run(assembly: &str, output_option: &str) -> String {
     let mut rng = rand::thread_rng();
     fs::create_dir_all("./tests/temp").unwrap();
     let binary_name = &format!("./tests/temp/{}", Alphanumeric.sample_string(&mut rng, 32));